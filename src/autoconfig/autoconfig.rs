@@ -0,0 +1,358 @@
+//! Autoconfig module.
+//!
+//! This module contains the discovery of the IMAP and SMTP
+//! configurations of an email address. The following sources are
+//! tried in order, the first one giving a configuration wins:
+//!
+//! 1. the autoconfig server of the provider
+//!    (`https://autoconfig.<domain>/mail/config-v1.1.xml`),
+//! 2. the well-known autoconfig URL of the domain
+//!    (`https://<domain>/.well-known/autoconfig/mail/config-v1.1.xml`),
+//! 3. the Mozilla ISPDB (`https://autoconfig.thunderbird.net/v1.1/<domain>`),
+//! 4. the DNS SRV records of the domain, as defined in the [RFC 6186]
+//!    and the [RFC 8314].
+//!
+//! [RFC 6186]: https://www.rfc-editor.org/rfc/rfc6186
+//! [RFC 8314]: https://www.rfc-editor.org/rfc/rfc8314
+
+use log::{debug, info, warn};
+use std::{cmp::Reverse, io, result};
+use thiserror::Error;
+
+#[cfg(feature = "imap-backend")]
+use crate::ImapConfig;
+
+#[cfg(feature = "smtp-sender")]
+use crate::SmtpConfig;
+
+use super::{
+    lookup::{
+        AutoconfigDns, AutoconfigHttp, DefaultAutoconfigDns, DefaultAutoconfigHttp, SrvRecord,
+    },
+    xml::ClientConfig,
+};
+
+/// Represents the default URL of the Mozilla ISPDB.
+pub const DEFAULT_ISPDB_URL: &str = "https://autoconfig.thunderbird.net/v1.1";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("cannot discover config: invalid email address {0}")]
+    ParseEmailAddressError(String),
+    #[error("cannot discover config of {0}: no config found")]
+    DiscoverConfigNotFoundError(String),
+    #[error("cannot create tls connector")]
+    CreateTlsConnectorError(#[source] native_tls::Error),
+    #[error("cannot get autoconfig document at {1}")]
+    GetHttpDocumentError(#[source] Box<ureq::Error>, String),
+    #[error("cannot read autoconfig document at {1}")]
+    ReadHttpDocumentError(#[source] io::Error, String),
+    #[error("cannot parse autoconfig document at {1}")]
+    ParseXmlDocumentError(#[source] quick_xml::DeError, String),
+    #[error("cannot create dns resolver from system config")]
+    CreateDnsResolverError(#[source] io::Error),
+    #[error("cannot lookup srv records of {1}")]
+    LookupSrvRecordsError(#[source] trust_dns_resolver::error::ResolveError, String),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents the source of a discovered configuration.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum AutoconfigSource {
+    /// Represents the autoconfig server of the provider, with the URL
+    /// of the document.
+    Provider(String),
+    /// Represents the well-known autoconfig URL of the domain.
+    WellKnown(String),
+    /// Represents the Mozilla ISPDB, with the URL of the document.
+    Ispdb(String),
+    /// Represents the DNS SRV records of the domain.
+    DnsSrv,
+}
+
+/// Represents a discovered configuration. Secrets are left undefined:
+/// the password (or the OAuth 2.0 configuration) still needs to be
+/// set.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Autoconfig {
+    pub source: AutoconfigSource,
+    #[cfg(feature = "imap-backend")]
+    pub imap: Option<ImapConfig>,
+    #[cfg(feature = "smtp-sender")]
+    pub smtp: Option<SmtpConfig>,
+}
+
+impl Autoconfig {
+    /// Discovers the configuration of the given email address, using
+    /// the default lookups.
+    pub fn discover(email: &str) -> Result<Self> {
+        AutoconfigBuilder::new().build(email)
+    }
+
+    fn new(source: AutoconfigSource) -> Self {
+        Self {
+            source,
+            #[cfg(feature = "imap-backend")]
+            imap: None,
+            #[cfg(feature = "smtp-sender")]
+            smtp: None,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        let empty = true;
+        #[cfg(feature = "imap-backend")]
+        let empty = empty && self.imap.is_none();
+        #[cfg(feature = "smtp-sender")]
+        let empty = empty && self.smtp.is_none();
+        empty
+    }
+}
+
+/// Represents the security of a connection.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Security {
+    Tls,
+    StartTls,
+    Plain,
+}
+
+impl Security {
+    fn from_socket_type(socket_type: &str) -> Option<Self> {
+        match socket_type.to_lowercase().as_str() {
+            "ssl" => Some(Self::Tls),
+            "starttls" => Some(Self::StartTls),
+            "plain" => Some(Self::Plain),
+            _ => None,
+        }
+    }
+
+    fn ssl(self) -> Option<bool> {
+        Some(self != Self::Plain)
+    }
+
+    fn starttls(self) -> Option<bool> {
+        Some(self == Self::StartTls)
+    }
+}
+
+#[cfg(feature = "imap-backend")]
+/// Checks that the given domain is a valid hostname, made of
+/// non-empty labels of letters, digits and hyphens, since it is put
+/// as is in the URLs of the autoconfig documents.
+fn is_hostname(domain: &str) -> bool {
+    domain.split('.').all(|label| {
+        !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
+
+fn imap_config(host: &str, port: u16, security: Security, login: String) -> ImapConfig {
+    ImapConfig {
+        host: host.to_owned(),
+        port,
+        ssl: security.ssl(),
+        starttls: security.starttls(),
+        login,
+        ..ImapConfig::default()
+    }
+}
+
+#[cfg(feature = "smtp-sender")]
+fn smtp_config(host: &str, port: u16, security: Security, login: String) -> SmtpConfig {
+    SmtpConfig {
+        host: host.to_owned(),
+        port,
+        ssl: security.ssl(),
+        starttls: security.starttls(),
+        login,
+        ..SmtpConfig::default()
+    }
+}
+
+/// Discovers configurations. The HTTP and DNS lookups can be
+/// replaced, for example by local stand-ins.
+pub struct AutoconfigBuilder {
+    http: Box<dyn AutoconfigHttp>,
+    dns: Box<dyn AutoconfigDns>,
+    ispdb_url: String,
+}
+
+impl Default for AutoconfigBuilder {
+    fn default() -> Self {
+        Self {
+            http: Box::new(DefaultAutoconfigHttp),
+            dns: Box::new(DefaultAutoconfigDns),
+            ispdb_url: DEFAULT_ISPDB_URL.to_owned(),
+        }
+    }
+}
+
+impl AutoconfigBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn http<H: AutoconfigHttp + 'static>(mut self, http: H) -> Self {
+        self.http = Box::new(http);
+        self
+    }
+
+    pub fn dns<D: AutoconfigDns + 'static>(mut self, dns: D) -> Self {
+        self.dns = Box::new(dns);
+        self
+    }
+
+    pub fn ispdb_url<U: ToString>(mut self, url: U) -> Self {
+        self.ispdb_url = url.to_string();
+        self
+    }
+
+    /// Discovers the configuration of the given email address. A
+    /// source failing with an error is skipped, the error is logged.
+    pub fn build(&self, email: &str) -> Result<Autoconfig> {
+        let domain = email
+            .rsplit_once('@')
+            .map(|(local_part, domain)| (local_part.trim(), domain.trim()))
+            .filter(|(local_part, domain)| !local_part.is_empty() && !domain.is_empty())
+            .map(|(_, domain)| domain.to_lowercase())
+            .filter(|domain| is_hostname(domain))
+            .ok_or_else(|| Error::ParseEmailAddressError(email.to_owned()))?;
+        let email_encoded = urlencoding::encode(email);
+
+        info!("discovering config of {email}");
+
+        let sources: [(fn(String) -> AutoconfigSource, String); 3] = [
+            (
+                AutoconfigSource::Provider,
+                format!("https://autoconfig.{domain}/mail/config-v1.1.xml?emailaddress={email_encoded}"),
+            ),
+            (
+                AutoconfigSource::WellKnown,
+                format!("https://{domain}/.well-known/autoconfig/mail/config-v1.1.xml?emailaddress={email_encoded}"),
+            ),
+            (
+                AutoconfigSource::Ispdb,
+                format!("{}/{domain}", self.ispdb_url.trim_end_matches('/')),
+            ),
+        ];
+
+        for (source, url) in sources {
+            match self.from_xml(email, &url) {
+                Ok(Some(mut config)) => {
+                    info!("config of {email} found at {url}");
+                    config.source = source(url);
+                    return Ok(config);
+                }
+                Ok(None) => debug!("no config of {email} found at {url}"),
+                Err(err) => warn!("skipping autoconfig source {url}: {err}"),
+            }
+        }
+
+        let config = self.from_srv(email, &domain);
+        if !config.is_empty() {
+            info!("config of {email} found in dns srv records");
+            return Ok(config);
+        }
+
+        Err(Error::DiscoverConfigNotFoundError(email.to_owned()))
+    }
+
+    /// Builds the configuration from the autoconfig document at the
+    /// given URL. The first IMAP and SMTP servers are taken, since
+    /// they are the preferred ones.
+    fn from_xml(&self, email: &str, url: &str) -> Result<Option<Autoconfig>> {
+        let xml = match self.http.get(url)? {
+            Some(xml) => xml,
+            None => return Ok(None),
+        };
+        let provider = ClientConfig::parse(&xml, url)?.email_provider;
+        let mut config = Autoconfig::new(AutoconfigSource::DnsSrv);
+
+        #[cfg(feature = "imap-backend")]
+        {
+            config.imap = provider
+                .incoming_servers
+                .iter()
+                .filter(|server| server.protocol.eq_ignore_ascii_case("imap"))
+                .find_map(|server| {
+                    let security = Security::from_socket_type(&server.socket_type)?;
+                    let login = server.login(email);
+                    Some(imap_config(&server.hostname, server.port, security, login))
+                });
+        }
+
+        #[cfg(feature = "smtp-sender")]
+        {
+            config.smtp = provider
+                .outgoing_servers
+                .iter()
+                .filter(|server| server.protocol.eq_ignore_ascii_case("smtp"))
+                .find_map(|server| {
+                    let security = Security::from_socket_type(&server.socket_type)?;
+                    let login = server.login(email);
+                    Some(smtp_config(&server.hostname, server.port, security, login))
+                });
+        }
+
+        Ok(if config.is_empty() {
+            None
+        } else {
+            Some(config)
+        })
+    }
+
+    /// Builds the configuration from the SRV records of the domain.
+    /// Implicit TLS services are preferred over STARTTLS ones.
+    #[allow(unused_variables)]
+    fn from_srv(&self, email: &str, domain: &str) -> Autoconfig {
+        let mut config = Autoconfig::new(AutoconfigSource::DnsSrv);
+
+        #[cfg(feature = "imap-backend")]
+        {
+            config.imap = self
+                .lookup_srv(&[
+                    (format!("_imaps._tcp.{domain}"), Security::Tls),
+                    (format!("_imap._tcp.{domain}"), Security::StartTls),
+                ])
+                .map(|(srv, security)| {
+                    imap_config(&srv.target, srv.port, security, email.to_owned())
+                });
+        }
+
+        #[cfg(feature = "smtp-sender")]
+        {
+            config.smtp = self
+                .lookup_srv(&[
+                    (format!("_submissions._tcp.{domain}"), Security::Tls),
+                    (format!("_submission._tcp.{domain}"), Security::StartTls),
+                ])
+                .map(|(srv, security)| {
+                    smtp_config(&srv.target, srv.port, security, email.to_owned())
+                });
+        }
+
+        config
+    }
+
+    /// Returns the best record of the first service available: the
+    /// one with the lowest priority, then with the highest weight.
+    #[allow(dead_code)]
+    fn lookup_srv(&self, services: &[(String, Security)]) -> Option<(SrvRecord, Security)> {
+        services.iter().find_map(|(name, security)| {
+            let records = match self.dns.srv(name) {
+                Ok(records) => records,
+                Err(err) => {
+                    warn!("skipping srv records of {name}: {err}");
+                    return None;
+                }
+            };
+
+            records
+                .into_iter()
+                .filter(|srv| !srv.target.is_empty() && srv.target != ".")
+                .min_by_key(|srv| (srv.priority, Reverse(srv.weight)))
+                .map(|srv| (srv, *security))
+        })
+    }
+}
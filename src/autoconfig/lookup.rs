@@ -0,0 +1,108 @@
+//! Autoconfig lookup module.
+//!
+//! This module contains the HTTP and DNS lookups used by the
+//! discovery. They are defined as traits, so that they can be
+//! replaced by local stand-ins.
+
+use log::debug;
+use native_tls::TlsConnector;
+use std::{io::Read, sync::Arc, time::Duration};
+use trust_dns_resolver::{error::ResolveErrorKind, Resolver};
+
+use super::autoconfig::{Error, Result};
+
+/// Represents the timeout of the default HTTP lookup.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Represents the maximum size of an autoconfig document.
+const HTTP_MAX_BODY_SIZE: u64 = 1024 * 1024;
+
+/// Represents a DNS SRV record, as defined in the [RFC 2782].
+///
+/// [RFC 2782]: https://www.rfc-editor.org/rfc/rfc2782
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    /// Represents the target host, without its trailing dot. A target
+    /// equal to `.` means that the service is not available.
+    pub target: String,
+}
+
+/// Represents the HTTP lookup of autoconfig documents.
+pub trait AutoconfigHttp {
+    /// Gets the body of the given URL. Returns `None` when the
+    /// document does not exist.
+    fn get(&self, url: &str) -> Result<Option<String>>;
+}
+
+/// Represents the DNS lookup of SRV records.
+pub trait AutoconfigDns {
+    /// Gets the SRV records of the given name. Returns an empty list
+    /// when the name has no record.
+    fn srv(&self, name: &str) -> Result<Vec<SrvRecord>>;
+}
+
+/// Represents the default HTTP lookup, over HTTPS.
+#[derive(Debug, Default, Clone)]
+pub struct DefaultAutoconfigHttp;
+
+impl AutoconfigHttp for DefaultAutoconfigHttp {
+    fn get(&self, url: &str) -> Result<Option<String>> {
+        let tls = TlsConnector::new().map_err(Error::CreateTlsConnectorError)?;
+        let agent = ureq::AgentBuilder::new()
+            .tls_connector(Arc::new(tls))
+            .timeout(HTTP_TIMEOUT)
+            .build();
+
+        let res = match agent.get(url).call() {
+            Ok(res) => res,
+            Err(ureq::Error::Status(code, _)) => {
+                debug!("autoconfig document not found at {url} (status {code})");
+                return Ok(None);
+            }
+            Err(err) => return Err(Error::GetHttpDocumentError(Box::new(err), url.to_owned())),
+        };
+
+        let mut body = String::new();
+        res.into_reader()
+            .take(HTTP_MAX_BODY_SIZE)
+            .read_to_string(&mut body)
+            .map_err(|err| Error::ReadHttpDocumentError(err, url.to_owned()))?;
+
+        Ok(Some(body))
+    }
+}
+
+/// Represents the default DNS lookup, using the system resolver
+/// configuration.
+#[derive(Debug, Default, Clone)]
+pub struct DefaultAutoconfigDns;
+
+impl AutoconfigDns for DefaultAutoconfigDns {
+    fn srv(&self, name: &str) -> Result<Vec<SrvRecord>> {
+        let resolver = Resolver::from_system_conf().map_err(Error::CreateDnsResolverError)?;
+
+        let lookup = match resolver.srv_lookup(name) {
+            Ok(lookup) => lookup,
+            Err(err) if matches!(err.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
+                debug!("no srv record found for {name}");
+                return Ok(Vec::new());
+            }
+            Err(err) => return Err(Error::LookupSrvRecordsError(err, name.to_owned())),
+        };
+
+        let records = lookup
+            .iter()
+            .map(|srv| SrvRecord {
+                priority: srv.priority(),
+                weight: srv.weight(),
+                port: srv.port(),
+                target: srv.target().to_utf8().trim_end_matches('.').to_owned(),
+            })
+            .collect();
+
+        Ok(records)
+    }
+}
@@ -0,0 +1,11 @@
+pub mod autoconfig;
+pub use autoconfig::{
+    Autoconfig, AutoconfigBuilder, AutoconfigSource, Error, Result, DEFAULT_ISPDB_URL,
+};
+
+pub mod lookup;
+pub use lookup::{
+    AutoconfigDns, AutoconfigHttp, DefaultAutoconfigDns, DefaultAutoconfigHttp, SrvRecord,
+};
+
+mod xml;
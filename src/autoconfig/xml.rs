@@ -0,0 +1,141 @@
+//! Autoconfig XML module.
+//!
+//! This module contains the representation of the [autoconfig]
+//! document format (`config-v1.1.xml`), shared by the autoconfig
+//! servers of the providers and by the Mozilla ISPDB. Only the parts
+//! needed by the discovery are represented.
+//!
+//! [autoconfig]: https://wiki.mozilla.org/Thunderbird:Autoconfiguration:ConfigFileFormat
+
+use serde::Deserialize;
+
+use super::autoconfig::{Error, Result};
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct ClientConfig {
+    #[serde(rename = "emailProvider")]
+    pub email_provider: EmailProvider,
+}
+
+impl ClientConfig {
+    pub fn parse(xml: &str, url: &str) -> Result<Self> {
+        quick_xml::de::from_str(xml).map_err(|err| Error::ParseXmlDocumentError(err, url.into()))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct EmailProvider {
+    #[serde(rename = "incomingServer", default)]
+    pub incoming_servers: Vec<Server>,
+    #[serde(rename = "outgoingServer", default)]
+    pub outgoing_servers: Vec<Server>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Server {
+    /// Represents the protocol of the server (`imap`, `pop3`,
+    /// `smtp`).
+    #[serde(rename = "@type")]
+    pub protocol: String,
+    pub hostname: String,
+    pub port: u16,
+    /// Represents the security of the connection (`SSL`, `STARTTLS`
+    /// or `plain`).
+    #[serde(rename = "socketType")]
+    pub socket_type: String,
+    /// Represents the login, with the `%EMAILADDRESS%`,
+    /// `%EMAILLOCALPART%` and `%EMAILDOMAIN%` placeholders.
+    pub username: Option<String>,
+}
+
+impl Server {
+    /// Builds the login of the given email address.
+    pub fn login(&self, email: &str) -> String {
+        let (local_part, domain) = email.rsplit_once('@').unwrap_or((email, ""));
+
+        self.username
+            .as_deref()
+            .unwrap_or("%EMAILADDRESS%")
+            .replace("%EMAILADDRESS%", email)
+            .replace("%EMAILLOCALPART%", local_part)
+            .replace("%EMAILDOMAIN%", domain)
+    }
+}
+
+#[cfg(test)]
+mod xml {
+    use concat_with::concat_line;
+
+    use super::ClientConfig;
+
+    const XML: &str = concat_line!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+        r#"<clientConfig version="1.1">"#,
+        r#"  <emailProvider id="example.com">"#,
+        r#"    <domain>example.com</domain>"#,
+        r#"    <displayName>Example</displayName>"#,
+        r#"    <incomingServer type="pop3">"#,
+        r#"      <hostname>pop.example.com</hostname>"#,
+        r#"      <port>995</port>"#,
+        r#"      <socketType>SSL</socketType>"#,
+        r#"      <username>%EMAILADDRESS%</username>"#,
+        r#"      <authentication>password-cleartext</authentication>"#,
+        r#"    </incomingServer>"#,
+        r#"    <incomingServer type="imap">"#,
+        r#"      <hostname>imap.example.com</hostname>"#,
+        r#"      <port>993</port>"#,
+        r#"      <socketType>SSL</socketType>"#,
+        r#"      <username>%EMAILLOCALPART%</username>"#,
+        r#"      <authentication>password-cleartext</authentication>"#,
+        r#"    </incomingServer>"#,
+        r#"    <outgoingServer type="smtp">"#,
+        r#"      <hostname>smtp.example.com</hostname>"#,
+        r#"      <port>587</port>"#,
+        r#"      <socketType>STARTTLS</socketType>"#,
+        r#"      <username>%EMAILLOCALPART%@%EMAILDOMAIN%</username>"#,
+        r#"      <authentication>password-cleartext</authentication>"#,
+        r#"    </outgoingServer>"#,
+        r#"  </emailProvider>"#,
+        r#"</clientConfig>"#,
+    );
+
+    #[test]
+    fn parse() {
+        let config = ClientConfig::parse(XML, "test").unwrap();
+        let incoming = &config.email_provider.incoming_servers;
+        let outgoing = &config.email_provider.outgoing_servers;
+
+        assert_eq!(incoming.len(), 2);
+        assert_eq!(incoming[1].protocol, "imap");
+        assert_eq!(incoming[1].hostname, "imap.example.com");
+        assert_eq!(incoming[1].port, 993);
+        assert_eq!(incoming[1].socket_type, "SSL");
+        assert_eq!(outgoing.len(), 1);
+        assert_eq!(outgoing[0].protocol, "smtp");
+        assert_eq!(outgoing[0].socket_type, "STARTTLS");
+    }
+
+    #[test]
+    fn login() {
+        let config = ClientConfig::parse(XML, "test").unwrap();
+        let provider = config.email_provider;
+
+        assert_eq!(
+            provider.incoming_servers[0].login("alice@example.com"),
+            "alice@example.com"
+        );
+        assert_eq!(
+            provider.incoming_servers[1].login("alice@example.com"),
+            "alice"
+        );
+        assert_eq!(
+            provider.outgoing_servers[0].login("alice@example.com"),
+            "alice@example.com"
+        );
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(ClientConfig::parse("<clientConfig/>", "test").is_err());
+    }
+}
@@ -5,12 +5,24 @@
 
 use log::info;
 use proc_lock::{lock, LockPath};
-use std::{any::Any, borrow::Cow, fmt, io, result};
+use std::{
+    any::Any,
+    borrow::Cow,
+    fmt, io,
+    ops::RangeInclusive,
+    panic, result,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
 use thiserror::Error;
 
 use crate::{
-    account, backend, email, envelope, folder, id_mapper, AccountConfig, BackendConfig, Emails,
-    Envelope, Envelopes, Flag, Flags, Folders, ImapBackendBuilder, MaildirBackend, MaildirConfig,
+    account, backend, email, envelope, folder, id_mapper, AccountConfig, BackendConfig,
+    BackendSubscription, Emails, Envelope, Envelopes, Flag, FlagConflictStrategy, Flags, Folders,
+    ImapBackendBuilder, MaildirBackend, MaildirConfig, SearchQuery, SortCriteria,
 };
 
 #[cfg(feature = "notmuch-backend")]
@@ -20,6 +32,8 @@ use crate::NotmuchBackend;
 pub enum Error {
     #[error("cannot build backend with an empty config")]
     BuildBackendError,
+    #[error("cannot subscribe to events of account {0}: not supported by the backend")]
+    SubscribeNotSupportedError(String),
     #[error("cannot lock synchronization for account {1}")]
     SyncAccountLockError(io::Error, String),
     #[error("synchronization not enabled for account {0}")]
@@ -39,7 +53,7 @@ pub enum Error {
 
     #[cfg(feature = "imap-backend")]
     #[error(transparent)]
-    ImapBackendError(#[from] backend::imap::Error),
+    ImapBackendError(Box<backend::imap::Error>),
     #[error(transparent)]
     MaildirBackendError(#[from] backend::maildir::Error),
     #[cfg(feature = "notmuch-backend")]
@@ -49,8 +63,24 @@ pub enum Error {
 
 pub type Result<T> = result::Result<T, Error>;
 
+// The IMAP error is boxed since it is much larger than the other
+// ones, which would make every backend result large.
+#[cfg(feature = "imap-backend")]
+impl From<backend::imap::Error> for Error {
+    fn from(err: backend::imap::Error) -> Self {
+        Self::ImapBackendError(Box::new(err))
+    }
+}
+
 pub trait Backend: Sync + Send {
     fn name(&self) -> String;
+    /// Returns the identifier of the endpoint the backend points to
+    /// (server, directory, database). It is used to key the
+    /// synchronization cache of two backends. Defaults to the
+    /// backend name.
+    fn endpoint(&self) -> String {
+        self.name()
+    }
 
     fn add_folder(&self, folder: &str) -> Result<()>;
     fn list_folders(&self) -> Result<Folders>;
@@ -64,11 +94,23 @@ pub trait Backend: Sync + Send {
     }
 
     fn list_envelopes(&self, folder: &str, page_size: usize, page: usize) -> Result<Envelopes>;
+    /// Lists envelopes changes of the given folder since the given
+    /// sync state. Returns `None` when the backend does not support
+    /// incremental synchronization, in which case the full listing
+    /// should be used instead.
+    fn list_envelopes_changes(
+        &self,
+        _folder: &str,
+        _state: Option<&BackendSyncState>,
+    ) -> Result<Option<BackendSyncChanges>> {
+        Ok(None)
+    }
+
     fn search_envelopes(
         &self,
         folder: &str,
-        query: &str,
-        sort: &str,
+        query: &SearchQuery,
+        sort: &SortCriteria,
         page_size: usize,
         page: usize,
     ) -> Result<Envelopes>;
@@ -154,6 +196,13 @@ pub trait Backend: Sync + Send {
         self.remove_flags(folder, internal_ids, flags)
     }
 
+    /// Subscribes to the events of the given folder. Events are
+    /// emitted from a dedicated thread until the returned
+    /// subscription is cancelled or dropped.
+    fn subscribe(&self, _folder: &str) -> Result<BackendSubscription> {
+        Err(Error::SubscribeNotSupportedError(self.name()))
+    }
+
     fn close(&self) -> Result<()> {
         Ok(())
     }
@@ -173,13 +222,14 @@ pub enum BackendSyncProgressEvent {
     ProcessFolderHunk(String),
 
     StartEnvelopesSync(String, usize, usize),
-    GetLocalCachedEnvelopes,
-    GetLocalEnvelopes,
-    GetRemoteCachedEnvelopes,
-    GetRemoteEnvelopes,
-    BuildEnvelopesPatch,
-    ProcessEnvelopesPatch(usize),
-    ProcessEnvelopeHunk(String),
+    RecoverEnvelopesPatch(String, usize),
+    GetLocalCachedEnvelopes(String),
+    GetLocalEnvelopes(String),
+    GetRemoteCachedEnvelopes(String),
+    GetRemoteEnvelopes(String),
+    BuildEnvelopesPatch(String),
+    ProcessEnvelopesPatch(String, usize),
+    ProcessEnvelopeHunk(String, String),
 }
 
 impl fmt::Display for BackendSyncProgressEvent {
@@ -194,24 +244,157 @@ impl fmt::Display for BackendSyncProgressEvent {
             Self::ProcessFolderHunk(s) => write!(f, "Processing folder hunk: {s}"),
 
             Self::StartEnvelopesSync(_, _, _) => write!(f, "Starting envelopes synchronization"),
-            Self::GetLocalCachedEnvelopes => write!(f, "Getting local cached envelopes"),
-            Self::GetLocalEnvelopes => write!(f, "Getting local envelopes"),
-            Self::GetRemoteCachedEnvelopes => write!(f, "Getting remote cached envelopes"),
-            Self::GetRemoteEnvelopes => write!(f, "Getting remote envelopes"),
-            Self::BuildEnvelopesPatch => write!(f, "Building envelopes patch"),
-            Self::ProcessEnvelopesPatch(n) => write!(f, "Processing {n} hunks of envelopes patch"),
-            Self::ProcessEnvelopeHunk(s) => write!(f, "Processing envelope hunk: {s}"),
+            Self::RecoverEnvelopesPatch(folder, n) => {
+                write!(
+                    f,
+                    "Recovering {n} hunks of interrupted envelopes patch of {folder}"
+                )
+            }
+            Self::GetLocalCachedEnvelopes(folder) => {
+                write!(f, "Getting local cached envelopes of {folder}")
+            }
+            Self::GetLocalEnvelopes(folder) => write!(f, "Getting local envelopes of {folder}"),
+            Self::GetRemoteCachedEnvelopes(folder) => {
+                write!(f, "Getting remote cached envelopes of {folder}")
+            }
+            Self::GetRemoteEnvelopes(folder) => write!(f, "Getting remote envelopes of {folder}"),
+            Self::BuildEnvelopesPatch(folder) => write!(f, "Building envelopes patch of {folder}"),
+            Self::ProcessEnvelopesPatch(folder, n) => {
+                write!(f, "Processing {n} hunks of envelopes patch of {folder}")
+            }
+            Self::ProcessEnvelopeHunk(folder, s) => {
+                write!(f, "Processing envelope hunk of {folder}: {s}")
+            }
         }
     }
 }
 
+/// Represents the direction of the synchronization between the local
+/// Maildir and the remote backend.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum BackendSyncMode {
+    /// Changes are synchronized both ways.
+    #[default]
+    Bidirectional,
+    /// The remote side is authoritative: remote changes are pulled,
+    /// local changes are never pushed. Folders and emails that only
+    /// exist local side are kept, other local changes are discarded.
+    PullOnly,
+    /// The local side is authoritative: local changes are pushed,
+    /// remote changes are never pulled. Folders and emails that only
+    /// exist remote side are kept, other remote changes are
+    /// discarded.
+    PushOnly,
+    /// The local side becomes an exact replica of the remote side:
+    /// same as [`BackendSyncMode::PullOnly`], except that folders and
+    /// emails that only exist local side are deleted.
+    Mirror,
+}
+
+impl BackendSyncMode {
+    /// Returns the authoritative side of a one-way synchronization,
+    /// or `None` for a bidirectional one.
+    pub(crate) fn source(&self) -> Option<SyncSide> {
+        match self {
+            Self::Bidirectional => None,
+            Self::PullOnly | Self::Mirror => Some(SyncSide::Remote),
+            Self::PushOnly => Some(SyncSide::Local),
+        }
+    }
+
+    /// Returns whether folders and emails that only exist on the
+    /// non-authoritative side are kept.
+    pub(crate) fn keep_target_only(&self) -> bool {
+        !matches!(self, Self::Mirror)
+    }
+
+    /// Returns whether the given side can be expunged. The
+    /// authoritative side of a one-way synchronization is never
+    /// modified, so it is never expunged either.
+    pub(crate) fn can_expunge(&self, side: SyncSide) -> bool {
+        self.source() != Some(side)
+    }
+}
+
+impl fmt::Display for BackendSyncMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bidirectional => write!(f, "bidirectional"),
+            Self::PullOnly => write!(f, "pull-only"),
+            Self::PushOnly => write!(f, "push-only"),
+            Self::Mirror => write!(f, "mirror"),
+        }
+    }
+}
+
+/// Represents a side of the synchronization.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum SyncSide {
+    Local,
+    Remote,
+}
+
+impl SyncSide {
+    pub(crate) fn other(&self) -> Self {
+        match self {
+            Self::Local => Self::Remote,
+            Self::Remote => Self::Local,
+        }
+    }
+}
+
+/// Represents the incremental synchronization state of a folder, as
+/// defined by the IMAP CONDSTORE and QRESYNC extensions ([RFC 7162]).
+///
+/// [RFC 7162]: https://www.rfc-editor.org/rfc/rfc7162
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BackendSyncState {
+    /// Represents the UIDVALIDITY of the folder.
+    pub uid_validity: u32,
+    /// Represents the HIGHESTMODSEQ of the folder.
+    pub highest_modseq: u64,
+    /// Represents the UIDNEXT of the folder.
+    pub uid_next: u32,
+}
+
+/// Represents the envelopes changes of a folder since a given sync
+/// state.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BackendSyncChanges {
+    /// Represents the new sync state of the folder.
+    pub state: BackendSyncState,
+    /// Represents whether envelopes contain the full listing of the
+    /// folder instead of the changed envelopes only. It happens when
+    /// there is no previous state or when the UIDVALIDITY changed.
+    pub full: bool,
+    /// Represents the envelopes added or changed since the previous
+    /// state.
+    pub envelopes: Envelopes,
+    /// Represents the ranges of internal ids removed since the
+    /// previous state.
+    pub vanished: Vec<RangeInclusive<u32>>,
+}
+
+impl BackendSyncChanges {
+    /// Checks if the given internal id vanished since the previous
+    /// state.
+    pub fn is_vanished(&self, internal_id: &str) -> bool {
+        internal_id
+            .parse::<u32>()
+            .map(|uid| self.vanished.iter().any(|range| range.contains(&uid)))
+            .unwrap_or_default()
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct BackendSyncReport {
+    pub mode: BackendSyncMode,
     pub folders: folder::sync::FoldersName,
     pub folders_patch: Vec<(folder::sync::Hunk, Option<folder::sync::Error>)>,
     pub folders_cache_patch: (Vec<folder::sync::CacheHunk>, Option<folder::sync::Error>),
     pub envelopes_patch: Vec<(envelope::sync::BackendHunk, Option<envelope::sync::Error>)>,
     pub envelopes_cache_patch: (Vec<envelope::sync::CacheHunk>, Vec<envelope::sync::Error>),
+    pub envelopes_recovered_patch: Vec<(envelope::sync::BackendHunk, envelope::sync::HunkRecovery)>,
 }
 
 pub struct BackendSyncBuilder<'a> {
@@ -219,6 +402,9 @@ pub struct BackendSyncBuilder<'a> {
     on_progress: Box<dyn Fn(BackendSyncProgressEvent) -> Result<()> + Sync + Send + 'a>,
     folders: Option<Vec<String>>,
     dry_run: bool,
+    mode: BackendSyncMode,
+    flag_conflict_strategy: FlagConflictStrategy,
+    folders_parallelism: usize,
 }
 
 impl<'a> BackendSyncBuilder<'a> {
@@ -228,6 +414,9 @@ impl<'a> BackendSyncBuilder<'a> {
             on_progress: Box::new(|_| Ok(())),
             folders: None,
             dry_run: false,
+            mode: BackendSyncMode::default(),
+            flag_conflict_strategy: FlagConflictStrategy::default(),
+            folders_parallelism: 1,
         }
     }
 
@@ -277,13 +466,74 @@ impl<'a> BackendSyncBuilder<'a> {
         self
     }
 
+    /// Sets the direction of the synchronization, see
+    /// [`BackendSyncMode`].
+    pub fn mode(mut self, mode: BackendSyncMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets the strategy used to resolve flag conflicts, see
+    /// [`FlagConflictStrategy`].
+    pub fn flag_conflict_strategy(mut self, strategy: FlagConflictStrategy) -> Self {
+        self.flag_conflict_strategy = strategy;
+        self
+    }
+
+    /// Sets the maximum number of folders synchronized concurrently.
+    /// Concurrent folders share the sessions of the IMAP sessions
+    /// pool (see [`BackendBuilder::sessions_pool_size`]), so a level
+    /// above the pool size does not speed the synchronization up.
+    /// Defaults to 1.
+    pub fn folders_parallelism(mut self, parallelism: usize) -> Self {
+        self.folders_parallelism = parallelism.max(1);
+        self
+    }
+
+    /// Synchronizes the given remote backend with the local Maildir
+    /// replica of the account, located in its sync directory.
     pub fn sync(&self, remote: &dyn Backend) -> Result<BackendSyncReport> {
         let account = &self.account_config.name;
         if !self.account_config.sync {
             return Err(Error::SyncNotEnabled(account.clone()));
         }
 
-        info!("starting synchronization");
+        let sync_dir = self.account_config.sync_dir()?;
+
+        // init local Maildir
+
+        let local = MaildirBackend::new(
+            Cow::Borrowed(self.account_config),
+            Cow::Owned(MaildirConfig {
+                root_dir: sync_dir.clone(),
+            }),
+        )?;
+
+        self.sync_with_cache_key(account, &local, remote)
+    }
+
+    /// Synchronizes any two backends, for example two IMAP servers
+    /// (migration) or a Maildir and a notmuch database. The
+    /// synchronization cache is stored in the sync directory of the
+    /// account, keyed by the endpoints of both backends (see
+    /// [`Backend::endpoint`]).
+    pub fn sync_backends(
+        &self,
+        local: &dyn Backend,
+        remote: &dyn Backend,
+    ) -> Result<BackendSyncReport> {
+        let cache_key = format!("{} <> {}", local.endpoint(), remote.endpoint());
+        self.sync_with_cache_key(&cache_key, local, remote)
+    }
+
+    fn sync_with_cache_key(
+        &self,
+        cache_key: &str,
+        local: &dyn Backend,
+        remote: &dyn Backend,
+    ) -> Result<BackendSyncReport> {
+        let account = &self.account_config.name;
+        info!("starting {} synchronization of {cache_key}", self.mode);
         let progress = &self.on_progress;
         let sync_dir = self.account_config.sync_dir()?;
         let lock_path = LockPath::Tmp(format!("himalaya-sync-{}.lock", account));
@@ -296,54 +546,108 @@ impl<'a> BackendSyncBuilder<'a> {
 
         folder::sync::Cache::init(&mut conn)?;
         envelope::sync::Cache::init(&mut conn)?;
-
-        // init local Maildir
-
-        let local = MaildirBackend::new(
-            Cow::Borrowed(self.account_config),
-            Cow::Owned(MaildirConfig {
-                root_dir: sync_dir.clone(),
-            }),
-        )?;
+        envelope::sync::Journal::init(&mut conn)?;
 
         let folders_sync_report = folder::SyncBuilder::new(self.account_config)
             .on_progress(|data| Ok(progress(data).map_err(Box::new)?))
             .folders(self.folders.clone())
             .dry_run(self.dry_run)
-            .sync(&mut conn, &local, remote)?;
+            .mode(self.mode)
+            .cache_key(cache_key)
+            .sync(&mut conn, local, remote)?;
 
         let envelopes = envelope::SyncBuilder::new(self.account_config)
             .on_progress(|data| Ok(progress(data).map_err(Box::new)?))
-            .dry_run(self.dry_run);
+            .cache_key(cache_key)
+            .dry_run(self.dry_run)
+            .mode(self.mode)
+            .flag_conflict_strategy(self.flag_conflict_strategy.clone());
 
-        let mut envelopes_patch = Vec::new();
-        let mut envelopes_cache_patch = (Vec::new(), Vec::new());
+        // Folders are distributed among workers, each one picking
+        // the next folder to synchronize. Backend round-trips run
+        // concurrently while accesses to the SQLite cache are
+        // serialized by the connection mutex.
+
+        let conn = Mutex::new(conn);
+        let folders: Vec<&String> = folders_sync_report.folders.iter().collect();
+        let next_folder = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
 
-        for (folder_num, folder) in folders_sync_report.folders.iter().enumerate() {
+        let sync_folder = |folder_num: usize, folder: &String| {
             progress(BackendSyncProgressEvent::StartEnvelopesSync(
                 folder.clone(),
                 folder_num + 1,
-                folders_sync_report.folders.len(),
+                folders.len(),
             ))?;
-            let report = envelopes.sync(folder, &mut conn, &local, remote)?;
+            let report = envelopes.sync(folder, &conn, local, remote)?;
+            if !self.dry_run {
+                if self.mode.can_expunge(SyncSide::Local) {
+                    local.expunge_folder(folder)?;
+                }
+                if self.mode.can_expunge(SyncSide::Remote) {
+                    remote.expunge_folder(folder)?;
+                }
+            }
+            Result::Ok(report)
+        };
+
+        let mut reports = thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.folders_parallelism.min(folders.len()))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut reports = Vec::new();
+                        while !failed.load(Ordering::Relaxed) {
+                            let folder_num = next_folder.fetch_add(1, Ordering::Relaxed);
+                            let Some(folder) = folders.get(folder_num) else {
+                                break;
+                            };
+                            let report = sync_folder(folder_num, folder);
+                            if report.is_err() {
+                                failed.store(true, Ordering::Relaxed);
+                            }
+                            reports.push((folder_num, report));
+                        }
+                        reports
+                    })
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .flat_map(|worker| {
+                    worker
+                        .join()
+                        .unwrap_or_else(|err| panic::resume_unwind(err))
+                })
+                .collect::<Vec<_>>()
+        });
+
+        reports.sort_by_key(|(folder_num, _)| *folder_num);
+
+        let mut envelopes_patch = Vec::new();
+        let mut envelopes_cache_patch = (Vec::new(), Vec::new());
+        let mut envelopes_recovered_patch = Vec::new();
+
+        for (_, report) in reports {
+            let report = report?;
+            envelopes_recovered_patch.extend(report.recovered_patch);
             envelopes_patch.extend(report.patch);
             envelopes_cache_patch.0.extend(report.cache_patch.0);
             if let Some(err) = report.cache_patch.1 {
                 envelopes_cache_patch.1.push(err);
             }
-
-            local.expunge_folder(folder)?;
-            remote.expunge_folder(folder)?;
         }
 
         drop(guard);
 
         Ok(BackendSyncReport {
+            mode: self.mode,
             folders: folders_sync_report.folders,
             folders_patch: folders_sync_report.patch,
             folders_cache_patch: folders_sync_report.cache_patch,
             envelopes_patch,
             envelopes_cache_patch,
+            envelopes_recovered_patch,
         })
     }
 }
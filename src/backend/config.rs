@@ -3,6 +3,8 @@
 //! This module contains the representation of the backend
 //! configuration of the user account.
 
+use serde::Deserialize;
+
 #[cfg(feature = "imap-backend")]
 use crate::ImapConfig;
 
@@ -11,8 +13,12 @@ use crate::MaildirConfig;
 #[cfg(feature = "notmuch-backend")]
 use crate::NotmuchConfig;
 
-/// Represents the backend configuration of the user account.
-#[derive(Debug, Clone, Eq, PartialEq)]
+/// Represents the backend configuration of the user account. In a
+/// configuration file, the backend is given as a table named after
+/// the variant (`backend.maildir`, `backend.imap` or
+/// `backend.notmuch`).
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum BackendConfig {
     None,
     Maildir(MaildirConfig),
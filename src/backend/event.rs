@@ -0,0 +1,192 @@
+//! Backend event module.
+//!
+//! This module contains the push-based events API of backends. A
+//! subscription watches a folder in a dedicated thread and yields
+//! typed events until it is cancelled or dropped.
+
+use log::{debug, warn};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{backend::Result, Envelope, Flags};
+
+/// Represents the events emitted by a backend subscription.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BackendEvent {
+    /// Represents a new envelope in the given folder.
+    EnvelopeAdded(String, Envelope),
+    /// Represents the new flags of the envelope matching the given
+    /// internal id, in the given folder.
+    FlagsChanged(String, String, Flags),
+    /// Represents the removal of the envelope matching the given
+    /// internal id from the given folder.
+    EnvelopeRemoved(String, String),
+    /// Represents the creation of the given folder.
+    FolderAdded(String),
+}
+
+impl fmt::Display for BackendEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EnvelopeAdded(folder, envelope) => {
+                write!(f, "Envelope {} added to folder {folder}", envelope.id)
+            }
+            Self::FlagsChanged(folder, id, flags) => write!(
+                f,
+                "Flags of envelope {id} from folder {folder} changed to {flags}",
+                flags = flags.to_string()
+            ),
+            Self::EnvelopeRemoved(folder, id) => {
+                write!(f, "Envelope {id} removed from folder {folder}")
+            }
+            Self::FolderAdded(folder) => write!(f, "Folder {folder} added"),
+        }
+    }
+}
+
+/// Represents the cancellation handle of a subscription. The handle
+/// can be cloned and sent to other threads.
+#[derive(Clone, Debug, Default)]
+pub struct BackendSubscriptionHandle(Arc<AtomicBool>);
+
+impl BackendSubscriptionHandle {
+    /// Cancels the subscription. The watcher thread stops at its
+    /// next wake up, which closes the events stream.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Represents the sending side of a subscription, given to the
+/// watcher thread.
+#[derive(Clone, Debug)]
+pub struct BackendEventSender {
+    sender: mpsc::Sender<Result<BackendEvent>>,
+    handle: BackendSubscriptionHandle,
+}
+
+impl BackendEventSender {
+    /// Sends the given event to the subscriber. Returns `false` when
+    /// the subscription has been cancelled or dropped, in which case
+    /// the watcher should stop.
+    pub fn send(&self, event: BackendEvent) -> bool {
+        debug!("{event}");
+        !self.handle.is_cancelled() && self.sender.send(Ok(event)).is_ok()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.handle.is_cancelled()
+    }
+}
+
+/// Represents a subscription to backend events. Events can be
+/// consumed by iterating over the subscription, which blocks until
+/// the next event. The iteration ends when the subscription is
+/// cancelled or when the watcher fails, in which case the error is
+/// yielded as the last item.
+#[derive(Debug)]
+pub struct BackendSubscription {
+    events: mpsc::Receiver<Result<BackendEvent>>,
+    handle: BackendSubscriptionHandle,
+}
+
+impl BackendSubscription {
+    /// Spawns the given watcher in a dedicated thread and returns the
+    /// associated subscription.
+    pub fn spawn<F>(watcher: F) -> Self
+    where
+        F: FnOnce(&BackendEventSender) -> Result<()> + Send + 'static,
+    {
+        let (sender, events) = mpsc::channel();
+        let handle = BackendSubscriptionHandle::default();
+        let sender = BackendEventSender {
+            sender,
+            handle: handle.clone(),
+        };
+
+        thread::spawn(move || {
+            if let Err(err) = watcher(&sender) {
+                warn!("error while watching backend events: {err}");
+                if !sender.is_cancelled() {
+                    let _ = sender.sender.send(Err(err));
+                }
+            }
+            debug!("backend events watcher stopped");
+        });
+
+        Self { events, handle }
+    }
+
+    /// Returns a cancellation handle of the subscription.
+    pub fn handle(&self) -> BackendSubscriptionHandle {
+        self.handle.clone()
+    }
+
+    /// Cancels the subscription.
+    pub fn cancel(&self) {
+        self.handle.cancel()
+    }
+
+    /// Returns the next event without blocking, if any.
+    pub fn try_next(&self) -> Option<Result<BackendEvent>> {
+        self.events.try_recv().ok()
+    }
+
+    /// Waits for the next event during the given amount of time.
+    pub fn next_timeout(&self, timeout: Duration) -> Option<Result<BackendEvent>> {
+        self.events.recv_timeout(timeout).ok()
+    }
+}
+
+impl Iterator for BackendSubscription {
+    type Item = Result<BackendEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.events.recv().ok()
+    }
+}
+
+impl Drop for BackendSubscription {
+    fn drop(&mut self) {
+        self.handle.cancel()
+    }
+}
+
+#[cfg(test)]
+mod backend_event {
+    use std::time::Duration;
+
+    use super::{BackendEvent, BackendSubscription};
+
+    #[test]
+    fn subscription() {
+        let subscription = BackendSubscription::spawn(|sender| {
+            sender.send(BackendEvent::FolderAdded("INBOX".into()));
+            while !sender.is_cancelled() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            Ok(())
+        });
+
+        let handle = subscription.handle();
+        let event = subscription.next_timeout(Duration::from_secs(5));
+        assert_eq!(
+            event.unwrap().unwrap(),
+            BackendEvent::FolderAdded("INBOX".into())
+        );
+
+        handle.cancel();
+        assert!(subscription.collect::<Vec<_>>().is_empty());
+    }
+}
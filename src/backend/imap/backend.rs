@@ -2,29 +2,39 @@
 //!
 //! This module contains the definition of the IMAP backend.
 
-use imap::extensions::idle::{stop_on_any, SetReadTimeout};
-use imap_proto::{NameAttribute, UidSetMember};
-use log::{debug, info, log_enabled, trace, Level};
+use imap::{
+    extensions::idle::SetReadTimeout,
+    types::{Flag as ImapFlag, UnsolicitedResponse},
+};
+use imap_proto::{AttributeValue, Capability, NameAttribute, UidSetMember};
+use log::{debug, info, log_enabled, trace, warn, Level};
 use native_tls::{TlsConnector, TlsStream};
 use rayon::prelude::*;
 use std::{
     any::Any,
     borrow::Cow,
     collections::HashSet,
-    convert::TryInto,
+    error,
     io::{self, Read, Write},
     net::TcpStream,
-    result, string,
-    sync::{Mutex, MutexGuard},
-    thread,
+    ops::RangeInclusive,
+    result, string, thread,
     time::Duration,
 };
 use thiserror::Error;
 use utf7_imap::{decode_utf7_imap as decode_utf7, encode_utf7_imap as encode_utf7};
 
 use crate::{
-    account, backend, email, envelope, process, AccountConfig, Backend, Emails, Envelope,
-    Envelopes, Flag, Flags, Folder, Folders, ImapConfig,
+    account,
+    backend::{
+        self,
+        imap::pool::{self, SessionPool, SessionPoolGuard, SessionPoolMetrics},
+    },
+    email, envelope,
+    oauth2::{self, OAuth2Method},
+    process, AccountConfig, Backend, BackendEvent, BackendEventSender, BackendSubscription,
+    BackendSyncChanges, BackendSyncState, Emails, Envelope, Envelopes, Flag, Flags, Folder,
+    Folders, ImapConfig, SearchQuery, SortCriteria,
 };
 
 #[derive(Error, Debug)]
@@ -54,8 +64,16 @@ pub enum Error {
     SearchNewEnvelopesError(#[source] imap::Error),
     #[error("cannot search imap envelopes in folder {1} with query: {2}")]
     SearchEnvelopesError(#[source] imap::Error, String, String),
+    #[error("cannot compile imap search query")]
+    CompileSearchQueryError(#[source] envelope::search_query::Error),
+    #[error("cannot search imap envelopes with non-ascii query {0:?}: non-synchronizing literals not supported by the server")]
+    SearchLiteralsNotSupportedError(String),
     #[error("cannot sort imap envelopes in folder {1} with query: {2}")]
     SortEnvelopesError(#[source] imap::Error, String, String),
+    #[error("cannot get imap server capabilities")]
+    GetCapabilitiesError(#[source] imap::Error),
+    #[error("cannot fetch imap envelopes changed since mod sequence {1} in folder {2}")]
+    FetchChangedEnvelopesError(#[source] imap::Error, u64, String),
     #[error("cannot get next imap envelope uid of folder {0}")]
     GetNextEnvelopeUidError(String),
 
@@ -96,34 +114,34 @@ pub enum Error {
     DecodeDateFromImapEnvelopeError(rfc2047_decoder::Error),
     #[error("cannot parse timestamp from imap envelope: {1}")]
     ParseTimestampFromImapEnvelopeError(mailparse::MailParseError, String),
-    #[error("cannot parse imap sort criterion {0}")]
-    ParseSortCriterionError(String),
     #[error("cannot decode subject of imap email {1}")]
     DecodeSubjectError(#[source] rfc2047_decoder::Error, String),
     #[error("cannot get imap sender of email {0}")]
     GetSenderError(String),
     #[error("cannot get uid of email sequence {0}")]
     GetUidError(u32),
+    #[error("cannot parse imap uid {1}")]
+    ParseUidError(#[source] std::num::ParseIntError, String),
 
     // Sessions
-    #[error("cannot find session from pool at cursor {0}")]
-    FindSessionByCursorError(usize),
     #[error("cannot parse Message-ID of email {0}")]
     ParseMessageIdError(#[source] string::FromUtf8Error, String),
-    #[error("cannot lock imap session: {0}")]
-    LockSessionError(String),
-    #[error("cannot lock imap sessions pool cursor: {0}")]
-    LockSessionsPoolCursorError(String),
     #[error("cannot create tls connector")]
     CreateTlsConnectorError(#[source] native_tls::Error),
     #[error("cannot connect to imap server")]
     ConnectImapServerError(#[source] imap::Error),
     #[error("cannot login to imap server")]
     LoginImapServerError(#[source] imap::Error),
+    #[error("cannot authenticate to imap server using oauth2")]
+    AuthenticateImapServerError(#[source] imap::Error),
     #[error("cannot start the idle mode")]
     StartIdleModeError(#[source] imap::Error),
     #[error("cannot close imap session")]
     CloseImapSessionError(#[source] imap::Error),
+    #[error("cannot check imap session")]
+    CheckSessionError(#[source] imap::Error),
+    #[error("cannot execute imap command: connection failed after {1} retries")]
+    RetriesExhaustedError(#[source] Box<backend::Error>, usize),
 
     // Other error forwarding
     #[error(transparent)]
@@ -134,6 +152,10 @@ pub enum Error {
     EmailError(#[from] email::Error),
     #[error(transparent)]
     MaildirBackend(#[from] backend::maildir::Error),
+    #[error(transparent)]
+    SessionsPoolError(#[from] pool::Error),
+    #[error(transparent)]
+    OAuth2Error(#[from] oauth2::Error),
 }
 
 pub type Result<T> = result::Result<T, Error>;
@@ -208,16 +230,17 @@ impl<'a> ImapBackendBuilder {
         imap_config: Cow<'a, ImapConfig>,
     ) -> Result<ImapBackend<'a>> {
         let passwd = imap_config.passwd()?;
-        let sessions_pool: Vec<_> = (0..=self.sessions_pool_size).collect();
+
+        // Only the first session is opened at build time, in order to
+        // report connection errors early. Other sessions are opened
+        // on demand by the pool.
+        let sessions_pool = SessionPool::new(self.sessions_pool_size);
+        sessions_pool.push(ImapBackend::create_session(&imap_config, passwd)?)?;
+
         let backend = ImapBackend {
             account_config,
-            imap_config: imap_config.clone(),
-            sessions_pool_size: self.sessions_pool_size.max(1),
-            sessions_pool_cursor: Mutex::new(0),
-            sessions_pool: sessions_pool
-                .par_iter()
-                .flat_map(|_| ImapBackend::create_session(&imap_config, &passwd).map(Mutex::new))
-                .collect(),
+            imap_config,
+            sessions_pool,
         };
 
         Ok(backend)
@@ -227,24 +250,31 @@ impl<'a> ImapBackendBuilder {
 pub struct ImapBackend<'a> {
     account_config: Cow<'a, AccountConfig>,
     imap_config: Cow<'a, ImapConfig>,
-    sessions_pool_size: usize,
-    sessions_pool_cursor: Mutex<usize>,
-    sessions_pool: Vec<Mutex<ImapSession>>,
+    sessions_pool: SessionPool<ImapSession>,
 }
 
+/// Represents the OAuth 2.0 SASL authenticator. The initial response
+/// is sent on the first (empty) continuation request, any further
+/// continuation request being an error challenge.
 #[derive(Debug)]
-struct OAuth2 {
-    user: String,
-    access_token: String,
+struct OAuth2Authenticator {
+    method: OAuth2Method,
+    response: String,
 }
 
-impl imap::Authenticator for OAuth2 {
+impl imap::Authenticator for OAuth2Authenticator {
     type Response = String;
-    fn process(&self, _: &[u8]) -> Self::Response {
-        format!(
-            "user={}\x01auth=Bearer {}\x01\x01",
-            self.user, self.access_token
-        )
+
+    fn process(&self, challenge: &[u8]) -> Self::Response {
+        if challenge.is_empty() {
+            self.response.clone()
+        } else {
+            warn!(
+                "imap oauth2 authentication failed: {}",
+                String::from_utf8_lossy(challenge)
+            );
+            self.method.sasl_error_response().to_owned()
+        }
     }
 }
 
@@ -256,7 +286,7 @@ impl<'a> ImapBackend<'a> {
         ImapBackendBuilder::default().build(account_config, imap_config)
     }
 
-    fn create_session<P>(config: &'a ImapConfig, passwd: P) -> Result<ImapSession>
+    fn create_session<P>(config: &ImapConfig, passwd: P) -> Result<ImapSession>
     where
         P: AsRef<str>,
     {
@@ -281,45 +311,179 @@ impl<'a> ImapBackend<'a> {
         }
         .map_err(Error::ConnectImapServerError)?;
 
-        let mut session = if let Some(access_token) = config.clone().access_token {
-            let auth = OAuth2 {
-                user: config.login.clone(),
-                access_token,
+        let mut session = if let Some(oauth2) = &config.oauth2 {
+            let auth = |token: String| OAuth2Authenticator {
+                method: oauth2.method,
+                response: oauth2.method.sasl_response(
+                    &config.login,
+                    &config.host,
+                    config.port,
+                    &token,
+                ),
             };
 
-            client.authenticate("XOAUTH2", &auth)
+            match client.authenticate(oauth2.method.as_str(), &auth(oauth2.access_token()?)) {
+                Ok(session) => Ok(session),
+                Err((err, client)) => {
+                    // The access token may have been revoked before
+                    // its expiration, so it is refreshed once.
+                    warn!("imap oauth2 authentication failed, refreshing access token");
+                    debug!("imap oauth2 authentication error: {err}");
+                    let token = oauth2.refresh_access_token()?;
+                    client
+                        .authenticate(oauth2.method.as_str(), &auth(token))
+                        .map_err(|(err, _)| Error::AuthenticateImapServerError(err))
+                }
+            }?
         } else {
-            client.login(&config.login, passwd.as_ref())
-        }
-        .map_err(|res| {
-            // TODO report this error - println!("Error: {:?}", res);
-            Error::LoginImapServerError(res.0)
-        })?;
+            client
+                .login(&config.login, passwd.as_ref())
+                .map_err(|(err, _)| {
+                    // the password may have changed since it has been
+                    // cached
+                    config.passwd.reset_cache();
+                    Error::LoginImapServerError(err)
+                })?
+        };
 
         session.debug = log_enabled!(Level::Trace);
 
+        // ENABLE applies to the whole session, so the extensions used
+        // by the incremental synchronization are enabled once here
+        // rather than before each synchronization.
+        let ext = {
+            let capabilities = session
+                .capabilities()
+                .map_err(Error::GetCapabilitiesError)?;
+            if !capabilities.has_str("ENABLE") {
+                None
+            } else if capabilities.has_str("QRESYNC") {
+                Some("QRESYNC")
+            } else if capabilities.has_str("CONDSTORE") {
+                Some("CONDSTORE")
+            } else {
+                None
+            }
+        };
+
+        if let Some(ext) = ext {
+            debug!("enabling imap extension {ext}");
+            if let Err(err) = session.run_command_and_check_ok(format!("ENABLE {ext}")) {
+                warn!("cannot enable imap extension {ext}: {err}");
+            }
+        }
+
         Result::Ok(session)
     }
 
-    pub fn session(&self) -> Result<MutexGuard<ImapSession>> {
-        let session = {
-            let mut cursor = self
-                .sessions_pool_cursor
-                .lock()
-                .map_err(|err| Error::LockSessionsPoolCursorError(err.to_string()))?;
-            let session = self
-                .sessions_pool
-                .get(*cursor)
-                .ok_or(Error::FindSessionByCursorError(*cursor))?;
-            // TODO: find a way to get the next available connection
-            // instead of the next one in the list
-            *cursor = (*cursor + 1) % self.sessions_pool_size;
-            session
+    /// Checks out the first idle session of the pool, opening a new
+    /// one if the pool is not full. Blocks until a session is
+    /// released otherwise.
+    pub fn session(&self) -> Result<SessionPoolGuard<'_, ImapSession>> {
+        self.sessions_pool.checkout(|| {
+            let passwd = self.imap_config.passwd()?;
+            ImapBackend::create_session(&self.imap_config, passwd)
+        })
+    }
+
+    pub fn sessions_pool_metrics(&self) -> Result<SessionPoolMetrics> {
+        Ok(self.sessions_pool.metrics()?)
+    }
+
+    /// Lists the capabilities announced by the IMAP server once
+    /// authenticated.
+    pub fn capabilities(&self) -> backend::Result<Vec<String>> {
+        self.with_session_retry(|session| {
+            let capabilities = session
+                .capabilities()
+                .map_err(Error::GetCapabilitiesError)?
+                .iter()
+                .map(|capability| match capability {
+                    Capability::Imap4rev1 => String::from("IMAP4rev1"),
+                    Capability::Auth(mechanism) => format!("AUTH={mechanism}"),
+                    Capability::Atom(atom) => atom.to_string(),
+                })
+                .collect();
+            Ok(capabilities)
+        })
+    }
+
+    /// Runs the given function with a session of the pool. Since the
+    /// function is not idempotent, it is not retried: the session is
+    /// checked before, and discarded if its connection is broken.
+    fn with_session<T, F>(&self, mut f: F) -> backend::Result<T>
+    where
+        F: FnMut(&mut ImapSession) -> backend::Result<T>,
+    {
+        self.retry(false, &mut f)
+    }
+
+    /// Runs the given idempotent function with a session of the
+    /// pool. If the connection breaks, the session is discarded and
+    /// the function is retried with a new one, according to the
+    /// retry policy of the config.
+    fn with_session_retry<T, F>(&self, mut f: F) -> backend::Result<T>
+    where
+        F: FnMut(&mut ImapSession) -> backend::Result<T>,
+    {
+        self.retry(true, &mut f)
+    }
+
+    fn retry<T, F>(&self, idempotent: bool, f: &mut F) -> backend::Result<T>
+    where
+        F: FnMut(&mut ImapSession) -> backend::Result<T>,
+    {
+        retry_with_backoff(
+            self.imap_config.retry_max(),
+            self.imap_config.retry_delay(),
+            || self.try_with_session(idempotent, f),
+        )
+    }
+
+    /// Runs the given function once. The outer error represents a
+    /// connection failure that can be retried, whereas the inner
+    /// result is the final result.
+    fn try_with_session<T, F>(
+        &self,
+        idempotent: bool,
+        f: &mut F,
+    ) -> result::Result<backend::Result<T>, backend::Error>
+    where
+        F: FnMut(&mut ImapSession) -> backend::Result<T>,
+    {
+        // Nothing has been sent to the server yet, so connection
+        // failures can always be retried.
+        let mut session = match self.session() {
+            Ok(session) => session,
+            Err(err) if is_connection_error(&err) => return Err(err.into()),
+            Err(err) => return Ok(Err(err.into())),
         };
 
-        session
-            .lock()
-            .map_err(|err| Error::LockSessionError(err.to_string()))
+        // Commands that cannot be retried are sent only once the
+        // session is known to be alive.
+        if !idempotent {
+            if let Err(err) = session.noop() {
+                session.discard();
+                let err = Error::CheckSessionError(err);
+                return if is_connection_error(&err) {
+                    Err(err.into())
+                } else {
+                    Ok(Err(err.into()))
+                };
+            }
+        }
+
+        match f(&mut session) {
+            Err(err) if is_connection_error(&err) => {
+                session.discard();
+                if idempotent {
+                    Err(err)
+                } else {
+                    Ok(Err(err))
+                }
+            }
+            res => Ok(res),
+        }
     }
 
     fn search_new_msgs(&self, session: &mut ImapSession, query: &str) -> Result<Vec<u32>> {
@@ -334,97 +498,411 @@ impl<'a> ImapBackend<'a> {
         Ok(uids)
     }
 
-    pub fn notify(&self, keepalive: u64, folder: &str) -> Result<()> {
-        let mut session = self.session()?;
+    /// Subscribes to the events of the given folder, using the given
+    /// IDLE timeout. Cancellation is checked each time the IDLE
+    /// command returns, so the timeout also bounds the time needed
+    /// for the watcher to stop.
+    pub fn subscribe_with_idle_timeout(
+        &self,
+        folder: &str,
+        idle_timeout: Duration,
+    ) -> Result<BackendSubscription> {
+        info!("subscribing to imap events of folder {folder}");
 
-        session
-            .examine(folder)
-            .map_err(|err| Error::ExamineFolderError(err, folder.to_owned()))?;
+        let imap_config = self.imap_config.clone().into_owned();
+        let passwd = imap_config.passwd()?;
+        let folder = folder.to_owned();
+
+        Ok(BackendSubscription::spawn(move |sender| {
+            Ok(watch_folder(
+                &imap_config,
+                &passwd,
+                &folder,
+                idle_timeout,
+                sender,
+            )?)
+        }))
+    }
 
-        debug!("init messages hashset");
-        let mut msgs_set: HashSet<u32> = self
-            .search_new_msgs(&mut session, &self.imap_config.notify_query())?
-            .iter()
-            .cloned()
-            .collect::<HashSet<_>>();
-        trace!("messages hashset: {:?}", msgs_set);
+    /// Runs the notify command for each new email matching the
+    /// notify query. Never returns, unless an error occurs.
+    #[deprecated(note = "use Backend::subscribe instead")]
+    pub fn notify(&self, keepalive: u64, folder: &str) -> backend::Result<()> {
+        let folder_encoded = encode_utf7(folder.to_owned());
+        let subscription = self.subscribe_with_idle_timeout(folder, Duration::new(keepalive, 0))?;
+
+        for event in subscription {
+            if let BackendEvent::EnvelopeAdded(_, envelope) = event? {
+                let uid = envelope
+                    .id
+                    .parse::<u32>()
+                    .map_err(|err| Error::ParseUidError(err, envelope.id.clone()))?;
+
+                let uids = {
+                    let mut session = self.session()?;
+                    session
+                        .examine(&folder_encoded)
+                        .map_err(|err| Error::ExamineFolderError(err, folder.to_owned()))?;
+                    self.search_new_msgs(&mut session, &self.imap_config.notify_query())?
+                };
+
+                if uids.contains(&uid) {
+                    debug!("notify message: {}", uid);
+                    trace!("message: {:?}", envelope);
+                    self.imap_config
+                        .run_notify_cmd(uid, &envelope.subject, &envelope.from.addr)
+                        .map_err(Error::ImapConfigError)?;
+                }
+            }
+        }
 
-        loop {
-            debug!("begin loop");
-            session
-                .idle()
-                .timeout(Duration::new(keepalive, 0))
-                .wait_while(stop_on_any)
-                .map_err(Error::StartIdleModeError)?;
+        Ok(())
+    }
 
-            let uids: Vec<u32> = self
-                .search_new_msgs(&mut session, &self.imap_config.notify_query())?
-                .into_iter()
-                .filter(|uid| msgs_set.get(uid).is_none())
-                .collect();
-            debug!("found {} new messages not in hashset", uids.len());
-            trace!("messages hashet: {:?}", msgs_set);
+    /// Runs the watch commands each time the given folder changes.
+    /// Never returns, unless an error occurs.
+    #[deprecated(note = "use Backend::subscribe instead")]
+    pub fn watch(&self, keepalive: u64, folder: &str) -> backend::Result<()> {
+        let run_watch_cmds = || {
+            let cmds = self.imap_config.watch_cmds().clone();
+            thread::spawn(move || {
+                debug!("batch execution of {} cmd(s)", cmds.len());
+                cmds.iter().for_each(|cmd| {
+                    if let Err(err) = process::run(cmd, &[]) {
+                        warn!("error while running watch command {cmd}: {err}");
+                    }
+                })
+            });
+        };
 
-            if !uids.is_empty() {
-                let uids = uids
-                    .iter()
-                    .map(|uid| uid.to_string())
-                    .collect::<Vec<_>>()
-                    .join(",");
-                let fetches = session
-                    .uid_fetch(uids, "(UID ENVELOPE)")
-                    .map_err(Error::FetchNewEnvelopesError)?;
+        run_watch_cmds();
 
-                for fetch in fetches.iter() {
-                    let msg = envelope::imap::from_raw(fetch)?;
-                    let uid = fetch.uid.ok_or_else(|| Error::GetUidError(fetch.message))?;
+        for event in self.subscribe_with_idle_timeout(folder, Duration::new(keepalive, 0))? {
+            event?;
+            run_watch_cmds();
+        }
 
-                    let from = msg.from.addr.clone();
-                    self.imap_config.run_notify_cmd(uid, &msg.subject, &from)?;
+        Ok(())
+    }
+}
 
-                    debug!("notify message: {}", uid);
-                    trace!("message: {:?}", msg);
+/// Maximum delay between two retries after a connection failure.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Runs the given function until it succeeds, retrying at most
+/// `retry_max` times. The outer error of the function represents a
+/// failure that can be retried, whereas the inner result is the final
+/// result. The delay between two retries starts at `retry_delay` and
+/// doubles after each retry, up to [`MAX_RETRY_DELAY`].
+fn retry_with_backoff<T, F>(
+    retry_max: usize,
+    mut retry_delay: Duration,
+    mut f: F,
+) -> backend::Result<T>
+where
+    F: FnMut() -> result::Result<backend::Result<T>, backend::Error>,
+{
+    let mut retries = 0;
+    retry_delay = retry_delay.min(MAX_RETRY_DELAY);
+
+    loop {
+        let err = match f() {
+            Ok(res) => return res,
+            Err(err) => err,
+        };
 
-                    debug!("insert message {} in hashset", uid);
-                    msgs_set.insert(uid);
-                    trace!("messages hashset: {:?}", msgs_set);
-                }
-            }
+        if retries >= retry_max {
+            return Err(Error::RetriesExhaustedError(Box::new(err), retries))?;
+        }
+
+        retries += 1;
+        warn!("imap connection failed: {err}");
+        warn!("retrying in {retry_delay:?} ({retries}/{retry_max})");
+        thread::sleep(retry_delay);
+        retry_delay = next_retry_delay(retry_delay);
+    }
+}
 
-            debug!("end loop");
+/// Doubles the given retry delay, up to [`MAX_RETRY_DELAY`].
+fn next_retry_delay(retry_delay: Duration) -> Duration {
+    retry_delay
+        .checked_mul(2)
+        .unwrap_or(MAX_RETRY_DELAY)
+        .min(MAX_RETRY_DELAY)
+}
+
+/// Default IDLE timeout used by subscriptions.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Checks if the given error or one of its sources represents a
+/// broken connection to the IMAP server.
+fn is_connection_error(err: &(dyn error::Error + 'static)) -> bool {
+    let mut source = Some(err);
+
+    while let Some(err) = source {
+        if let Some(err) = err.downcast_ref::<imap::Error>() {
+            return matches!(
+                err,
+                imap::Error::Io(_) | imap::Error::ConnectionLost | imap::Error::Bye(_)
+            );
         }
+        source = err.source();
     }
 
-    pub fn watch(&self, keepalive: u64, mbox: &str) -> Result<()> {
-        debug!("examine folder: {}", mbox);
-        let mut session = self.session()?;
+    false
+}
 
-        session
-            .examine(mbox)
-            .map_err(|err| Error::ExamineFolderError(err, mbox.to_owned()))?;
+fn list_folder_names(session: &mut ImapSession) -> Result<HashSet<String>> {
+    let folders = session
+        .list(Some(""), Some("*"))
+        .map_err(Error::ListFoldersError)?;
+    Ok(folders
+        .iter()
+        .filter(|folder| !folder.attributes().contains(&NameAttribute::NoSelect))
+        .map(|folder| decode_utf7(folder.name().into()))
+        .collect())
+}
 
-        loop {
-            debug!("begin loop");
+/// Tracks the uids of the watched folder by sequence number, so that
+/// the unsolicited responses received while idling can be turned
+/// into events without fetching the whole folder again.
+#[derive(Debug, Default)]
+struct IdleState {
+    /// The uids of the known emails, ordered by sequence number.
+    uids: Vec<u32>,
+    /// The number of emails announced by the server.
+    exists: usize,
+}
 
-            let cmds = self.imap_config.watch_cmds().clone();
-            thread::spawn(move || {
-                debug!("batch execution of {} cmd(s)", cmds.len());
-                cmds.iter().for_each(|cmd| match process::run(cmd, &[]) {
-                    // TODO: manage errors
-                    Err(_) => (),
-                    Ok(_) => (),
+impl IdleState {
+    fn new(mut uids: Vec<u32>) -> Self {
+        uids.sort_unstable();
+        let exists = uids.len();
+        Self { uids, exists }
+    }
+
+    /// Returns whether the given response reports a change of the
+    /// folder. Other responses (`OK`, `RECENT`…) do not wake up the
+    /// subscription.
+    fn is_change(response: &UnsolicitedResponse) -> bool {
+        matches!(
+            response,
+            UnsolicitedResponse::Exists(_)
+                | UnsolicitedResponse::Expunge(_)
+                | UnsolicitedResponse::Fetch { .. }
+                | UnsolicitedResponse::Vanished { earlier: false, .. }
+        )
+    }
+
+    fn remove(&mut self, folder: &str, index: usize) -> BackendEvent {
+        let uid = self.uids.remove(index);
+        self.exists = self.exists.saturating_sub(1);
+        BackendEvent::EnvelopeRemoved(folder.to_owned(), uid.to_string())
+    }
+
+    /// Applies the given unsolicited response and returns the
+    /// resulting events. New emails are only counted, see
+    /// [`IdleState::new_seqs`].
+    fn apply(&mut self, folder: &str, response: UnsolicitedResponse) -> Vec<BackendEvent> {
+        match response {
+            UnsolicitedResponse::Exists(exists) => {
+                self.exists = exists as usize;
+                vec![]
+            }
+            UnsolicitedResponse::Expunge(seq)
+                if (1..=self.uids.len()).contains(&(seq as usize)) =>
+            {
+                vec![self.remove(folder, seq as usize - 1)]
+            }
+            UnsolicitedResponse::Expunge(_) => {
+                // the expunged email was not fetched yet
+                self.exists = self.exists.saturating_sub(1);
+                vec![]
+            }
+            UnsolicitedResponse::Vanished {
+                earlier: false,
+                uids,
+            } => uids
+                .into_iter()
+                .flatten()
+                .filter_map(|uid| {
+                    let index = self.uids.binary_search(&uid).ok()?;
+                    Some(self.remove(folder, index))
                 })
-            });
+                .collect(),
+            UnsolicitedResponse::Fetch { id, attributes } => {
+                let mut uid = self.uids.get((id as usize).wrapping_sub(1)).cloned();
+                let mut flags = None;
+                for attribute in attributes {
+                    match attribute {
+                        AttributeValue::Uid(fetched_uid) => uid = Some(fetched_uid),
+                        AttributeValue::Flags(fetched_flags) => {
+                            flags = Some(Flags::from_iter(
+                                ImapFlag::from_strs(fetched_flags).map(|flag| Flag::from(&flag)),
+                            ))
+                        }
+                        _ => (),
+                    }
+                }
+                match (uid, flags) {
+                    (Some(uid), Some(flags)) if self.uids.binary_search(&uid).is_ok() => {
+                        vec![BackendEvent::FlagsChanged(
+                            folder.to_owned(),
+                            uid.to_string(),
+                            flags,
+                        )]
+                    }
+                    _ => vec![],
+                }
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Returns the sequence numbers of the emails announced by the
+    /// server but not fetched yet.
+    fn new_seqs(&self) -> Option<String> {
+        if self.exists > self.uids.len() {
+            Some(format!("{}:{}", self.uids.len() + 1, self.exists))
+        } else {
+            None
+        }
+    }
+
+    /// Adds the uids of the fetched new emails.
+    fn extend(&mut self, uids: impl IntoIterator<Item = u32>) {
+        self.uids.extend(uids);
+        self.uids.sort_unstable();
+        self.uids.dedup();
+        self.exists = self.exists.max(self.uids.len());
+    }
+}
 
+/// Watches the given folder using a dedicated IMAP session, and
+/// sends events derived from the unsolicited responses received
+/// while idling. Only new emails are fetched, and folders are listed
+/// again only when the watched folder changed, since IDLE does not
+/// report new folders.
+fn watch_folder(
+    config: &ImapConfig,
+    passwd: &str,
+    folder: &str,
+    idle_timeout: Duration,
+    sender: &BackendEventSender,
+) -> Result<()> {
+    let folder_encoded = encode_utf7(folder.to_owned());
+    let mut session = ImapBackend::create_session(config, passwd)?;
+    session
+        .examine(&folder_encoded)
+        .map_err(|err| Error::ExamineFolderError(err, folder.to_owned()))?;
+
+    let mut folders = list_folder_names(&mut session)?;
+    let uids = session
+        .uid_search("ALL")
+        .map_err(Error::SearchNewEnvelopesError)?;
+    let mut state = IdleState::new(uids.into_iter().collect());
+
+    // responses received before the search are already part of it
+    session.unsolicited_responses.try_iter().for_each(drop);
+
+    while !sender.is_cancelled() {
+        let mut responses: Vec<_> = session.unsolicited_responses.try_iter().collect();
+
+        if responses.is_empty() {
+            debug!("begin idle loop");
             session
                 .idle()
-                .timeout(Duration::new(keepalive, 0))
-                .wait_while(stop_on_any)
+                .timeout(idle_timeout)
+                .keepalive(false)
+                .wait_while(|response| {
+                    if IdleState::is_change(&response) {
+                        responses.push(response);
+                        false
+                    } else {
+                        true
+                    }
+                })
                 .map_err(Error::StartIdleModeError)?;
+            responses.extend(session.unsolicited_responses.try_iter());
+            debug!("end idle loop");
+        }
+
+        if sender.is_cancelled() {
+            break;
+        }
+
+        if !responses.iter().any(IdleState::is_change) {
+            continue;
+        }
+
+        for response in responses {
+            for event in state.apply(folder, response) {
+                if !sender.send(event) {
+                    return Ok(());
+                }
+            }
+        }
 
-            debug!("end loop");
+        if let Some(seqs) = state.new_seqs() {
+            let fetches = session
+                .fetch(&seqs, "(UID FLAGS ENVELOPE)")
+                .map_err(Error::FetchNewEnvelopesError)?;
+            state.extend(fetches.iter().filter_map(|fetch| fetch.uid));
+            for envelope in envelope::imap::from_raws(fetches)?.iter().rev() {
+                if !sender.send(BackendEvent::EnvelopeAdded(
+                    folder.to_owned(),
+                    envelope.clone(),
+                )) {
+                    return Ok(());
+                }
+            }
         }
+
+        let next_folders = list_folder_names(&mut session)?;
+        for added_folder in next_folders.difference(&folders) {
+            if !sender.send(BackendEvent::FolderAdded(added_folder.clone())) {
+                return Ok(());
+            }
+        }
+        folders = next_folders;
     }
+
+    session.logout().map_err(Error::CloseImapSessionError)?;
+    Ok(())
+}
+
+/// Computes the ranges of uids lower than `uid_next` that are not
+/// part of the given uids, which correspond to the vanished uids
+/// when the server does not support the QRESYNC extension.
+fn vanished_uids(uids: &HashSet<u32>, uid_next: u32) -> Vec<RangeInclusive<u32>> {
+    let mut uids = uids
+        .iter()
+        .filter(|uid| **uid < uid_next)
+        .cloned()
+        .collect::<Vec<_>>();
+    uids.sort_unstable();
+
+    let mut ranges = Vec::new();
+    let mut begin = 1;
+    for uid in uids.into_iter().chain([uid_next]) {
+        if uid > begin {
+            ranges.push(begin..=uid - 1);
+        }
+        begin = uid.saturating_add(1);
+    }
+    ranges
+}
+
+/// Computes the bounds of the given page, clamped to the given
+/// length. A page size of 0 means no pagination.
+fn page_bounds(len: usize, page_size: usize, page: usize) -> (usize, usize) {
+    if page_size == 0 {
+        return (0, len);
+    }
+
+    let begin = len.min(page * page_size);
+    let end = len.min(begin + page_size);
+    (begin, end)
 }
 
 impl<'a> Backend for ImapBackend<'a> {
@@ -432,46 +910,55 @@ impl<'a> Backend for ImapBackend<'a> {
         self.account_config.name.clone()
     }
 
+    fn endpoint(&self) -> String {
+        let ImapConfig {
+            login, host, port, ..
+        } = self.imap_config.as_ref();
+        format!("imap://{login}@{host}:{port}")
+    }
+
     fn add_folder(&self, folder: &str) -> backend::Result<()> {
         info!("adding imap folder {folder}");
 
         let folder_encoded = encode_utf7(folder.to_owned());
         trace!("utf7 encoded folder: {folder_encoded}");
 
-        let mut session = self.session()?;
-        session
-            .create(folder_encoded)
-            .map_err(|err| Error::CreateFolderError(err, folder.to_owned()))?;
+        self.with_session(|session| {
+            session
+                .create(&folder_encoded)
+                .map_err(|err| Error::CreateFolderError(err, folder.to_owned()))?;
 
-        Ok(())
+            Ok(())
+        })
     }
 
     fn list_folders(&self) -> backend::Result<Folders> {
         info!("listing imap folders");
 
-        let mut session = self.session()?;
-        let folders = session
-            .list(Some(""), Some("*"))
-            .map_err(Error::ListFoldersError)?;
-        let folders = Folders::from_iter(folders.iter().filter_map(|folder| {
-            if folder.attributes().contains(&NameAttribute::NoSelect) {
-                None
-            } else {
-                Some(Folder {
-                    delim: folder.delimiter().unwrap_or_default().into(),
-                    name: decode_utf7(folder.name().into()),
-                    desc: folder
-                        .attributes()
-                        .iter()
-                        .map(|attr| format!("{attr:?}"))
-                        .collect::<Vec<_>>()
-                        .join(", "),
-                })
-            }
-        }));
-        trace!("imap folders: {:?}", folders);
+        self.with_session_retry(|session| {
+            let folders = session
+                .list(Some(""), Some("*"))
+                .map_err(Error::ListFoldersError)?;
+            let folders = Folders::from_iter(folders.iter().filter_map(|folder| {
+                if folder.attributes().contains(&NameAttribute::NoSelect) {
+                    None
+                } else {
+                    Some(Folder {
+                        delim: folder.delimiter().unwrap_or_default().into(),
+                        name: decode_utf7(folder.name().into()),
+                        desc: folder
+                            .attributes()
+                            .iter()
+                            .map(|attr| format!("{attr:?}"))
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                    })
+                }
+            }));
+            trace!("imap folders: {:?}", folders);
 
-        Ok(folders)
+            Ok(folders)
+        })
     }
 
     fn expunge_folder(&self, folder: &str) -> backend::Result<()> {
@@ -480,15 +967,16 @@ impl<'a> Backend for ImapBackend<'a> {
         let folder_encoded = encode_utf7(folder.to_owned());
         trace!("utf7 encoded folder: {folder_encoded}");
 
-        let mut session = self.session()?;
-        session
-            .select(folder_encoded)
-            .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
-        session
-            .expunge()
-            .map_err(|err| Error::ExpungeFolderError(err, folder.to_owned()))?;
+        self.with_session_retry(|session| {
+            session
+                .select(&folder_encoded)
+                .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
+            session
+                .expunge()
+                .map_err(|err| Error::ExpungeFolderError(err, folder.to_owned()))?;
 
-        Ok(())
+            Ok(())
+        })
     }
 
     fn purge_folder(&self, folder: &str) -> backend::Result<()> {
@@ -500,18 +988,19 @@ impl<'a> Backend for ImapBackend<'a> {
         let flags = Flags::from_iter([Flag::Deleted]);
         let uids = String::from("1:*");
 
-        let mut session = self.session()?;
-        session
-            .select(folder_encoded)
-            .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
-        session
-            .uid_store(&uids, format!("+FLAGS ({})", flags.to_imap_query()))
-            .map_err(|err| Error::AddFlagsError(err, flags.to_imap_query(), uids))?;
-        session
-            .expunge()
-            .map_err(|err| Error::ExpungeFolderError(err, folder.to_owned()))?;
+        self.with_session_retry(|session| {
+            session
+                .select(&folder_encoded)
+                .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
+            session
+                .uid_store(&uids, format!("+FLAGS ({})", flags.to_imap_query()))
+                .map_err(|err| Error::AddFlagsError(err, flags.to_imap_query(), uids.clone()))?;
+            session
+                .expunge()
+                .map_err(|err| Error::ExpungeFolderError(err, folder.to_owned()))?;
 
-        Ok(())
+            Ok(())
+        })
     }
 
     fn delete_folder(&self, folder: &str) -> backend::Result<()> {
@@ -520,12 +1009,13 @@ impl<'a> Backend for ImapBackend<'a> {
         let folder_encoded = encode_utf7(folder.to_owned());
         trace!("utf7 encoded folder: {folder_encoded}");
 
-        let mut session = self.session()?;
-        session
-            .delete(&folder_encoded)
-            .map_err(|err| Error::DeleteFolderError(err, folder.to_owned()))?;
+        self.with_session(|session| {
+            session
+                .delete(&folder_encoded)
+                .map_err(|err| Error::DeleteFolderError(err, folder.to_owned()))?;
 
-        Ok(())
+            Ok(())
+        })
     }
 
     fn get_envelope(&self, folder: &str, uid: &str) -> backend::Result<Envelope> {
@@ -534,21 +1024,22 @@ impl<'a> Backend for ImapBackend<'a> {
         let folder_encoded = encode_utf7(folder.to_owned());
         trace!("utf7 encoded folder: {folder_encoded}");
 
-        let mut session = self.session()?;
-        session
-            .select(&folder_encoded)
-            .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
-        let fetches = session
-            .uid_fetch(uid, "(UID FLAGS ENVELOPE)")
-            .map_err(|err| Error::FetchEmailsByUidError(err, uid.to_owned()))?;
-        let fetch = fetches
-            .get(0)
-            .ok_or_else(|| Error::GetEnvelopeError(uid.to_owned()))?;
-
-        let envelope = envelope::imap::from_raw(&fetch)?;
-        trace!("imap envelope: {envelope:#?}");
-
-        Ok(envelope)
+        self.with_session_retry(|session| {
+            session
+                .select(&folder_encoded)
+                .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
+            let fetches = session
+                .uid_fetch(uid, "(UID FLAGS ENVELOPE)")
+                .map_err(|err| Error::FetchEmailsByUidError(err, uid.to_owned()))?;
+            let fetch = fetches
+                .get(0)
+                .ok_or_else(|| Error::GetEnvelopeError(uid.to_owned()))?;
+
+            let envelope = envelope::imap::from_raw(&fetch)?;
+            trace!("imap envelope: {envelope:#?}");
+
+            Ok(envelope)
+        })
     }
 
     fn list_envelopes(
@@ -562,122 +1053,314 @@ impl<'a> Backend for ImapBackend<'a> {
         let folder_encoded = encode_utf7(folder.to_owned());
         trace!("utf7 encoded folder: {folder_encoded}");
 
-        let mut session = self.session()?;
-        let folder_size = session
-            .select(&folder_encoded)
-            .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?
-            .exists as usize;
-        trace!("folder size: {folder_size}");
+        self.with_session_retry(|session| {
+            let folder_size = session
+                .select(&folder_encoded)
+                .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?
+                .exists as usize;
+            trace!("folder size: {folder_size}");
 
-        if folder_size == 0 {
-            return Ok(Envelopes::default());
-        }
+            if folder_size == 0 {
+                return Ok(Envelopes::default());
+            }
 
-        let page_cursor = page * page_size;
-        if page_cursor >= folder_size {
-            return Err(Error::ListEnvelopesOutOfBounds(page + 1))?;
-        }
+            let page_cursor = page * page_size;
+            if page_cursor >= folder_size {
+                return Err(Error::ListEnvelopesOutOfBounds(page + 1))?;
+            }
 
-        let range = if page_size == 0 {
-            String::from("1:*")
-        } else {
-            let page_size = page_size.min(folder_size);
-            let mut count = 1;
-            let mut cursor = folder_size - (folder_size.min(page_cursor));
-            let mut range = cursor.to_string();
-            while cursor > 0 && count < page_size {
-                count += 1;
-                cursor -= 1;
-                if count > 1 {
-                    range.push(',');
+            let range = if page_size == 0 {
+                String::from("1:*")
+            } else {
+                let page_size = page_size.min(folder_size);
+                let mut count = 1;
+                let mut cursor = folder_size - (folder_size.min(page_cursor));
+                let mut range = cursor.to_string();
+                while cursor > 0 && count < page_size {
+                    count += 1;
+                    cursor -= 1;
+                    if count > 1 {
+                        range.push(',');
+                    }
+                    range.push_str(&cursor.to_string());
                 }
-                range.push_str(&cursor.to_string());
+                range
+            };
+            trace!("page: {page}");
+            trace!("page size: {page_size}");
+            trace!("seq range: {range}");
+
+            let fetches = session
+                .fetch(&range, "(UID FLAGS ENVELOPE)")
+                .map_err(|err| Error::FetchEmailsByUidRangeError(err, range))?;
+            let envelopes = envelope::imap::from_raws(fetches)?;
+            trace!("imap envelopes: {envelopes:#?}");
+
+            Ok(envelopes)
+        })
+    }
+
+    fn list_envelopes_changes(
+        &self,
+        folder: &str,
+        state: Option<&BackendSyncState>,
+    ) -> backend::Result<Option<BackendSyncChanges>> {
+        info!("listing imap envelopes changes from folder {folder}");
+        trace!("sync state: {state:?}");
+
+        let folder_encoded = encode_utf7(folder.to_owned());
+        trace!("utf7 encoded folder: {folder_encoded}");
+
+        self.with_session_retry(|session| {
+            let (qresync, condstore) = {
+                let capabilities = session
+                    .capabilities()
+                    .map_err(Error::GetCapabilitiesError)?;
+                let qresync = capabilities.has_str("QRESYNC");
+                let condstore = qresync || capabilities.has_str("CONDSTORE");
+                (qresync, condstore)
+            };
+            trace!("condstore extension supported: {condstore}");
+            trace!("qresync extension supported: {qresync}");
+
+            if !condstore {
+                return Ok(None);
+            }
+
+            let mbox = session
+                .select(&folder_encoded)
+                .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
+            trace!("mailbox: {mbox:?}");
+
+            let next_state = match (mbox.uid_validity, mbox.highest_mod_seq) {
+                (Some(uid_validity), Some(highest_modseq)) => BackendSyncState {
+                    uid_validity,
+                    highest_modseq,
+                    uid_next: mbox.uid_next.unwrap_or_default(),
+                },
+                _ => {
+                    debug!("mod sequences not supported by folder {folder}");
+                    return Ok(None);
+                }
+            };
+            let folder_size = mbox.exists;
+
+            let state = match state {
+                Some(state) if state.uid_validity == next_state.uid_validity => state,
+                _ => {
+                    debug!("uid validity of folder {folder} changed, listing all envelopes");
+                    let envelopes = if folder_size == 0 {
+                        Envelopes::default()
+                    } else {
+                        let fetches = session
+                            .uid_fetch("1:*", "(UID FLAGS ENVELOPE)")
+                            .map_err(|err| Error::FetchEmailsByUidRangeError(err, "1:*".into()))?;
+                        envelope::imap::from_raws(fetches)?
+                    };
+
+                    return Ok(Some(BackendSyncChanges {
+                        state: next_state,
+                        full: true,
+                        envelopes,
+                        vanished: Vec::new(),
+                    }));
+                }
+            };
+
+            if state.highest_modseq == next_state.highest_modseq {
+                debug!("folder {folder} did not change since last synchronization");
+                return Ok(Some(BackendSyncChanges {
+                    state: next_state,
+                    ..Default::default()
+                }));
+            }
+
+            if folder_size == 0 {
+                return Ok(Some(BackendSyncChanges {
+                    state: next_state,
+                    vanished: vec![1..=u32::MAX],
+                    ..Default::default()
+                }));
             }
-            range
-        };
-        trace!("page: {page}");
-        trace!("page size: {page_size}");
-        trace!("seq range: {range}");
 
-        let fetches = session
-            .fetch(&range, "(UID FLAGS ENVELOPE)")
-            .map_err(|err| Error::FetchEmailsByUidRangeError(err, range))?;
-        let envelopes = envelope::imap::from_raws(fetches)?;
-        trace!("imap envelopes: {envelopes:#?}");
+            // Discards unsolicited responses received before the fetch,
+            // so that only the relevant VANISHED responses are collected.
+            while session.unsolicited_responses.try_recv().is_ok() {}
 
-        Ok(envelopes)
+            let modifiers = if qresync {
+                format!("(CHANGEDSINCE {} VANISHED)", state.highest_modseq)
+            } else {
+                format!("(CHANGEDSINCE {})", state.highest_modseq)
+            };
+            let fetches = session
+                .uid_fetch("1:*", format!("(UID FLAGS ENVELOPE) {modifiers}"))
+                .map_err(|err| {
+                    Error::FetchChangedEnvelopesError(err, state.highest_modseq, folder.to_owned())
+                })?;
+            let envelopes = envelope::imap::from_raws(fetches)?;
+            trace!("changed envelopes: {envelopes:#?}");
+
+            let vanished = if qresync {
+                session
+                    .unsolicited_responses
+                    .try_iter()
+                    .filter_map(|res| match res {
+                        UnsolicitedResponse::Vanished { uids, .. } => Some(uids),
+                        _ => None,
+                    })
+                    .flatten()
+                    .collect()
+            } else {
+                let uids = session.uid_search("ALL").map_err(|err| {
+                    Error::SearchEnvelopesError(err, folder.to_owned(), "ALL".into())
+                })?;
+                vanished_uids(&uids, state.uid_next)
+            };
+            trace!("vanished uids: {vanished:?}");
+
+            Ok(Some(BackendSyncChanges {
+                state: next_state,
+                full: false,
+                envelopes,
+                vanished,
+            }))
+        })
     }
 
     fn search_envelopes(
         &self,
         folder: &str,
-        query: &str,
-        sort: &str,
+        query: &SearchQuery,
+        sort: &SortCriteria,
         page_size: usize,
         page: usize,
     ) -> backend::Result<Envelopes> {
         info!("searching imap envelopes from folder {folder}");
 
+        let query = query
+            .to_imap_query()
+            .map_err(Error::CompileSearchQueryError)?;
+        trace!("imap query: {query}");
+        trace!("sort: {sort:?}");
+
         let folder_encoded = encode_utf7(folder.to_owned());
         trace!("utf7 encoded folder: {folder_encoded}");
 
-        let mut session = self.session()?;
-        let folder_size = session
-            .select(&folder_encoded)
-            .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?
-            .exists as usize;
-        trace!("folder size: {folder_size}");
+        self.with_session_retry(|session| {
+            let folder_size = session
+                .select(&folder_encoded)
+                .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?
+                .exists as usize;
+            trace!("folder size: {folder_size}");
 
-        if folder_size == 0 {
-            return Ok(Envelopes::default());
-        }
+            if folder_size == 0 {
+                return Ok(Envelopes::default());
+            }
 
-        let uids: Vec<String> = if sort.is_empty() {
-            session
-                .uid_search(query)
-                .map_err(|err| {
-                    Error::SearchEnvelopesError(err, folder.to_owned(), query.to_owned())
-                })?
-                .iter()
-                .map(|seq| seq.to_string())
-                .collect()
-        } else {
-            let sort: envelope::imap::SortCriteria = sort.try_into()?;
-            session
-                .uid_sort(&sort, imap::extensions::sort::SortCharset::Utf8, query)
-                .map_err(|err| Error::SortEnvelopesError(err, folder.to_owned(), query.to_owned()))?
+            let (sort_supported, literal_plus, literal_minus) = {
+                let capabilities = session
+                    .capabilities()
+                    .map_err(Error::GetCapabilitiesError)?;
+                (
+                    capabilities.has_str("SORT"),
+                    capabilities.has_str("LITERAL+"),
+                    capabilities.has_str("LITERAL-"),
+                )
+            };
+            trace!("sort extension supported: {sort_supported}");
+
+            // Non-ASCII strings are compiled to non-synchronizing
+            // literals, which LITERAL- limits to 4096 bytes.
+            if !query.is_ascii() && !literal_plus && !(literal_minus && query.len() <= 4096) {
+                return Err(Error::SearchLiteralsNotSupportedError(query.clone()).into());
+            }
+
+            if !sort_supported {
+                // Without the SORT extension, all matching envelopes need
+                // to be fetched in order to be sorted client-side.
+                let charset = if query.is_ascii() {
+                    ""
+                } else {
+                    "CHARSET UTF-8 "
+                };
+                let uids: Vec<String> = session
+                    .uid_search(format!("{charset}{query}"))
+                    .map_err(|err| {
+                        Error::SearchEnvelopesError(err, folder.to_owned(), query.clone())
+                    })?
+                    .iter()
+                    .map(|uid| uid.to_string())
+                    .collect();
+                trace!("uids: {uids:?}");
+
+                if uids.is_empty() {
+                    return Ok(Envelopes::default());
+                }
+
+                let uid_range = uids.join(",");
+                let fetches = session
+                    .uid_fetch(&uid_range, "(UID FLAGS ENVELOPE INTERNALDATE RFC822.SIZE)")
+                    .map_err(|err| Error::FetchEmailsByUidRangeError(err, uid_range))?;
+                let mut entries = fetches
+                    .iter()
+                    .map(|fetch| {
+                        let envelope = envelope::imap::from_raw(fetch)?;
+                        Ok((
+                            envelope::imap::sort_keys_from_raw(fetch, &envelope),
+                            envelope,
+                        ))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                sort.sort_by_keys(&mut entries, |(keys, _)| keys);
+
+                let (page_begin, page_end) = page_bounds(entries.len(), page_size, page);
+                trace!("page: {page}");
+                trace!("page size: {page_size}");
+                trace!("page bounds: {page_begin}..{page_end}");
+
+                let envelopes = entries
+                    .drain(page_begin..page_end)
+                    .map(|(_, envelope)| envelope)
+                    .collect::<Envelopes>();
+                trace!("imap envelopes: {envelopes:#?}");
+
+                return Ok(envelopes);
+            }
+
+            let uids: Vec<String> = session
+                .uid_sort(
+                    &sort.to_imap_sort_criteria(),
+                    imap::extensions::sort::SortCharset::Utf8,
+                    &query,
+                )
+                .map_err(|err| Error::SortEnvelopesError(err, folder.to_owned(), query.clone()))?
                 .iter()
                 .map(|uid| uid.to_string())
-                .collect()
-        };
-        trace!("uids: {uids:?}");
+                .collect();
+            trace!("uids: {uids:?}");
 
-        if uids.is_empty() {
-            return Ok(Envelopes::default());
-        }
+            let (page_begin, page_end) = page_bounds(uids.len(), page_size, page);
+            let uids = &uids[page_begin..page_end];
+            trace!("page: {page}");
+            trace!("page size: {page_size}");
+            trace!("page uids: {uids:?}");
 
-        let uid_range = if page_size > 0 {
-            let begin = uids.len().min(page * page_size);
-            let end = begin + uids.len().min(page_size);
-            if end > begin + 1 {
-                uids[begin..end].join(",")
-            } else {
-                uids[0].to_string()
+            if uids.is_empty() {
+                return Ok(Envelopes::default());
             }
-        } else {
-            uids.join(",")
-        };
-        trace!("page: {page}");
-        trace!("page size: {page_size}");
-        trace!("uid range: {uid_range}");
 
-        let fetches = session
-            .uid_fetch(&uid_range, "(UID FLAGS ENVELOPE)")
-            .map_err(|err| Error::FetchEmailsByUidRangeError(err, uid_range))?;
-        let envelopes = envelope::imap::from_raws(fetches)?;
-        trace!("imap envelopes: {envelopes:#?}");
-
-        Ok(envelopes)
+            let uid_range = uids.join(",");
+            let fetches = session
+                .uid_fetch(&uid_range, "(UID FLAGS ENVELOPE)")
+                .map_err(|err| Error::FetchEmailsByUidRangeError(err, uid_range))?;
+            let mut envelopes = envelope::imap::from_raws(fetches)?;
+            // Fetches are returned in the server order, not in the sort
+            // order, so envelopes need to be reordered.
+            envelopes.sort_by_key(|envelope| uids.iter().position(|uid| *uid == envelope.id));
+            trace!("imap envelopes: {envelopes:#?}");
+
+            Ok(envelopes)
+        })
     }
 
     fn add_email(&self, folder: &str, email: &[u8], flags: &Flags) -> backend::Result<String> {
@@ -689,35 +1372,39 @@ impl<'a> Backend for ImapBackend<'a> {
         let folder_encoded = encode_utf7(folder.to_owned());
         trace!("utf7 encoded folder: {folder_encoded}");
 
-        let mut session = self.session()?;
-        let appended = session
-            .append(&folder, email)
-            .flags(flags.into_imap_flags_vec())
-            .finish()
-            .map_err(|err| Error::AppendEmailError(err, folder.to_owned()))?;
-
-        let uid = match appended.uids {
-            Some(mut uids) if uids.len() == 1 => match uids.get_mut(0).unwrap() {
-                UidSetMember::Uid(uid) => Ok(*uid),
-                UidSetMember::UidRange(uids) => Ok(uids.next().ok_or_else(|| {
-                    Error::GetAddedEmailUidFromRangeError(uids.fold(String::new(), |range, uid| {
-                        if range.is_empty() {
-                            uid.to_string()
-                        } else {
-                            range + ", " + &uid.to_string()
-                        }
-                    }))
-                })?),
-            },
-            _ => {
-                // TODO: find a way to retrieve the UID of the added
-                // email (by Message-ID?)
-                Err(Error::GetAddedEmailUidError)
-            }
-        }?;
-        trace!("uid: {uid}");
+        self.with_session(|session| {
+            let appended = session
+                .append(&folder, email)
+                .flags(flags.into_imap_flags_vec())
+                .finish()
+                .map_err(|err| Error::AppendEmailError(err, folder.to_owned()))?;
+
+            let uid = match appended.uids {
+                Some(mut uids) if uids.len() == 1 => match uids.get_mut(0).unwrap() {
+                    UidSetMember::Uid(uid) => Ok(*uid),
+                    UidSetMember::UidRange(uids) => Ok(uids.next().ok_or_else(|| {
+                        Error::GetAddedEmailUidFromRangeError(uids.fold(
+                            String::new(),
+                            |range, uid| {
+                                if range.is_empty() {
+                                    uid.to_string()
+                                } else {
+                                    range + ", " + &uid.to_string()
+                                }
+                            },
+                        ))
+                    })?),
+                },
+                _ => {
+                    // TODO: find a way to retrieve the UID of the added
+                    // email (by Message-ID?)
+                    Err(Error::GetAddedEmailUidError)
+                }
+            }?;
+            trace!("uid: {uid}");
 
-        Ok(uid.to_string())
+            Ok(uid.to_string())
+        })
     }
 
     fn preview_emails(&self, folder: &str, uids: Vec<&str>) -> backend::Result<Emails> {
@@ -727,15 +1414,16 @@ impl<'a> Backend for ImapBackend<'a> {
         let folder_encoded = encode_utf7(folder.to_owned());
         trace!("utf7 encoded folder: {folder_encoded}");
 
-        let mut session = self.session()?;
-        session
-            .select(&folder_encoded)
-            .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
-        let fetches = session
-            .uid_fetch(&uids, "BODY.PEEK[]")
-            .map_err(|err| Error::FetchEmailsByUidRangeError(err, uids))?;
-
-        Ok(Emails::try_from(fetches)?)
+        self.with_session_retry(|session| {
+            session
+                .select(&folder_encoded)
+                .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
+            let fetches = session
+                .uid_fetch(&uids, "BODY.PEEK[]")
+                .map_err(|err| Error::FetchEmailsByUidRangeError(err, uids.clone()))?;
+
+            Ok(Emails::try_from(fetches)?)
+        })
     }
 
     fn get_emails(&self, folder: &str, uids: Vec<&str>) -> backend::Result<Emails> {
@@ -745,15 +1433,16 @@ impl<'a> Backend for ImapBackend<'a> {
         let folder_encoded = encode_utf7(folder.to_owned());
         trace!("utf7 encoded folder: {folder_encoded}");
 
-        let mut session = self.session()?;
-        session
-            .select(&folder_encoded)
-            .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
-        let fetches = session
-            .uid_fetch(&uids, "BODY[]")
-            .map_err(|err| Error::FetchEmailsByUidRangeError(err, uids))?;
-
-        Ok(Emails::try_from(fetches)?)
+        self.with_session_retry(|session| {
+            session
+                .select(&folder_encoded)
+                .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
+            let fetches = session
+                .uid_fetch(&uids, "BODY[]")
+                .map_err(|err| Error::FetchEmailsByUidRangeError(err, uids.clone()))?;
+
+            Ok(Emails::try_from(fetches)?)
+        })
     }
 
     fn copy_emails(
@@ -770,15 +1459,21 @@ impl<'a> Backend for ImapBackend<'a> {
         trace!("utf7 encoded from folder: {}", from_folder_encoded);
         trace!("utf7 encoded to folder: {}", to_folder_encoded);
 
-        let mut session = self.session()?;
-        session
-            .select(from_folder_encoded)
-            .map_err(|err| Error::SelectFolderError(err, from_folder.to_owned()))?;
-        session.uid_copy(&uids, to_folder_encoded).map_err(|err| {
-            Error::CopyEmailError(err, uids, from_folder.to_owned(), to_folder.to_owned())
-        })?;
-
-        Ok(())
+        self.with_session(|session| {
+            session
+                .select(&from_folder_encoded)
+                .map_err(|err| Error::SelectFolderError(err, from_folder.to_owned()))?;
+            session.uid_copy(&uids, &to_folder_encoded).map_err(|err| {
+                Error::CopyEmailError(
+                    err,
+                    uids.clone(),
+                    from_folder.to_owned(),
+                    to_folder.to_owned(),
+                )
+            })?;
+
+            Ok(())
+        })
     }
 
     fn move_emails(
@@ -795,15 +1490,21 @@ impl<'a> Backend for ImapBackend<'a> {
         trace!("utf7 encoded from folder: {}", from_folder_encoded);
         trace!("utf7 encoded to folder: {}", to_folder_encoded);
 
-        let mut session = self.session()?;
-        session
-            .select(from_folder_encoded)
-            .map_err(|err| Error::SelectFolderError(err, from_folder.to_owned()))?;
-        session.uid_mv(&uids, to_folder_encoded).map_err(|err| {
-            Error::MoveEmailError(err, uids, from_folder.to_owned(), to_folder.to_owned())
-        })?;
-
-        Ok(())
+        self.with_session(|session| {
+            session
+                .select(&from_folder_encoded)
+                .map_err(|err| Error::SelectFolderError(err, from_folder.to_owned()))?;
+            session.uid_mv(&uids, &to_folder_encoded).map_err(|err| {
+                Error::MoveEmailError(
+                    err,
+                    uids.clone(),
+                    from_folder.to_owned(),
+                    to_folder.to_owned(),
+                )
+            })?;
+
+            Ok(())
+        })
     }
 
     fn delete_emails(&self, folder: &str, uids: Vec<&str>) -> backend::Result<()> {
@@ -826,15 +1527,16 @@ impl<'a> Backend for ImapBackend<'a> {
         let folder_encoded = encode_utf7(folder.to_owned());
         debug!("utf7 encoded folder: {}", folder_encoded);
 
-        let mut session = self.session()?;
-        session
-            .select(&folder_encoded)
-            .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
-        session
-            .uid_store(&uids, format!("+FLAGS ({})", flags.to_imap_query()))
-            .map_err(|err| Error::AddFlagsError(err, flags.to_imap_query(), uids))?;
+        self.with_session_retry(|session| {
+            session
+                .select(&folder_encoded)
+                .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
+            session
+                .uid_store(&uids, format!("+FLAGS ({})", flags.to_imap_query()))
+                .map_err(|err| Error::AddFlagsError(err, flags.to_imap_query(), uids.clone()))?;
 
-        Ok(())
+            Ok(())
+        })
     }
 
     fn set_flags(&self, folder: &str, uids: Vec<&str>, flags: &Flags) -> backend::Result<()> {
@@ -847,15 +1549,16 @@ impl<'a> Backend for ImapBackend<'a> {
         let folder_encoded = encode_utf7(folder.to_owned());
         debug!("utf7 encoded folder: {}", folder_encoded);
 
-        let mut session = self.session()?;
-        session
-            .select(&folder_encoded)
-            .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
-        session
-            .uid_store(&uids, format!("FLAGS ({})", flags.to_imap_query()))
-            .map_err(|err| Error::SetFlagsError(err, flags.to_imap_query(), uids))?;
+        self.with_session_retry(|session| {
+            session
+                .select(&folder_encoded)
+                .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
+            session
+                .uid_store(&uids, format!("FLAGS ({})", flags.to_imap_query()))
+                .map_err(|err| Error::SetFlagsError(err, flags.to_imap_query(), uids.clone()))?;
 
-        Ok(())
+            Ok(())
+        })
     }
 
     fn remove_flags(&self, folder: &str, uids: Vec<&str>, flags: &Flags) -> backend::Result<()> {
@@ -868,24 +1571,28 @@ impl<'a> Backend for ImapBackend<'a> {
         let folder_encoded = encode_utf7(folder.to_owned());
         debug!("utf7 encoded folder: {}", folder_encoded);
 
-        let mut session = self.session()?;
-        session
-            .select(&folder_encoded)
-            .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
-        session
-            .uid_store(&uids, format!("-FLAGS ({})", flags.to_imap_query()))
-            .map_err(|err| Error::RemoveFlagsError(err, flags.to_imap_query(), uids))?;
+        self.with_session_retry(|session| {
+            session
+                .select(&folder_encoded)
+                .map_err(|err| Error::SelectFolderError(err, folder.to_owned()))?;
+            session
+                .uid_store(&uids, format!("-FLAGS ({})", flags.to_imap_query()))
+                .map_err(|err| Error::RemoveFlagsError(err, flags.to_imap_query(), uids.clone()))?;
 
-        Ok(())
+            Ok(())
+        })
+    }
+
+    fn subscribe(&self, folder: &str) -> backend::Result<BackendSubscription> {
+        Ok(self.subscribe_with_idle_timeout(folder, IDLE_TIMEOUT)?)
     }
 
     fn close(&self) -> backend::Result<()> {
-        self.sessions_pool.par_iter().try_for_each(|session| {
-            let mut session = session
-                .lock()
-                .map_err(|err| Error::LockSessionError(err.to_string()))?;
-            session.logout().map_err(Error::CloseImapSessionError)
-        })?;
+        self.sessions_pool
+            .drain()
+            .map_err(Error::from)?
+            .par_iter_mut()
+            .try_for_each(|session| session.logout().map_err(Error::CloseImapSessionError))?;
 
         Ok(())
     }
@@ -894,3 +1601,169 @@ impl<'a> Backend for ImapBackend<'a> {
         self
     }
 }
+
+#[cfg(test)]
+mod imap_backend {
+    use imap::types::UnsolicitedResponse;
+    use imap_proto::AttributeValue;
+    use std::{borrow::Cow, collections::HashSet, io, time::Duration};
+
+    use crate::{backend, BackendEvent, Flag, Flags};
+
+    use super::{
+        is_connection_error, next_retry_delay, retry_with_backoff, vanished_uids, Error, IdleState,
+        MAX_RETRY_DELAY,
+    };
+
+    #[test]
+    fn connection_errors() {
+        let err = |err| backend::Error::from(Error::SelectFolderError(err, "INBOX".into()));
+
+        assert!(is_connection_error(&err(imap::Error::ConnectionLost)));
+        assert!(is_connection_error(&err(imap::Error::Io(io::Error::from(
+            io::ErrorKind::BrokenPipe
+        )))));
+        assert!(!is_connection_error(&err(imap::Error::Append)));
+        assert!(!is_connection_error(&backend::Error::from(
+            Error::GetAddedEmailUidError
+        )));
+    }
+
+    #[test]
+    fn retry_backoff() {
+        let connection_lost = || {
+            backend::Error::from(Error::SelectFolderError(
+                imap::Error::ConnectionLost,
+                "INBOX".into(),
+            ))
+        };
+
+        // a failing session followed by a success
+        let mut attempts = 0;
+        let res = retry_with_backoff(3, Duration::from_millis(1), || {
+            attempts += 1;
+            if attempts == 1 {
+                Err(connection_lost())
+            } else {
+                Ok(Ok(attempts))
+            }
+        });
+        assert_eq!(res.unwrap(), 2);
+
+        // a final error is not retried
+        let mut attempts = 0;
+        let res: backend::Result<()> = retry_with_backoff(3, Duration::from_millis(1), || {
+            attempts += 1;
+            Ok(Err(Error::GetAddedEmailUidError.into()))
+        });
+        assert!(res.is_err());
+        assert_eq!(attempts, 1);
+
+        // retries exhaustion
+        let mut attempts = 0;
+        let res: backend::Result<()> = retry_with_backoff(2, Duration::from_millis(1), || {
+            attempts += 1;
+            Err(connection_lost())
+        });
+        assert_eq!(attempts, 3);
+        match res {
+            Err(backend::Error::ImapBackendError(err)) => match *err {
+                Error::RetriesExhaustedError(err, 2) => assert!(is_connection_error(&*err)),
+                err => panic!("unexpected error: {err:?}"),
+            },
+            res => panic!("unexpected result: {res:?}"),
+        }
+    }
+
+    #[test]
+    fn retry_delay_cap() {
+        assert_eq!(
+            next_retry_delay(Duration::from_millis(500)),
+            Duration::from_secs(1)
+        );
+        assert_eq!(next_retry_delay(Duration::from_secs(40)), MAX_RETRY_DELAY);
+        assert_eq!(next_retry_delay(MAX_RETRY_DELAY), MAX_RETRY_DELAY);
+        assert_eq!(next_retry_delay(Duration::MAX), MAX_RETRY_DELAY);
+
+        let mut delay = Duration::from_secs(1);
+        for _ in 0..1000 {
+            delay = next_retry_delay(delay);
+        }
+        assert_eq!(delay, MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn vanished_uids_ranges() {
+        assert_eq!(vanished_uids(&HashSet::new(), 1), vec![]);
+        assert_eq!(vanished_uids(&HashSet::new(), 4), vec![1..=3]);
+        assert_eq!(vanished_uids(&HashSet::from_iter([1, 2, 3]), 4), vec![]);
+        assert_eq!(
+            vanished_uids(&HashSet::from_iter([2, 5, 6, 9]), 10),
+            vec![1..=1, 3..=4, 7..=8]
+        );
+        assert_eq!(vanished_uids(&HashSet::from_iter([1, 12]), 10), vec![2..=9]);
+    }
+
+    #[test]
+    fn idle_state() {
+        let mut state = IdleState::new(vec![30, 10, 20]);
+        let removed = |uid: &str| BackendEvent::EnvelopeRemoved("INBOX".into(), uid.into());
+
+        assert!(!IdleState::is_change(&UnsolicitedResponse::Ok {
+            code: None,
+            information: Some("Still here".into()),
+        }));
+        assert!(!IdleState::is_change(&UnsolicitedResponse::Recent(1)));
+        assert!(IdleState::is_change(&UnsolicitedResponse::Exists(4)));
+
+        // flags changes are reported by sequence number or by uid
+        let flags = |id, uid: Option<u32>| UnsolicitedResponse::Fetch {
+            id,
+            attributes: [AttributeValue::Flags(vec![Cow::Borrowed("\\Seen")])]
+                .into_iter()
+                .chain(uid.map(AttributeValue::Uid))
+                .collect(),
+        };
+        assert_eq!(
+            state.apply("INBOX", flags(2, None)),
+            vec![BackendEvent::FlagsChanged(
+                "INBOX".into(),
+                "20".into(),
+                Flags::from_iter([Flag::Seen])
+            )]
+        );
+        assert_eq!(state.apply("INBOX", flags(7, Some(30))).len(), 1);
+        assert!(state.apply("INBOX", flags(7, None)).is_empty());
+
+        // only new emails are fetched
+        assert_eq!(state.new_seqs(), None);
+        assert!(state
+            .apply("INBOX", UnsolicitedResponse::Exists(5))
+            .is_empty());
+        assert_eq!(state.new_seqs(), Some("4:5".into()));
+        state.extend([40, 50]);
+        assert_eq!(state.new_seqs(), None);
+
+        // expunged sequence numbers shift the following ones
+        assert_eq!(
+            state.apply("INBOX", UnsolicitedResponse::Expunge(2)),
+            vec![removed("20")]
+        );
+        assert_eq!(
+            state.apply("INBOX", UnsolicitedResponse::Expunge(2)),
+            vec![removed("30")]
+        );
+        assert_eq!(
+            state.apply(
+                "INBOX",
+                UnsolicitedResponse::Vanished {
+                    earlier: false,
+                    uids: vec![1..=10, 50..=60],
+                }
+            ),
+            vec![removed("10"), removed("50")]
+        );
+        assert_eq!(state.uids, vec![40]);
+        assert_eq!(state.new_seqs(), None);
+    }
+}
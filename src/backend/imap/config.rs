@@ -3,18 +3,17 @@
 //! This module contains the representation of the IMAP backend
 //! configuration of the user account.
 
-use std::result;
+use serde::Deserialize;
+use std::{result, time::Duration};
 use thiserror::Error;
 
-use crate::process;
+use crate::{process, secret, OAuth2Config, Secret};
 
 #[cfg(feature = "imap-backend")]
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("cannot get imap password")]
-    GetPasswdError(#[source] process::Error),
-    #[error("cannot get imap password: password is empty")]
-    GetPasswdEmptyError,
+    GetPasswdError(#[source] secret::Error),
     #[error("cannot start the notify mode")]
     StartNotifyModeError(#[source] process::Error),
 }
@@ -23,7 +22,8 @@ pub type Result<T> = result::Result<T, Error>;
 
 /// Represents the IMAP backend configuration.
 #[cfg(feature = "imap-backend")]
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct ImapConfig {
     /// Represents the IMAP server host.
     pub host: String,
@@ -37,12 +37,12 @@ pub struct ImapConfig {
     pub insecure: Option<bool>,
     /// Represents the IMAP server login.
     pub login: String,
-    /// Represents the IMAP server password command.
-    pub passwd_cmd: Option<String>,
-    /// Represents the IMAP password,
-    pub password: Option<String>,
-    /// Represents an oauth2 access token.
-    pub access_token: Option<String>,
+    /// Represents the IMAP server password.
+    #[serde(default)]
+    pub passwd: Secret,
+    /// Represents the OAuth 2.0 config, used instead of the password
+    /// when defined.
+    pub oauth2: Option<OAuth2Config>,
 
     /// Represents the IMAP notify command.
     pub notify_cmd: Option<String>,
@@ -51,29 +51,25 @@ pub struct ImapConfig {
     pub notify_query: Option<String>,
     /// Represents the watch commands.
     pub watch_cmds: Option<Vec<String>>,
+
+    /// Represents the maximum number of times an idempotent command
+    /// is retried after a connection failure. Defaults to 3.
+    pub retry_max: Option<usize>,
+    /// Represents the delay before the first retry, in milliseconds.
+    /// The delay doubles after each retry, up to one minute. Defaults
+    /// to 500.
+    pub retry_delay: Option<u64>,
 }
 
 #[cfg(feature = "imap-backend")]
 impl ImapConfig {
-    /// Executes the IMAP password command in order to retrieve the
-    /// IMAP server password.
+    /// Retrieves the IMAP server password. The password is cached,
+    /// see [`Secret::get`].
     pub fn passwd(&self) -> Result<String> {
-        if self.access_token.is_some() {
+        if self.oauth2.is_some() {
             return Ok("".to_string());
         }
-        if let Some(password) = &self.password {
-            return Ok(password.to_owned());
-        }
-        let Some(passwd_cmd) = &self.passwd_cmd else {
-            return Err(Error::GetPasswdEmptyError);
-        };
-        let passwd = process::run(&passwd_cmd, &[]).map_err(Error::GetPasswdError)?;
-        let passwd = String::from_utf8_lossy(&passwd).to_string();
-        let passwd = passwd
-            .lines()
-            .next()
-            .ok_or_else(|| Error::GetPasswdEmptyError)?;
-        Ok(passwd.to_owned())
+        self.passwd.get().map_err(Error::GetPasswdError)
     }
 
     /// Gets the SSL IMAP option.
@@ -115,6 +111,16 @@ impl ImapConfig {
             .unwrap_or_else(|| String::from("NEW"))
     }
 
+    /// Gets the maximum number of retries after a connection failure.
+    pub fn retry_max(&self) -> usize {
+        self.retry_max.unwrap_or(3)
+    }
+
+    /// Gets the delay before the first retry.
+    pub fn retry_delay(&self) -> Duration {
+        Duration::from_millis(self.retry_delay.unwrap_or(500))
+    }
+
     pub fn watch_cmds(&self) -> Vec<String> {
         self.watch_cmds
             .as_ref()
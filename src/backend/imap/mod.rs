@@ -3,3 +3,6 @@ pub use config::ImapConfig;
 
 pub mod backend;
 pub use backend::*;
+
+pub mod pool;
+pub use pool::{SessionPool, SessionPoolGuard, SessionPoolMetrics};
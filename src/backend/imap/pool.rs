@@ -0,0 +1,398 @@
+//! IMAP session pool module.
+//!
+//! This module contains the pool of sessions used by the IMAP
+//! backend. Sessions are opened lazily, up to the size of the pool,
+//! and the first idle session is handed out to the caller. When all
+//! sessions are in use, the caller waits until one is released.
+
+use log::{debug, trace, warn};
+use std::{
+    ops::{Deref, DerefMut},
+    result,
+    sync::{Condvar, Mutex, MutexGuard, PoisonError},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("cannot lock imap sessions pool: {0}")]
+    LockSessionsPoolError(String),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents the metrics of a sessions pool.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SessionPoolMetrics {
+    /// Represents the maximum number of open sessions.
+    pub size: usize,
+    /// Represents the number of open sessions, idle or in use.
+    pub open: usize,
+    /// Represents the number of idle sessions.
+    pub idle: usize,
+    /// Represents the number of sessions currently in use.
+    pub in_use: usize,
+    /// Represents the number of callers waiting for a session.
+    pub waiting: usize,
+    /// Represents the total number of sessions opened by the pool.
+    pub created: usize,
+    /// Represents the total number of sessions handed out.
+    pub checkouts: usize,
+    /// Represents the total number of checkouts that had to wait for
+    /// a session to be released.
+    pub waits: usize,
+}
+
+#[derive(Debug)]
+struct SessionPoolState<S> {
+    idle: Vec<S>,
+    open: usize,
+    waiting: usize,
+    created: usize,
+    checkouts: usize,
+    waits: usize,
+}
+
+/// Represents the sessions pool.
+#[derive(Debug)]
+pub struct SessionPool<S> {
+    size: usize,
+    state: Mutex<SessionPoolState<S>>,
+    released: Condvar,
+}
+
+impl<S> SessionPool<S> {
+    /// Creates an empty pool able to hold the given number of
+    /// sessions (at least one).
+    pub fn new(size: usize) -> Self {
+        Self {
+            size: size.max(1),
+            state: Mutex::new(SessionPoolState {
+                idle: Vec::new(),
+                open: 0,
+                waiting: 0,
+                created: 0,
+                checkouts: 0,
+                waits: 0,
+            }),
+            released: Condvar::new(),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, SessionPoolState<S>>> {
+        self.state
+            .lock()
+            .map_err(|err| Error::LockSessionsPoolError(err.to_string()))
+    }
+
+    /// Adds the given open session to the pool, as an idle session.
+    pub fn push(&self, session: S) -> Result<()> {
+        let mut state = self.lock()?;
+        state.idle.push(session);
+        state.open += 1;
+        state.created += 1;
+        drop(state);
+
+        self.released.notify_one();
+        Ok(())
+    }
+
+    /// Checks out the first idle session. If there is none and the
+    /// pool is not full, a new session is opened using the given
+    /// function. Otherwise waits for a session to be released.
+    pub fn checkout<E, F>(&self, create: F) -> result::Result<SessionPoolGuard<'_, S>, E>
+    where
+        E: From<Error>,
+        F: FnOnce() -> result::Result<S, E>,
+    {
+        let mut state = self.lock()?;
+        let mut waited = false;
+
+        loop {
+            if let Some(session) = state.idle.pop() {
+                state.checkouts += 1;
+                trace!("checking out idle session");
+                return Ok(SessionPoolGuard::new(self, session));
+            }
+
+            if state.open < self.size {
+                // The slot is reserved before opening the session, so
+                // that the lock is not held during the connection.
+                state.open += 1;
+                drop(state);
+
+                debug!("opening new session");
+                return match create() {
+                    Ok(session) => {
+                        let mut state = self.lock()?;
+                        state.created += 1;
+                        state.checkouts += 1;
+                        Ok(SessionPoolGuard::new(self, session))
+                    }
+                    Err(err) => {
+                        self.close_slot();
+                        Err(err)
+                    }
+                };
+            }
+
+            if !waited {
+                state.waits += 1;
+                waited = true;
+            }
+
+            debug!("no idle session, waiting for one to be released");
+            state.waiting += 1;
+            state = self
+                .released
+                .wait(state)
+                .map_err(|err| Error::LockSessionsPoolError(err.to_string()))?;
+            state.waiting -= 1;
+        }
+    }
+
+    /// Takes all the idle sessions out of the pool. Sessions in use
+    /// are left untouched: they go back to the pool when their guard
+    /// is dropped, and can be taken by a later call.
+    pub fn drain(&self) -> Result<Vec<S>> {
+        let mut state = self.lock()?;
+        let sessions: Vec<S> = state.idle.drain(..).collect();
+        state.open -= sessions.len();
+        if state.open > 0 {
+            warn!("{} session(s) still in use, leaving them open", state.open);
+        }
+        drop(state);
+
+        self.released.notify_all();
+        Ok(sessions)
+    }
+
+    pub fn metrics(&self) -> Result<SessionPoolMetrics> {
+        let state = self.lock()?;
+
+        Ok(SessionPoolMetrics {
+            size: self.size,
+            open: state.open,
+            idle: state.idle.len(),
+            in_use: state.open - state.idle.len(),
+            waiting: state.waiting,
+            created: state.created,
+            checkouts: state.checkouts,
+            waits: state.waits,
+        })
+    }
+
+    /// Locks the state even if the lock is poisoned. Used by the
+    /// guards, which cannot report errors: skipping the update would
+    /// leave the session slot in use forever, and later checkouts
+    /// would wait for it endlessly.
+    fn lock_unpoisoned(&self) -> MutexGuard<'_, SessionPoolState<S>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn release(&self, session: S) {
+        self.lock_unpoisoned().idle.push(session);
+        self.released.notify_one();
+    }
+
+    fn close_slot(&self) {
+        self.lock_unpoisoned().open -= 1;
+        self.released.notify_one();
+    }
+}
+
+/// Represents a session checked out from the pool. The session goes
+/// back to the pool when the guard is dropped.
+#[derive(Debug)]
+pub struct SessionPoolGuard<'a, S> {
+    pool: &'a SessionPool<S>,
+    session: Option<S>,
+}
+
+impl<'a, S> SessionPoolGuard<'a, S> {
+    fn new(pool: &'a SessionPool<S>, session: S) -> Self {
+        Self {
+            pool,
+            session: Some(session),
+        }
+    }
+
+    /// Discards the session instead of giving it back to the pool,
+    /// for example when its connection is broken. The pool will open
+    /// a new session instead.
+    pub fn discard(mut self) {
+        if self.session.take().is_some() {
+            self.pool.close_slot()
+        }
+    }
+}
+
+impl<S> Deref for SessionPoolGuard<'_, S> {
+    type Target = S;
+
+    fn deref(&self) -> &Self::Target {
+        self.session
+            .as_ref()
+            .expect("session should be checked out")
+    }
+}
+
+impl<S> DerefMut for SessionPoolGuard<'_, S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.session
+            .as_mut()
+            .expect("session should be checked out")
+    }
+}
+
+impl<S> Drop for SessionPoolGuard<'_, S> {
+    fn drop(&mut self) {
+        if let Some(session) = self.session.take() {
+            self.pool.release(session)
+        }
+    }
+}
+
+#[cfg(test)]
+mod imap_pool {
+    use std::{
+        panic,
+        sync::{Arc, Barrier},
+        thread,
+        time::Duration,
+    };
+
+    use super::{Error, SessionPool};
+
+    #[test]
+    fn grows_lazily() {
+        let pool = SessionPool::new(2);
+        assert_eq!(pool.metrics().unwrap().open, 0);
+
+        let a = pool.checkout(|| Ok::<_, Error>(1)).unwrap();
+        let b = pool.checkout(|| Ok::<_, Error>(2)).unwrap();
+        assert_eq!((*a, *b), (1, 2));
+
+        let metrics = pool.metrics().unwrap();
+        assert_eq!(metrics.open, 2);
+        assert_eq!(metrics.in_use, 2);
+        assert_eq!(metrics.created, 2);
+
+        drop(a);
+        let c = pool.checkout(|| Ok::<_, Error>(3)).unwrap();
+        assert_eq!(*c, 1);
+
+        let metrics = pool.metrics().unwrap();
+        assert_eq!(metrics.created, 2);
+        assert_eq!(metrics.checkouts, 3);
+    }
+
+    #[test]
+    fn hands_out_idle_session() {
+        let pool = SessionPool::new(3);
+        pool.push(1).unwrap();
+
+        // the idle session is reused instead of opening a new one
+        for _ in 0..3 {
+            let session = pool
+                .checkout(|| -> Result<i32, Error> { panic!("should not open a session") })
+                .unwrap();
+            assert_eq!(*session, 1);
+        }
+
+        assert_eq!(pool.metrics().unwrap().open, 1);
+    }
+
+    #[test]
+    fn waits_for_release() {
+        let pool = Arc::new(SessionPool::new(1));
+        let session = pool.checkout(|| Ok::<_, Error>(1)).unwrap();
+        let barrier = Arc::new(Barrier::new(2));
+
+        let handle = thread::spawn({
+            let pool = pool.clone();
+            let barrier = barrier.clone();
+            move || {
+                barrier.wait();
+                let session = pool.checkout(|| Ok::<_, Error>(2)).unwrap();
+                *session
+            }
+        });
+
+        barrier.wait();
+        while pool.metrics().unwrap().waiting == 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        drop(session);
+
+        assert_eq!(handle.join().unwrap(), 1);
+
+        let metrics = pool.metrics().unwrap();
+        assert_eq!(metrics.created, 1);
+        assert_eq!(metrics.waits, 1);
+        assert_eq!(metrics.idle, 1);
+    }
+
+    #[test]
+    fn releases_slot_on_error() {
+        let pool = SessionPool::<i32>::new(1);
+
+        let res = pool.checkout(|| Err(Error::LockSessionsPoolError("test".into())));
+        assert!(res.is_err());
+        assert_eq!(pool.metrics().unwrap().open, 0);
+
+        let session = pool.checkout(|| Ok::<_, Error>(1)).unwrap();
+        assert_eq!(*session, 1);
+    }
+
+    #[test]
+    fn discard() {
+        let pool = SessionPool::new(1);
+        pool.checkout(|| Ok::<_, Error>(1)).unwrap().discard();
+        assert_eq!(pool.metrics().unwrap().open, 0);
+
+        let session = pool.checkout(|| Ok::<_, Error>(2)).unwrap();
+        assert_eq!(*session, 2);
+        assert_eq!(pool.metrics().unwrap().created, 2);
+    }
+
+    #[test]
+    fn drain() {
+        let pool = SessionPool::new(2);
+        pool.push(1).unwrap();
+        let session = pool.checkout(|| Ok::<_, Error>(2)).unwrap();
+        let _ = pool.checkout(|| Ok::<_, Error>(3)).unwrap();
+
+        assert_eq!(pool.drain().unwrap(), vec![3]);
+
+        let metrics = pool.metrics().unwrap();
+        assert_eq!(metrics.open, 1);
+        assert_eq!(metrics.in_use, 1);
+        drop(session);
+    }
+
+    #[test]
+    fn release_poisoned() {
+        let pool = SessionPool::new(2);
+        let a = pool.checkout(|| Ok::<_, Error>(1)).unwrap();
+        let b = pool.checkout(|| Ok::<_, Error>(2)).unwrap();
+
+        let _ = panic::catch_unwind(|| {
+            let _state = pool.state.lock().unwrap();
+            panic!("poisoning the pool");
+        });
+        assert!(pool.state.is_poisoned());
+
+        // the guards still give their session back to the pool
+        drop(a);
+        b.discard();
+
+        let state = pool.lock_unpoisoned();
+        assert_eq!(state.idle, vec![1]);
+        assert_eq!(state.open, 1);
+    }
+}
@@ -3,23 +3,29 @@
 //! This module contains the definition of the maildir backend and its
 //! traits implementation.
 
-use log::{info, trace, warn};
+use log::{debug, info, trace, warn};
 use maildir::Maildir;
+use notify::{event::ModifyKind, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use rayon::prelude::*;
 use std::{
     any::Any,
     borrow::Cow,
+    collections::{HashMap, HashSet},
     env,
     ffi::OsStr,
     fs, io,
-    path::{self, PathBuf},
+    path::{self, Path, PathBuf},
     result,
+    sync::mpsc::{self, RecvTimeoutError},
+    time::Duration,
 };
 use thiserror::Error;
 
 use crate::{
     account::{self, config::DEFAULT_TRASH_FOLDER},
-    backend, email, AccountConfig, Backend, Emails, Envelope, Envelopes, Flag, Flags, Folder,
-    Folders, IdMapper, MaildirConfig, DEFAULT_INBOX_FOLDER,
+    backend::{self, BackendEvent, BackendEventSender, BackendSubscription},
+    email, envelope, AccountConfig, Backend, Emails, Envelope, Envelopes, Flag, Flags, Folder,
+    Folders, IdMapper, MaildirConfig, SearchQuery, SortCriteria, DEFAULT_INBOX_FOLDER,
 };
 
 #[derive(Debug, Error)]
@@ -50,8 +56,6 @@ pub enum Error {
     ParseSubdirError(path::PathBuf),
     #[error("cannot get maildir envelopes at page {0}")]
     GetEnvelopesOutOfBoundsError(usize),
-    #[error("cannot search maildir envelopes: feature not implemented")]
-    SearchEnvelopesUnimplementedError,
     #[error("cannot get maildir message {0}")]
     GetMsgError(String),
     #[error("cannot decode maildir entry")]
@@ -82,6 +86,12 @@ pub enum Error {
     SetFlagsError(#[source] io::Error),
     #[error("cannot remove maildir flags")]
     RemoveFlagsError(#[source] io::Error),
+    #[error("cannot create maildir watcher")]
+    CreateWatcherError(#[source] notify::Error),
+    #[error("cannot watch maildir directory {1}")]
+    WatchDirError(#[source] notify::Error, PathBuf),
+    #[error("cannot receive maildir watcher event")]
+    ReceiveWatchEventError(#[source] notify::Error),
 
     #[error(transparent)]
     ConfigError(#[from] account::config::Error),
@@ -100,6 +110,21 @@ pub struct MaildirBackend<'a> {
 
 const ID_MAPPER_DB_FILE_NAME: &str = ".id-mapper.sqlite";
 
+#[cfg(unix)]
+const INFO_SUFFIX_SEPARATOR: &str = ":2,";
+#[cfg(windows)]
+const INFO_SUFFIX_SEPARATOR: &str = ";2,";
+
+/// Represents the maximum amount of time the watcher waits for a
+/// filesystem event before checking if the subscription has been
+/// cancelled.
+const WATCH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Represents the amount of time the watcher keeps collecting
+/// filesystem events before processing them, so that the two halves
+/// of a rename are processed together.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(100);
+
 impl<'a> MaildirBackend<'a> {
     pub fn new(
         account_config: Cow<'a, AccountConfig>,
@@ -239,6 +264,10 @@ impl<'a> Backend for MaildirBackend<'a> {
         self.account_config.name.clone()
     }
 
+    fn endpoint(&self) -> String {
+        format!("maildir://{}", self.mdir.path().display())
+    }
+
     fn add_folder(&self, folder: &str) -> backend::Result<()> {
         info!("adding maildir folder {}", folder);
 
@@ -426,13 +455,65 @@ impl<'a> Backend for MaildirBackend<'a> {
 
     fn search_envelopes(
         &self,
-        _folder: &str,
-        _query: &str,
-        _sort: &str,
-        _page_size: usize,
-        _page: usize,
+        folder: &str,
+        query: &SearchQuery,
+        sort: &SortCriteria,
+        page_size: usize,
+        page: usize,
     ) -> backend::Result<Envelopes> {
-        Err(Error::SearchEnvelopesUnimplementedError)?
+        info!("searching maildir envelopes from folder {folder}");
+        trace!("query: {query:?}");
+        trace!("sort: {sort:?}");
+        trace!("page size: {page_size}");
+        trace!("page: {page}");
+
+        let with_body = query.needs_body();
+
+        let mdir = self.get_mdir_from_dir(folder)?;
+        let id_mapper = self.id_mapper(folder)?;
+        let entries = mdir
+            .list_cur()
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|entry| {
+                let entry = entry.map_err(Error::DecodeEntryError)?;
+                envelope::maildir::SearchEntry::from_entry(entry, with_body)
+            })
+            .filter(|entry| match entry {
+                Ok(entry) => query.matches(entry),
+                Err(_) => true,
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut entries = entries
+            .into_iter()
+            .map(|entry| (entry.sort_keys(), entry))
+            .collect::<Vec<_>>();
+
+        let page_begin = page * page_size;
+        trace!("page begin: {}", page_begin);
+        if page_begin > entries.len() {
+            return Err(Error::GetEnvelopesOutOfBoundsError(page_begin + 1))?;
+        }
+
+        let page_end = entries.len().min(if page_size == 0 {
+            entries.len()
+        } else {
+            page_begin + page_size
+        });
+        trace!("page end: {}", page_end);
+
+        sort.sort_by_keys(&mut entries, |(keys, _)| keys);
+        let envelopes = entries[page_begin..page_end]
+            .iter()
+            .map(|(_, entry)| {
+                Ok(Envelope {
+                    id: id_mapper.get_id(&entry.envelope.internal_id)?,
+                    ..entry.envelope.clone()
+                })
+            })
+            .collect::<Result<Envelopes>>()?;
+
+        Ok(envelopes)
     }
 
     fn add_email(&self, folder: &str, email: &[u8], flags: &Flags) -> backend::Result<String> {
@@ -847,7 +928,218 @@ impl<'a> Backend for MaildirBackend<'a> {
         Ok(())
     }
 
+    /// Watches the `new/` and `cur/` directories of the given folder
+    /// for changes made by external programs (MDAs, synchronizers or
+    /// other clients). Flag changes are detected through the info
+    /// suffix of renamed entries. The root directory is also watched
+    /// in order to detect new folders.
+    fn subscribe(&self, folder: &str) -> backend::Result<BackendSubscription> {
+        info!("subscribing to maildir events of folder {folder}");
+
+        let mdir = self.get_mdir_from_dir(folder)?;
+
+        let (events_tx, events) = mpsc::channel();
+        let mut watcher =
+            notify::recommended_watcher(events_tx).map_err(Error::CreateWatcherError)?;
+        for dir in [
+            self.mdir.path().to_owned(),
+            mdir.path().join("new"),
+            mdir.path().join("cur"),
+        ] {
+            watcher
+                .watch(&dir, RecursiveMode::NonRecursive)
+                .map_err(|err| Error::WatchDirError(err, dir.clone()))?;
+        }
+
+        // The entries are listed after the watcher started so that no
+        // change can be missed in between.
+        let entries = list_entries_flags(&mdir)?;
+        trace!("maildir entries flags: {:#?}", entries);
+
+        let backend = MaildirBackend {
+            account_config: Cow::Owned(self.account_config.clone().into_owned()),
+            mdir: Maildir::from(self.mdir.path().to_owned()),
+            db_path: self.db_path.clone(),
+        };
+        let folder = folder.to_owned();
+
+        Ok(BackendSubscription::spawn(move |sender| {
+            Ok(watch_folder(
+                &backend, watcher, events, &folder, &mdir, entries, sender,
+            )?)
+        }))
+    }
+
     fn as_any(&'static self) -> &(dyn Any) {
         self
     }
 }
+
+/// Parses the internal id and the flags of the maildir entry located
+/// at the given path, based on the info suffix of its file name.
+/// Hidden files are ignored.
+fn parse_entry_path(path: &Path) -> Option<(String, Flags)> {
+    let name = path.file_name()?.to_str()?;
+
+    if name.starts_with('.') {
+        return None;
+    }
+
+    match name.split_once(INFO_SUFFIX_SEPARATOR) {
+        Some((id, flags)) => Some((id.to_owned(), flags.chars().map(Flag::from).collect())),
+        None => Some((name.to_owned(), Flags::default())),
+    }
+}
+
+/// Lists the flags of the entries of both `new/` and `cur/`
+/// directories of the given maildir, indexed by internal id.
+fn list_entries_flags(mdir: &Maildir) -> Result<HashMap<String, Flags>> {
+    mdir.list_new()
+        .chain(mdir.list_cur())
+        .map(|entry| {
+            let entry = entry.map_err(Error::GetSubdirEntryError)?;
+            Ok((entry.id().to_owned(), Flags::from(&entry)))
+        })
+        .collect()
+}
+
+/// Receives the paths changed by the next batch of filesystem
+/// events. Returns `None` when no event occurred before the watch
+/// timeout.
+fn recv_changed_paths(
+    events: &mpsc::Receiver<notify::Result<notify::Event>>,
+) -> Result<Option<Vec<PathBuf>>> {
+    let mut paths = Vec::new();
+    let mut timeout = WATCH_TIMEOUT;
+
+    loop {
+        let event = match events.recv_timeout(timeout) {
+            Ok(event) => event.map_err(Error::ReceiveWatchEventError)?,
+            Err(RecvTimeoutError::Timeout) if paths.is_empty() => return Ok(None),
+            Err(_) => return Ok(Some(paths)),
+        };
+
+        match event.kind {
+            EventKind::Any
+            | EventKind::Create(_)
+            | EventKind::Remove(_)
+            | EventKind::Modify(ModifyKind::Any | ModifyKind::Name(_)) => {
+                for path in event.paths {
+                    if !paths.contains(&path) {
+                        paths.push(path)
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        timeout = WATCH_DEBOUNCE;
+    }
+}
+
+/// Watches the given maildir folder until the subscription is
+/// cancelled. The given entries represent the initial state of the
+/// folder, which is diffed against each batch of changed paths.
+fn watch_folder(
+    backend: &MaildirBackend,
+    _watcher: RecommendedWatcher,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+    folder: &str,
+    mdir: &Maildir,
+    mut entries: HashMap<String, Flags>,
+    sender: &BackendEventSender,
+) -> Result<()> {
+    let root_dir = backend.mdir.path();
+    let new_dir = mdir.path().join("new");
+    let cur_dir = mdir.path().join("cur");
+
+    while !sender.is_cancelled() {
+        let paths = match recv_changed_paths(&events)? {
+            Some(paths) => paths,
+            None => continue,
+        };
+        trace!("maildir changed paths: {:#?}", paths);
+
+        let mut removed_ids = HashSet::new();
+
+        for path in paths {
+            let dir = path.parent();
+
+            if dir == Some(root_dir) {
+                let name = path
+                    .file_name()
+                    .and_then(OsStr::to_str)
+                    .and_then(|name| name.strip_prefix('.'))
+                    .filter(|name| !name.is_empty() && path.is_dir());
+
+                if let Some(name) = name {
+                    if !sender.send(BackendEvent::FolderAdded(backend.decode_folder(name))) {
+                        return Ok(());
+                    }
+                }
+
+                continue;
+            }
+
+            if dir != Some(&new_dir) && dir != Some(&cur_dir) {
+                continue;
+            }
+
+            let (internal_id, flags) = match parse_entry_path(&path) {
+                Some(entry) => entry,
+                None => continue,
+            };
+
+            // The entry may have been renamed, in which case the
+            // other half of the rename decides whether it has really
+            // been removed.
+            if !path.is_file() {
+                removed_ids.insert(internal_id);
+                continue;
+            }
+
+            removed_ids.remove(&internal_id);
+
+            let event = match entries.insert(internal_id.clone(), flags.clone()) {
+                None => {
+                    let entry = match mdir.find(&internal_id) {
+                        Some(entry) => entry,
+                        None => {
+                            debug!("maildir entry {internal_id} vanished, skipping it");
+                            entries.remove(&internal_id);
+                            continue;
+                        }
+                    };
+                    let mut envelope = Envelope::try_from(entry)?;
+                    envelope.id = backend.id_mapper(folder)?.get_id(&internal_id)?;
+                    BackendEvent::EnvelopeAdded(folder.to_owned(), envelope)
+                }
+                Some(prev_flags) if prev_flags != flags => {
+                    BackendEvent::FlagsChanged(folder.to_owned(), internal_id, flags)
+                }
+                Some(_) => continue,
+            };
+
+            if !sender.send(event) {
+                return Ok(());
+            }
+        }
+
+        for internal_id in removed_ids {
+            if !entries.contains_key(&internal_id) || mdir.find(&internal_id).is_some() {
+                continue;
+            }
+
+            entries.remove(&internal_id);
+
+            if !sender.send(BackendEvent::EnvelopeRemoved(
+                folder.to_owned(),
+                internal_id,
+            )) {
+                return Ok(());
+            }
+        }
+    }
+
+    Ok(())
+}
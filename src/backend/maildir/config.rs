@@ -3,10 +3,12 @@
 //! This module contains the representation of the Maildir backend
 //! configuration of the user account.
 
+use serde::Deserialize;
 use std::path::PathBuf;
 
 /// Represents the Maildir backend config.
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct MaildirConfig {
     /// Represents the Maildir root directory.
     pub root_dir: PathBuf,
@@ -1,5 +1,6 @@
 mod backend;
 mod config;
+pub mod event;
 pub mod id_mapper;
 
 #[cfg(feature = "imap-backend")]
@@ -8,13 +9,18 @@ pub mod maildir;
 #[cfg(feature = "notmuch-backend")]
 pub mod notmuch;
 
+pub(crate) use self::backend::SyncSide;
 pub use self::backend::{
-    Backend, BackendBuilder, BackendSyncBuilder, BackendSyncProgressEvent, Error, Result,
+    Backend, BackendBuilder, BackendSyncBuilder, BackendSyncChanges, BackendSyncMode,
+    BackendSyncProgressEvent, BackendSyncReport, BackendSyncState, Error, Result,
 };
 pub use self::config::BackendConfig;
+pub use self::event::{
+    BackendEvent, BackendEventSender, BackendSubscription, BackendSubscriptionHandle,
+};
 pub use self::id_mapper::IdMapper;
 #[cfg(feature = "imap-backend")]
-pub use self::imap::{ImapBackend, ImapBackendBuilder, ImapConfig};
+pub use self::imap::{ImapBackend, ImapBackendBuilder, ImapConfig, SessionPoolMetrics};
 pub use self::maildir::{MaildirBackend, MaildirConfig};
 #[cfg(feature = "notmuch-backend")]
 pub use self::notmuch::{NotmuchBackend, NotmuchConfig};
@@ -5,9 +5,12 @@ use thiserror::Error;
 
 use crate::{
     account, backend, email,
-    envelope::notmuch::{envelope, envelopes},
+    envelope::{
+        notmuch::{envelope, envelopes},
+        search_query,
+    },
     id_mapper, AccountConfig, Backend, Emails, Envelope, Envelopes, Flag, Flags, Folder, Folders,
-    IdMapper, NotmuchConfig,
+    IdMapper, NotmuchConfig, SearchQuery, SortCriteria,
 };
 
 #[derive(Debug, Error)]
@@ -53,6 +56,8 @@ pub enum Error {
     BuildQueryError(#[source] notmuch::Error),
     #[error("cannot search notmuch envelopes")]
     SearchEnvelopesError(#[source] notmuch::Error),
+    #[error("cannot compile notmuch search query")]
+    CompileSearchQueryError(#[source] search_query::Error),
     #[error("cannot get notmuch envelopes at page {0}")]
     GetEnvelopesOutOfBoundsError(usize),
     #[error("cannot add notmuch mailbox: feature not implemented")]
@@ -147,37 +152,43 @@ impl<'a> NotmuchBackend<'a> {
         Ok(id_mapper)
     }
 
-    fn _search_envelopes(&self, query: &str, page_size: usize, page: usize) -> Result<Envelopes> {
+    fn _search_envelopes(
+        &self,
+        query: &str,
+        sort: &SortCriteria,
+        page_size: usize,
+        page: usize,
+    ) -> Result<Envelopes> {
         let id_mapper = self.id_mapper()?;
-        let mut envelopes = self.with_db(|db| {
+        let mut entries = self.with_db(|db| {
             let query_builder = db.create_query(query).map_err(Error::BuildQueryError)?;
-            envelopes::from_raws(
+            envelopes::from_raws_with_sort_keys(
                 query_builder
                     .search_messages()
                     .map_err(Error::SearchEnvelopesError)?,
             )
         })?;
-        trace!("envelopes: {envelopes:#?}");
+        trace!("envelopes: {entries:#?}");
 
         // Calculates pagination boundaries.
         let page_begin = page * page_size;
         trace!("page begin: {:?}", page_begin);
-        if page_begin > envelopes.len() {
+        if page_begin > entries.len() {
             return Err(Error::GetEnvelopesOutOfBoundsError(page_begin + 1))?;
         }
-        let page_end = envelopes.len().min(page_begin + page_size);
+        let page_end = entries.len().min(page_begin + page_size);
         trace!("page end: {:?}", page_end);
 
-        envelopes.sort_by(|a, b| b.date.partial_cmp(&a.date).unwrap());
-        *envelopes = envelopes[page_begin..page_end]
+        sort.sort_by_keys(&mut entries, |(keys, _)| keys);
+        let envelopes = entries[page_begin..page_end]
             .iter()
-            .map(|envelope| {
+            .map(|(_, envelope)| {
                 Ok(Envelope {
                     id: id_mapper.get_id(&envelope.internal_id)?,
                     ..envelope.clone()
                 })
             })
-            .collect::<Result<Vec<_>>>()?;
+            .collect::<Result<Envelopes>>()?;
 
         Ok(envelopes)
     }
@@ -188,6 +199,10 @@ impl<'a> Backend for NotmuchBackend<'a> {
         self.account_config.name.clone()
     }
 
+    fn endpoint(&self) -> String {
+        format!("notmuch://{}", self.backend_config.db_path.display())
+    }
+
     fn add_folder(&self, _folder: &str) -> backend::Result<()> {
         Err(Error::AddMboxUnimplementedError)?
     }
@@ -266,7 +281,8 @@ impl<'a> Backend for NotmuchBackend<'a> {
             .unwrap_or_else(|_| String::from("all"));
         trace!("query: {query}");
 
-        let envelopes = self._search_envelopes(&query, page_size, page)?;
+        let envelopes =
+            self._search_envelopes(&query, &SortCriteria::default(), page_size, page)?;
         trace!("envelopes: {envelopes:#?}");
 
         Ok(envelopes)
@@ -275,23 +291,30 @@ impl<'a> Backend for NotmuchBackend<'a> {
     fn search_envelopes(
         &self,
         virtual_folder: &str,
-        query: &str,
-        _sort: &str,
+        query: &SearchQuery,
+        sort: &SortCriteria,
         page_size: usize,
         page: usize,
     ) -> backend::Result<Envelopes> {
         info!("searching notmuch envelopes from virtual folder {virtual_folder}");
 
-        let query = if query.is_empty() {
-            self.account_config
-                .folder_alias(virtual_folder)
-                .unwrap_or_else(|_| String::from("all"))
-        } else {
-            query.to_owned()
+        let folder_query = self.account_config.folder_alias(virtual_folder);
+        let query = match (query, folder_query) {
+            (SearchQuery::All, Ok(folder_query)) => folder_query,
+            (SearchQuery::All, Err(_)) => String::from("all"),
+            (query, Ok(folder_query)) => format!(
+                "({folder_query}) and {}",
+                query
+                    .to_notmuch_query()
+                    .map_err(Error::CompileSearchQueryError)?
+            ),
+            (query, Err(_)) => query
+                .to_notmuch_query()
+                .map_err(Error::CompileSearchQueryError)?,
         };
         trace!("query: {query}");
 
-        let envelopes = self._search_envelopes(&query, page_size, page)?;
+        let envelopes = self._search_envelopes(&query, sort, page_size, page)?;
         trace!("envelopes: {envelopes:#?}");
 
         Ok(envelopes)
@@ -3,11 +3,13 @@
 //! This module contains the representation of the notmuch backend
 //! configuration of the user account.
 
+use serde::Deserialize;
 use std::path::PathBuf;
 
 /// Represents the Notmuch backend config.
 #[cfg(feature = "notmuch-backend")]
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct NotmuchConfig {
     /// Represents the notmuch database path.
     pub db_path: PathBuf,
@@ -0,0 +1,501 @@
+//! Doctor module.
+//!
+//! This module contains the diagnostics of an account configuration.
+//! Instead of failing late with obscure errors, the doctor resolves
+//! the secrets, connects and authenticates to the backend and to the
+//! sender, checks that the folder aliases exist and that the sync
+//! directory is writable, then gathers the results in a report.
+
+use log::{debug, info};
+use std::{borrow::Cow, env, error, fmt, fs, path::Path};
+
+use crate::{
+    error::error_chain, AccountConfig, Backend, BackendConfig, EmailSender, Lmtp, MaildirBackend,
+    DEFAULT_DRAFTS_FOLDER, DEFAULT_INBOX_FOLDER, DEFAULT_SENT_FOLDER, DEFAULT_TRASH_FOLDER,
+};
+
+#[cfg(feature = "imap-backend")]
+use crate::{ImapBackendBuilder, ImapConfig};
+
+#[cfg(feature = "notmuch-backend")]
+use crate::NotmuchBackend;
+
+#[cfg(feature = "smtp-sender")]
+use crate::Smtp;
+
+/// Represents the name of the file written in the sync directory to
+/// check that it is writable.
+const SYNC_DIR_PROBE_FILE_NAME: &str = ".himalaya-doctor";
+
+/// Represents the status of a check.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum DoctorStatus {
+    Ok,
+    /// The configuration works, but may not behave as expected.
+    Warning,
+    /// The configuration does not work.
+    Error,
+}
+
+impl DoctorStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+}
+
+impl fmt::Display for DoctorStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Represents the result of one check.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DoctorCheck {
+    /// Represents the checked part of the configuration, for example
+    /// `backend.passwd` or `folder.sent`.
+    pub key: String,
+    pub status: DoctorStatus,
+    pub message: String,
+}
+
+impl fmt::Display for DoctorCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}: {}", self.status, self.key, self.message)
+    }
+}
+
+/// Represents the report of the doctor.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct DoctorReport {
+    /// Represents the checks, in the order they were made.
+    pub checks: Vec<DoctorCheck>,
+    /// Represents the capabilities announced by the backend server.
+    pub backend_capabilities: Vec<String>,
+    /// Represents the capabilities announced by the sender server.
+    pub sender_capabilities: Vec<String>,
+}
+
+impl DoctorReport {
+    /// Returns `true` if no check failed. Warnings are tolerated.
+    pub fn is_ok(&self) -> bool {
+        self.errors().next().is_none()
+    }
+
+    /// Returns the failed checks.
+    pub fn errors(&self) -> impl Iterator<Item = &DoctorCheck> {
+        self.checks
+            .iter()
+            .filter(|check| check.status == DoctorStatus::Error)
+    }
+
+    /// Returns the checks that passed with a warning.
+    pub fn warnings(&self) -> impl Iterator<Item = &DoctorCheck> {
+        self.checks
+            .iter()
+            .filter(|check| check.status == DoctorStatus::Warning)
+    }
+
+    /// Gets the check matching the given key.
+    pub fn get(&self, key: &str) -> Option<&DoctorCheck> {
+        self.checks.iter().find(|check| check.key == key)
+    }
+
+    fn push<K: ToString, M: ToString>(&mut self, key: K, status: DoctorStatus, message: M) {
+        let check = DoctorCheck {
+            key: key.to_string(),
+            status,
+            message: message.to_string(),
+        };
+        debug!("{check}");
+        self.checks.push(check);
+    }
+
+    fn ok<K: ToString, M: ToString>(&mut self, key: K, message: M) {
+        self.push(key, DoctorStatus::Ok, message)
+    }
+
+    fn warning<K: ToString, M: ToString>(&mut self, key: K, message: M) {
+        self.push(key, DoctorStatus::Warning, message)
+    }
+
+    fn error<K: ToString>(&mut self, key: K, err: &dyn error::Error) {
+        self.push(key, DoctorStatus::Error, error_chain(err))
+    }
+
+    /// Reports the result of the resolution of a secret. Returns
+    /// `false` if the secret cannot be resolved.
+    fn secret<T, E: error::Error>(&mut self, key: &str, res: Result<T, E>) -> bool {
+        match res {
+            Ok(_) => {
+                self.ok(key, "secret resolved");
+                true
+            }
+            Err(err) => {
+                self.error(key, &err);
+                false
+            }
+        }
+    }
+}
+
+impl fmt::Display for DoctorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            writeln!(f, "{check}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Represents the error of a check that does not come from the
+/// library.
+#[derive(Debug)]
+struct DoctorError(String);
+
+impl fmt::Display for DoctorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl error::Error for DoctorError {}
+
+/// Diagnoses the configuration of an account. The backend and the
+/// sender default to the ones of the account configuration.
+pub struct Doctor<'a> {
+    account_config: &'a AccountConfig,
+    backend_config: &'a BackendConfig,
+    email_sender: &'a EmailSender,
+}
+
+impl<'a> Doctor<'a> {
+    pub fn new(account_config: &'a AccountConfig) -> Self {
+        Self {
+            account_config,
+            backend_config: &account_config.backend,
+            email_sender: &account_config.email_sender,
+        }
+    }
+
+    pub fn backend_config(mut self, backend_config: &'a BackendConfig) -> Self {
+        self.backend_config = backend_config;
+        self
+    }
+
+    pub fn email_sender(mut self, email_sender: &'a EmailSender) -> Self {
+        self.email_sender = email_sender;
+        self
+    }
+
+    /// Runs all the checks. Checks depending on a failed one are
+    /// skipped: folders are not checked if the backend cannot be
+    /// reached, for example.
+    pub fn check(&self) -> DoctorReport {
+        info!("diagnosing account {}", self.account_config.name);
+
+        let mut report = DoctorReport::default();
+
+        match self.account_config.addr() {
+            Ok(addr) => report.ok("email", format!("address {addr} is valid")),
+            Err(err) => report.error("email", &err),
+        }
+
+        self.check_sync_dir(&mut report);
+        self.check_backend(&mut report);
+        self.check_sender(&mut report);
+
+        report
+    }
+
+    fn check_sync_dir(&self, report: &mut DoctorReport) {
+        let key = "sync-dir";
+
+        let (dir, configured) = match &self.account_config.sync_dir {
+            Some(dir) => (dir.clone(), true),
+            None => match self.account_config.default_sync_dir() {
+                Ok(dir) => (dir, false),
+                Err(err) => return report.error(key, &err),
+            },
+        };
+        let path = dir.display();
+
+        if !dir.is_dir() {
+            if configured {
+                let err = DoctorError(format!("sync directory {path} does not exist"));
+                report.error(key, &err);
+            } else {
+                report.warning(
+                    key,
+                    format!("sync directory {path} does not exist yet, it will be created"),
+                );
+            }
+            return;
+        }
+
+        let probe = dir.join(SYNC_DIR_PROBE_FILE_NAME);
+        match fs::write(&probe, b"").and_then(|()| fs::remove_file(&probe)) {
+            Ok(()) => report.ok(key, format!("sync directory {path} is writable")),
+            Err(err) => {
+                let err = DoctorError(format!("sync directory {path} is not writable: {err}"));
+                report.error(key, &err);
+            }
+        }
+    }
+
+    fn check_backend(&self, report: &mut DoctorReport) {
+        let key = "backend";
+
+        let backend: Box<dyn Backend> = match self.backend_config {
+            BackendConfig::None => {
+                return report.warning(key, "no backend defined");
+            }
+            BackendConfig::Maildir(config) => {
+                // the backend creates the missing directories, which
+                // would hide a wrong path
+                if !config.root_dir.is_dir() {
+                    let path = config.root_dir.display();
+                    let err = DoctorError(format!("maildir directory {path} does not exist"));
+                    return report.error(key, &err);
+                }
+
+                let backend =
+                    MaildirBackend::new(Cow::Borrowed(self.account_config), Cow::Borrowed(config));
+                match backend {
+                    Ok(backend) => Box::new(backend),
+                    Err(err) => return report.error(key, &err),
+                }
+            }
+            #[cfg(feature = "imap-backend")]
+            BackendConfig::Imap(config) => match self.check_imap(report, config) {
+                Some(backend) => backend,
+                None => return,
+            },
+            #[cfg(feature = "notmuch-backend")]
+            BackendConfig::Notmuch(config) => {
+                let backend =
+                    NotmuchBackend::new(Cow::Borrowed(self.account_config), Cow::Borrowed(config));
+                match backend {
+                    Ok(backend) => Box::new(backend),
+                    Err(err) => return report.error(key, &err),
+                }
+            }
+        };
+
+        report.ok(key, format!("{} backend is reachable", backend.name()));
+        self.check_folders(report, backend.as_ref());
+
+        if let Err(err) = backend.close() {
+            debug!("cannot close backend: {err}");
+        }
+    }
+
+    #[cfg(feature = "imap-backend")]
+    fn check_imap(
+        &self,
+        report: &mut DoctorReport,
+        config: &ImapConfig,
+    ) -> Option<Box<dyn Backend + 'a>> {
+        let resolved = match &config.oauth2 {
+            Some(oauth2) => report.secret("backend.oauth2", oauth2.access_token()),
+            None => report.secret("backend.passwd", config.passwd()),
+        };
+        if !resolved {
+            return None;
+        }
+
+        // the doctor reports failures instead of retrying
+        let config = ImapConfig {
+            retry_max: Some(0),
+            ..config.clone()
+        };
+        let backend =
+            ImapBackendBuilder::new().build(Cow::Borrowed(self.account_config), Cow::Owned(config));
+        let backend = match backend {
+            Ok(backend) => backend,
+            Err(err) => {
+                report.error("backend", &err);
+                return None;
+            }
+        };
+
+        match backend.capabilities() {
+            Ok(capabilities) => report.backend_capabilities = capabilities,
+            Err(err) => report.warning("backend.capabilities", error_chain(&err)),
+        }
+
+        Some(Box::new(backend))
+    }
+
+    fn check_folders(&self, report: &mut DoctorReport, backend: &dyn Backend) {
+        let folders = match backend.list_folders() {
+            Ok(folders) => folders,
+            Err(err) => return report.error("folders", &err),
+        };
+
+        let aliases = [
+            ("inbox", DEFAULT_INBOX_FOLDER),
+            ("sent", DEFAULT_SENT_FOLDER),
+            ("drafts", DEFAULT_DRAFTS_FOLDER),
+            ("trash", DEFAULT_TRASH_FOLDER),
+        ];
+
+        for (name, folder) in aliases {
+            let key = format!("folder.{name}");
+
+            let alias = match self.account_config.folder_alias(folder) {
+                Ok(alias) => alias,
+                Err(err) => {
+                    report.error(key, &err);
+                    continue;
+                }
+            };
+
+            // the inbox name is case-insensitive
+            let exists = folders.iter().any(|folder| {
+                folder.name == alias
+                    || (alias.eq_ignore_ascii_case(DEFAULT_INBOX_FOLDER)
+                        && folder.name.eq_ignore_ascii_case(DEFAULT_INBOX_FOLDER))
+            });
+
+            if exists {
+                report.ok(key, format!("folder {alias} exists"));
+            } else if name == "inbox" {
+                report.error(key, &DoctorError(format!("folder {alias} not found")));
+            } else {
+                report.warning(
+                    key,
+                    format!("folder {alias} not found, check the folder-aliases option"),
+                );
+            }
+        }
+    }
+
+    fn check_sender(&self, report: &mut DoctorReport) {
+        let key = "sender";
+
+        match self.email_sender {
+            EmailSender::None => report.warning(key, "no sender defined"),
+            #[cfg(feature = "smtp-sender")]
+            EmailSender::Smtp(config) => {
+                let resolved = match &config.oauth2 {
+                    Some(oauth2) => report.secret("sender.oauth2", oauth2.access_token()),
+                    None => report.secret("sender.passwd", config.credentials()),
+                };
+                if !resolved {
+                    return;
+                }
+
+                match Smtp::new(self.account_config, config).capabilities() {
+                    Ok(capabilities) => {
+                        report.sender_capabilities = capabilities;
+                        report.ok(
+                            key,
+                            format!("authenticated to {}:{}", config.host, config.port),
+                        );
+                    }
+                    Err(err) => report.error(key, &err),
+                }
+            }
+            EmailSender::Sendmail(config) => {
+                let program = config.cmd.split_whitespace().next().unwrap_or_default();
+                if find_program(program) {
+                    report.ok(key, format!("command {program} found"));
+                } else {
+                    let err = DoctorError(format!("command {program} not found"));
+                    report.error(key, &err);
+                }
+            }
+            EmailSender::Lmtp(config) => {
+                match Lmtp::new(self.account_config, config).capabilities() {
+                    Ok(capabilities) => {
+                        report.sender_capabilities = capabilities;
+                        report.ok(key, "lmtp server is reachable");
+                    }
+                    Err(err) => report.error(key, &err),
+                }
+            }
+        }
+    }
+}
+
+/// Checks that the given program exists, either as a path or in one
+/// of the directories of the `PATH` environment variable.
+fn find_program(program: &str) -> bool {
+    if program.is_empty() {
+        return false;
+    }
+
+    if program.contains('/') {
+        return Path::new(program).is_file();
+    }
+
+    env::var_os("PATH")
+        .map(|path| env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod doctor {
+    use std::fs;
+
+    use crate::{
+        AccountConfig, BackendConfig, DoctorStatus, EmailSender, MaildirConfig, SendmailConfig,
+    };
+
+    use super::Doctor;
+
+    #[test]
+    fn maildir_and_sendmail() {
+        let dir = tempfile::tempdir().unwrap();
+        let mdir = dir.path().join("mail");
+        for folder in [".Sent", ".Drafts"] {
+            for subdir in ["cur", "new", "tmp"] {
+                fs::create_dir_all(mdir.join(folder).join(subdir)).unwrap();
+            }
+        }
+
+        let account_config = AccountConfig {
+            name: "account".into(),
+            email: "alice@localhost".into(),
+            sync_dir: Some(dir.path().to_owned()),
+            backend: BackendConfig::Maildir(MaildirConfig {
+                root_dir: mdir.clone(),
+            }),
+            email_sender: EmailSender::Sendmail(SendmailConfig {
+                cmd: "cat -".into(),
+            }),
+            ..AccountConfig::default()
+        };
+
+        let report = Doctor::new(&account_config).check();
+        assert!(report.is_ok(), "{report}");
+        assert_eq!(report.get("sync-dir").unwrap().status, DoctorStatus::Ok);
+        assert_eq!(report.get("folder.inbox").unwrap().status, DoctorStatus::Ok);
+        assert_eq!(report.get("folder.sent").unwrap().status, DoctorStatus::Ok);
+        assert_eq!(report.get("sender").unwrap().status, DoctorStatus::Ok);
+
+        // the trash folder does not exist
+        let warnings: Vec<_> = report.warnings().map(|check| check.key.as_str()).collect();
+        assert_eq!(warnings, vec!["folder.trash"]);
+
+        let account_config = AccountConfig {
+            sync_dir: Some(dir.path().join("missing")),
+            backend: BackendConfig::Maildir(MaildirConfig {
+                root_dir: dir.path().join("missing"),
+            }),
+            email_sender: EmailSender::Sendmail(SendmailConfig {
+                cmd: "himalaya-missing-command -t".into(),
+            }),
+            ..account_config
+        };
+
+        let report = Doctor::new(&account_config).check();
+        let errors: Vec<_> = report.errors().map(|check| check.key.as_str()).collect();
+        assert_eq!(errors, vec!["sync-dir", "backend", "sender"]);
+    }
+}
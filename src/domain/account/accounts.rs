@@ -0,0 +1,358 @@
+//! Accounts config module.
+//!
+//! This module contains the representation of the configuration file,
+//! holding one or many accounts. The file is written in [TOML]:
+//!
+//! ```toml
+//! # Account used when none is given. Optional when the file
+//! # contains only one account.
+//! default-account = "personal"
+//!
+//! [accounts.personal]
+//! email = "alice@example.com"             # required
+//! display-name = "Alice"
+//! signature-delim = "-- \n"
+//! signature = "~/.signature"              # path or raw signature
+//! downloads-dir = "~/Downloads"
+//! folder-listing-page-size = 10
+//! folder-aliases = { sent = "Sent Items", trash = "Deleted" }
+//! email-listing-page-size = 10
+//! email-reading-headers = ["From", "To", "Subject"]
+//! email-reading-format = "flowed"         # or "auto", or { fixed = 80 }
+//! email-reading-verify-cmd = "gpg --verify -q"
+//! email-reading-decrypt-cmd = "gpg -dq"
+//! email-writing-sign-cmd = "gpg -o - -saq"
+//! email-writing-encrypt-cmd = "gpg -o - -eqar <recipient>"
+//! email-writing-headers = ["From", "To", "Subject"]
+//! email-sending-save-copy = true
+//! email-hooks.pre-send = "cat"
+//! sync = true
+//! sync-dir = "~/.cache/himalaya/personal"
+//!
+//! # One of backend.maildir, backend.imap or backend.notmuch.
+//! [accounts.personal.backend.imap]
+//! host = "imap.example.com"               # required
+//! port = 993                              # required
+//! login = "alice@example.com"             # required
+//! passwd.cmd = "pass show example"        # see below
+//! ssl = true
+//! starttls = false
+//! insecure = false
+//! notify-cmd = "notify-send <subject>"
+//! notify-query = "NEW"
+//! watch-cmds = ["mbsync -a"]
+//! retry-max = 3
+//! retry-delay = 500
+//!
+//! # One of sender.smtp, sender.sendmail or sender.lmtp.
+//! [accounts.personal.sender.smtp]
+//! host = "smtp.example.com"               # required
+//! port = 465                              # required
+//! login = "alice@example.com"             # required
+//! passwd.env = "EXAMPLE_PASSWD"
+//! ssl = true
+//! starttls = false
+//! insecure = false
+//! dsn = { notify = ["failure", "delay"], ret = "headers" }
+//!
+//! [accounts.work]
+//! email = "alice@work.example.com"
+//!
+//! [accounts.work.backend.maildir]
+//! root-dir = "~/Mail/work"
+//!
+//! [accounts.work.sender.sendmail]
+//! cmd = "msmtp -t"
+//! ```
+//!
+//! Other backends and senders are configured this way:
+//!
+//! ```toml
+//! [accounts.example.backend.notmuch]
+//! db-path = "~/Mail"
+//!
+//! [accounts.example.sender.lmtp]
+//! host = "localhost"                      # or socket = "/run/lmtp"
+//! port = 24
+//! lhlo-name = "localhost"
+//! timeout = 60                            # seconds, 0 disables it
+//! ```
+//!
+//! Passwords are given as a [`Secret`](crate::Secret) (`raw`, `cmd`,
+//! `env`, `file` or `encrypted-file`). The IMAP backend and the SMTP
+//! sender can use OAuth 2.0 instead:
+//!
+//! ```toml
+//! [accounts.example.backend.imap.oauth2]
+//! method = "xoauth2"                      # or "oauthbearer"
+//! client-id = "client-id"                 # required
+//! client-secret.cmd = "pass show example-client-secret"
+//! token-url = "https://example.com/token" # required
+//! refresh-token.cmd = "pass show example-refresh-token"
+//! access-token = "token"
+//! ```
+//!
+//! Unknown keys are rejected, and errors point at the offending key.
+//!
+//! [TOML]: https://toml.io
+
+use lettre::Address;
+use serde::Deserialize;
+use std::{collections::HashMap, fs, io, path::PathBuf, result};
+use thiserror::Error;
+
+use crate::{AccountConfig, BackendConfig, EmailSender};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("cannot read config file {1}")]
+    ReadConfigFileError(#[source] io::Error, PathBuf),
+    #[error("cannot parse config file {1}")]
+    ParseConfigFileError(#[source] toml::de::Error, PathBuf),
+    #[error("cannot parse config")]
+    ParseConfigError(#[source] toml::de::Error),
+    #[error("invalid config key {0}: {1}")]
+    InvalidConfigKeyError(String, String),
+    #[error("cannot find account {0}")]
+    GetAccountNotFoundError(String),
+    #[error("cannot find default account: default-account is not defined")]
+    GetDefaultAccountNotDefinedError,
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents the configuration file, holding one or many accounts.
+/// See the [module documentation](self) for the schema.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct AccountsConfig {
+    /// Represents the name of the account used when none is given.
+    /// Can be omitted when there is only one account.
+    pub default_account: Option<String>,
+    /// Represents the accounts, by name.
+    #[serde(default)]
+    pub accounts: HashMap<String, AccountConfig>,
+}
+
+impl AccountsConfig {
+    /// Parses the given TOML configuration, then validates it.
+    pub fn from_toml_str(toml: &str) -> Result<Self> {
+        let config: Self = toml::from_str(toml).map_err(Error::ParseConfigError)?;
+        config.init()
+    }
+
+    /// Reads and parses the given TOML configuration file, then
+    /// validates it.
+    pub fn from_toml_file<P: Into<PathBuf>>(path: P) -> Result<Self> {
+        let path = path.into();
+        let toml = fs::read_to_string(&path)
+            .map_err(|err| Error::ReadConfigFileError(err, path.clone()))?;
+        let config: Self =
+            toml::from_str(&toml).map_err(|err| Error::ParseConfigFileError(err, path))?;
+        config.init()
+    }
+
+    /// Gets the account matching the given name, or the default
+    /// account if no name is given.
+    pub fn account(&self, name: Option<&str>) -> Result<&AccountConfig> {
+        let name = match (name, &self.default_account) {
+            (Some(name), _) => name,
+            (None, Some(name)) => name.as_str(),
+            (None, None) if self.accounts.len() == 1 => {
+                return Ok(self.accounts.values().next().unwrap());
+            }
+            (None, None) => return Err(Error::GetDefaultAccountNotDefinedError),
+        };
+
+        self.accounts
+            .get(name)
+            .ok_or_else(|| Error::GetAccountNotFoundError(name.to_owned()))
+    }
+
+    /// Names the accounts after their key, then validates them.
+    fn init(mut self) -> Result<Self> {
+        for (name, account) in self.accounts.iter_mut() {
+            account.name = name.clone();
+        }
+
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Checks the constraints that cannot be expressed by the types
+    /// of the configuration.
+    fn validate(&self) -> Result<()> {
+        let invalid =
+            |key: String, reason: &str| Err(Error::InvalidConfigKeyError(key, reason.to_owned()));
+
+        if self.accounts.is_empty() {
+            return invalid("accounts".into(), "at least one account must be defined");
+        }
+
+        if let Some(name) = &self.default_account {
+            if !self.accounts.contains_key(name) {
+                return invalid(
+                    "default-account".into(),
+                    &format!("account {name} is not defined"),
+                );
+            }
+        }
+
+        for (name, account) in &self.accounts {
+            let key = |key: &str| format!("accounts.{name}.{key}");
+
+            if account.email.parse::<Address>().is_err() {
+                return invalid(key("email"), "invalid email address");
+            }
+
+            match &account.backend {
+                #[cfg(feature = "imap-backend")]
+                BackendConfig::Imap(config) if config.passwd.is_undefined() => {
+                    if config.oauth2.is_none() {
+                        return invalid(
+                            key("backend.imap.passwd"),
+                            "either passwd or oauth2 must be defined",
+                        );
+                    }
+                }
+                _ => (),
+            }
+
+            match &account.email_sender {
+                #[cfg(feature = "smtp-sender")]
+                EmailSender::Smtp(config) if config.passwd.is_undefined() => {
+                    if config.oauth2.is_none() {
+                        return invalid(
+                            key("sender.smtp.passwd"),
+                            "either passwd or oauth2 must be defined",
+                        );
+                    }
+                }
+                EmailSender::Lmtp(config) if config.socket.is_none() => {
+                    if config.host.is_empty() || config.port == 0 {
+                        return invalid(
+                            key("sender.lmtp.host"),
+                            "either host and port or socket must be defined",
+                        );
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod accounts {
+    use std::path::PathBuf;
+
+    use crate::{
+        BackendConfig, EmailSender, EmailTextPlainFormat, MaildirConfig, Secret, SecretSource,
+    };
+
+    use super::{AccountsConfig, Error};
+
+    const CONFIG: &str = r#"
+        default-account = "personal"
+
+        [accounts.personal]
+        email = "alice@localhost"
+        email-reading-format = { fixed = 80 }
+        folder-aliases = { sent = "Sent Items" }
+
+        [accounts.personal.backend.imap]
+        host = "localhost"
+        port = 993
+        login = "alice"
+        passwd.cmd = "echo password"
+
+        [accounts.personal.sender.smtp]
+        host = "localhost"
+        port = 465
+        login = "alice"
+        passwd.encrypted-file = { path = "passwd.gpg", decrypt-cmd = "gpg -dq" }
+        dsn = { notify = ["failure"], ret = "headers" }
+
+        [accounts.work]
+        email = "alice@work.localhost"
+        backend.maildir.root-dir = "/tmp/work"
+        sender.sendmail.cmd = "msmtp -t"
+    "#;
+
+    #[test]
+    fn multi_accounts() {
+        let config = AccountsConfig::from_toml_str(CONFIG).unwrap();
+
+        let personal = config.account(None).unwrap();
+        assert_eq!(personal.name, "personal");
+        assert_eq!(
+            personal.email_reading_format,
+            EmailTextPlainFormat::Fixed(80)
+        );
+        assert_eq!(personal.sent_folder_alias().unwrap(), "Sent Items");
+        match (&personal.backend, &personal.email_sender) {
+            (BackendConfig::Imap(imap), EmailSender::Smtp(smtp)) => {
+                assert_eq!(imap.passwd, Secret::new_cmd("echo password"));
+                assert_eq!(
+                    smtp.passwd.source,
+                    SecretSource::EncryptedFile {
+                        path: PathBuf::from("passwd.gpg"),
+                        decrypt_cmd: "gpg -dq".into(),
+                    }
+                );
+                assert_eq!(smtp.dsn.as_ref().unwrap().notify_param(), "FAILURE");
+            }
+            configs => panic!("unexpected backend and sender {configs:?}"),
+        }
+
+        let work = config.account(Some("work")).unwrap();
+        assert_eq!(
+            work.backend,
+            BackendConfig::Maildir(MaildirConfig {
+                root_dir: "/tmp/work".into(),
+            })
+        );
+        assert!(config.account(Some("unknown")).is_err());
+    }
+
+    #[test]
+    fn unknown_key() {
+        let config = CONFIG.replace(
+            "login = \"alice\"\n        passwd.cmd",
+            "logni = \"alice\"\n        passwd.cmd",
+        );
+        let err = AccountsConfig::from_toml_str(&config).unwrap_err();
+
+        let Error::ParseConfigError(err) = err else {
+            panic!("unexpected error {err:?}");
+        };
+        let err = err.to_string();
+        assert!(err.contains("line 12"), "{err}");
+        assert!(err.contains("unknown field `logni`"), "{err}");
+    }
+
+    #[test]
+    fn invalid_key() {
+        let config = CONFIG.replace("alice@work.localhost", "alice");
+        assert!(matches!(
+            AccountsConfig::from_toml_str(&config),
+            Err(Error::InvalidConfigKeyError(key, _)) if key == "accounts.work.email"
+        ));
+
+        let config = CONFIG.replace("passwd.cmd = \"echo password\"", "");
+        assert!(matches!(
+            AccountsConfig::from_toml_str(&config),
+            Err(Error::InvalidConfigKeyError(key, _)) if key == "accounts.personal.backend.imap.passwd"
+        ));
+
+        let config = CONFIG.replace("default-account = \"personal\"", "");
+        assert!(matches!(
+            AccountsConfig::from_toml_str(&config)
+                .unwrap()
+                .account(None),
+            Err(Error::GetDefaultAccountNotDefinedError)
+        ));
+    }
+}
@@ -6,11 +6,12 @@
 use dirs::data_dir;
 use lettre::{address::AddressError, message::Mailbox};
 use log::warn;
+use serde::Deserialize;
 use shellexpand;
 use std::{collections::HashMap, env, ffi::OsStr, fs, io, path::PathBuf, result};
 use thiserror::Error;
 
-use crate::{process, EmailHooks, EmailSender, EmailTextPlainFormat};
+use crate::{process, BackendConfig, EmailHooks, EmailSender, EmailTextPlainFormat};
 
 pub const DEFAULT_PAGE_SIZE: usize = 10;
 pub const DEFAULT_SIGNATURE_DELIM: &str = "-- \n";
@@ -47,10 +48,15 @@ pub enum Error {
 
 pub type Result<T> = result::Result<T, Error>;
 
-/// Represents the configuration of the user account.
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+/// Represents the configuration of the user account. See
+/// [`AccountsConfig`](crate::AccountsConfig) for the configuration
+/// file schema.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct AccountConfig {
-    /// Represents the name of the current user account.
+    /// Represents the name of the current user account. When
+    /// deserialized, the name is the key of the account table.
+    #[serde(skip)]
     pub name: String,
     /// Represents the email address of the user.
     pub email: String,
@@ -66,6 +72,7 @@ pub struct AccountConfig {
     /// Represents the page size when listing folders.
     pub folder_listing_page_size: Option<usize>,
     /// Represents the folder aliases hash map.
+    #[serde(default)]
     pub folder_aliases: HashMap<String, String>,
 
     /// Represents the page size when listing emails.
@@ -75,6 +82,7 @@ pub struct AccountConfig {
     pub email_reading_headers: Option<Vec<String>>,
     /// Represents the text/plain format as defined in the
     /// [RFC 2646](https://www.ietf.org/rfc/rfc2646.txt).
+    #[serde(default)]
     pub email_reading_format: EmailTextPlainFormat,
     /// Represents the command used to verify an email.
     pub email_reading_verify_cmd: Option<String>,
@@ -88,16 +96,28 @@ pub struct AccountConfig {
     /// them (new/reply/forward).
     pub email_writing_headers: Option<Vec<String>>,
     /// Represents the email sender provider.
+    #[serde(default, rename = "sender")]
     pub email_sender: EmailSender,
+    /// Saves a copy of sent emails to the sent folder. Defaults to
+    /// `true`. Should be disabled when the server already saves sent
+    /// emails by itself (like Gmail does).
+    pub email_sending_save_copy: Option<bool>,
     /// Represents the email hooks.
+    #[serde(default)]
     pub email_hooks: EmailHooks,
 
     /// Enables the automatic synchronization of this account with a
     /// local Maildir backend.
+    #[serde(default)]
     pub sync: bool,
     /// Customizes the root directory where the Maildir cache is
     /// saved. Defaults to `$XDG_DATA_HOME/himalaya/<account-name>`.
     pub sync_dir: Option<PathBuf>,
+
+    /// Represents the backend configuration of the account, to be
+    /// given to [`BackendBuilder::build`](crate::BackendBuilder).
+    #[serde(default)]
+    pub backend: BackendConfig,
 }
 
 impl AccountConfig {
@@ -200,6 +220,10 @@ impl AccountConfig {
         self.email_listing_page_size.unwrap_or(DEFAULT_PAGE_SIZE)
     }
 
+    pub fn email_sending_save_copy(&self) -> bool {
+        self.email_sending_save_copy.unwrap_or(true)
+    }
+
     pub fn email_reading_headers(&self) -> Vec<String> {
         self.email_reading_headers
             .as_ref()
@@ -242,33 +266,34 @@ impl AccountConfig {
     }
 
     pub fn sync(&self) -> bool {
-        self.sync
-            && match self.sync_dir.as_ref() {
-                Some(dir) => dir.is_dir(),
-                None => data_dir()
-                    .map(|dir| dir.join("himalaya").join(&self.name).is_dir())
-                    .unwrap_or_default(),
-            }
+        self.sync && self.sync_dir_exists()
     }
 
     pub fn sync_dir_exists(&self) -> bool {
         match self.sync_dir.as_ref() {
             Some(dir) => dir.is_dir(),
-            None => data_dir()
-                .map(|dir| dir.join("himalaya").join(&self.name).is_dir())
+            None => self
+                .default_sync_dir()
+                .map(|dir| dir.is_dir())
                 .unwrap_or_default(),
         }
     }
 
+    /// Gets the sync directory used when none is configured, located
+    /// at `$XDG_DATA_HOME/himalaya/<account-name>`. The directory may
+    /// not exist yet.
+    pub fn default_sync_dir(&self) -> Result<PathBuf> {
+        data_dir()
+            .map(|dir| dir.join("himalaya").join(&self.name))
+            .ok_or(Error::GetXdgDataDirError)
+    }
+
     pub fn sync_dir(&self) -> Result<PathBuf> {
         match self.sync_dir.as_ref().filter(|dir| dir.is_dir()) {
             Some(dir) => Ok(dir.clone()),
             None => {
                 warn!("sync dir not set or invalid, falling back to $XDG_DATA_HOME/himalaya");
-                let sync_dir = data_dir()
-                    .map(|dir| dir.join("himalaya"))
-                    .ok_or(Error::GetXdgDataDirError)?
-                    .join(&self.name);
+                let sync_dir = self.default_sync_dir()?;
                 fs::create_dir_all(&sync_dir).map_err(Error::CreateXdgDataDirsError)?;
                 Ok(sync_dir)
             }
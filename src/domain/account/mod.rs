@@ -1,6 +1,8 @@
+pub mod accounts;
 pub mod config;
 
+pub use accounts::AccountsConfig;
 pub use config::{
     AccountConfig, DEFAULT_DRAFTS_FOLDER, DEFAULT_INBOX_FOLDER, DEFAULT_PAGE_SIZE,
-    DEFAULT_SENT_FOLDER, DEFAULT_SIGNATURE_DELIM,
+    DEFAULT_SENT_FOLDER, DEFAULT_SIGNATURE_DELIM, DEFAULT_TRASH_FOLDER,
 };
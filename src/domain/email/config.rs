@@ -2,12 +2,17 @@
 //!
 //! This module contains structures related to email configuration.
 
-use crate::SendmailConfig;
+use serde::Deserialize;
+
 #[cfg(feature = "smtp-sender")]
 use crate::SmtpConfig;
+use crate::{LmtpConfig, SendmailConfig};
 
-/// Represents the email sender provider.
-#[derive(Debug, Clone, Eq, PartialEq)]
+/// Represents the email sender provider. In a configuration file,
+/// the sender is given as a table named after the variant
+/// (`sender.smtp`, `sender.sendmail` or `sender.lmtp`).
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum EmailSender {
     None,
     #[cfg(feature = "smtp-sender")]
@@ -15,6 +20,9 @@ pub enum EmailSender {
     Smtp(SmtpConfig),
     /// Represents the sendmail command.
     Sendmail(SendmailConfig),
+    /// Represents the LMTP client, delivering emails to a local
+    /// server.
+    Lmtp(LmtpConfig),
 }
 
 impl Default for EmailSender {
@@ -26,7 +34,8 @@ impl Default for EmailSender {
 /// Represents the text/plain format as defined in the [RFC2646].
 ///
 /// [RFC2646]: https://www.ietf.org/rfc/rfc2646.txt
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum EmailTextPlainFormat {
     /// Makes the content fit its container.
     Auto,
@@ -44,7 +53,8 @@ impl Default for EmailTextPlainFormat {
 
 /// Represents the email hooks. Useful for doing extra email
 /// processing before or after sending it.
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct EmailHooks {
     /// Represents the hook called just before sending an email.
     pub pre_send: Option<String>,
@@ -222,10 +222,14 @@ impl Email<'_> {
                     if in_pgp_signed_part {
                         if let Some(ref verify_cmd) = config.email_reading_verify_cmd {
                             let signature = part.get_body_raw().map_err(Error::ParseEmailError)?;
-                            let (_, exit_code) = process::pipe(verify_cmd, &signature)
+                            let output = process::pipe(verify_cmd, &signature, None)
                                 .map_err(Error::VerifyEmailPartError)?;
-                            if exit_code != 0 {
-                                warn!("the signature could not be verified");
+                            if !output.status.success() {
+                                warn!(
+                                    "the signature could not be verified ({}): {}",
+                                    output.status,
+                                    output.stderr_lossy()
+                                );
                             }
                         } else {
                             warn!("no verify command found, cannot verify signature");
@@ -245,9 +249,8 @@ impl Email<'_> {
                             Some(ref decrypt_cmd) => {
                                 let encrypted_body =
                                     part.get_body_raw().map_err(Error::ParseEmailError)?;
-                                let (decrypted_part, _) =
-                                    process::pipe(decrypt_cmd, &encrypted_body)
-                                        .map_err(Error::DecryptEmailPartError)?;
+                                let decrypted_part = process::run(decrypt_cmd, &encrypted_body)
+                                    .map_err(Error::DecryptEmailPartError)?;
                                 let parsed = mailparse::parse_mail(&decrypted_part)
                                     .map_err(Error::ParseEmailError)?;
                                 tpl =
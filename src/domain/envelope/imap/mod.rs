@@ -1,5 +1,6 @@
 pub mod envelope;
 pub mod envelopes;
+pub mod search_query;
 pub mod sort_criterion;
 
 pub use envelope::*;
@@ -0,0 +1,167 @@
+//! IMAP search query module.
+//!
+//! This module contains the compiler of the backend-agnostic
+//! [`SearchQuery`] to IMAP `SEARCH` keys, as defined in the [RFC
+//! 3501].
+//!
+//! [RFC 3501]: https://www.rfc-editor.org/rfc/rfc3501#section-6.4.4
+
+use chrono::NaiveDate;
+
+use crate::{
+    envelope::search_query::{Error, Result},
+    Flag, SearchQuery,
+};
+
+/// Quotes the given string. Quoted strings cannot contain line
+/// breaks, which would end the command. Quoted strings are limited
+/// to ASCII, so other strings are sent as non-synchronizing literals
+/// ([RFC 7888]).
+///
+/// [RFC 7888]: https://www.rfc-editor.org/rfc/rfc7888
+fn quote(s: &str) -> Result<String> {
+    if s.contains(['\r', '\n']) {
+        return Err(Error::CompileLineBreakError(s.to_owned()));
+    }
+
+    if !s.is_ascii() {
+        return Ok(format!("{{{}+}}\r\n{s}", s.len()));
+    }
+
+    Ok(format!(
+        "\"{}\"",
+        s.replace('\\', "\\\\").replace('"', "\\\"")
+    ))
+}
+
+/// Checks that the given keyword is a valid IMAP atom.
+fn keyword(keyword: &str) -> Result<&str> {
+    let is_atom_char =
+        |c: char| c.is_ascii() && !c.is_ascii_control() && !"(){ %*\"\\]".contains(c);
+
+    if keyword.is_empty() || !keyword.chars().all(is_atom_char) {
+        return Err(Error::CompileInvalidImapKeywordError(keyword.to_owned()));
+    }
+
+    Ok(keyword)
+}
+
+fn date(date: &NaiveDate) -> String {
+    date.format("%d-%b-%Y").to_string()
+}
+
+impl SearchQuery {
+    /// Compiles the query to IMAP `SEARCH` keys. Fails when a
+    /// custom flag is not a valid IMAP keyword or when a pattern
+    /// contains a line break. A query containing non-ASCII patterns
+    /// requires the `CHARSET UTF-8` search argument and a server
+    /// supporting non-synchronizing literals.
+    pub fn to_imap_query(&self) -> Result<String> {
+        Ok(match self {
+            Self::All => String::from("ALL"),
+            Self::Flag(Flag::Seen) => String::from("SEEN"),
+            Self::Flag(Flag::Answered) => String::from("ANSWERED"),
+            Self::Flag(Flag::Flagged) => String::from("FLAGGED"),
+            Self::Flag(Flag::Deleted) => String::from("DELETED"),
+            Self::Flag(Flag::Draft) => String::from("DRAFT"),
+            Self::Flag(Flag::Recent) => String::from("RECENT"),
+            Self::Flag(Flag::Custom(flag)) => format!("KEYWORD {}", keyword(flag)?),
+            Self::From(pattern) => format!("FROM {}", quote(pattern)?),
+            Self::To(pattern) => format!("TO {}", quote(pattern)?),
+            Self::Cc(pattern) => format!("CC {}", quote(pattern)?),
+            Self::Bcc(pattern) => format!("BCC {}", quote(pattern)?),
+            Self::Subject(pattern) => format!("SUBJECT {}", quote(pattern)?),
+            Self::Header(key, pattern) => format!("HEADER {} {}", quote(key)?, quote(pattern)?),
+            Self::Body(pattern) => format!("BODY {}", quote(pattern)?),
+            Self::Text(pattern) => format!("TEXT {}", quote(pattern)?),
+            Self::Before(limit) => format!("BEFORE {}", date(limit)),
+            Self::On(limit) => format!("ON {}", date(limit)),
+            Self::Since(limit) => format!("SINCE {}", date(limit)),
+            Self::SentBefore(limit) => format!("SENTBEFORE {}", date(limit)),
+            Self::SentOn(limit) => format!("SENTON {}", date(limit)),
+            Self::SentSince(limit) => format!("SENTSINCE {}", date(limit)),
+            Self::Larger(size) => format!("LARGER {size}"),
+            Self::Smaller(size) => format!("SMALLER {size}"),
+            Self::Not(query) => format!("NOT {}", query.to_imap_query()?),
+            Self::Or(left, right) => {
+                format!("OR {} {}", left.to_imap_query()?, right.to_imap_query()?)
+            }
+            Self::And(queries) if queries.is_empty() => String::from("ALL"),
+            Self::And(queries) => format!(
+                "({})",
+                queries
+                    .iter()
+                    .map(Self::to_imap_query)
+                    .collect::<Result<Vec<_>>>()?
+                    .join(" ")
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod imap_search_query {
+    use crate::{envelope::search_query::Error, SearchQuery};
+
+    #[test]
+    fn to_imap_query() {
+        let compile = |query: &str| {
+            query
+                .parse::<SearchQuery>()
+                .unwrap()
+                .to_imap_query()
+                .unwrap()
+        };
+
+        assert_eq!(compile(""), "ALL");
+        assert_eq!(compile("flag seen"), "SEEN");
+        assert_eq!(compile("not flag $Junk"), "NOT KEYWORD $Junk");
+        assert_eq!(compile("from \"a\\\"b\""), "FROM \"a\\\"b\"");
+        assert_eq!(compile("header X-Spam yes"), "HEADER \"X-Spam\" \"yes\"");
+        assert_eq!(
+            compile("subject hello since 2023-02-01 or larger 1024"),
+            "OR (SUBJECT \"hello\" SINCE 01-Feb-2023) LARGER 1024"
+        );
+        assert_eq!(
+            compile("subject \"héllo\" from a"),
+            "(SUBJECT {6+}\r\nhéllo FROM \"a\")"
+        );
+    }
+
+    #[test]
+    fn to_imap_query_invalid_keyword() {
+        let compile = |query: &str| query.parse::<SearchQuery>().unwrap().to_imap_query();
+
+        for query in [
+            "flag \"a b\"",
+            "flag \"a\\\"b\"",
+            "flag \"a)\"",
+            "flag \"a\r\nb\"",
+        ] {
+            assert!(
+                matches!(
+                    compile(query),
+                    Err(Error::CompileInvalidImapKeywordError(_))
+                ),
+                "{query:?} should not compile"
+            );
+        }
+    }
+
+    #[test]
+    fn to_imap_query_line_break() {
+        let compile = |query: &str| query.parse::<SearchQuery>().unwrap().to_imap_query();
+
+        for query in [
+            "subject \"a\r\nA1 DELETE INBOX\"",
+            "from \"a\nb\"",
+            "header \"X\rY\" yes",
+            "not body \"a\rb\"",
+        ] {
+            assert!(
+                matches!(compile(query), Err(Error::CompileLineBreakError(_))),
+                "{query:?} should not compile"
+            );
+        }
+    }
+}
@@ -1,65 +1,101 @@
-//! Message sort criteria module.
+//! IMAP sort criteria module.
 //!
-//! This module regroups everything related to deserialization of
-//! message sort criteria.
-use imap;
+//! This module contains the compiler of the backend-agnostic
+//! [`SortCriteria`] to IMAP `SORT` criteria, as well as the
+//! extraction of [`SortKeys`] from IMAP fetches, used when the server
+//! does not support the `SORT` extension.
+use chrono::{DateTime, Local};
+use imap::{self, types::Fetch};
+use imap_proto::types::Address;
+use std::borrow::Cow;
 
-use std::{convert::TryFrom, ops::Deref};
-
-use crate::backend::imap::Error;
+use crate::{
+    envelope::{SortCriterion, SortCriterionKind, SortCriterionOrder, SortKeys},
+    Envelope, SortCriteria,
+};
 
 pub type ImapSortCriterion<'a> = imap::extensions::sort::SortCriterion<'a>;
 
-/// Represents the message sort criteria. It is just a wrapper around
-/// the `imap::extensions::sort::SortCriterion`.
-pub struct SortCriteria<'a>(Vec<imap::extensions::sort::SortCriterion<'a>>);
+impl SortCriterion {
+    /// Compiles the criterion to the IMAP `SORT` criterion.
+    pub fn to_imap_sort_criterion(&self) -> ImapSortCriterion<'static> {
+        let kind = match self.0 {
+            SortCriterionKind::Arrival => &ImapSortCriterion::Arrival,
+            SortCriterionKind::Cc => &ImapSortCriterion::Cc,
+            SortCriterionKind::Date => &ImapSortCriterion::Date,
+            SortCriterionKind::From => &ImapSortCriterion::From,
+            SortCriterionKind::Size => &ImapSortCriterion::Size,
+            SortCriterionKind::Subject => &ImapSortCriterion::Subject,
+            SortCriterionKind::To => &ImapSortCriterion::To,
+        };
 
-impl<'a> Deref for SortCriteria<'a> {
-    type Target = Vec<ImapSortCriterion<'a>>;
+        match self.1 {
+            SortCriterionOrder::Asc => kind.clone(),
+            SortCriterionOrder::Desc => ImapSortCriterion::Reverse(kind),
+        }
+    }
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+impl SortCriteria {
+    /// Compiles the criteria to IMAP `SORT` criteria.
+    pub fn to_imap_sort_criteria(&self) -> Vec<ImapSortCriterion<'static>> {
+        self.iter()
+            .map(SortCriterion::to_imap_sort_criterion)
+            .collect()
     }
 }
 
-impl<'a> TryFrom<&'a str> for SortCriteria<'a> {
-    type Error = Error;
+fn first_addr(addrs: Option<&Vec<Address>>) -> String {
+    let decode = |input: &Cow<[u8]>| String::from_utf8_lossy(input).to_lowercase();
 
-    fn try_from(criteria_str: &'a str) -> Result<Self, Self::Error> {
-        let mut criteria = vec![];
-        for criterion_str in criteria_str.split(" ") {
-            criteria.push(match criterion_str.trim() {
-                "arrival:asc" | "arrival" => Ok(imap::extensions::sort::SortCriterion::Arrival),
-                "arrival:desc" => Ok(imap::extensions::sort::SortCriterion::Reverse(
-                    &imap::extensions::sort::SortCriterion::Arrival,
-                )),
-                "cc:asc" | "cc" => Ok(imap::extensions::sort::SortCriterion::Cc),
-                "cc:desc" => Ok(imap::extensions::sort::SortCriterion::Reverse(
-                    &imap::extensions::sort::SortCriterion::Cc,
-                )),
-                "date:asc" | "date" => Ok(imap::extensions::sort::SortCriterion::Date),
-                "date:desc" => Ok(imap::extensions::sort::SortCriterion::Reverse(
-                    &imap::extensions::sort::SortCriterion::Date,
-                )),
-                "from:asc" | "from" => Ok(imap::extensions::sort::SortCriterion::From),
-                "from:desc" => Ok(imap::extensions::sort::SortCriterion::Reverse(
-                    &imap::extensions::sort::SortCriterion::From,
-                )),
-                "size:asc" | "size" => Ok(imap::extensions::sort::SortCriterion::Size),
-                "size:desc" => Ok(imap::extensions::sort::SortCriterion::Reverse(
-                    &imap::extensions::sort::SortCriterion::Size,
-                )),
-                "subject:asc" | "subject" => Ok(imap::extensions::sort::SortCriterion::Subject),
-                "subject:desc" => Ok(imap::extensions::sort::SortCriterion::Reverse(
-                    &imap::extensions::sort::SortCriterion::Subject,
-                )),
-                "to:asc" | "to" => Ok(imap::extensions::sort::SortCriterion::To),
-                "to:desc" => Ok(imap::extensions::sort::SortCriterion::Reverse(
-                    &imap::extensions::sort::SortCriterion::To,
-                )),
-                _ => Err(Error::ParseSortCriterionError(criterion_str.to_owned())),
-            }?);
-        }
-        Ok(Self(criteria))
+    addrs
+        .and_then(|addrs| addrs.first())
+        .map(|addr| match (addr.mailbox.as_ref(), addr.host.as_ref()) {
+            (Some(mbox), Some(host)) => format!("{}@{}", decode(mbox), decode(host)),
+            (Some(mbox), None) => decode(mbox),
+            _ => String::new(),
+        })
+        .unwrap_or_default()
+}
+
+/// Builds the sort keys of the given fetch. The fetch is expected to
+/// contain `ENVELOPE`, `INTERNALDATE` and `RFC822.SIZE` items.
+pub fn sort_keys_from_raw(fetch: &Fetch, envelope: &Envelope) -> SortKeys {
+    let raw_envelope = fetch.envelope();
+
+    SortKeys {
+        arrival: fetch
+            .internal_date()
+            .map(|date| DateTime::<Local>::from(date))
+            .unwrap_or_default(),
+        cc: first_addr(raw_envelope.and_then(|envelope| envelope.cc.as_ref())),
+        date: envelope.date,
+        from: envelope.from.addr.to_lowercase(),
+        size: fetch.size.unwrap_or_default() as u64,
+        subject: envelope.subject.to_lowercase(),
+        to: first_addr(raw_envelope.and_then(|envelope| envelope.to.as_ref())),
+    }
+}
+
+#[cfg(test)]
+mod imap_sort_criterion {
+    use crate::SortCriteria;
+
+    use super::ImapSortCriterion;
+
+    #[test]
+    fn to_imap_sort_criteria() {
+        let criteria = "date:desc subject"
+            .parse::<SortCriteria>()
+            .unwrap()
+            .to_imap_sort_criteria();
+
+        assert_eq!(
+            criteria,
+            vec![
+                ImapSortCriterion::Reverse(&ImapSortCriterion::Date),
+                ImapSortCriterion::Subject,
+            ]
+        );
     }
 }
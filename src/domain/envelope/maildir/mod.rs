@@ -1,5 +1,7 @@
 pub mod envelope;
 pub mod envelopes;
+pub mod search_query;
 
 pub use envelope::*;
 pub use envelopes::*;
+pub use search_query::*;
@@ -0,0 +1,232 @@
+//! Maildir search query module.
+//!
+//! This module contains the in-process search engine used by the
+//! Maildir backend: the backend-agnostic [`SearchQuery`] is evaluated
+//! as a predicate against each Maildir entry.
+
+use chrono::{DateTime, Local};
+use mailparse::ParsedMail;
+use std::{convert::TryFrom, fs};
+
+use crate::{
+    backend::maildir::{Error, Result},
+    envelope::SortKeys,
+    Envelope, SearchQuery,
+};
+
+/// Represents a Maildir entry prepared for the search engine.
+#[derive(Clone, Debug, Default)]
+pub struct SearchEntry {
+    /// Represents the envelope of the entry.
+    pub envelope: Envelope,
+    /// Represents the decoded headers of the entry, with lowercased
+    /// keys.
+    pub headers: Vec<(String, String)>,
+    /// Represents the concatenation of the text parts of the entry.
+    /// Only available when the query needs it.
+    pub body: Option<String>,
+    /// Represents the size of the entry, in bytes.
+    pub size: u64,
+    /// Represents the date the entry arrived in the Maildir.
+    pub arrival: DateTime<Local>,
+}
+
+impl SearchEntry {
+    /// Builds a search entry from a Maildir entry. The body is only
+    /// parsed when `with_body` is enabled, since it is the most
+    /// expensive part of the entry.
+    pub fn from_entry(mut entry: maildir::MailEntry, with_body: bool) -> Result<Self> {
+        let metadata = fs::metadata(entry.path()).map_err(Error::DecodeEntryError)?;
+        let size = metadata.len();
+        let arrival = metadata
+            .modified()
+            .map(DateTime::<Local>::from)
+            .unwrap_or_default();
+
+        let headers = entry
+            .headers()
+            .map_err(Error::ParseMsgError)?
+            .iter()
+            .map(|header| (header.get_key().to_lowercase(), header.get_value()))
+            .collect();
+
+        let body = if with_body {
+            let parsed = entry.parsed().map_err(Error::ParseMsgError)?;
+            let mut body = String::new();
+            collect_text_parts(&parsed, &mut body);
+            Some(body)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            envelope: Envelope::try_from(entry)?,
+            headers,
+            body,
+            size,
+            arrival,
+        })
+    }
+
+    /// Returns the first value of the given lowercased header key.
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Builds the sort keys of the entry.
+    pub fn sort_keys(&self) -> SortKeys {
+        let first_addr = |key| self.header(key).map(SortKeys::first_addr);
+
+        SortKeys {
+            arrival: self.arrival,
+            cc: first_addr("cc").unwrap_or_default(),
+            date: self.envelope.date,
+            from: first_addr("from").unwrap_or_default(),
+            size: self.size,
+            subject: self.envelope.subject.to_lowercase(),
+            to: first_addr("to").unwrap_or_default(),
+        }
+    }
+
+    fn header_contains(&self, key: &str, pattern: &str) -> bool {
+        self.headers
+            .iter()
+            .filter(|(k, _)| k == key)
+            .any(|(_, v)| contains(v, pattern))
+    }
+}
+
+fn collect_text_parts(part: &ParsedMail, body: &mut String) {
+    if part.subparts.is_empty() {
+        if part.ctype.mimetype.starts_with("text/") {
+            if let Ok(text) = part.get_body() {
+                body.push_str(&text);
+                body.push('\n');
+            }
+        }
+    } else {
+        for subpart in &part.subparts {
+            collect_text_parts(subpart, body);
+        }
+    }
+}
+
+fn contains(haystack: &str, needle: &str) -> bool {
+    haystack.to_lowercase().contains(&needle.to_lowercase())
+}
+
+impl SearchQuery {
+    /// Checks if the query needs the body of the emails in order to
+    /// be evaluated.
+    pub fn needs_body(&self) -> bool {
+        match self {
+            Self::Body(_) | Self::Text(_) => true,
+            Self::Not(query) => query.needs_body(),
+            Self::Or(left, right) => left.needs_body() || right.needs_body(),
+            Self::And(queries) => queries.iter().any(Self::needs_body),
+            _ => false,
+        }
+    }
+
+    /// Checks if the given search entry matches the query.
+    pub fn matches(&self, entry: &SearchEntry) -> bool {
+        let date = entry.envelope.date.date_naive();
+        let arrival = entry.arrival.date_naive();
+
+        match self {
+            Self::All => true,
+            Self::Flag(flag) => entry.envelope.flags.contains(flag),
+            Self::From(pattern) => entry.header_contains("from", pattern),
+            Self::To(pattern) => entry.header_contains("to", pattern),
+            Self::Cc(pattern) => entry.header_contains("cc", pattern),
+            Self::Bcc(pattern) => entry.header_contains("bcc", pattern),
+            Self::Subject(pattern) => entry.header_contains("subject", pattern),
+            Self::Header(key, pattern) => entry.header_contains(&key.to_lowercase(), pattern),
+            Self::Body(pattern) => entry
+                .body
+                .as_ref()
+                .map(|body| contains(body, pattern))
+                .unwrap_or_default(),
+            Self::Text(pattern) => {
+                entry.headers.iter().any(|(_, v)| contains(v, pattern))
+                    || Self::Body(pattern.clone()).matches(entry)
+            }
+            Self::Before(limit) => arrival < *limit,
+            Self::On(limit) => arrival == *limit,
+            Self::Since(limit) => arrival >= *limit,
+            Self::SentBefore(limit) => date < *limit,
+            Self::SentOn(limit) => date == *limit,
+            Self::SentSince(limit) => date >= *limit,
+            Self::Larger(size) => entry.size > *size,
+            Self::Smaller(size) => entry.size < *size,
+            Self::Not(query) => !query.matches(entry),
+            Self::Or(left, right) => left.matches(entry) || right.matches(entry),
+            Self::And(queries) => queries.iter().all(|query| query.matches(entry)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod maildir_search_query {
+    use chrono::{DateTime, Local, NaiveDate, NaiveDateTime};
+
+    use crate::{envelope::Mailbox, Envelope, Flag, Flags, SearchQuery};
+
+    use super::SearchEntry;
+
+    fn local_date_time(date: NaiveDate) -> DateTime<Local> {
+        NaiveDateTime::from(date)
+            .and_local_timezone(Local)
+            .earliest()
+            .unwrap_or_default()
+    }
+
+    fn entry() -> SearchEntry {
+        SearchEntry {
+            envelope: Envelope {
+                flags: Flags::from_iter([Flag::Seen]),
+                from: Mailbox::new_nameless("alice@localhost"),
+                subject: "Hello, world!".into(),
+                date: local_date_time(NaiveDate::from_ymd_opt(2023, 2, 14).unwrap()),
+                ..Envelope::default()
+            },
+            headers: vec![
+                ("from".into(), "Alice <alice@localhost>".into()),
+                ("to".into(), "bob@localhost".into()),
+                ("subject".into(), "Hello, world!".into()),
+            ],
+            body: Some("Plain message!".into()),
+            size: 128,
+            arrival: local_date_time(NaiveDate::from_ymd_opt(2023, 2, 15).unwrap()),
+        }
+    }
+
+    #[test]
+    fn matches() {
+        let entry = entry();
+        let matches = |query: &str| query.parse::<SearchQuery>().unwrap().matches(&entry);
+
+        assert!(matches("all"));
+        assert!(matches("flag seen"));
+        assert!(!matches("not flag seen"));
+        assert!(matches("from ALICE"));
+        assert!(!matches("from bob"));
+        assert!(matches("to bob"));
+        assert!(matches("subject world"));
+        assert!(matches("body plain"));
+        assert!(matches("text plain"));
+        assert!(matches("text alice"));
+        assert!(matches("header Subject hello"));
+        assert!(matches("sent-on 2023-02-14"));
+        assert!(matches("sent-since 2023-02-14"));
+        assert!(!matches("sent-before 2023-02-14"));
+        assert!(matches("on 2023-02-15"));
+        assert!(matches("before 2023-02-16"));
+        assert!(matches("larger 100 smaller 200"));
+        assert!(matches("from bob or subject hello"));
+        assert!(matches("not flag flagged"));
+    }
+}
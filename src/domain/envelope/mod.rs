@@ -5,9 +5,15 @@ pub mod imap;
 pub mod maildir;
 #[cfg(feature = "notmuch-backend")]
 pub mod notmuch;
+pub mod search_query;
+pub mod sort_criterion;
 pub mod sync;
 
 pub use self::envelope::*;
 pub use self::envelopes::*;
+pub use self::search_query::SearchQuery;
+pub use self::sort_criterion::{
+    SortCriteria, SortCriterion, SortCriterionKind, SortCriterionOrder, SortKeys,
+};
 pub use self::sync::Cache;
 pub use self::sync::SyncBuilder;
@@ -3,13 +3,14 @@
 //! This module provides Notmuch types and conversion utilities
 //! related to the envelope
 
-use chrono::{Local, NaiveDateTime};
+use chrono::{DateTime, Local, NaiveDateTime};
 use log::{info, trace};
 use notmuch;
+use std::fs;
 
 use crate::{
     backend::notmuch::{Error, Result},
-    envelope::Mailbox,
+    envelope::{Mailbox, SortKeys},
     Envelope, Flag,
 };
 
@@ -75,3 +76,34 @@ pub fn from_raw(raw: RawEnvelope) -> Result<Envelope> {
     info!("end: try building envelope from notmuch parsed mail");
     Ok(envelope)
 }
+
+/// Builds the sort keys of the given raw envelope. Notmuch does not
+/// keep track of the arrival date, so the modification time of the
+/// email file is used instead.
+pub fn sort_keys_from_raw(raw: &RawEnvelope) -> Result<SortKeys> {
+    let header = |key: &str| {
+        Result::Ok(
+            raw.header(key)
+                .map_err(|err| Error::ParseMsgHeaderError(err, key.to_owned()))?
+                .map(|header| header.to_string())
+                .unwrap_or_default(),
+        )
+    };
+    let metadata = fs::metadata(raw.filename()).ok();
+
+    Ok(SortKeys {
+        arrival: metadata
+            .as_ref()
+            .and_then(|metadata| metadata.modified().ok())
+            .map(DateTime::<Local>::from)
+            .unwrap_or_default(),
+        cc: SortKeys::first_addr(&header("cc")?),
+        date: NaiveDateTime::from_timestamp_opt(raw.date(), 0)
+            .and_then(|date| date.and_local_timezone(Local).earliest())
+            .unwrap_or_default(),
+        from: SortKeys::first_addr(&header("from")?),
+        size: metadata.map(|metadata| metadata.len()).unwrap_or_default(),
+        subject: header("subject")?.to_lowercase(),
+        to: SortKeys::first_addr(&header("to")?),
+    })
+}
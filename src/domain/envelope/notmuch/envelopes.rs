@@ -1,4 +1,4 @@
-use crate::{backend::notmuch::Result, Envelopes};
+use crate::{backend::notmuch::Result, envelope::SortKeys, Envelope, Envelopes};
 
 use super::envelope;
 
@@ -14,3 +14,14 @@ pub fn from_raws(raws: RawEnvelopes) -> Result<Envelopes> {
     }
     Ok(envelopes)
 }
+
+/// Builds envelopes alongside with their sort keys.
+pub fn from_raws_with_sort_keys(raws: RawEnvelopes) -> Result<Vec<(SortKeys, Envelope)>> {
+    let mut entries = Vec::new();
+    for msg in raws {
+        let keys = envelope::sort_keys_from_raw(&msg)?;
+        let envelope = envelope::from_raw(msg)?;
+        entries.push((keys, envelope));
+    }
+    Ok(entries)
+}
@@ -1,2 +1,3 @@
 pub mod envelope;
 pub mod envelopes;
+pub mod search_query;
@@ -0,0 +1,115 @@
+//! Notmuch search query module.
+//!
+//! This module contains the compiler of the backend-agnostic
+//! [`SearchQuery`] to notmuch search terms. Notmuch only indexes the
+//! `Date` header, so received and sent dates are both compiled to
+//! `date:` ranges. Size conditions are not supported.
+
+use chrono::NaiveDate;
+use log::warn;
+
+use crate::{
+    envelope::search_query::{Error, Result},
+    SearchQuery,
+};
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+fn date(date: &NaiveDate) -> String {
+    date.format("%Y-%m-%d").to_string()
+}
+
+fn date_before(limit: &NaiveDate) -> String {
+    match limit.pred_opt() {
+        Some(limit) => format!("date:..{}", date(&limit)),
+        None => String::from("not *"),
+    }
+}
+
+impl SearchQuery {
+    /// Compiles the query to notmuch search terms. Fails when the
+    /// query contains a size condition, since dropping it would
+    /// change the meaning of the query when it is negated or part of
+    /// a disjunction.
+    pub fn to_notmuch_query(&self) -> Result<String> {
+        Ok(match self {
+            Self::All => String::from("*"),
+            Self::Flag(flag) => format!("tag:{}", quote(&flag.to_string())),
+            Self::From(pattern) => format!("from:{}", quote(pattern)),
+            Self::To(pattern) | Self::Cc(pattern) | Self::Bcc(pattern) => {
+                format!("to:{}", quote(pattern))
+            }
+            Self::Subject(pattern) => format!("subject:{}", quote(pattern)),
+            Self::Header(key, pattern) => {
+                warn!("notmuch does not index header {key}, searching in the whole email instead");
+                quote(pattern)
+            }
+            Self::Body(pattern) => format!("body:{}", quote(pattern)),
+            Self::Text(pattern) => quote(pattern),
+            Self::Before(limit) | Self::SentBefore(limit) => date_before(limit),
+            Self::On(limit) | Self::SentOn(limit) => {
+                format!("date:{}..{}", date(limit), date(limit))
+            }
+            Self::Since(limit) | Self::SentSince(limit) => format!("date:{}..", date(limit)),
+            Self::Larger(_) | Self::Smaller(_) => {
+                return Err(Error::CompileNotmuchSizeUnsupportedError)
+            }
+            Self::Not(query) => format!("(not {})", query.to_notmuch_query()?),
+            Self::Or(left, right) => format!(
+                "({} or {})",
+                left.to_notmuch_query()?,
+                right.to_notmuch_query()?
+            ),
+            Self::And(queries) if queries.is_empty() => String::from("*"),
+            Self::And(queries) => format!(
+                "({})",
+                queries
+                    .iter()
+                    .map(Self::to_notmuch_query)
+                    .collect::<Result<Vec<_>>>()?
+                    .join(" and ")
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod notmuch_search_query {
+    use crate::{envelope::search_query::Error, SearchQuery};
+
+    #[test]
+    fn to_notmuch_query() {
+        let compile = |query: &str| {
+            query
+                .parse::<SearchQuery>()
+                .unwrap()
+                .to_notmuch_query()
+                .unwrap()
+        };
+
+        assert_eq!(compile(""), "*");
+        assert_eq!(compile("not flag seen"), "(not tag:\"seen\")");
+        assert_eq!(compile("cc \"a \\\"b\""), "to:\"a \"\"b\"");
+        assert_eq!(
+            compile("subject hello before 2023-02-01 or body world"),
+            "((subject:\"hello\" and date:..2023-01-31) or body:\"world\")"
+        );
+    }
+
+    #[test]
+    fn to_notmuch_query_size() {
+        let compile = |query: &str| query.parse::<SearchQuery>().unwrap().to_notmuch_query();
+
+        for query in ["not larger 1000", "subject x or smaller 10", "larger 1"] {
+            assert!(
+                matches!(
+                    compile(query),
+                    Err(Error::CompileNotmuchSizeUnsupportedError)
+                ),
+                "{query:?} should not compile"
+            );
+        }
+    }
+}
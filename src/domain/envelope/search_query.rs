@@ -0,0 +1,375 @@
+//! Search query module.
+//!
+//! This module contains the backend-agnostic representation of an
+//! envelopes search query. Each backend compiles this query to its
+//! own native form (IMAP `SEARCH` keys, notmuch query, in-memory
+//! predicate for Maildir).
+//!
+//! Queries can be built manually or parsed from a small human
+//! syntax:
+//!
+//! ```text
+//! from alice and not flag seen
+//! subject "hello world" or (to bob since 2023-02-01)
+//! ```
+//!
+//! Conditions are implicitly joined by `and`. Available conditions:
+//! `all`, `from`, `to`, `cc`, `bcc`, `subject`, `body`, `text`,
+//! `header <name>`, `flag`, `before`, `on`, `since`, `sent-before`,
+//! `sent-on`, `sent-since`, `larger` and `smaller`. Dates follow the
+//! `YYYY-MM-DD` format.
+
+use chrono::NaiveDate;
+use log::trace;
+use std::{iter::Peekable, result, str::FromStr, vec::IntoIter};
+use thiserror::Error;
+
+use crate::Flag;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("cannot parse search query: missing argument for {0}")]
+    ParseMissingArgError(String),
+    #[error("cannot parse search query: missing closing parenthesis")]
+    ParseMissingClosingParenError,
+    #[error("cannot parse search query: unexpected token {0}")]
+    ParseUnexpectedTokenError(String),
+    #[error("cannot parse search query: unterminated quoted string")]
+    ParseUnterminatedStringError,
+    #[error("cannot parse search query date {1}")]
+    ParseDateError(#[source] chrono::ParseError, String),
+    #[error("cannot parse search query size {0}")]
+    ParseSizeError(String),
+    #[error("cannot compile search query: invalid imap keyword {0:?}")]
+    CompileInvalidImapKeywordError(String),
+    #[error("cannot compile search query: line break in {0:?}")]
+    CompileLineBreakError(String),
+    #[error("cannot compile search query: notmuch does not support size conditions")]
+    CompileNotmuchSizeUnsupportedError,
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents the envelopes search query.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SearchQuery {
+    /// Matches all emails.
+    All,
+    /// Matches emails containing the given flag.
+    Flag(Flag),
+    /// Matches emails whose `From` header contains the given string.
+    From(String),
+    /// Matches emails whose `To` header contains the given string.
+    To(String),
+    /// Matches emails whose `Cc` header contains the given string.
+    Cc(String),
+    /// Matches emails whose `Bcc` header contains the given string.
+    Bcc(String),
+    /// Matches emails whose `Subject` header contains the given
+    /// string.
+    Subject(String),
+    /// Matches emails whose given header contains the given string.
+    Header(String, String),
+    /// Matches emails whose text body contains the given string.
+    Body(String),
+    /// Matches emails whose headers or text body contain the given
+    /// string.
+    Text(String),
+    /// Matches emails received before the given date.
+    Before(NaiveDate),
+    /// Matches emails received on the given date.
+    On(NaiveDate),
+    /// Matches emails received on or after the given date.
+    Since(NaiveDate),
+    /// Matches emails whose `Date` header is before the given date.
+    SentBefore(NaiveDate),
+    /// Matches emails whose `Date` header is on the given date.
+    SentOn(NaiveDate),
+    /// Matches emails whose `Date` header is on or after the given
+    /// date.
+    SentSince(NaiveDate),
+    /// Matches emails bigger than the given amount of bytes.
+    Larger(u64),
+    /// Matches emails smaller than the given amount of bytes.
+    Smaller(u64),
+    /// Matches emails that do not match the inner query.
+    Not(Box<SearchQuery>),
+    /// Matches emails that match at least one of the inner queries.
+    Or(Box<SearchQuery>, Box<SearchQuery>),
+    /// Matches emails that match all the inner queries.
+    And(Vec<SearchQuery>),
+}
+
+impl Default for SearchQuery {
+    fn default() -> Self {
+        Self::All
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    OpenParen,
+    CloseParen,
+}
+
+impl Token {
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self, Self::Word(word) if word.eq_ignore_ascii_case(keyword))
+    }
+}
+
+/// Splits a raw query into tokens. Double-quoted strings are kept
+/// as a single token, and parenthesis are always standalone tokens.
+fn tokenize(query: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => (),
+            '(' => tokens.push(Token::OpenParen),
+            ')' => tokens.push(Token::CloseParen),
+            '"' => {
+                let mut token = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) => token.push(c),
+                            None => return Err(Error::ParseUnterminatedStringError),
+                        },
+                        Some(c) => token.push(c),
+                        None => return Err(Error::ParseUnterminatedStringError),
+                    }
+                }
+                tokens.push(Token::Quoted(token));
+            }
+            c => {
+                let mut token = c.to_string();
+                while let Some(c) = chars.peek() {
+                    if c.is_whitespace() || *c == '(' || *c == ')' {
+                        break;
+                    }
+                    token.push(*c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(token));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+type Tokens = Peekable<IntoIter<Token>>;
+
+fn parse_string(tokens: &mut Tokens, key: &str) -> Result<String> {
+    match tokens.next() {
+        Some(Token::Word(arg)) | Some(Token::Quoted(arg)) => Ok(arg),
+        _ => Err(Error::ParseMissingArgError(key.to_owned())),
+    }
+}
+
+fn parse_date(tokens: &mut Tokens, key: &str) -> Result<NaiveDate> {
+    let date = parse_string(tokens, key)?;
+    NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(&date, "%d-%b-%Y"))
+        .map_err(|err| Error::ParseDateError(err, date))
+}
+
+fn parse_size(tokens: &mut Tokens, key: &str) -> Result<u64> {
+    let size = parse_string(tokens, key)?;
+    size.parse().map_err(|_| Error::ParseSizeError(size))
+}
+
+fn parse_or(tokens: &mut Tokens) -> Result<SearchQuery> {
+    let mut query = parse_and(tokens)?;
+
+    while tokens
+        .peek()
+        .map(|t| t.is_keyword("or"))
+        .unwrap_or_default()
+    {
+        tokens.next();
+        query = SearchQuery::Or(Box::new(query), Box::new(parse_and(tokens)?));
+    }
+
+    Ok(query)
+}
+
+fn parse_and(tokens: &mut Tokens) -> Result<SearchQuery> {
+    let mut queries = vec![parse_not(tokens)?];
+
+    loop {
+        match tokens.peek() {
+            None | Some(Token::CloseParen) => break,
+            Some(token) if token.is_keyword("or") => break,
+            Some(token) if token.is_keyword("and") => {
+                tokens.next();
+                queries.push(parse_not(tokens)?);
+            }
+            Some(_) => queries.push(parse_not(tokens)?),
+        }
+    }
+
+    Ok(if queries.len() == 1 {
+        queries.remove(0)
+    } else {
+        SearchQuery::And(queries)
+    })
+}
+
+fn parse_not(tokens: &mut Tokens) -> Result<SearchQuery> {
+    if tokens
+        .peek()
+        .map(|t| t.is_keyword("not"))
+        .unwrap_or_default()
+    {
+        tokens.next();
+        Ok(SearchQuery::Not(Box::new(parse_not(tokens)?)))
+    } else {
+        parse_condition(tokens)
+    }
+}
+
+fn parse_condition(tokens: &mut Tokens) -> Result<SearchQuery> {
+    let key = match tokens.next() {
+        Some(Token::OpenParen) => {
+            let query = parse_or(tokens)?;
+            return match tokens.next() {
+                Some(Token::CloseParen) => Ok(query),
+                _ => Err(Error::ParseMissingClosingParenError),
+            };
+        }
+        Some(Token::Word(key)) => key,
+        Some(Token::Quoted(token)) => return Err(Error::ParseUnexpectedTokenError(token)),
+        Some(Token::CloseParen) => return Err(Error::ParseUnexpectedTokenError(")".into())),
+        None => return Err(Error::ParseMissingArgError(String::from("search query"))),
+    };
+    trace!("search key: {key}");
+
+    Ok(match key.to_lowercase().as_str() {
+        "all" => SearchQuery::All,
+        "flag" => {
+            let flag = parse_string(tokens, &key)?;
+            match Flag::from(flag.to_lowercase()) {
+                Flag::Custom(_) => SearchQuery::Flag(Flag::Custom(flag)),
+                flag => SearchQuery::Flag(flag),
+            }
+        }
+        "from" => SearchQuery::From(parse_string(tokens, &key)?),
+        "to" => SearchQuery::To(parse_string(tokens, &key)?),
+        "cc" => SearchQuery::Cc(parse_string(tokens, &key)?),
+        "bcc" => SearchQuery::Bcc(parse_string(tokens, &key)?),
+        "subject" => SearchQuery::Subject(parse_string(tokens, &key)?),
+        "body" => SearchQuery::Body(parse_string(tokens, &key)?),
+        "text" => SearchQuery::Text(parse_string(tokens, &key)?),
+        "header" => {
+            let header = parse_string(tokens, &key)?;
+            SearchQuery::Header(header, parse_string(tokens, &key)?)
+        }
+        "before" => SearchQuery::Before(parse_date(tokens, &key)?),
+        "on" => SearchQuery::On(parse_date(tokens, &key)?),
+        "since" => SearchQuery::Since(parse_date(tokens, &key)?),
+        "sent-before" => SearchQuery::SentBefore(parse_date(tokens, &key)?),
+        "sent-on" => SearchQuery::SentOn(parse_date(tokens, &key)?),
+        "sent-since" => SearchQuery::SentSince(parse_date(tokens, &key)?),
+        "larger" => SearchQuery::Larger(parse_size(tokens, &key)?),
+        "smaller" => SearchQuery::Smaller(parse_size(tokens, &key)?),
+        _ => return Err(Error::ParseUnexpectedTokenError(key)),
+    })
+}
+
+impl FromStr for SearchQuery {
+    type Err = Error;
+
+    fn from_str(query: &str) -> Result<Self> {
+        let mut tokens = tokenize(query)?.into_iter().peekable();
+
+        if tokens.peek().is_none() {
+            return Ok(SearchQuery::All);
+        }
+
+        let query = parse_or(&mut tokens)?;
+
+        if let Some(token) = tokens.next() {
+            return Err(Error::ParseUnexpectedTokenError(format!("{token:?}")));
+        }
+
+        trace!("search query: {query:?}");
+        Ok(query)
+    }
+}
+
+#[cfg(test)]
+mod search_query {
+    use chrono::NaiveDate;
+
+    use crate::Flag;
+
+    use super::SearchQuery;
+
+    #[test]
+    fn parse() {
+        assert_eq!("".parse::<SearchQuery>().unwrap(), SearchQuery::All);
+        assert_eq!(
+            "from alice".parse::<SearchQuery>().unwrap(),
+            SearchQuery::From("alice".into())
+        );
+        assert_eq!(
+            "subject \"hello world\" and not flag seen"
+                .parse::<SearchQuery>()
+                .unwrap(),
+            SearchQuery::And(vec![
+                SearchQuery::Subject("hello world".into()),
+                SearchQuery::Not(Box::new(SearchQuery::Flag(Flag::Seen))),
+            ])
+        );
+        assert_eq!(
+            "from alice to bob or since 2023-02-01"
+                .parse::<SearchQuery>()
+                .unwrap(),
+            SearchQuery::Or(
+                Box::new(SearchQuery::And(vec![
+                    SearchQuery::From("alice".into()),
+                    SearchQuery::To("bob".into()),
+                ])),
+                Box::new(SearchQuery::Since(
+                    NaiveDate::from_ymd_opt(2023, 2, 1).unwrap()
+                )),
+            )
+        );
+        assert_eq!(
+            "FROM \"or\" (subject a OR subject b)"
+                .parse::<SearchQuery>()
+                .unwrap(),
+            SearchQuery::And(vec![
+                SearchQuery::From("or".into()),
+                SearchQuery::Or(
+                    Box::new(SearchQuery::Subject("a".into())),
+                    Box::new(SearchQuery::Subject("b".into())),
+                ),
+            ])
+        );
+        assert_eq!(
+            "header X-Spam yes larger 1024 sent-on 14-Feb-2023"
+                .parse::<SearchQuery>()
+                .unwrap(),
+            SearchQuery::And(vec![
+                SearchQuery::Header("X-Spam".into(), "yes".into()),
+                SearchQuery::Larger(1024),
+                SearchQuery::SentOn(NaiveDate::from_ymd_opt(2023, 2, 14).unwrap()),
+            ])
+        );
+
+        assert!("from".parse::<SearchQuery>().is_err());
+        assert!("(from alice".parse::<SearchQuery>().is_err());
+        assert!("from alice)".parse::<SearchQuery>().is_err());
+        assert!("since yesterday".parse::<SearchQuery>().is_err());
+        assert!("larger big".parse::<SearchQuery>().is_err());
+        assert!("subject \"hello".parse::<SearchQuery>().is_err());
+        assert!("unknown".parse::<SearchQuery>().is_err());
+    }
+}
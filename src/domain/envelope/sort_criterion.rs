@@ -0,0 +1,220 @@
+//! Sort criteria module.
+//!
+//! This module contains the backend-agnostic representation of the
+//! envelopes sort criteria. Backends able to sort natively (like the
+//! IMAP `SORT` extension) compile the criteria to their own form,
+//! others sort client-side using [`SortKeys`].
+//!
+//! Criteria can be parsed from a space-separated list of
+//! `kind[:asc|:desc]`, for example `date:desc subject`.
+
+use chrono::{DateTime, Local};
+use std::{cmp::Ordering, ops::Deref, result, str::FromStr};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("cannot parse sort criterion {0}")]
+    ParseSortCriterionError(String),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents the sort criterion kind.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SortCriterionKind {
+    Arrival,
+    Cc,
+    Date,
+    From,
+    Size,
+    Subject,
+    To,
+}
+
+/// Represents the sort criterion order.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SortCriterionOrder {
+    Asc,
+    Desc,
+}
+
+/// Represents the envelope sort criterion.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SortCriterion(pub SortCriterionKind, pub SortCriterionOrder);
+
+impl SortCriterion {
+    fn cmp(&self, a: &SortKeys, b: &SortKeys) -> Ordering {
+        let ordering = match self.0 {
+            SortCriterionKind::Arrival => a.arrival.cmp(&b.arrival),
+            SortCriterionKind::Cc => a.cc.cmp(&b.cc),
+            SortCriterionKind::Date => a.date.cmp(&b.date),
+            SortCriterionKind::From => a.from.cmp(&b.from),
+            SortCriterionKind::Size => a.size.cmp(&b.size),
+            SortCriterionKind::Subject => a.subject.cmp(&b.subject),
+            SortCriterionKind::To => a.to.cmp(&b.to),
+        };
+
+        match self.1 {
+            SortCriterionOrder::Asc => ordering,
+            SortCriterionOrder::Desc => ordering.reverse(),
+        }
+    }
+}
+
+/// Represents the values an envelope can be sorted by, used by
+/// backends that sort client-side. Addresses and subject are
+/// expected to be lowercased.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SortKeys {
+    /// Represents the date the email arrived in the folder.
+    pub arrival: DateTime<Local>,
+    /// Represents the first address of the `Cc` header.
+    pub cc: String,
+    /// Represents the `Date` header.
+    pub date: DateTime<Local>,
+    /// Represents the first address of the `From` header.
+    pub from: String,
+    /// Represents the size of the email, in bytes.
+    pub size: u64,
+    /// Represents the `Subject` header.
+    pub subject: String,
+    /// Represents the first address of the `To` header.
+    pub to: String,
+}
+
+impl SortKeys {
+    /// Extracts the lowercased first address of the given raw
+    /// address list header, or an empty string if it cannot be
+    /// parsed.
+    pub fn first_addr(header: &str) -> String {
+        mailparse::addrparse(header)
+            .ok()
+            .and_then(|addrs| {
+                addrs.first().map(|addr| match addr {
+                    mailparse::MailAddr::Single(single) => single.addr.to_lowercase(),
+                    mailparse::MailAddr::Group(group) => group.group_name.to_lowercase(),
+                })
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Represents the envelope sort criteria.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SortCriteria(Vec<SortCriterion>);
+
+impl Default for SortCriteria {
+    fn default() -> Self {
+        Self(vec![SortCriterion(
+            SortCriterionKind::Date,
+            SortCriterionOrder::Desc,
+        )])
+    }
+}
+
+impl Deref for SortCriteria {
+    type Target = Vec<SortCriterion>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl From<Vec<SortCriterion>> for SortCriteria {
+    fn from(criteria: Vec<SortCriterion>) -> Self {
+        Self(criteria)
+    }
+}
+
+impl SortCriteria {
+    /// Sorts the given items, criterion after criterion, using the
+    /// sort keys returned by the given function.
+    pub fn sort_by_keys<T, F>(&self, items: &mut [T], keys: F)
+    where
+        F: Fn(&T) -> &SortKeys,
+    {
+        items.sort_by(|a, b| {
+            self.iter().fold(Ordering::Equal, |ordering, criterion| {
+                ordering.then_with(|| criterion.cmp(keys(a), keys(b)))
+            })
+        });
+    }
+}
+
+impl FromStr for SortCriteria {
+    type Err = Error;
+
+    fn from_str(criteria_str: &str) -> Result<Self> {
+        if criteria_str.trim().is_empty() {
+            return Ok(Self::default());
+        }
+
+        let mut criteria = vec![];
+        for criterion_str in criteria_str.split_whitespace() {
+            let (kind, order) = match criterion_str.split_once(':') {
+                Some((kind, "asc")) => (kind, SortCriterionOrder::Asc),
+                Some((kind, "desc")) => (kind, SortCriterionOrder::Desc),
+                Some(_) => return Err(Error::ParseSortCriterionError(criterion_str.to_owned())),
+                None => (criterion_str, SortCriterionOrder::Asc),
+            };
+            let kind = match kind {
+                "arrival" => SortCriterionKind::Arrival,
+                "cc" => SortCriterionKind::Cc,
+                "date" => SortCriterionKind::Date,
+                "from" => SortCriterionKind::From,
+                "size" => SortCriterionKind::Size,
+                "subject" => SortCriterionKind::Subject,
+                "to" => SortCriterionKind::To,
+                _ => return Err(Error::ParseSortCriterionError(criterion_str.to_owned())),
+            };
+            criteria.push(SortCriterion(kind, order));
+        }
+        Ok(Self(criteria))
+    }
+}
+
+#[cfg(test)]
+mod sort_criterion {
+    use super::{SortCriteria, SortCriterion, SortCriterionKind, SortCriterionOrder, SortKeys};
+
+    #[test]
+    fn parse() {
+        assert_eq!("".parse::<SortCriteria>().unwrap(), SortCriteria::default());
+        assert_eq!(
+            "date:desc subject".parse::<SortCriteria>().unwrap(),
+            SortCriteria::from(vec![
+                SortCriterion(SortCriterionKind::Date, SortCriterionOrder::Desc),
+                SortCriterion(SortCriterionKind::Subject, SortCriterionOrder::Asc),
+            ])
+        );
+
+        assert!("date:up".parse::<SortCriteria>().is_err());
+        assert!("unknown".parse::<SortCriteria>().is_err());
+    }
+
+    #[test]
+    fn sort_by_keys() {
+        let keys = |from: &str, subject: &str, size| SortKeys {
+            from: SortKeys::first_addr(from),
+            subject: subject.into(),
+            size,
+            ..SortKeys::default()
+        };
+        let mut items = vec![
+            keys("Bob <bob@localhost>", "b", 2),
+            keys("alice@localhost", "b", 1),
+            keys("ALICE@localhost", "a", 3),
+        ];
+
+        "from subject:desc"
+            .parse::<SortCriteria>()
+            .unwrap()
+            .sort_by_keys(&mut items, |keys| keys);
+
+        assert_eq!(
+            items.iter().map(|keys| keys.size).collect::<Vec<_>>(),
+            vec![1, 3, 2]
+        );
+    }
+}
@@ -2,7 +2,7 @@ use chrono::{DateTime, Local};
 use log::warn;
 use rusqlite::types::Value;
 
-use crate::{envelope::Mailbox, Envelope, Envelopes};
+use crate::{envelope::Mailbox, BackendSyncState, Envelope, Envelopes};
 
 use super::Result;
 
@@ -42,6 +42,29 @@ const SELECT_ENVELOPES: &str = "
     ORDER BY date DESC
 ";
 
+const CREATE_SYNC_STATES_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS sync_states (
+        account        TEXT    NOT NULL,
+        folder         TEXT    NOT NULL,
+        uid_validity   INTEGER NOT NULL,
+        highest_modseq INTEGER NOT NULL,
+        uid_next       INTEGER NOT NULL,
+        UNIQUE(account, folder)
+    )
+";
+
+const SELECT_SYNC_STATE: &str = "
+    SELECT uid_validity, highest_modseq, uid_next
+    FROM sync_states
+    WHERE account = ?
+    AND folder = ?
+";
+
+const UPSERT_SYNC_STATE: &str = "
+    INSERT OR REPLACE INTO sync_states
+    VALUES (?, ?, ?, ?, ?)
+";
+
 pub struct Cache;
 
 impl Cache {
@@ -49,6 +72,54 @@ impl Cache {
 
     pub fn init(conn: &mut rusqlite::Connection) -> Result<()> {
         conn.execute(CREATE_ENVELOPES_TABLE, ())?;
+        conn.execute(CREATE_SYNC_STATES_TABLE, ())?;
+        Ok(())
+    }
+
+    pub fn get_remote_sync_state<N, F>(
+        conn: &mut rusqlite::Connection,
+        name: N,
+        folder: F,
+    ) -> Result<Option<BackendSyncState>>
+    where
+        N: AsRef<str>,
+        F: AsRef<str>,
+    {
+        let mut stmt = conn.prepare(SELECT_SYNC_STATE)?;
+        let state = stmt
+            .query_map([name.as_ref(), folder.as_ref()], |row| {
+                Ok(BackendSyncState {
+                    uid_validity: row.get(0)?,
+                    highest_modseq: row.get::<usize, i64>(1)? as u64,
+                    uid_next: row.get(2)?,
+                })
+            })?
+            .next()
+            .transpose()?;
+
+        Ok(state)
+    }
+
+    pub fn set_remote_sync_state<N, F>(
+        conn: &mut rusqlite::Connection,
+        name: N,
+        folder: F,
+        state: &BackendSyncState,
+    ) -> Result<()>
+    where
+        N: AsRef<str>,
+        F: AsRef<str>,
+    {
+        conn.execute(
+            UPSERT_SYNC_STATE,
+            (
+                name.as_ref(),
+                folder.as_ref(),
+                state.uid_validity,
+                state.highest_modseq as i64,
+                state.uid_next,
+            ),
+        )?;
         Ok(())
     }
 
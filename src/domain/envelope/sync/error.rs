@@ -12,8 +12,10 @@ pub enum Error {
     LockConnectionPoolCursorError(String),
     #[error("cannot find email by internal id {0}")]
     FindConnectionByCursorError(usize),
-    #[error("cannot find email by internal id {0}")]
+    #[error("cannot lock synchronization cache connection: {0}")]
     LockConnectionError(String),
+    #[error("cannot parse journal hunk {0}")]
+    ParseJournalHunkError(String),
 
     #[error(transparent)]
     SqliteError(#[from] rusqlite::Error),
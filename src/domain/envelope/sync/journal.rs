@@ -0,0 +1,346 @@
+use chrono::{DateTime, Local};
+use log::warn;
+
+use crate::{envelope::Mailbox, Envelope};
+
+use super::{BackendHunk, Error, HunkKind, HunkKindRestricted, Patch, Result};
+
+const CREATE_JOURNAL_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS envelopes_journal (
+        account     TEXT     NOT NULL,
+        folder      TEXT     NOT NULL,
+        position    INTEGER  NOT NULL,
+        kind        TEXT     NOT NULL,
+        source      TEXT     DEFAULT NULL,
+        target      TEXT     NOT NULL,
+        refresh     INTEGER  NOT NULL,
+        id          TEXT     NOT NULL,
+        internal_id TEXT     NOT NULL,
+        message_id  TEXT     NOT NULL,
+        flags       TEXT     NOT NULL,
+        sender      TEXT     NOT NULL,
+        subject     TEXT     NOT NULL,
+        date        DATETIME NOT NULL,
+        UNIQUE(account, folder, position)
+    )
+";
+
+const INSERT_HUNK: &str = "
+    INSERT INTO envelopes_journal
+    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+";
+
+const DELETE_HUNKS: &str = "
+    DELETE FROM envelopes_journal
+    WHERE account = ?
+    AND folder = ?
+";
+
+const SELECT_HUNKS: &str = "
+    SELECT kind, source, target, refresh, id, internal_id, message_id, flags, sender, subject, date
+    FROM envelopes_journal
+    WHERE account = ?
+    AND folder = ?
+    ORDER BY position
+";
+
+/// Represents a journaled hunk, as stored in the database.
+struct Row {
+    kind: String,
+    source: Option<String>,
+    target: String,
+    refresh: bool,
+    envelope: Envelope,
+}
+
+/// Write-ahead journal of the envelopes patches. A patch is recorded
+/// before being applied to the backends, and the journal is cleared
+/// in the same transaction as the one applying the cache patch. A
+/// journal that is not empty at the beginning of a synchronization
+/// means that the previous one was interrupted.
+pub struct Journal;
+
+impl Journal {
+    pub fn init(conn: &mut rusqlite::Connection) -> Result<()> {
+        conn.execute(CREATE_JOURNAL_TABLE, ())?;
+        Ok(())
+    }
+
+    /// Records the given patch, replacing any previous one of the
+    /// same folder.
+    pub fn record<N, F>(
+        conn: &mut rusqlite::Connection,
+        name: N,
+        folder: F,
+        patch: &Patch,
+    ) -> Result<()>
+    where
+        N: AsRef<str>,
+        F: AsRef<str>,
+    {
+        let tx = conn.transaction()?;
+        Self::clear(&tx, name.as_ref(), folder.as_ref())?;
+
+        for (position, hunk) in patch.iter().flatten().enumerate() {
+            let empty = Envelope::default();
+            let (kind, source, target, refresh, internal_id, envelope) = match hunk {
+                BackendHunk::CacheEnvelope(_, internal_id, source) => (
+                    "cache",
+                    None,
+                    restricted_to_str(source),
+                    false,
+                    internal_id,
+                    &empty,
+                ),
+                BackendHunk::CopyEmail(_, envelope, source, target, refresh) => (
+                    "copy",
+                    Some(restricted_to_str(source)),
+                    restricted_to_str(target),
+                    *refresh,
+                    &envelope.internal_id,
+                    envelope,
+                ),
+                BackendHunk::RemoveEmail(_, internal_id, target) => (
+                    "remove",
+                    None,
+                    kind_to_str(target),
+                    false,
+                    internal_id,
+                    &empty,
+                ),
+                BackendHunk::SetFlags(_, envelope, target) => (
+                    "flags",
+                    None,
+                    kind_to_str(target),
+                    false,
+                    &envelope.internal_id,
+                    envelope,
+                ),
+            };
+
+            tx.execute(
+                INSERT_HUNK,
+                rusqlite::params![
+                    name.as_ref(),
+                    folder.as_ref(),
+                    position,
+                    kind,
+                    source,
+                    target,
+                    refresh,
+                    &envelope.id,
+                    internal_id,
+                    &envelope.message_id,
+                    envelope.flags.to_string(),
+                    &envelope.from.addr,
+                    &envelope.subject,
+                    envelope.date.to_rfc3339(),
+                ],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Lists the hunks of the recorded patch of the given folder, in
+    /// the order they were recorded.
+    pub fn list<N, F>(
+        conn: &mut rusqlite::Connection,
+        name: N,
+        folder: F,
+    ) -> Result<Vec<BackendHunk>>
+    where
+        N: AsRef<str>,
+        F: AsRef<str>,
+    {
+        let folder = folder.as_ref();
+        let mut stmt = conn.prepare(SELECT_HUNKS)?;
+        let rows: Vec<Row> = stmt
+            .query_map([name.as_ref(), folder], |row| {
+                Ok(Row {
+                    kind: row.get(0)?,
+                    source: row.get(1)?,
+                    target: row.get(2)?,
+                    refresh: row.get(3)?,
+                    envelope: Envelope {
+                        id: row.get(4)?,
+                        internal_id: row.get(5)?,
+                        message_id: row.get(6)?,
+                        flags: row.get::<usize, String>(7)?.as_str().into(),
+                        from: Mailbox::new_nameless(row.get::<usize, String>(8)?),
+                        subject: row.get(9)?,
+                        date: {
+                            let date: String = row.get(10)?;
+                            match DateTime::parse_from_rfc3339(&date) {
+                                Ok(date) => date.with_timezone(&Local),
+                                Err(err) => {
+                                    warn!("invalid date {}, skipping it: {}", date, err);
+                                    DateTime::default()
+                                }
+                            }
+                        },
+                    },
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        rows.into_iter()
+            .map(|row| parse_hunk(folder, row))
+            .collect()
+    }
+
+    /// Clears the recorded patch of the given folder.
+    pub fn clear<N, F>(tx: &rusqlite::Transaction, name: N, folder: F) -> Result<()>
+    where
+        N: AsRef<str>,
+        F: AsRef<str>,
+    {
+        tx.execute(DELETE_HUNKS, [name.as_ref(), folder.as_ref()])?;
+        Ok(())
+    }
+}
+
+fn kind_to_str(kind: &HunkKind) -> &'static str {
+    match kind {
+        HunkKind::LocalCache => "local-cache",
+        HunkKind::Local => "local",
+        HunkKind::RemoteCache => "remote-cache",
+        HunkKind::Remote => "remote",
+    }
+}
+
+fn kind_from_str(kind: &str) -> Result<HunkKind> {
+    match kind {
+        "local-cache" => Ok(HunkKind::LocalCache),
+        "local" => Ok(HunkKind::Local),
+        "remote-cache" => Ok(HunkKind::RemoteCache),
+        "remote" => Ok(HunkKind::Remote),
+        kind => Err(Error::ParseJournalHunkError(kind.to_owned())),
+    }
+}
+
+fn restricted_to_str(kind: &HunkKindRestricted) -> &'static str {
+    match kind {
+        HunkKindRestricted::Local => "local",
+        HunkKindRestricted::Remote => "remote",
+    }
+}
+
+fn restricted_from_str(kind: &str) -> Result<HunkKindRestricted> {
+    match kind {
+        "local" => Ok(HunkKindRestricted::Local),
+        "remote" => Ok(HunkKindRestricted::Remote),
+        kind => Err(Error::ParseJournalHunkError(kind.to_owned())),
+    }
+}
+
+fn parse_hunk(folder: &str, row: Row) -> Result<BackendHunk> {
+    let folder = folder.to_owned();
+    match row.kind.as_str() {
+        "cache" => Ok(BackendHunk::CacheEnvelope(
+            folder,
+            row.envelope.internal_id,
+            restricted_from_str(&row.target)?,
+        )),
+        "copy" => Ok(BackendHunk::CopyEmail(
+            folder,
+            row.envelope,
+            restricted_from_str(row.source.as_deref().unwrap_or_default())?,
+            restricted_from_str(&row.target)?,
+            row.refresh,
+        )),
+        "remove" => Ok(BackendHunk::RemoveEmail(
+            folder,
+            row.envelope.internal_id,
+            kind_from_str(&row.target)?,
+        )),
+        "flags" => Ok(BackendHunk::SetFlags(
+            folder,
+            row.envelope,
+            kind_from_str(&row.target)?,
+        )),
+        kind => Err(Error::ParseJournalHunkError(kind.to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod journal {
+    use chrono::{DateTime, Local};
+
+    use crate::{
+        envelope::{
+            sync::{BackendHunk, HunkKind, HunkKindRestricted},
+            Mailbox,
+        },
+        Envelope, Flag, Flags,
+    };
+
+    use super::Journal;
+
+    #[test]
+    fn record_list_clear() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        Journal::init(&mut conn).unwrap();
+
+        let envelope = Envelope {
+            id: "1".into(),
+            internal_id: "1".into(),
+            message_id: "<1@localhost>".into(),
+            flags: Flags::from_iter([Flag::Seen, Flag::Flagged]),
+            from: Mailbox::new_nameless("alice@localhost"),
+            subject: "subject".into(),
+            date: DateTime::parse_from_rfc3339("2023-01-01T00:00:00+00:00")
+                .unwrap()
+                .with_timezone(&Local),
+        };
+
+        let patch = vec![
+            vec![
+                BackendHunk::CopyEmail(
+                    "INBOX".into(),
+                    envelope.clone(),
+                    HunkKindRestricted::Local,
+                    HunkKindRestricted::Remote,
+                    true,
+                ),
+                BackendHunk::SetFlags("INBOX".into(), envelope.clone(), HunkKind::RemoteCache),
+            ],
+            vec![BackendHunk::RemoveEmail(
+                "INBOX".into(),
+                "2".into(),
+                HunkKind::Local,
+            )],
+            vec![BackendHunk::CacheEnvelope(
+                "INBOX".into(),
+                "3".into(),
+                HunkKindRestricted::Remote,
+            )],
+        ];
+
+        Journal::record(&mut conn, "account", "INBOX", &patch).unwrap();
+        Journal::record(&mut conn, "account", "Sent", &patch[1..].to_vec()).unwrap();
+
+        let hunks = Journal::list(&mut conn, "account", "INBOX").unwrap();
+        assert_eq!(hunks, patch.concat());
+        match &hunks[1] {
+            BackendHunk::SetFlags(_, journaled, _) => {
+                assert_eq!(journaled.flags, envelope.flags);
+                assert_eq!(journaled.date, envelope.date);
+            }
+            hunk => panic!("unexpected hunk: {hunk:?}"),
+        }
+
+        let tx = conn.transaction().unwrap();
+        Journal::clear(&tx, "account", "INBOX").unwrap();
+        tx.commit().unwrap();
+
+        assert!(Journal::list(&mut conn, "account", "INBOX")
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            Journal::list(&mut conn, "account", "Sent").unwrap().len(),
+            2
+        );
+    }
+}
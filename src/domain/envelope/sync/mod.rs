@@ -1,7 +1,9 @@
 pub mod cache;
 mod error;
+pub mod journal;
 pub mod sync;
 
 pub use self::cache::Cache;
 pub use self::error::*;
+pub use self::journal::Journal;
 pub use self::sync::*;
@@ -3,11 +3,15 @@ use rayon::prelude::*;
 use std::{
     collections::{HashMap, HashSet},
     fmt,
+    sync::{Mutex, MutexGuard},
 };
 
-use crate::{flag, AccountConfig, Backend, BackendSyncProgressEvent, Envelope, MaildirBackend};
+use crate::{
+    backend::SyncSide, flag, AccountConfig, Backend, BackendSyncMode, BackendSyncProgressEvent,
+    Envelope, FlagConflictStrategy,
+};
 
-use super::{Cache, Error, Result};
+use super::{Cache, Error, Journal, Result};
 
 pub type Envelopes = HashMap<String, Envelope>;
 
@@ -36,6 +40,15 @@ pub enum HunkKindRestricted {
     Remote,
 }
 
+impl From<SyncSide> for HunkKindRestricted {
+    fn from(side: SyncSide) -> Self {
+        match side {
+            SyncSide::Local => Self::Local,
+            SyncSide::Remote => Self::Remote,
+        }
+    }
+}
+
 impl fmt::Display for HunkKindRestricted {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -101,15 +114,31 @@ impl fmt::Display for BackendHunk {
 
 pub type Patch = Vec<Vec<BackendHunk>>;
 
+/// Represents the outcome of a hunk recovered from the journal of an
+/// interrupted synchronization.
+#[derive(Debug)]
+pub enum HunkRecovery {
+    /// The hunk was found already applied.
+    Replayed,
+    /// The hunk has been discarded, and is left to the next patch.
+    RolledBack,
+    /// The hunk could not be recovered.
+    Failed(Error),
+}
+
 #[derive(Debug, Default)]
 pub struct SyncReport {
     pub patch: Vec<(BackendHunk, Option<Error>)>,
     pub cache_patch: (Vec<CacheHunk>, Option<Error>),
+    pub recovered_patch: Vec<(BackendHunk, HunkRecovery)>,
 }
 
 pub struct SyncBuilder<'a> {
     account_config: &'a AccountConfig,
+    cache_key: String,
     dry_run: bool,
+    mode: BackendSyncMode,
+    flag_conflict_strategy: FlagConflictStrategy,
     on_progress: Box<dyn Fn(BackendSyncProgressEvent) -> Result<()> + Sync + Send + 'a>,
 }
 
@@ -117,7 +146,10 @@ impl<'a> SyncBuilder<'a> {
     pub fn new(account_config: &'a AccountConfig) -> Self {
         Self {
             account_config,
+            cache_key: account_config.name.clone(),
             dry_run: false,
+            mode: BackendSyncMode::default(),
+            flag_conflict_strategy: FlagConflictStrategy::default(),
             on_progress: Box::new(|_| Ok(())),
         }
     }
@@ -127,6 +159,26 @@ impl<'a> SyncBuilder<'a> {
         self
     }
 
+    /// Sets the key of the synchronization cache, which identifies
+    /// the pair of synchronized backends. Defaults to the account
+    /// name.
+    pub fn cache_key<K: ToString>(mut self, key: K) -> Self {
+        self.cache_key = key.to_string();
+        self
+    }
+
+    pub fn mode(mut self, mode: BackendSyncMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets the strategy used to resolve flag conflicts, see
+    /// [`FlagConflictStrategy`].
+    pub fn flag_conflict_strategy(mut self, strategy: FlagConflictStrategy) -> Self {
+        self.flag_conflict_strategy = strategy;
+        self
+    }
+
     pub fn on_progress<F>(mut self, f: F) -> Self
     where
         F: Fn(BackendSyncProgressEvent) -> Result<()> + Sync + Send + 'a,
@@ -145,28 +197,37 @@ impl<'a> SyncBuilder<'a> {
     pub fn sync<F>(
         &self,
         folder: F,
-        conn: &mut rusqlite::Connection,
-        local: &MaildirBackend,
+        conn: &Mutex<rusqlite::Connection>,
+        local: &dyn Backend,
         remote: &dyn Backend,
     ) -> Result<SyncReport>
     where
         F: ToString,
     {
         let account = &self.account_config.name;
+        let cache_key = &self.cache_key;
         let folder = folder.to_string();
         info!("synchronizing {folder} envelopes of account {account}");
 
-        self.try_progress(BackendSyncProgressEvent::GetLocalCachedEnvelopes);
+        let recovered_patch = if self.dry_run {
+            Vec::new()
+        } else {
+            self.recover(&folder, conn, local, remote)?
+        };
+
+        self.try_progress(BackendSyncProgressEvent::GetLocalCachedEnvelopes(
+            folder.clone(),
+        ));
 
         let local_envelopes_cached: Envelopes = HashMap::from_iter(
-            Cache::list_local_envelopes(conn, account, &folder)?
+            Cache::list_local_envelopes(&mut *lock(conn)?, cache_key, &folder)?
                 .iter()
                 .map(|envelope| (envelope.message_id.clone(), envelope.clone())),
         );
 
         trace!("local envelopes cached: {:#?}", local_envelopes_cached);
 
-        self.try_progress(BackendSyncProgressEvent::GetLocalEnvelopes);
+        self.try_progress(BackendSyncProgressEvent::GetLocalEnvelopes(folder.clone()));
 
         let local_envelopes: Envelopes = HashMap::from_iter(
             local
@@ -189,40 +250,93 @@ impl<'a> SyncBuilder<'a> {
 
         trace!("local envelopes: {:#?}", local_envelopes);
 
-        self.try_progress(BackendSyncProgressEvent::GetRemoteCachedEnvelopes);
+        self.try_progress(BackendSyncProgressEvent::GetRemoteCachedEnvelopes(
+            folder.clone(),
+        ));
 
         let remote_envelopes_cached: Envelopes = HashMap::from_iter(
-            Cache::list_remote_envelopes(conn, account, &folder)?
+            Cache::list_remote_envelopes(&mut *lock(conn)?, cache_key, &folder)?
                 .iter()
                 .map(|envelope| (envelope.message_id.clone(), envelope.clone())),
         );
 
         trace!("remote envelopes cached: {:#?}", remote_envelopes_cached);
 
-        self.try_progress(BackendSyncProgressEvent::GetRemoteEnvelopes);
+        self.try_progress(BackendSyncProgressEvent::GetRemoteEnvelopes(folder.clone()));
 
-        let remote_envelopes: Envelopes = HashMap::from_iter(
-            remote
-                .list_envelopes(&folder, 0, 0)
-                .or_else(|err| {
-                    if self.dry_run {
-                        Ok(Default::default())
-                    } else {
-                        Err(Box::new(err))
-                    }
-                })?
-                .iter()
-                .map(|envelope| {
+        let remote_sync_state =
+            Cache::get_remote_sync_state(&mut *lock(conn)?, cache_key, &folder)?;
+        trace!("remote sync state: {:#?}", remote_sync_state);
+
+        let remote_changes = remote
+            .list_envelopes_changes(&folder, remote_sync_state.as_ref())
+            .or_else(|err| {
+                if self.dry_run {
+                    Ok(None)
+                } else {
+                    Err(Box::new(err))
+                }
+            })?;
+
+        let remote_envelopes: Envelopes = match &remote_changes {
+            // Incremental changes are applied on top of the remote
+            // cache, which reflects the remote folder at the time of
+            // the previous synchronization.
+            Some(changes) if !changes.full => {
+                debug!(
+                    "applying {} changed and {} vanished remote envelopes ranges",
+                    changes.envelopes.len(),
+                    changes.vanished.len()
+                );
+                let changed_ids: HashSet<&str> = changes
+                    .envelopes
+                    .iter()
+                    .map(|envelope| envelope.internal_id.as_str())
+                    .collect();
+                let mut envelopes = remote_envelopes_cached.clone();
+                envelopes.retain(|_, envelope| {
+                    !changes.is_vanished(&envelope.internal_id)
+                        && !changed_ids.contains(envelope.internal_id.as_str())
+                });
+                envelopes.extend(changes.envelopes.iter().map(|envelope| {
                     (
                         envelope.message_id.clone(),
                         envelope.clone_without_custom_flags(),
                     )
-                }),
-        );
+                }));
+                envelopes
+            }
+            Some(changes) => HashMap::from_iter(changes.envelopes.iter().map(|envelope| {
+                (
+                    envelope.message_id.clone(),
+                    envelope.clone_without_custom_flags(),
+                )
+            })),
+            None => HashMap::from_iter(
+                remote
+                    .list_envelopes(&folder, 0, 0)
+                    .or_else(|err| {
+                        if self.dry_run {
+                            Ok(Default::default())
+                        } else {
+                            Err(Box::new(err))
+                        }
+                    })?
+                    .iter()
+                    .map(|envelope| {
+                        (
+                            envelope.message_id.clone(),
+                            envelope.clone_without_custom_flags(),
+                        )
+                    }),
+            ),
+        };
 
         trace!("remote envelopes: {:#?}", remote_envelopes);
 
-        self.try_progress(BackendSyncProgressEvent::BuildEnvelopesPatch);
+        self.try_progress(BackendSyncProgressEvent::BuildEnvelopesPatch(
+            folder.clone(),
+        ));
 
         let patch = build_patch(
             &folder,
@@ -230,9 +344,14 @@ impl<'a> SyncBuilder<'a> {
             local_envelopes,
             remote_envelopes_cached,
             remote_envelopes,
+            &self.flag_conflict_strategy,
+            self.mode,
         );
 
-        self.try_progress(BackendSyncProgressEvent::ProcessEnvelopesPatch(patch.len()));
+        self.try_progress(BackendSyncProgressEvent::ProcessEnvelopesPatch(
+            folder.clone(),
+            patch.len(),
+        ));
 
         debug!("envelopes patch: {:#?}", patch);
 
@@ -246,171 +365,12 @@ impl<'a> SyncBuilder<'a> {
                 .map(|patch| (patch, None))
                 .collect();
         } else {
-            let process_hunk = |hunk: &BackendHunk| {
-                Result::Ok(match hunk {
-                    BackendHunk::CacheEnvelope(folder, internal_id, HunkKindRestricted::Local) => {
-                        let envelope = local
-                            .get_envelope_internal(folder, &internal_id)
-                            .map_err(Box::new)?;
-                        vec![CacheHunk::InsertEnvelope(
-                            folder.clone(),
-                            envelope.clone_without_custom_flags(),
-                            TargetRestricted::Local,
-                        )]
-                    }
-                    BackendHunk::CacheEnvelope(folder, internal_id, HunkKindRestricted::Remote) => {
-                        let envelope = remote
-                            .get_envelope_internal(&folder, &internal_id)
-                            .map_err(Box::new)?;
-                        vec![CacheHunk::InsertEnvelope(
-                            folder.clone(),
-                            envelope.clone_without_custom_flags(),
-                            TargetRestricted::Remote,
-                        )]
-                    }
-                    BackendHunk::CopyEmail(
-                        folder,
-                        envelope,
-                        source,
-                        target,
-                        refresh_source_cache,
-                    ) => {
-                        let mut cache_hunks = vec![];
-                        let internal_ids = vec![envelope.internal_id.as_str()];
-                        let emails = match source {
-                            HunkKindRestricted::Local => {
-                                if *refresh_source_cache {
-                                    cache_hunks.push(CacheHunk::InsertEnvelope(
-                                        folder.clone(),
-                                        envelope.clone_without_custom_flags(),
-                                        TargetRestricted::Local,
-                                    ))
-                                };
-                                local.preview_emails_internal(folder, internal_ids)
-                            }
-                            HunkKindRestricted::Remote => {
-                                if *refresh_source_cache {
-                                    cache_hunks.push(CacheHunk::InsertEnvelope(
-                                        folder.clone(),
-                                        envelope.clone_without_custom_flags(),
-                                        TargetRestricted::Remote,
-                                    ))
-                                };
-                                remote.preview_emails_internal(folder, internal_ids)
-                            }
-                        }
-                        .map_err(Box::new)?;
-                        let emails = emails.to_vec();
-                        let email = emails
-                            .first()
-                            .ok_or_else(|| Error::FindEmailError(envelope.internal_id.clone()))?;
-
-                        match target {
-                            HunkKindRestricted::Local => {
-                                let internal_id = local
-                                    .add_email_internal(folder, email.raw()?, &envelope.flags)
-                                    .map_err(Box::new)?;
-                                let envelope = local
-                                    .get_envelope_internal(folder, &internal_id)
-                                    .map_err(Box::new)?;
-                                cache_hunks.push(CacheHunk::InsertEnvelope(
-                                    folder.clone(),
-                                    envelope.clone_without_custom_flags(),
-                                    TargetRestricted::Local,
-                                ));
-                            }
-                            HunkKindRestricted::Remote => {
-                                let internal_id = remote
-                                    .add_email_internal(&folder, email.raw()?, &envelope.flags)
-                                    .map_err(Box::new)?;
-                                let envelope = remote
-                                    .get_envelope_internal(&folder, &internal_id)
-                                    .map_err(Box::new)?;
-                                cache_hunks.push(CacheHunk::InsertEnvelope(
-                                    folder.clone(),
-                                    envelope.clone_without_custom_flags(),
-                                    TargetRestricted::Remote,
-                                ));
-                            }
-                        };
-                        cache_hunks
-                    }
-                    BackendHunk::RemoveEmail(folder, internal_id, HunkKind::LocalCache) => {
-                        vec![CacheHunk::DeleteEnvelope(
-                            folder.clone(),
-                            internal_id.clone(),
-                            TargetRestricted::Local,
-                        )]
-                    }
-                    BackendHunk::RemoveEmail(folder, internal_id, HunkKind::Local) => {
-                        local
-                            .mark_emails_as_deleted_internal(folder, vec![internal_id])
-                            .map_err(Box::new)?;
-                        vec![]
-                    }
-                    BackendHunk::RemoveEmail(folder, internal_id, HunkKind::RemoteCache) => {
-                        vec![CacheHunk::DeleteEnvelope(
-                            folder.clone(),
-                            internal_id.clone(),
-                            TargetRestricted::Remote,
-                        )]
-                    }
-                    BackendHunk::RemoveEmail(folder, internal_id, HunkKind::Remote) => {
-                        remote
-                            .mark_emails_as_deleted_internal(folder, vec![internal_id])
-                            .map_err(Box::new)?;
-                        vec![]
-                    }
-                    BackendHunk::SetFlags(folder, envelope, HunkKind::LocalCache) => {
-                        vec![
-                            CacheHunk::DeleteEnvelope(
-                                folder.clone(),
-                                envelope.internal_id.clone(),
-                                TargetRestricted::Local,
-                            ),
-                            CacheHunk::InsertEnvelope(
-                                folder.clone(),
-                                envelope.clone(),
-                                TargetRestricted::Local,
-                            ),
-                        ]
-                    }
-                    BackendHunk::SetFlags(folder, envelope, HunkKind::Local) => {
-                        local
-                            .set_flags_internal(
-                                folder,
-                                vec![&envelope.internal_id],
-                                &envelope.flags,
-                            )
-                            .map_err(Box::new)?;
-                        vec![]
-                    }
-                    BackendHunk::SetFlags(folder, envelope, HunkKind::RemoteCache) => {
-                        vec![
-                            CacheHunk::DeleteEnvelope(
-                                folder.clone(),
-                                envelope.internal_id.clone(),
-                                TargetRestricted::Remote,
-                            ),
-                            CacheHunk::InsertEnvelope(
-                                folder.clone(),
-                                envelope.clone(),
-                                TargetRestricted::Remote,
-                            ),
-                        ]
-                    }
-                    BackendHunk::SetFlags(folder, envelope, HunkKind::Remote) => {
-                        remote
-                            .set_flags_internal(
-                                folder,
-                                vec![&envelope.internal_id],
-                                &envelope.flags,
-                            )
-                            .map_err(Box::new)?;
-                        vec![]
-                    }
-                })
-            };
+            // The patch is journaled before being applied, so that the
+            // next synchronization can recover it if this one gets
+            // interrupted.
+            if !patch.is_empty() {
+                Journal::record(&mut *lock(conn)?, cache_key, &folder, &patch)?;
+            }
 
             report = patch
                 .par_iter()
@@ -421,9 +381,12 @@ impl<'a> SyncBuilder<'a> {
                         trace!("processing hunk: {hunk:#?}");
                         debug!("{hunk_str}");
 
-                        self.try_progress(BackendSyncProgressEvent::ProcessEnvelopeHunk(hunk_str));
+                        self.try_progress(BackendSyncProgressEvent::ProcessEnvelopeHunk(
+                            folder.clone(),
+                            hunk_str,
+                        ));
 
-                        match process_hunk(hunk) {
+                        match self.process_hunk(hunk, local, remote) {
                             Ok(cache_hunks) => {
                                 report.patch.push((hunk.clone(), None));
                                 report.cache_patch.0.extend(cache_hunks);
@@ -443,26 +406,11 @@ impl<'a> SyncBuilder<'a> {
                     r1
                 });
 
-            let mut process_cache_patch = || {
+            let process_cache_patch = || {
+                let mut conn = lock(conn)?;
                 let tx = conn.transaction()?;
-                for hunk in &report.cache_patch.0 {
-                    match hunk {
-                        CacheHunk::InsertEnvelope(folder, envelope, TargetRestricted::Local) => {
-                            Cache::insert_local_envelope(&tx, account, folder, envelope.clone())?
-                        }
-                        CacheHunk::InsertEnvelope(folder, envelope, TargetRestricted::Remote) => {
-                            Cache::insert_remote_envelope(&tx, account, folder, envelope.clone())?
-                        }
-                        CacheHunk::DeleteEnvelope(folder, internal_id, TargetRestricted::Local) => {
-                            Cache::delete_local_envelope(&tx, account, folder, internal_id)?
-                        }
-                        CacheHunk::DeleteEnvelope(
-                            folder,
-                            internal_id,
-                            TargetRestricted::Remote,
-                        ) => Cache::delete_remote_envelope(&tx, account, folder, internal_id)?,
-                    }
-                }
+                apply_cache_patch(&tx, cache_key, &report.cache_patch.0)?;
+                Journal::clear(&tx, cache_key, &folder)?;
                 tx.commit()?;
                 Result::Ok(())
             };
@@ -471,12 +419,362 @@ impl<'a> SyncBuilder<'a> {
                 warn!("error while processing cache patch: {err}");
                 report.cache_patch.1 = Some(err);
             }
+
+            // The sync state is saved only when the whole patch
+            // succeeded, otherwise changes that failed to be applied
+            // would be lost by the next incremental synchronization.
+            let patch_succeeded =
+                report.cache_patch.1.is_none() && report.patch.iter().all(|(_, err)| err.is_none());
+
+            if let Some(changes) = remote_changes.filter(|_| patch_succeeded) {
+                trace!("saving remote sync state: {:#?}", changes.state);
+                if let Err(err) = lock(conn).and_then(|mut conn| {
+                    Ok(Cache::set_remote_sync_state(
+                        &mut conn,
+                        cache_key,
+                        &folder,
+                        &changes.state,
+                    )?)
+                }) {
+                    warn!("error while saving remote sync state: {err}");
+                    report.cache_patch.1 = Some(err);
+                }
+            }
         }
 
+        report.recovered_patch = recovered_patch;
+
         trace!("sync report: {:#?}", report);
 
         Ok(report)
     }
+
+    /// Recovers the patch left in the journal by an interrupted
+    /// synchronization of the given folder. Copies are the only
+    /// hunks that the next patch cannot recompute: a copy is
+    /// considered applied when the target folder contains an email
+    /// with the same Message-ID, otherwise it is rolled back.
+    ///
+    /// Other hunks are rolled back without being replayed, since the
+    /// emails may have changed since the interruption. The cache
+    /// patch of the interrupted synchronization has not been
+    /// applied, so the cache still reflects the state preceding it
+    /// and the next patch recomputes those hunks from the current
+    /// state of the backends. The resulting cache patch is applied
+    /// and the journal cleared in a single transaction.
+    fn recover(
+        &self,
+        folder: &str,
+        conn: &Mutex<rusqlite::Connection>,
+        local: &dyn Backend,
+        remote: &dyn Backend,
+    ) -> Result<Vec<(BackendHunk, HunkRecovery)>> {
+        let cache_key = &self.cache_key;
+        let hunks = Journal::list(&mut *lock(conn)?, cache_key, folder)?;
+
+        if hunks.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        warn!(
+            "recovering {} hunks of interrupted {folder} envelopes patch",
+            hunks.len()
+        );
+        self.try_progress(BackendSyncProgressEvent::RecoverEnvelopesPatch(
+            folder.to_owned(),
+            hunks.len(),
+        ));
+
+        let mut local_envelopes = None;
+        let mut remote_envelopes = None;
+        let mut recovered_patch = Vec::new();
+        let mut cache_patch = Vec::new();
+
+        for hunk in hunks {
+            debug!("recovering hunk: {hunk}");
+
+            let recovered = match &hunk {
+                BackendHunk::CopyEmail(folder, envelope, source, target, refresh_source_cache) => {
+                    let (backend, envelopes) = match target {
+                        HunkKindRestricted::Local => (local, &mut local_envelopes),
+                        HunkKindRestricted::Remote => (remote, &mut remote_envelopes),
+                    };
+                    find_envelope(backend, folder, &envelope.message_id, envelopes).map(|copied| {
+                        match copied {
+                            Some(copied) => {
+                                let mut cache_hunks = vec![];
+                                if *refresh_source_cache {
+                                    cache_hunks.push(CacheHunk::InsertEnvelope(
+                                        folder.clone(),
+                                        envelope.clone_without_custom_flags(),
+                                        source.clone(),
+                                    ));
+                                }
+                                cache_hunks.push(CacheHunk::InsertEnvelope(
+                                    folder.clone(),
+                                    copied,
+                                    target.clone(),
+                                ));
+                                (HunkRecovery::Replayed, cache_hunks)
+                            }
+                            None => (HunkRecovery::RolledBack, vec![]),
+                        }
+                    })
+                }
+                _ => Ok((HunkRecovery::RolledBack, vec![])),
+            };
+
+            match recovered {
+                Ok((recovery, cache_hunks)) => {
+                    cache_patch.extend(cache_hunks);
+                    recovered_patch.push((hunk, recovery));
+                }
+                Err(err) => {
+                    warn!("error while recovering hunk {hunk:?}, skipping it: {err:?}");
+                    recovered_patch.push((hunk, HunkRecovery::Failed(err)));
+                }
+            }
+        }
+
+        // Insertions are preceded by deletions so that the cache
+        // patch can be applied whatever the state of the cache.
+        let cache_patch: Vec<CacheHunk> = cache_patch
+            .into_iter()
+            .flat_map(|hunk| match hunk {
+                CacheHunk::InsertEnvelope(folder, envelope, target) => vec![
+                    CacheHunk::DeleteEnvelope(
+                        folder.clone(),
+                        envelope.internal_id.clone(),
+                        target.clone(),
+                    ),
+                    CacheHunk::InsertEnvelope(folder, envelope, target),
+                ],
+                hunk => vec![hunk],
+            })
+            .collect();
+
+        let mut conn = lock(conn)?;
+        let tx = conn.transaction()?;
+        apply_cache_patch(&tx, cache_key, &cache_patch)?;
+        Journal::clear(&tx, cache_key, folder)?;
+        tx.commit()?;
+
+        Ok(recovered_patch)
+    }
+
+    /// Applies the given hunk to the backends and returns the
+    /// resulting cache hunks.
+    fn process_hunk(
+        &self,
+        hunk: &BackendHunk,
+        local: &dyn Backend,
+        remote: &dyn Backend,
+    ) -> Result<Vec<CacheHunk>> {
+        Result::Ok(match hunk {
+            BackendHunk::CacheEnvelope(folder, internal_id, HunkKindRestricted::Local) => {
+                let envelope = local
+                    .get_envelope_internal(folder, &internal_id)
+                    .map_err(Box::new)?;
+                vec![CacheHunk::InsertEnvelope(
+                    folder.clone(),
+                    envelope.clone_without_custom_flags(),
+                    TargetRestricted::Local,
+                )]
+            }
+            BackendHunk::CacheEnvelope(folder, internal_id, HunkKindRestricted::Remote) => {
+                let envelope = remote
+                    .get_envelope_internal(&folder, &internal_id)
+                    .map_err(Box::new)?;
+                vec![CacheHunk::InsertEnvelope(
+                    folder.clone(),
+                    envelope.clone_without_custom_flags(),
+                    TargetRestricted::Remote,
+                )]
+            }
+            BackendHunk::CopyEmail(folder, envelope, source, target, refresh_source_cache) => {
+                let mut cache_hunks = vec![];
+                let internal_ids = vec![envelope.internal_id.as_str()];
+                let emails = match source {
+                    HunkKindRestricted::Local => {
+                        if *refresh_source_cache {
+                            cache_hunks.push(CacheHunk::InsertEnvelope(
+                                folder.clone(),
+                                envelope.clone_without_custom_flags(),
+                                TargetRestricted::Local,
+                            ))
+                        };
+                        local.preview_emails_internal(folder, internal_ids)
+                    }
+                    HunkKindRestricted::Remote => {
+                        if *refresh_source_cache {
+                            cache_hunks.push(CacheHunk::InsertEnvelope(
+                                folder.clone(),
+                                envelope.clone_without_custom_flags(),
+                                TargetRestricted::Remote,
+                            ))
+                        };
+                        remote.preview_emails_internal(folder, internal_ids)
+                    }
+                }
+                .map_err(Box::new)?;
+                let emails = emails.to_vec();
+                let email = emails
+                    .first()
+                    .ok_or_else(|| Error::FindEmailError(envelope.internal_id.clone()))?;
+
+                match target {
+                    HunkKindRestricted::Local => {
+                        let internal_id = local
+                            .add_email_internal(folder, email.raw()?, &envelope.flags)
+                            .map_err(Box::new)?;
+                        let envelope = local
+                            .get_envelope_internal(folder, &internal_id)
+                            .map_err(Box::new)?;
+                        cache_hunks.push(CacheHunk::InsertEnvelope(
+                            folder.clone(),
+                            envelope.clone_without_custom_flags(),
+                            TargetRestricted::Local,
+                        ));
+                    }
+                    HunkKindRestricted::Remote => {
+                        let internal_id = remote
+                            .add_email_internal(&folder, email.raw()?, &envelope.flags)
+                            .map_err(Box::new)?;
+                        let envelope = remote
+                            .get_envelope_internal(&folder, &internal_id)
+                            .map_err(Box::new)?;
+                        cache_hunks.push(CacheHunk::InsertEnvelope(
+                            folder.clone(),
+                            envelope.clone_without_custom_flags(),
+                            TargetRestricted::Remote,
+                        ));
+                    }
+                };
+                cache_hunks
+            }
+            BackendHunk::RemoveEmail(folder, internal_id, HunkKind::LocalCache) => {
+                vec![CacheHunk::DeleteEnvelope(
+                    folder.clone(),
+                    internal_id.clone(),
+                    TargetRestricted::Local,
+                )]
+            }
+            BackendHunk::RemoveEmail(folder, internal_id, HunkKind::Local) => {
+                local
+                    .mark_emails_as_deleted_internal(folder, vec![internal_id])
+                    .map_err(Box::new)?;
+                vec![]
+            }
+            BackendHunk::RemoveEmail(folder, internal_id, HunkKind::RemoteCache) => {
+                vec![CacheHunk::DeleteEnvelope(
+                    folder.clone(),
+                    internal_id.clone(),
+                    TargetRestricted::Remote,
+                )]
+            }
+            BackendHunk::RemoveEmail(folder, internal_id, HunkKind::Remote) => {
+                remote
+                    .mark_emails_as_deleted_internal(folder, vec![internal_id])
+                    .map_err(Box::new)?;
+                vec![]
+            }
+            BackendHunk::SetFlags(folder, envelope, HunkKind::LocalCache) => {
+                vec![
+                    CacheHunk::DeleteEnvelope(
+                        folder.clone(),
+                        envelope.internal_id.clone(),
+                        TargetRestricted::Local,
+                    ),
+                    CacheHunk::InsertEnvelope(
+                        folder.clone(),
+                        envelope.clone(),
+                        TargetRestricted::Local,
+                    ),
+                ]
+            }
+            BackendHunk::SetFlags(folder, envelope, HunkKind::Local) => {
+                local
+                    .set_flags_internal(folder, vec![&envelope.internal_id], &envelope.flags)
+                    .map_err(Box::new)?;
+                vec![]
+            }
+            BackendHunk::SetFlags(folder, envelope, HunkKind::RemoteCache) => {
+                vec![
+                    CacheHunk::DeleteEnvelope(
+                        folder.clone(),
+                        envelope.internal_id.clone(),
+                        TargetRestricted::Remote,
+                    ),
+                    CacheHunk::InsertEnvelope(
+                        folder.clone(),
+                        envelope.clone(),
+                        TargetRestricted::Remote,
+                    ),
+                ]
+            }
+            BackendHunk::SetFlags(folder, envelope, HunkKind::Remote) => {
+                remote
+                    .set_flags_internal(folder, vec![&envelope.internal_id], &envelope.flags)
+                    .map_err(Box::new)?;
+                vec![]
+            }
+        })
+    }
+}
+
+fn apply_cache_patch(
+    tx: &rusqlite::Transaction,
+    cache_key: &str,
+    cache_patch: &[CacheHunk],
+) -> Result<()> {
+    for hunk in cache_patch {
+        match hunk {
+            CacheHunk::InsertEnvelope(folder, envelope, TargetRestricted::Local) => {
+                Cache::insert_local_envelope(tx, cache_key, folder, envelope.clone())?
+            }
+            CacheHunk::InsertEnvelope(folder, envelope, TargetRestricted::Remote) => {
+                Cache::insert_remote_envelope(tx, cache_key, folder, envelope.clone())?
+            }
+            CacheHunk::DeleteEnvelope(folder, internal_id, TargetRestricted::Local) => {
+                Cache::delete_local_envelope(tx, cache_key, folder, internal_id)?
+            }
+            CacheHunk::DeleteEnvelope(folder, internal_id, TargetRestricted::Remote) => {
+                Cache::delete_remote_envelope(tx, cache_key, folder, internal_id)?
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Finds the envelope matching the given Message-ID in the given
+/// folder. The folder is listed once, then kept in `envelopes`.
+fn find_envelope(
+    backend: &dyn Backend,
+    folder: &str,
+    message_id: &str,
+    envelopes: &mut Option<Envelopes>,
+) -> Result<Option<Envelope>> {
+    if envelopes.is_none() {
+        let listed = backend.list_envelopes(folder, 0, 0).map_err(Box::new)?;
+        *envelopes = Some(HashMap::from_iter(listed.iter().map(|envelope| {
+            (
+                envelope.message_id.clone(),
+                envelope.clone_without_custom_flags(),
+            )
+        })));
+    }
+
+    Ok(envelopes
+        .as_ref()
+        .and_then(|envelopes| envelopes.get(message_id))
+        .cloned())
+}
+
+/// Locks the connection to the synchronization cache, which is
+/// shared by folders synchronized concurrently.
+fn lock(conn: &Mutex<rusqlite::Connection>) -> Result<MutexGuard<'_, rusqlite::Connection>> {
+    conn.lock()
+        .map_err(|err| Error::LockConnectionError(err.to_string()))
 }
 
 pub fn build_patch<F>(
@@ -485,10 +783,24 @@ pub fn build_patch<F>(
     local: Envelopes,
     remote_cache: Envelopes,
     remote: Envelopes,
+    flag_conflict_strategy: &FlagConflictStrategy,
+    mode: BackendSyncMode,
 ) -> Patch
 where
     F: Clone + ToString,
 {
+    if let Some(source) = mode.source() {
+        return build_one_way_patch(
+            folder,
+            local_cache,
+            local,
+            remote_cache,
+            remote,
+            source,
+            mode.keep_target_only(),
+        );
+    }
+
     let mut patch: Patch = vec![];
     let mut message_ids = HashSet::new();
 
@@ -662,7 +974,13 @@ where
                     HunkKindRestricted::Local,
                 )]);
 
-                let flags = flag::sync_all(None, Some(local), Some(remote_cache), Some(remote));
+                let flags = flag::sync_all(
+                    None,
+                    Some(local),
+                    Some(remote_cache),
+                    Some(remote),
+                    flag_conflict_strategy,
+                );
 
                 if local.flags != flags {
                     patch.push(vec![BackendHunk::SetFlags(
@@ -812,7 +1130,13 @@ where
             // needs to be updated. Flags also need to be
             // synchronized.
             (Some(local_cache), Some(local), None, Some(remote)) => {
-                let flags = flag::sync_all(Some(local_cache), Some(local), None, Some(remote));
+                let flags = flag::sync_all(
+                    Some(local_cache),
+                    Some(local),
+                    None,
+                    Some(remote),
+                    flag_conflict_strategy,
+                );
 
                 if local_cache.flags != flags {
                     patch.push(vec![BackendHunk::SetFlags(
@@ -887,6 +1211,7 @@ where
                     Some(local),
                     Some(remote_cache),
                     Some(remote),
+                    flag_conflict_strategy,
                 );
 
                 if local_cache.flags != flags {
@@ -939,9 +1264,194 @@ where
     patch
 }
 
+/// Builds the envelopes patch of a one-way synchronization. The
+/// source side is never modified, the target side follows it (flags
+/// included, so flag conflicts cannot happen). Emails that only exist
+/// target side are kept when `keep_target_only` is enabled, unless
+/// they were removed source side.
+fn build_one_way_patch<F>(
+    folder: F,
+    local_cache: Envelopes,
+    local: Envelopes,
+    remote_cache: Envelopes,
+    remote: Envelopes,
+    source: SyncSide,
+    keep_target_only: bool,
+) -> Patch
+where
+    F: Clone + ToString,
+{
+    let mut patch: Patch = vec![];
+    let mut message_ids = HashSet::new();
+
+    message_ids.extend(local_cache.keys().map(String::as_str));
+    message_ids.extend(local.keys().map(String::as_str));
+    message_ids.extend(remote_cache.keys().map(String::as_str));
+    message_ids.extend(remote.keys().map(String::as_str));
+
+    let target = source.other();
+    let (source_cache_kind, target_cache_kind, target_kind) = match source {
+        SyncSide::Local => (
+            HunkKind::LocalCache,
+            HunkKind::RemoteCache,
+            HunkKind::Remote,
+        ),
+        SyncSide::Remote => (HunkKind::RemoteCache, HunkKind::LocalCache, HunkKind::Local),
+    };
+    let (source_cache_envelopes, source_envelopes, target_cache_envelopes, target_envelopes) =
+        match source {
+            SyncSide::Local => (&local_cache, &local, &remote_cache, &remote),
+            SyncSide::Remote => (&remote_cache, &remote, &local_cache, &local),
+        };
+
+    for message_id in message_ids {
+        let source_cache = source_cache_envelopes.get(message_id);
+        let source_envelope = source_envelopes.get(message_id);
+        let target_cache = target_cache_envelopes.get(message_id);
+        let target_envelope = target_envelopes.get(message_id);
+
+        match source_envelope {
+            Some(source_envelope) => {
+                // The source cache follows the source side.
+                match source_cache {
+                    None => patch.push(vec![BackendHunk::CacheEnvelope(
+                        folder.to_string(),
+                        source_envelope.internal_id.clone(),
+                        source.into(),
+                    )]),
+                    Some(source_cache) if source_cache.flags != source_envelope.flags => patch
+                        .push(vec![BackendHunk::SetFlags(
+                            folder.to_string(),
+                            Envelope {
+                                flags: source_envelope.flags.clone(),
+                                ..source_cache.clone()
+                            },
+                            source_cache_kind.clone(),
+                        )]),
+                    Some(_) => (),
+                }
+
+                // The target side and its cache follow the source
+                // side.
+                let mut hunks = vec![];
+
+                match target_envelope {
+                    None => {
+                        if let Some(target_cache) = target_cache {
+                            hunks.push(BackendHunk::RemoveEmail(
+                                folder.to_string(),
+                                target_cache.internal_id.clone(),
+                                target_cache_kind.clone(),
+                            ));
+                        }
+                        hunks.push(BackendHunk::CopyEmail(
+                            folder.to_string(),
+                            source_envelope.clone(),
+                            source.into(),
+                            target.into(),
+                            false,
+                        ));
+                    }
+                    Some(target_envelope) => {
+                        if target_envelope.flags != source_envelope.flags {
+                            hunks.push(BackendHunk::SetFlags(
+                                folder.to_string(),
+                                Envelope {
+                                    flags: source_envelope.flags.clone(),
+                                    ..target_envelope.clone()
+                                },
+                                target_kind.clone(),
+                            ));
+                        }
+
+                        match target_cache {
+                            None => hunks.push(BackendHunk::CacheEnvelope(
+                                folder.to_string(),
+                                target_envelope.internal_id.clone(),
+                                target.into(),
+                            )),
+                            Some(target_cache) if target_cache.flags != source_envelope.flags => {
+                                hunks.push(BackendHunk::SetFlags(
+                                    folder.to_string(),
+                                    Envelope {
+                                        flags: source_envelope.flags.clone(),
+                                        ..target_cache.clone()
+                                    },
+                                    target_cache_kind.clone(),
+                                ))
+                            }
+                            Some(_) => (),
+                        }
+                    }
+                }
+
+                if !hunks.is_empty() {
+                    patch.push(hunks);
+                }
+            }
+            None => {
+                if let Some(source_cache) = source_cache {
+                    patch.push(vec![BackendHunk::RemoveEmail(
+                        folder.to_string(),
+                        source_cache.internal_id.clone(),
+                        source_cache_kind.clone(),
+                    )]);
+                }
+
+                // An email missing source side but present in the
+                // source cache has been removed source side.
+                let keep = keep_target_only && source_cache.is_none();
+
+                match (target_cache, target_envelope) {
+                    (None, Some(target_envelope)) if keep => {
+                        patch.push(vec![BackendHunk::CacheEnvelope(
+                            folder.to_string(),
+                            target_envelope.internal_id.clone(),
+                            target.into(),
+                        )])
+                    }
+                    (Some(target_cache), Some(target_envelope))
+                        if keep && target_cache.flags != target_envelope.flags =>
+                    {
+                        patch.push(vec![BackendHunk::SetFlags(
+                            folder.to_string(),
+                            Envelope {
+                                flags: target_envelope.flags.clone(),
+                                ..target_cache.clone()
+                            },
+                            target_cache_kind.clone(),
+                        )])
+                    }
+                    (_, Some(_)) if keep => (),
+                    (target_cache, target_envelope) => {
+                        if let Some(target_cache) = target_cache {
+                            patch.push(vec![BackendHunk::RemoveEmail(
+                                folder.to_string(),
+                                target_cache.internal_id.clone(),
+                                target_cache_kind.clone(),
+                            )]);
+                        }
+                        if let Some(target_envelope) = target_envelope {
+                            patch.push(vec![BackendHunk::RemoveEmail(
+                                folder.to_string(),
+                                target_envelope.internal_id.clone(),
+                                target_kind.clone(),
+                            )]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    patch
+}
+
 #[cfg(test)]
 mod envelopes_sync {
-    use crate::{Envelope, Flag, Flags};
+    use crate::{
+        BackendSyncMode, Envelope, Flag, FlagConflictResolution, FlagConflictStrategy, Flags,
+    };
 
     use super::{BackendHunk, Envelopes, HunkKind, HunkKindRestricted, Patch};
 
@@ -953,7 +1463,15 @@ mod envelopes_sync {
         let remote = Envelopes::default();
 
         assert_eq!(
-            super::build_patch("inbox", local_cache, local, remote_cache, remote),
+            super::build_patch(
+                "inbox",
+                local_cache,
+                local,
+                remote_cache,
+                remote,
+                &FlagConflictStrategy::default(),
+                BackendSyncMode::Bidirectional,
+            ),
             vec![] as Patch
         );
     }
@@ -973,7 +1491,15 @@ mod envelopes_sync {
         )]);
 
         assert_eq!(
-            super::build_patch("inbox", local_cache, local, remote_cache, remote),
+            super::build_patch(
+                "inbox",
+                local_cache,
+                local,
+                remote_cache,
+                remote,
+                &FlagConflictStrategy::default(),
+                BackendSyncMode::Bidirectional,
+            ),
             vec![vec![BackendHunk::CopyEmail(
                 "inbox".into(),
                 Envelope {
@@ -1003,7 +1529,15 @@ mod envelopes_sync {
         let remote = Envelopes::default();
 
         assert_eq!(
-            super::build_patch("inbox", local_cache, local, remote_cache, remote),
+            super::build_patch(
+                "inbox",
+                local_cache,
+                local,
+                remote_cache,
+                remote,
+                &FlagConflictStrategy::default(),
+                BackendSyncMode::Bidirectional,
+            ),
             vec![vec![BackendHunk::RemoveEmail(
                 "inbox".into(),
                 "remote-cache-id".into(),
@@ -1034,7 +1568,15 @@ mod envelopes_sync {
         )]);
 
         assert_eq!(
-            super::build_patch("inbox", local_cache, local, remote_cache, remote),
+            super::build_patch(
+                "inbox",
+                local_cache,
+                local,
+                remote_cache,
+                remote,
+                &FlagConflictStrategy::default(),
+                BackendSyncMode::Bidirectional,
+            ),
             vec![vec![BackendHunk::CopyEmail(
                 "inbox".into(),
                 Envelope {
@@ -1071,7 +1613,15 @@ mod envelopes_sync {
         )]);
 
         assert_eq!(
-            super::build_patch("inbox", local_cache, local, remote_cache, remote),
+            super::build_patch(
+                "inbox",
+                local_cache,
+                local,
+                remote_cache,
+                remote,
+                &FlagConflictStrategy::default(),
+                BackendSyncMode::Bidirectional,
+            ),
             vec![
                 vec![BackendHunk::CopyEmail(
                     "inbox".into(),
@@ -1112,7 +1662,15 @@ mod envelopes_sync {
         let remote = Envelopes::default();
 
         assert_eq!(
-            super::build_patch("inbox", local_cache, local, remote_cache, remote),
+            super::build_patch(
+                "inbox",
+                local_cache,
+                local,
+                remote_cache,
+                remote,
+                &FlagConflictStrategy::default(),
+                BackendSyncMode::Bidirectional,
+            ),
             vec![vec![BackendHunk::CopyEmail(
                 "inbox".into(),
                 Envelope {
@@ -1222,10 +1780,18 @@ mod envelopes_sync {
             ),
         ]);
 
-        let patch = super::build_patch("inbox", local_cache, local, remote_cache, remote)
-            .into_iter()
-            .flatten()
-            .collect::<Vec<_>>();
+        let patch = super::build_patch(
+            "inbox",
+            local_cache,
+            local,
+            remote_cache,
+            remote,
+            &FlagConflictStrategy::default(),
+            BackendSyncMode::Bidirectional,
+        )
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
 
         assert_eq!(patch.len(), 10);
         assert!(patch.contains(&BackendHunk::RemoveEmail(
@@ -1336,7 +1902,15 @@ mod envelopes_sync {
         let remote = Envelopes::default();
 
         assert_eq!(
-            super::build_patch("inbox", local_cache, local, remote_cache, remote),
+            super::build_patch(
+                "inbox",
+                local_cache,
+                local,
+                remote_cache,
+                remote,
+                &FlagConflictStrategy::default(),
+                BackendSyncMode::Bidirectional,
+            ),
             vec![vec![
                 BackendHunk::RemoveEmail("inbox".into(), "remote-id".into(), HunkKind::RemoteCache),
                 BackendHunk::CopyEmail(
@@ -1383,7 +1957,15 @@ mod envelopes_sync {
         )]);
 
         assert_eq!(
-            super::build_patch("inbox", local_cache, local, remote_cache, remote),
+            super::build_patch(
+                "inbox",
+                local_cache,
+                local,
+                remote_cache,
+                remote,
+                &FlagConflictStrategy::default(),
+                BackendSyncMode::Bidirectional,
+            ),
             vec![vec![BackendHunk::CacheEnvelope(
                 "inbox".into(),
                 "local-id".into(),
@@ -1407,7 +1989,15 @@ mod envelopes_sync {
         let remote = Envelopes::default();
 
         assert_eq!(
-            super::build_patch("inbox", local_cache, local, remote_cache, remote),
+            super::build_patch(
+                "inbox",
+                local_cache,
+                local,
+                remote_cache,
+                remote,
+                &FlagConflictStrategy::default(),
+                BackendSyncMode::Bidirectional,
+            ),
             vec![vec![BackendHunk::RemoveEmail(
                 "inbox".into(),
                 "local-cache-id".into(),
@@ -1438,7 +2028,15 @@ mod envelopes_sync {
         )]);
 
         assert_eq!(
-            super::build_patch("inbox", local_cache, local, remote_cache, remote),
+            super::build_patch(
+                "inbox",
+                local_cache,
+                local,
+                remote_cache,
+                remote,
+                &FlagConflictStrategy::default(),
+                BackendSyncMode::Bidirectional,
+            ),
             vec![vec![
                 BackendHunk::RemoveEmail(
                     "inbox".into(),
@@ -1482,7 +2080,15 @@ mod envelopes_sync {
         let remote = Envelopes::default();
 
         assert_eq!(
-            super::build_patch("inbox", local_cache, local, remote_cache, remote),
+            super::build_patch(
+                "inbox",
+                local_cache,
+                local,
+                remote_cache,
+                remote,
+                &FlagConflictStrategy::default(),
+                BackendSyncMode::Bidirectional,
+            ),
             vec![
                 vec![BackendHunk::RemoveEmail(
                     "inbox".into(),
@@ -1527,7 +2133,15 @@ mod envelopes_sync {
         )]);
 
         assert_eq!(
-            super::build_patch("inbox", local_cache, local, remote_cache, remote),
+            super::build_patch(
+                "inbox",
+                local_cache,
+                local,
+                remote_cache,
+                remote,
+                &FlagConflictStrategy::default(),
+                BackendSyncMode::Bidirectional,
+            ),
             vec![
                 vec![BackendHunk::RemoveEmail(
                     "inbox".into(),
@@ -1570,7 +2184,15 @@ mod envelopes_sync {
         let remote = Envelopes::default();
 
         assert_eq!(
-            super::build_patch("inbox", local_cache, local, remote_cache, remote),
+            super::build_patch(
+                "inbox",
+                local_cache,
+                local,
+                remote_cache,
+                remote,
+                &FlagConflictStrategy::default(),
+                BackendSyncMode::Bidirectional,
+            ),
             vec![vec![BackendHunk::CopyEmail(
                 "inbox".into(),
                 Envelope {
@@ -1607,7 +2229,15 @@ mod envelopes_sync {
         let remote = Envelopes::default();
 
         assert_eq!(
-            super::build_patch("inbox", local_cache, local, remote_cache, remote),
+            super::build_patch(
+                "inbox",
+                local_cache,
+                local,
+                remote_cache,
+                remote,
+                &FlagConflictStrategy::default(),
+                BackendSyncMode::Bidirectional,
+            ),
             vec![
                 vec![BackendHunk::CopyEmail(
                     "inbox".into(),
@@ -1662,7 +2292,15 @@ mod envelopes_sync {
         )]);
 
         assert_eq!(
-            super::build_patch("inbox", local_cache, local, remote_cache, remote),
+            super::build_patch(
+                "inbox",
+                local_cache,
+                local,
+                remote_cache,
+                remote,
+                &FlagConflictStrategy::default(),
+                BackendSyncMode::Bidirectional,
+            ),
             vec![vec![BackendHunk::CacheEnvelope(
                 "inbox".into(),
                 "remote-id".into(),
@@ -1671,6 +2309,72 @@ mod envelopes_sync {
         );
     }
 
+    #[test]
+    fn build_patch_1101_flag_conflict() {
+        let local_cache = Envelopes::from_iter([(
+            "message_id".into(),
+            Envelope {
+                internal_id: "local-cache-id".into(),
+                flags: "seen".into(),
+                ..Envelope::default()
+            },
+        )]);
+        let local = Envelopes::from_iter([(
+            "message_id".into(),
+            Envelope {
+                internal_id: "local-id".into(),
+                flags: Flags::default(),
+                ..Envelope::default()
+            },
+        )]);
+        let remote_cache = Envelopes::default();
+        let remote = Envelopes::from_iter([(
+            "message_id".into(),
+            Envelope {
+                internal_id: "remote-id".into(),
+                flags: "seen".into(),
+                ..Envelope::default()
+            },
+        )]);
+
+        assert_eq!(
+            super::build_patch(
+                "inbox",
+                local_cache,
+                local,
+                remote_cache,
+                remote,
+                &FlagConflictStrategy::new(FlagConflictResolution::PreferLocal),
+                BackendSyncMode::Bidirectional,
+            ),
+            vec![
+                vec![BackendHunk::SetFlags(
+                    "inbox".into(),
+                    Envelope {
+                        internal_id: "local-cache-id".into(),
+                        flags: Flags::default(),
+                        ..Envelope::default()
+                    },
+                    HunkKind::LocalCache,
+                )],
+                vec![BackendHunk::SetFlags(
+                    "inbox".into(),
+                    Envelope {
+                        internal_id: "remote-id".into(),
+                        flags: Flags::default(),
+                        ..Envelope::default()
+                    },
+                    HunkKind::Remote,
+                )],
+                vec![BackendHunk::CacheEnvelope(
+                    "inbox".into(),
+                    "remote-id".into(),
+                    HunkKindRestricted::Remote,
+                )],
+            ],
+        );
+    }
+
     #[test]
     fn build_patch_1110() {
         let local_cache = Envelopes::from_iter([(
@@ -1700,7 +2404,15 @@ mod envelopes_sync {
         let remote = Envelopes::default();
 
         assert_eq!(
-            super::build_patch("inbox", local_cache, local, remote_cache, remote),
+            super::build_patch(
+                "inbox",
+                local_cache,
+                local,
+                remote_cache,
+                remote,
+                &FlagConflictStrategy::default(),
+                BackendSyncMode::Bidirectional,
+            ),
             vec![
                 vec![BackendHunk::RemoveEmail(
                     "inbox".into(),
@@ -1720,4 +2432,162 @@ mod envelopes_sync {
             ]
         );
     }
+
+    #[test]
+    fn build_patch_one_way() {
+        let envelopes = |id: &str, flags: &str| {
+            Envelopes::from_iter([(
+                "message_id".into(),
+                Envelope {
+                    internal_id: id.into(),
+                    flags: flags.into(),
+                    ..Envelope::default()
+                },
+            )])
+        };
+        let build_patch = |local_cache, local, remote_cache, remote, mode| {
+            super::build_patch(
+                "inbox",
+                local_cache,
+                local,
+                remote_cache,
+                remote,
+                &FlagConflictStrategy::default(),
+                mode,
+            )
+        };
+
+        // 0100: email added local side, kept local side only
+        assert_eq!(
+            build_patch(
+                Envelopes::default(),
+                envelopes("local-id", "seen"),
+                Envelopes::default(),
+                Envelopes::default(),
+                BackendSyncMode::PullOnly,
+            ),
+            vec![vec![BackendHunk::CacheEnvelope(
+                "inbox".into(),
+                "local-id".into(),
+                HunkKindRestricted::Local,
+            )]],
+        );
+
+        // 0100: email added local side, removed by the mirror
+        assert_eq!(
+            build_patch(
+                Envelopes::default(),
+                envelopes("local-id", "seen"),
+                Envelopes::default(),
+                Envelopes::default(),
+                BackendSyncMode::Mirror,
+            ),
+            vec![vec![BackendHunk::RemoveEmail(
+                "inbox".into(),
+                "local-id".into(),
+                HunkKind::Local,
+            )]],
+        );
+
+        // 0100: email added local side, pushed
+        assert_eq!(
+            build_patch(
+                Envelopes::default(),
+                envelopes("local-id", "seen"),
+                Envelopes::default(),
+                Envelopes::default(),
+                BackendSyncMode::PushOnly,
+            ),
+            vec![
+                vec![BackendHunk::CacheEnvelope(
+                    "inbox".into(),
+                    "local-id".into(),
+                    HunkKindRestricted::Local,
+                )],
+                vec![BackendHunk::CopyEmail(
+                    "inbox".into(),
+                    Envelope {
+                        internal_id: "local-id".into(),
+                        flags: "seen".into(),
+                        ..Envelope::default()
+                    },
+                    HunkKindRestricted::Local,
+                    HunkKindRestricted::Remote,
+                    false,
+                )],
+            ],
+        );
+
+        // 1011: email removed local side, pulled back
+        assert_eq!(
+            build_patch(
+                envelopes("local-cache-id", "seen"),
+                Envelopes::default(),
+                envelopes("remote-cache-id", "seen"),
+                envelopes("remote-id", "seen"),
+                BackendSyncMode::PullOnly,
+            ),
+            vec![vec![
+                BackendHunk::RemoveEmail(
+                    "inbox".into(),
+                    "local-cache-id".into(),
+                    HunkKind::LocalCache,
+                ),
+                BackendHunk::CopyEmail(
+                    "inbox".into(),
+                    Envelope {
+                        internal_id: "remote-id".into(),
+                        flags: "seen".into(),
+                        ..Envelope::default()
+                    },
+                    HunkKindRestricted::Remote,
+                    HunkKindRestricted::Local,
+                    false,
+                ),
+            ]],
+        );
+
+        // 1111: flag removed remote side and added local side, remote
+        // flags win
+        assert_eq!(
+            build_patch(
+                envelopes("local-cache-id", ""),
+                envelopes("local-id", "seen flagged"),
+                envelopes("remote-cache-id", ""),
+                envelopes("remote-id", "seen"),
+                BackendSyncMode::PullOnly,
+            ),
+            vec![
+                vec![BackendHunk::SetFlags(
+                    "inbox".into(),
+                    Envelope {
+                        internal_id: "remote-cache-id".into(),
+                        flags: "seen".into(),
+                        ..Envelope::default()
+                    },
+                    HunkKind::RemoteCache,
+                )],
+                vec![
+                    BackendHunk::SetFlags(
+                        "inbox".into(),
+                        Envelope {
+                            internal_id: "local-id".into(),
+                            flags: "seen".into(),
+                            ..Envelope::default()
+                        },
+                        HunkKind::Local,
+                    ),
+                    BackendHunk::SetFlags(
+                        "inbox".into(),
+                        Envelope {
+                            internal_id: "local-cache-id".into(),
+                            flags: "seen".into(),
+                            ..Envelope::default()
+                        },
+                        HunkKind::LocalCache,
+                    ),
+                ],
+            ],
+        );
+    }
 }
@@ -7,4 +7,4 @@ pub mod sync;
 
 pub use self::flag::*;
 pub use self::flags::*;
-pub use self::sync::sync_all;
+pub use self::sync::{sync_all, FlagConflictResolution, FlagConflictStrategy};
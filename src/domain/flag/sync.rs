@@ -1,12 +1,85 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use crate::{Envelope, Flag, Flags};
 
+/// Represents the way a flag conflict is resolved. A conflict occurs
+/// when a flag is present on one side and absent on the other, and
+/// the caches cannot tell which side changed last (for example a flag
+/// removed on one device and added on another one).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FlagConflictResolution {
+    /// The local side wins.
+    PreferLocal,
+    /// The remote side wins.
+    PreferRemote,
+    /// The flag is kept, whatever the side it comes from.
+    Union,
+    /// The flag is removed, whatever the side it comes from.
+    Intersection,
+}
+
+impl FlagConflictResolution {
+    fn resolve(&self, local: bool, remote: bool) -> bool {
+        match self {
+            Self::PreferLocal => local,
+            Self::PreferRemote => remote,
+            Self::Union => local || remote,
+            Self::Intersection => local && remote,
+        }
+    }
+}
+
+/// Represents the strategy used to resolve flag conflicts during the
+/// synchronization. The default resolution can be overridden per
+/// flag.
+///
+/// The default strategy keeps conflicting flags in order not to lose
+/// data, except [`Flag::Deleted`] which is removed.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FlagConflictStrategy {
+    pub default: FlagConflictResolution,
+    pub overrides: HashMap<Flag, FlagConflictResolution>,
+}
+
+impl Default for FlagConflictStrategy {
+    fn default() -> Self {
+        Self::new(FlagConflictResolution::Union)
+            .with_override(Flag::Deleted, FlagConflictResolution::Intersection)
+    }
+}
+
+impl FlagConflictStrategy {
+    /// Creates a strategy resolving every flag the same way.
+    pub fn new(default: FlagConflictResolution) -> Self {
+        Self {
+            default,
+            overrides: HashMap::default(),
+        }
+    }
+
+    pub fn with_override(mut self, flag: Flag, resolution: FlagConflictResolution) -> Self {
+        self.overrides.insert(flag, resolution);
+        self
+    }
+
+    /// Returns the resolution of the given flag.
+    pub fn resolution(&self, flag: &Flag) -> FlagConflictResolution {
+        self.overrides.get(flag).copied().unwrap_or(self.default)
+    }
+
+    /// Resolves the conflict of the given flag, and returns whether
+    /// it should be kept.
+    pub fn resolve(&self, flag: &Flag, local: bool, remote: bool) -> bool {
+        self.resolution(flag).resolve(local, remote)
+    }
+}
+
 pub fn sync_all(
     local_cache: Option<&Envelope>,
     local: Option<&Envelope>,
     remote_cache: Option<&Envelope>,
     remote: Option<&Envelope>,
+    strategy: &FlagConflictStrategy,
 ) -> Flags {
     let mut synchronized_flags: HashSet<Flag> = HashSet::default();
 
@@ -42,16 +115,11 @@ pub fn sync_all(
             // The flag exists in remote side but not in local side,
             // which means there is a conflict. Since we cannot
             // determine which side (local removed or remote added) is
-            // the most up-to-date, it is safer to consider the remote
-            // added side up-to-date (or local removed in case of
-            // [`Flag::Deleted`]) in order not to lose data.
-            //
-            // TODO: make this behaviour customizable.
-            (None, None, Some(_), Some(_)) if flag == Flag::Deleted => {
-                synchronized_flags.remove(&flag);
-            }
+            // the most up-to-date, the conflict strategy decides.
             (None, None, Some(_), Some(_)) => {
-                synchronized_flags.insert(flag.clone());
+                if strategy.resolve(&flag, false, true) {
+                    synchronized_flags.insert(flag.clone());
+                }
             }
 
             // The flag only exists in local side, which means a new
@@ -71,16 +139,11 @@ pub fn sync_all(
             // which means a new (same) flag has been added local side
             // but removed remote side. Since we cannot determine
             // which side (local added or remote removed) is the most
-            // up-to-date, it is safer to consider the local added
-            // side up-to-date (or remote removed in case of
-            // [`Flag::Deleted`]) in order not to lose data.
-            //
-            // TODO: make this behaviour customizable.
-            (None, Some(_), Some(_), None) if flag == Flag::Deleted => {
-                synchronized_flags.remove(&flag);
-            }
+            // up-to-date, the conflict strategy decides.
             (None, Some(_), Some(_), None) => {
-                synchronized_flags.insert(flag.clone());
+                if strategy.resolve(&flag, true, false) {
+                    synchronized_flags.insert(flag.clone());
+                }
             }
 
             // The flag exists everywhere except in local cache, which
@@ -99,16 +162,11 @@ pub fn sync_all(
             // which means a new (same) flag has been removed local
             // cache side but added remote side. Since we cannot
             // determine which side (local removed or remote added) is
-            // the most up-to-date, it is safer to consider the remote
-            // added side up-to-date (or local removed in case of
-            // [`Flag::Deleted`]) in order not to lose data.
-            //
-            // TODO: make this behaviour customizable.
-            (Some(_), None, None, Some(_)) if flag == Flag::Deleted => {
-                synchronized_flags.remove(&flag);
-            }
+            // the most up-to-date, the conflict strategy decides.
             (Some(_), None, None, Some(_)) => {
-                synchronized_flags.insert(flag.clone());
+                if strategy.resolve(&flag, false, true) {
+                    synchronized_flags.insert(flag.clone());
+                }
             }
 
             // The flag exists in both caches, which means a old flag
@@ -126,17 +184,12 @@ pub fn sync_all(
 
             // The flag exists in the local sides but not in remote
             // sides, which means there is a conflict. Since we cannot
-            // determine which side is the most up-to-date, it is
-            // safer to consider the local side side up-to-date (or
-            // remote side in case of [`Flag::Deleted`]) in order not
-            // to lose data.
-            //
-            // TODO: make this behaviour customizable.
-            (Some(_), Some(_), None, None) if flag == Flag::Deleted => {
-                synchronized_flags.remove(&flag);
-            }
+            // determine which side is the most up-to-date, the
+            // conflict strategy decides.
             (Some(_), Some(_), None, None) => {
-                synchronized_flags.insert(flag.clone());
+                if strategy.resolve(&flag, true, false) {
+                    synchronized_flags.insert(flag.clone());
+                }
             }
 
             // The flag exists everywhere except in remote cache side,
@@ -167,9 +220,14 @@ pub fn sync_all(
 mod sync_flags {
     use crate::{Envelope, Flag, Flags};
 
+    use super::{FlagConflictResolution, FlagConflictStrategy};
+
     #[test]
     fn sync_all() {
-        assert_eq!(super::sync_all(None, None, None, None), Flags::default());
+        assert_eq!(
+            super::sync_all(None, None, None, None, &FlagConflictStrategy::default()),
+            Flags::default()
+        );
 
         assert_eq!(
             super::sync_all(
@@ -180,6 +238,7 @@ mod sync_flags {
                     flags: Flags::from_iter([Flag::Seen]),
                     ..Envelope::default()
                 }),
+                &FlagConflictStrategy::default(),
             ),
             Flags::from_iter([Flag::Seen]),
         );
@@ -193,6 +252,7 @@ mod sync_flags {
                     flags: Flags::from_iter([Flag::Seen]),
                     ..Envelope::default()
                 }),
+                &FlagConflictStrategy::default(),
             ),
             Flags::from_iter([Flag::Seen]),
         );
@@ -206,6 +266,7 @@ mod sync_flags {
                     ..Envelope::default()
                 }),
                 Some(&Envelope::default()),
+                &FlagConflictStrategy::default(),
             ),
             Flags::default()
         );
@@ -222,6 +283,7 @@ mod sync_flags {
                     flags: Flags::from_iter([Flag::Seen]),
                     ..Envelope::default()
                 }),
+                &FlagConflictStrategy::default(),
             ),
             Flags::from_iter([Flag::Seen]),
         );
@@ -235,6 +297,7 @@ mod sync_flags {
                 }),
                 Some(&Envelope::default()),
                 Some(&Envelope::default()),
+                &FlagConflictStrategy::default(),
             ),
             Flags::from_iter([Flag::Seen]),
         );
@@ -251,6 +314,7 @@ mod sync_flags {
                     flags: Flags::from_iter([Flag::Seen]),
                     ..Envelope::default()
                 }),
+                &FlagConflictStrategy::default(),
             ),
             Flags::from_iter([Flag::Seen]),
         );
@@ -267,6 +331,7 @@ mod sync_flags {
                     ..Envelope::default()
                 }),
                 Some(&Envelope::default()),
+                &FlagConflictStrategy::default(),
             ),
             Flags::from_iter([Flag::Seen]),
         );
@@ -286,6 +351,7 @@ mod sync_flags {
                     flags: Flags::from_iter([Flag::Seen]),
                     ..Envelope::default()
                 }),
+                &FlagConflictStrategy::default(),
             ),
             Flags::from_iter([Flag::Seen]),
         );
@@ -299,6 +365,7 @@ mod sync_flags {
                 Some(&Envelope::default()),
                 Some(&Envelope::default()),
                 Some(&Envelope::default()),
+                &FlagConflictStrategy::default(),
             ),
             Flags::default()
         );
@@ -315,6 +382,7 @@ mod sync_flags {
                     flags: Flags::from_iter([Flag::Seen]),
                     ..Envelope::default()
                 }),
+                &FlagConflictStrategy::default(),
             ),
             Flags::from_iter([Flag::Seen]),
         );
@@ -331,6 +399,7 @@ mod sync_flags {
                     ..Envelope::default()
                 }),
                 Some(&Envelope::default()),
+                &FlagConflictStrategy::default(),
             ),
             Flags::default(),
         );
@@ -350,6 +419,7 @@ mod sync_flags {
                     flags: Flags::from_iter([Flag::Seen]),
                     ..Envelope::default()
                 }),
+                &FlagConflictStrategy::default(),
             ),
             Flags::default(),
         );
@@ -366,6 +436,7 @@ mod sync_flags {
                 }),
                 Some(&Envelope::default()),
                 Some(&Envelope::default()),
+                &FlagConflictStrategy::default(),
             ),
             Flags::from_iter([Flag::Seen]),
         );
@@ -385,6 +456,7 @@ mod sync_flags {
                     flags: Flags::from_iter([Flag::Seen]),
                     ..Envelope::default()
                 }),
+                &FlagConflictStrategy::default(),
             ),
             Flags::from_iter([Flag::Seen]),
         );
@@ -404,6 +476,7 @@ mod sync_flags {
                     ..Envelope::default()
                 }),
                 Some(&Envelope::default()),
+                &FlagConflictStrategy::default(),
             ),
             Flags::default(),
         );
@@ -426,8 +499,58 @@ mod sync_flags {
                     flags: Flags::from_iter([Flag::Seen, Flag::Flagged]),
                     ..Envelope::default()
                 }),
+                &FlagConflictStrategy::default(),
             ),
             Flags::from_iter([Flag::Seen, Flag::Flagged]),
         );
     }
+
+    #[test]
+    fn sync_all_conflict_strategies() {
+        let seen = Envelope {
+            flags: Flags::from_iter([Flag::Seen, Flag::Deleted]),
+            ..Envelope::default()
+        };
+        let empty = Envelope::default();
+
+        // flags removed local side (caches do not know them yet) and
+        // added remote side
+        let sync = |strategy: FlagConflictStrategy| {
+            super::sync_all(Some(&seen), Some(&empty), None, Some(&seen), &strategy)
+        };
+
+        assert_eq!(
+            sync(FlagConflictStrategy::default()),
+            Flags::from_iter([Flag::Seen])
+        );
+        assert_eq!(
+            sync(FlagConflictStrategy::new(
+                FlagConflictResolution::PreferLocal
+            )),
+            Flags::default()
+        );
+        assert_eq!(
+            sync(FlagConflictStrategy::new(
+                FlagConflictResolution::PreferRemote
+            )),
+            Flags::from_iter([Flag::Seen, Flag::Deleted])
+        );
+        assert_eq!(
+            sync(FlagConflictStrategy::new(FlagConflictResolution::Union)),
+            Flags::from_iter([Flag::Seen, Flag::Deleted])
+        );
+        assert_eq!(
+            sync(FlagConflictStrategy::new(
+                FlagConflictResolution::Intersection
+            )),
+            Flags::default()
+        );
+        assert_eq!(
+            sync(
+                FlagConflictStrategy::new(FlagConflictResolution::PreferLocal)
+                    .with_override(Flag::Seen, FlagConflictResolution::PreferRemote)
+            ),
+            Flags::from_iter([Flag::Seen])
+        );
+    }
 }
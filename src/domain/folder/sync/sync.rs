@@ -2,7 +2,7 @@ use log::{debug, info, trace, warn};
 use rayon::prelude::*;
 use std::{collections::HashSet, fmt};
 
-use crate::{AccountConfig, Backend, BackendSyncProgressEvent, MaildirBackend};
+use crate::{backend::SyncSide, AccountConfig, Backend, BackendSyncMode, BackendSyncProgressEvent};
 
 use super::{Cache, Error, Result};
 
@@ -67,18 +67,22 @@ pub struct SyncReport {
 
 pub struct SyncBuilder<'a> {
     account_config: &'a AccountConfig,
+    cache_key: String,
     on_progress: Box<dyn Fn(BackendSyncProgressEvent) -> Result<()> + Sync + Send + 'a>,
     folders: Option<Vec<String>>,
     dry_run: bool,
+    mode: BackendSyncMode,
 }
 
 impl<'a> SyncBuilder<'a> {
     pub fn new(account_config: &'a AccountConfig) -> Self {
         Self {
             account_config,
+            cache_key: account_config.name.clone(),
             on_progress: Box::new(|_| Ok(())),
             folders: None,
             dry_run: false,
+            mode: BackendSyncMode::default(),
         }
     }
 
@@ -116,19 +120,33 @@ impl<'a> SyncBuilder<'a> {
         self
     }
 
+    /// Sets the key of the synchronization cache, which identifies
+    /// the pair of synchronized backends. Defaults to the account
+    /// name.
+    pub fn cache_key<K: ToString>(mut self, key: K) -> Self {
+        self.cache_key = key.to_string();
+        self
+    }
+
+    pub fn mode(mut self, mode: BackendSyncMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
     pub fn sync(
         &self,
         conn: &mut rusqlite::Connection,
-        local: &MaildirBackend,
+        local: &dyn Backend,
         remote: &dyn Backend,
     ) -> Result<SyncReport> {
         let account = &self.account_config.name;
+        let cache_key = &self.cache_key;
         info!("starting folders synchronization of account {account}");
 
         self.try_progress(BackendSyncProgressEvent::GetLocalCachedFolders);
 
         let local_folders_cached: FoldersName = HashSet::from_iter(
-            Cache::list_local_folders(conn, account, self.folders.as_ref())?
+            Cache::list_local_folders(conn, cache_key, self.folders.as_ref())?
                 .iter()
                 .cloned(),
         );
@@ -164,7 +182,7 @@ impl<'a> SyncBuilder<'a> {
         self.try_progress(BackendSyncProgressEvent::GetRemoteCachedFolders);
 
         let remote_folders_cached: FoldersName = HashSet::from_iter(
-            Cache::list_remote_folders(conn, account, self.folders.as_ref())?
+            Cache::list_remote_folders(conn, cache_key, self.folders.as_ref())?
                 .iter()
                 .cloned(),
         );
@@ -204,6 +222,7 @@ impl<'a> SyncBuilder<'a> {
             local_folders,
             remote_folders_cached,
             remote_folders,
+            self.mode,
         );
 
         self.try_progress(BackendSyncProgressEvent::ProcessFoldersPatch(patch.len()));
@@ -295,16 +314,16 @@ impl<'a> SyncBuilder<'a> {
                 for hunk in &report.cache_patch.0 {
                     match hunk {
                         CacheHunk::CreateFolder(folder, TargetRestricted::Local) => {
-                            Cache::insert_local_folder(&tx, account, folder)?;
+                            Cache::insert_local_folder(&tx, cache_key, folder)?;
                         }
                         CacheHunk::CreateFolder(folder, TargetRestricted::Remote) => {
-                            Cache::insert_remote_folder(&tx, account, folder)?;
+                            Cache::insert_remote_folder(&tx, cache_key, folder)?;
                         }
                         CacheHunk::DeleteFolder(folder, TargetRestricted::Local) => {
-                            Cache::delete_local_folder(&tx, account, folder)?;
+                            Cache::delete_local_folder(&tx, cache_key, folder)?;
                         }
                         CacheHunk::DeleteFolder(folder, TargetRestricted::Remote) => {
-                            Cache::delete_remote_folder(&tx, account, folder)?;
+                            Cache::delete_remote_folder(&tx, cache_key, folder)?;
                         }
                     }
                 }
@@ -333,12 +352,26 @@ impl<'a> SyncBuilder<'a> {
     }
 }
 
+/// Builds the folders patch, and returns it with the name of the
+/// folders whose envelopes need to be synchronized.
 pub fn build_patch(
     local_cache: FoldersName,
     local: FoldersName,
     remote_cache: FoldersName,
     remote: FoldersName,
+    mode: BackendSyncMode,
 ) -> (Patch, FoldersName) {
+    if let Some(source) = mode.source() {
+        return build_one_way_patch(
+            local_cache,
+            local,
+            remote_cache,
+            remote,
+            source,
+            mode.keep_target_only(),
+        );
+    }
+
     let mut patch: Patch = vec![];
     let mut folders: FoldersName = HashSet::new();
 
@@ -453,8 +486,73 @@ pub fn build_patch(
     (patch, folders)
 }
 
+/// Builds the folders patch of a one-way synchronization. The source
+/// side is never modified, the target side follows it. Folders that
+/// only exist target side are kept when `keep_target_only` is
+/// enabled, unless they were removed source side. Only folders that
+/// exist source side have their envelopes synchronized.
+fn build_one_way_patch(
+    local_cache: FoldersName,
+    local: FoldersName,
+    remote_cache: FoldersName,
+    remote: FoldersName,
+    source: SyncSide,
+    keep_target_only: bool,
+) -> (Patch, FoldersName) {
+    let mut patch: Patch = vec![];
+    let mut folders: FoldersName = HashSet::new();
+    let mut all_folders: FoldersName = HashSet::new();
+
+    all_folders.extend(local_cache.clone());
+    all_folders.extend(local.clone());
+    all_folders.extend(remote_cache.clone());
+    all_folders.extend(remote.clone());
+
+    for folder in all_folders {
+        let in_local_cache = local_cache.contains(&folder);
+        let in_local = local.contains(&folder);
+        let in_remote_cache = remote_cache.contains(&folder);
+        let in_remote = remote.contains(&folder);
+
+        let (in_source_cache, in_source, in_target) = match source {
+            SyncSide::Local => (in_local_cache, in_local, in_remote),
+            SyncSide::Remote => (in_remote_cache, in_remote, in_local),
+        };
+
+        // A folder missing source side but present in the source
+        // cache has been removed source side.
+        let in_target_expected = in_source || (keep_target_only && in_target && !in_source_cache);
+
+        let (in_local_expected, in_remote_expected) = match source {
+            SyncSide::Local => (in_source, in_target_expected),
+            SyncSide::Remote => (in_target_expected, in_source),
+        };
+
+        for (exists, expected, target) in [
+            (in_local_cache, in_local_expected, HunkKind::LocalCache),
+            (in_local, in_local_expected, HunkKind::Local),
+            (in_remote_cache, in_remote_expected, HunkKind::RemoteCache),
+            (in_remote, in_remote_expected, HunkKind::Remote),
+        ] {
+            if !exists && expected {
+                patch.push(Hunk::CreateFolder(folder.clone(), target));
+            } else if exists && !expected {
+                patch.push(Hunk::DeleteFolder(folder.clone(), target));
+            }
+        }
+
+        if in_source {
+            folders.insert(folder);
+        }
+    }
+
+    (patch, folders)
+}
+
 #[cfg(test)]
 mod folders_sync {
+    use crate::BackendSyncMode;
+
     use super::{FoldersName, Hunk, HunkKind, Patch};
 
     #[test]
@@ -466,6 +564,7 @@ mod folders_sync {
                 FoldersName::default(),
                 FoldersName::default(),
                 FoldersName::default(),
+                BackendSyncMode::Bidirectional,
             ),
             (vec![] as Patch, FoldersName::default()),
         );
@@ -477,6 +576,7 @@ mod folders_sync {
                 FoldersName::default(),
                 FoldersName::default(),
                 FoldersName::from_iter(["folder".into()]),
+                BackendSyncMode::Bidirectional,
             ),
             (
                 vec![
@@ -495,6 +595,7 @@ mod folders_sync {
                 FoldersName::default(),
                 FoldersName::from_iter(["folder".into()]),
                 FoldersName::default(),
+                BackendSyncMode::Bidirectional,
             ),
             (
                 vec![Hunk::DeleteFolder("folder".into(), HunkKind::RemoteCache)],
@@ -509,6 +610,7 @@ mod folders_sync {
                 FoldersName::default(),
                 FoldersName::from_iter(["folder".into()]),
                 FoldersName::from_iter(["folder".into()]),
+                BackendSyncMode::Bidirectional,
             ),
             (
                 vec![
@@ -526,6 +628,7 @@ mod folders_sync {
                 FoldersName::from_iter(["folder".into()]),
                 FoldersName::default(),
                 FoldersName::default(),
+                BackendSyncMode::Bidirectional,
             ),
             (
                 vec![
@@ -544,6 +647,7 @@ mod folders_sync {
                 FoldersName::from_iter(["folder".into()]),
                 FoldersName::default(),
                 FoldersName::from_iter(["folder".into()]),
+                BackendSyncMode::Bidirectional,
             ),
             (
                 vec![
@@ -561,6 +665,7 @@ mod folders_sync {
                 FoldersName::from_iter(["folder".into()]),
                 FoldersName::from_iter(["folder".into()]),
                 FoldersName::default(),
+                BackendSyncMode::Bidirectional,
             ),
             (
                 vec![
@@ -578,6 +683,7 @@ mod folders_sync {
                 FoldersName::from_iter(["folder".into()]),
                 FoldersName::from_iter(["folder".into()]),
                 FoldersName::from_iter(["folder".into()]),
+                BackendSyncMode::Bidirectional,
             ),
             (
                 vec![Hunk::CreateFolder("folder".into(), HunkKind::LocalCache)],
@@ -592,6 +698,7 @@ mod folders_sync {
                 FoldersName::default(),
                 FoldersName::default(),
                 FoldersName::default(),
+                BackendSyncMode::Bidirectional,
             ),
             (
                 vec![Hunk::DeleteFolder("folder".into(), HunkKind::LocalCache)],
@@ -606,6 +713,7 @@ mod folders_sync {
                 FoldersName::default(),
                 FoldersName::default(),
                 FoldersName::from_iter(["folder".into()]),
+                BackendSyncMode::Bidirectional,
             ),
             (
                 vec![
@@ -623,6 +731,7 @@ mod folders_sync {
                 FoldersName::default(),
                 FoldersName::from_iter(["folder".into()]),
                 FoldersName::default(),
+                BackendSyncMode::Bidirectional,
             ),
             (
                 vec![
@@ -640,6 +749,7 @@ mod folders_sync {
                 FoldersName::default(),
                 FoldersName::from_iter(["folder".into()]),
                 FoldersName::from_iter(["folder".into()]),
+                BackendSyncMode::Bidirectional,
             ),
             (
                 vec![
@@ -658,6 +768,7 @@ mod folders_sync {
                 FoldersName::from_iter(["folder".into()]),
                 FoldersName::default(),
                 FoldersName::default(),
+                BackendSyncMode::Bidirectional,
             ),
             (
                 vec![
@@ -675,6 +786,7 @@ mod folders_sync {
                 FoldersName::from_iter(["folder".into()]),
                 FoldersName::default(),
                 FoldersName::from_iter(["folder".into()]),
+                BackendSyncMode::Bidirectional,
             ),
             (
                 vec![Hunk::CreateFolder("folder".into(), HunkKind::RemoteCache)],
@@ -689,6 +801,7 @@ mod folders_sync {
                 FoldersName::from_iter(["folder".into()]),
                 FoldersName::from_iter(["folder".into()]),
                 FoldersName::default(),
+                BackendSyncMode::Bidirectional,
             ),
             (
                 vec![
@@ -707,8 +820,112 @@ mod folders_sync {
                 FoldersName::from_iter(["folder".into()]),
                 FoldersName::from_iter(["folder".into()]),
                 FoldersName::from_iter(["folder".into()]),
+                BackendSyncMode::Bidirectional,
             ),
             (vec![] as Patch, FoldersName::from_iter(["folder".into()])),
         );
     }
+
+    #[test]
+    fn build_folder_patch_one_way() {
+        let folder = || FoldersName::from_iter(["folder".into()]);
+
+        // 0100: folder added local side, kept local side only
+        assert_eq!(
+            super::build_patch(
+                FoldersName::default(),
+                folder(),
+                FoldersName::default(),
+                FoldersName::default(),
+                BackendSyncMode::PullOnly,
+            ),
+            (
+                vec![Hunk::CreateFolder("folder".into(), HunkKind::LocalCache)],
+                FoldersName::default(),
+            ),
+        );
+
+        // 0001: folder added remote side, pulled
+        assert_eq!(
+            super::build_patch(
+                FoldersName::default(),
+                FoldersName::default(),
+                FoldersName::default(),
+                folder(),
+                BackendSyncMode::PullOnly,
+            ),
+            (
+                vec![
+                    Hunk::CreateFolder("folder".into(), HunkKind::LocalCache),
+                    Hunk::CreateFolder("folder".into(), HunkKind::Local),
+                    Hunk::CreateFolder("folder".into(), HunkKind::RemoteCache),
+                ],
+                folder(),
+            ),
+        );
+
+        // 1110: folder removed remote side, removed local side
+        assert_eq!(
+            super::build_patch(
+                folder(),
+                folder(),
+                folder(),
+                FoldersName::default(),
+                BackendSyncMode::PullOnly,
+            ),
+            (
+                vec![
+                    Hunk::DeleteFolder("folder".into(), HunkKind::LocalCache),
+                    Hunk::DeleteFolder("folder".into(), HunkKind::Local),
+                    Hunk::DeleteFolder("folder".into(), HunkKind::RemoteCache),
+                ],
+                FoldersName::default(),
+            ),
+        );
+
+        // 1110: folder removed remote side, pushed back
+        assert_eq!(
+            super::build_patch(
+                folder(),
+                folder(),
+                folder(),
+                FoldersName::default(),
+                BackendSyncMode::PushOnly,
+            ),
+            (
+                vec![Hunk::CreateFolder("folder".into(), HunkKind::Remote)],
+                folder(),
+            ),
+        );
+
+        // 0001: folder added remote side, kept remote side only
+        assert_eq!(
+            super::build_patch(
+                FoldersName::default(),
+                FoldersName::default(),
+                FoldersName::default(),
+                folder(),
+                BackendSyncMode::PushOnly,
+            ),
+            (
+                vec![Hunk::CreateFolder("folder".into(), HunkKind::RemoteCache)],
+                FoldersName::default(),
+            ),
+        );
+
+        // 0100: folder added local side, removed by the mirror
+        assert_eq!(
+            super::build_patch(
+                FoldersName::default(),
+                folder(),
+                FoldersName::default(),
+                FoldersName::default(),
+                BackendSyncMode::Mirror,
+            ),
+            (
+                vec![Hunk::DeleteFolder("folder".into(), HunkKind::Local)],
+                FoldersName::default(),
+            ),
+        );
+    }
 }
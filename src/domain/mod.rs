@@ -6,6 +6,6 @@ pub mod folder;
 
 pub use account::*;
 pub use email::*;
-pub use envelope::{Envelope, Envelopes};
-pub use flag::{Flag, Flags};
+pub use envelope::{Envelope, Envelopes, SearchQuery, SortCriteria};
+pub use flag::{Flag, FlagConflictResolution, FlagConflictStrategy, Flags};
 pub use folder::*;
@@ -0,0 +1,21 @@
+//! Error module.
+//!
+//! This module contains helpers shared by the error types of the
+//! crate.
+
+use std::error;
+
+/// Joins the error with all its sources, so that the whole cause of a
+/// failure can be recorded or displayed on a single line.
+pub(crate) fn error_chain(err: &dyn error::Error) -> String {
+    let mut chain = err.to_string();
+    let mut source = err.source();
+
+    while let Some(err) = source {
+        chain.push_str(": ");
+        chain.push_str(&err.to_string());
+        source = err.source();
+    }
+
+    chain
+}
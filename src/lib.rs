@@ -4,6 +4,7 @@ pub use mime_msg_builder::{
     tpl::{HeaderVal, ShowHeaders, ShowTextPartsStrategy, Tpl, TplBuilder},
 };
 
+pub(crate) mod error;
 pub(crate) mod process;
 
 pub mod backend;
@@ -14,3 +15,17 @@ pub use sender::*;
 
 pub mod domain;
 pub use domain::*;
+
+pub mod secret;
+pub use secret::{Secret, SecretCache, SecretSource};
+
+pub mod oauth2;
+pub use oauth2::{OAuth2Config, OAuth2Method, OAuth2TokenCache};
+
+pub mod doctor;
+pub use doctor::{Doctor, DoctorCheck, DoctorReport, DoctorStatus};
+
+pub mod autoconfig;
+pub use autoconfig::{
+    Autoconfig, AutoconfigBuilder, AutoconfigDns, AutoconfigHttp, AutoconfigSource,
+};
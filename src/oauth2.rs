@@ -0,0 +1,325 @@
+//! OAuth 2.0 module.
+//!
+//! This module contains the OAuth 2.0 configuration shared by the
+//! IMAP backend and the SMTP sender. Access tokens are obtained from
+//! the token endpoint using the refresh token grant, cached until
+//! they expire and refreshed again when they are rejected by the
+//! server.
+
+use log::{debug, info};
+use native_tls::TlsConnector;
+use serde::Deserialize;
+use std::{
+    fmt, io, result,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+use crate::{secret, Secret};
+
+/// Represents the margin taken before the expiration of an access
+/// token, so that a token is never used right before it expires.
+const EXPIRATION_MARGIN: Duration = Duration::from_secs(60);
+
+/// Represents the timeout of the requests to the token endpoint. The
+/// token cache is locked during the refresh, so an unresponsive
+/// endpoint would otherwise block every connection attempt.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("cannot refresh oauth2 access token: refresh token is missing")]
+    GetRefreshTokenError,
+    #[error("cannot get oauth2 refresh token")]
+    GetRefreshTokenSecretError(#[source] secret::Error),
+    #[error("cannot get oauth2 client secret")]
+    GetClientSecretError(#[source] secret::Error),
+    #[error("cannot refresh oauth2 access token at {1}")]
+    RefreshAccessTokenError(#[source] Box<ureq::Error>, String),
+    #[error("cannot refresh oauth2 access token at {1}: {2}")]
+    RefreshAccessTokenResponseError(u16, String, String),
+    #[error("cannot read oauth2 token response from {1}")]
+    ReadTokenResponseError(#[source] io::Error, String),
+    #[error("cannot parse oauth2 token response from {1}")]
+    ParseTokenResponseError(#[source] serde_json::Error, String),
+    #[error("cannot create tls connector")]
+    CreateTlsConnectorError(#[source] native_tls::Error),
+    #[error("cannot lock oauth2 token cache: {0}")]
+    LockTokenCacheError(String),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents the SASL mechanism used to authenticate with an OAuth
+/// 2.0 access token.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OAuth2Method {
+    /// Represents the non-standard `XOAUTH2` mechanism, supported by
+    /// most providers.
+    #[default]
+    XOAuth2,
+    /// Represents the `OAUTHBEARER` mechanism, defined in [RFC 7628].
+    ///
+    /// [RFC 7628]: https://www.rfc-editor.org/rfc/rfc7628
+    OAuthBearer,
+}
+
+impl OAuth2Method {
+    /// Returns the SASL name of the mechanism.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::XOAuth2 => "XOAUTH2",
+            Self::OAuthBearer => "OAUTHBEARER",
+        }
+    }
+
+    /// Builds the initial (not yet base64-encoded) client response
+    /// of the mechanism.
+    pub fn sasl_response(&self, login: &str, host: &str, port: u16, token: &str) -> String {
+        match self {
+            Self::XOAuth2 => format!("user={login}\x01auth=Bearer {token}\x01\x01"),
+            Self::OAuthBearer => {
+                // The authorization identity is a saslname of the GS2
+                // header, where ',' and '=' must be escaped (RFC 5801).
+                let login = login.replace('=', "=3D").replace(',', "=2C");
+                format!("n,a={login},\x01host={host}\x01port={port}\x01auth=Bearer {token}\x01\x01")
+            }
+        }
+    }
+
+    /// Builds the client response to send back when the server
+    /// answers the initial response with an error challenge, so that
+    /// the server can terminate the exchange.
+    pub fn sasl_error_response(&self) -> &'static str {
+        match self {
+            Self::XOAuth2 => "",
+            Self::OAuthBearer => "\x01",
+        }
+    }
+}
+
+impl fmt::Display for OAuth2Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Default)]
+struct OAuth2Token {
+    access_token: Option<String>,
+    refresh_token: Option<String>,
+    expires_at: Option<Instant>,
+}
+
+/// Represents the access token cache of an OAuth 2.0 config. The
+/// cache is shared between clones of the config, and it is not taken
+/// into account when comparing configs.
+#[derive(Clone, Debug, Default)]
+pub struct OAuth2TokenCache(Arc<Mutex<Option<OAuth2Token>>>);
+
+impl PartialEq for OAuth2TokenCache {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for OAuth2TokenCache {}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+    refresh_token: Option<String>,
+}
+
+/// Represents the OAuth 2.0 configuration.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct OAuth2Config {
+    /// Represents the SASL mechanism used to authenticate. Defaults
+    /// to `xoauth2`.
+    #[serde(default)]
+    pub method: OAuth2Method,
+    /// Represents the client identifier.
+    pub client_id: String,
+    /// Represents the client secret, if the client is confidential.
+    #[serde(default)]
+    pub client_secret: Secret,
+    /// Represents the URL of the token endpoint.
+    pub token_url: String,
+    /// Represents the refresh token.
+    #[serde(default)]
+    pub refresh_token: Secret,
+    /// Represents an initial access token, used until it is rejected
+    /// by the server.
+    pub access_token: Option<String>,
+    /// Represents the cache of the access token.
+    #[serde(skip)]
+    pub cache: OAuth2TokenCache,
+}
+
+impl OAuth2Config {
+    /// Returns the cached access token, or refreshes it if it is
+    /// missing or expired.
+    pub fn access_token(&self) -> Result<String> {
+        let mut cache = self.lock_cache()?;
+
+        if cache.is_none() {
+            *cache = Some(OAuth2Token {
+                access_token: self.access_token.clone(),
+                refresh_token: self.initial_refresh_token()?,
+                expires_at: None,
+            });
+        }
+        let token = cache.as_mut().unwrap();
+
+        let expired = token
+            .expires_at
+            .map(|expires_at| expires_at <= Instant::now() + EXPIRATION_MARGIN)
+            .unwrap_or_default();
+
+        match &token.access_token {
+            Some(access_token) if !expired => Ok(access_token.clone()),
+            _ => self.refresh(token),
+        }
+    }
+
+    /// Refreshes the access token, for example after it has been
+    /// rejected by the server.
+    pub fn refresh_access_token(&self) -> Result<String> {
+        let mut cache = self.lock_cache()?;
+
+        if cache.is_none() {
+            *cache = Some(OAuth2Token {
+                refresh_token: self.initial_refresh_token()?,
+                ..OAuth2Token::default()
+            });
+        }
+        let token = cache.as_mut().unwrap();
+
+        self.refresh(token)
+    }
+
+    fn initial_refresh_token(&self) -> Result<Option<String>> {
+        if self.refresh_token.is_undefined() {
+            return Ok(None);
+        }
+
+        self.refresh_token
+            .get()
+            .map(Some)
+            .map_err(Error::GetRefreshTokenSecretError)
+    }
+
+    fn lock_cache(&self) -> Result<MutexGuard<'_, Option<OAuth2Token>>> {
+        self.cache
+            .0
+            .lock()
+            .map_err(|err| Error::LockTokenCacheError(err.to_string()))
+    }
+
+    fn refresh(&self, token: &mut OAuth2Token) -> Result<String> {
+        info!("refreshing oauth2 access token at {}", self.token_url);
+
+        let refresh_token = token
+            .refresh_token
+            .clone()
+            .ok_or(Error::GetRefreshTokenError)?;
+
+        let client_secret = if self.client_secret.is_undefined() {
+            None
+        } else {
+            Some(
+                self.client_secret
+                    .get()
+                    .map_err(Error::GetClientSecretError)?,
+            )
+        };
+
+        let mut form = vec![
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token.as_str()),
+            ("client_id", self.client_id.as_str()),
+        ];
+        if let Some(client_secret) = &client_secret {
+            form.push(("client_secret", client_secret.as_str()));
+        }
+
+        let tls = TlsConnector::new().map_err(Error::CreateTlsConnectorError)?;
+        let agent = ureq::AgentBuilder::new()
+            .tls_connector(Arc::new(tls))
+            .timeout(HTTP_TIMEOUT)
+            .build();
+
+        let res = match agent.post(&self.token_url).send_form(&form) {
+            Ok(res) => res,
+            Err(ureq::Error::Status(code, res)) => {
+                let body = res.into_string().unwrap_or_default();
+                return Err(Error::RefreshAccessTokenResponseError(
+                    code,
+                    self.token_url.clone(),
+                    body,
+                ));
+            }
+            Err(err) => {
+                return Err(Error::RefreshAccessTokenError(
+                    Box::new(err),
+                    self.token_url.clone(),
+                ))
+            }
+        };
+
+        let body = res
+            .into_string()
+            .map_err(|err| Error::ReadTokenResponseError(err, self.token_url.clone()))?;
+        let res: TokenResponse = serde_json::from_str(&body)
+            .map_err(|err| Error::ParseTokenResponseError(err, self.token_url.clone()))?;
+        debug!("oauth2 access token expires in {:?}s", res.expires_in);
+
+        token.access_token = Some(res.access_token.clone());
+        token.expires_at = res
+            .expires_in
+            .map(|secs| Instant::now() + Duration::from_secs(secs));
+        // Some providers rotate refresh tokens.
+        if let Some(refresh_token) = res.refresh_token {
+            token.refresh_token = Some(refresh_token);
+        }
+
+        Ok(res.access_token)
+    }
+}
+
+#[cfg(test)]
+mod oauth2 {
+    use super::{OAuth2Config, OAuth2Method};
+
+    #[test]
+    fn sasl_response() {
+        assert_eq!(
+            OAuth2Method::XOAuth2.sasl_response("bob@localhost", "localhost", 993, "token"),
+            "user=bob@localhost\x01auth=Bearer token\x01\x01"
+        );
+        assert_eq!(
+            OAuth2Method::OAuthBearer.sasl_response("bob@localhost", "localhost", 993, "token"),
+            "n,a=bob@localhost,\x01host=localhost\x01port=993\x01auth=Bearer token\x01\x01"
+        );
+        assert_eq!(
+            OAuth2Method::OAuthBearer.sasl_response("b=o,b", "localhost", 993, "token"),
+            "n,a=b=3Do=2Cb,\x01host=localhost\x01port=993\x01auth=Bearer token\x01\x01"
+        );
+    }
+
+    #[test]
+    fn initial_access_token() {
+        let config = OAuth2Config {
+            access_token: Some("token".into()),
+            ..OAuth2Config::default()
+        };
+
+        assert_eq!(config.access_token().unwrap(), "token");
+        // without refresh token, the access token cannot be refreshed
+        assert!(config.refresh_access_token().is_err());
+    }
+}
@@ -3,15 +3,21 @@
 //! This module contains cross platform helpers around the
 //! `std::process` crate.
 
-use log::debug;
+use log::{debug, warn};
 use std::{
     env,
     io::{self, prelude::*},
-    process::{Command, Stdio},
+    process::{Child, Command, ExitStatus, Stdio},
     result, string,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 use thiserror::Error;
 
+/// Represents the interval between two checks of the status of a
+/// process running with a timeout.
+const WAIT_INTERVAL: Duration = Duration::from_millis(10);
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("cannot run command {1:?}")]
@@ -28,32 +34,85 @@ pub enum Error {
     GetStdoutError,
     #[error("cannot read data from standard output")]
     ReadStdoutError(#[source] io::Error),
+    #[error("cannot get standard error")]
+    GetStderrError,
+    #[error("cannot read data from standard error")]
+    ReadStderrError(#[source] io::Error),
+    #[error("cannot wait for command {1:?}")]
+    WaitProcessError(#[source] io::Error, String),
+    #[error("cannot kill command {1:?}")]
+    KillProcessError(#[source] io::Error, String),
+    #[error("command {0:?} timed out after {1:?}")]
+    CmdTimeoutError(String, Duration),
+    #[error("command {0:?} failed with {1}: {2}")]
+    CmdFailedError(String, ExitStatus, String),
 }
 
 pub type Result<T> = result::Result<T, Error>;
 
-/// Runs the given command and returns the output as UTF8 string.
+/// Represents the output of a finished command.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Output {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl Output {
+    /// Returns the standard error as a trimmed, lossy UTF-8 string.
+    pub fn stderr_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.stderr).trim().to_owned()
+    }
+}
+
+/// Runs the given command and returns its standard output. The
+/// command is split by pipes, each part receiving the output of the
+/// previous one. The first part failing with a non-zero exit status
+/// stops the pipeline with an error containing its standard error.
 pub fn run(cmd: &str, input: &[u8]) -> Result<Vec<u8>> {
+    run_with_timeout(cmd, input, None)
+}
+
+/// Same as [`run`], but each part of the pipeline is killed when it
+/// does not finish before the given timeout.
+pub fn run_with_timeout(cmd: &str, input: &[u8], timeout: Option<Duration>) -> Result<Vec<u8>> {
     let mut output = input.to_owned();
 
     for cmd in cmd.split('|') {
+        let cmd = cmd.trim();
         debug!("running command: {}", cmd);
-        (output, _) = pipe(cmd.trim(), &output)?;
+
+        let out = pipe(cmd, &output, timeout)?;
+        if !out.status.success() {
+            return Err(Error::CmdFailedError(
+                cmd.to_owned(),
+                out.status,
+                out.stderr_lossy(),
+            ));
+        }
+
+        let stderr = out.stderr_lossy();
+        if !stderr.is_empty() {
+            debug!("command {cmd:?} stderr: {stderr}");
+        }
+
+        output = out.stdout;
     }
 
     Ok(output)
 }
 
-/// Runs the given command in a pipeline and returns the raw output.
-pub fn pipe(cmd: &str, input: &[u8]) -> Result<(Vec<u8>, usize)> {
-    let mut output = Vec::new();
-
+/// Runs the given command in a pipeline, waits for it to finish and
+/// returns its exit status, standard output and standard error. A
+/// non-zero exit status is not considered as an error. The process
+/// is killed when it does not finish before the given timeout.
+pub fn pipe(cmd: &str, input: &[u8], timeout: Option<Duration>) -> Result<Output> {
     let windows = cfg!(target_os = "windows")
         && !(env::var("MSYSTEM")
             .map(|env| env.starts_with("MINGW"))
             .unwrap_or_default());
 
-    let pipeline = if windows {
+    let mut child = if windows {
         Command::new("cmd")
             .args(&["/C", cmd])
             .stdin(Stdio::piped())
@@ -71,17 +130,132 @@ pub fn pipe(cmd: &str, input: &[u8]) -> Result<(Vec<u8>, usize)> {
     }
     .map_err(|err| Error::SpawnProcessError(err, cmd.to_string()))?;
 
-    pipeline
-        .stdin
-        .ok_or_else(|| Error::GetStdinError)?
-        .write_all(input)
-        .map_err(Error::WriteStdinError)?;
+    // Standard input is written and standard output and error are
+    // read from dedicated threads, so that a process filling one of
+    // its pipes does not block the others.
+    let mut stdin = child.stdin.take().ok_or(Error::GetStdinError)?;
+    let input = input.to_owned();
+    let stdin = thread::spawn(move || match stdin.write_all(&input) {
+        // the process does not need to read all its input
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        res => res,
+    });
+    let stdout = read(child.stdout.take().ok_or(Error::GetStdoutError)?);
+    let stderr = read(child.stderr.take().ok_or(Error::GetStderrError)?);
+
+    let status = wait(&mut child, cmd, timeout)?;
+
+    join(stdin).map_err(Error::WriteStdinError)?;
+    let stdout = join(stdout).map_err(Error::ReadStdoutError)?;
+    let stderr = join(stderr).map_err(Error::ReadStderrError)?;
+
+    Ok(Output {
+        status,
+        stdout,
+        stderr,
+    })
+}
+
+fn read<R: Read + Send + 'static>(mut reader: R) -> JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output)?;
+        Ok(output)
+    })
+}
+
+fn join<T>(handle: JoinHandle<io::Result<T>>) -> io::Result<T> {
+    handle
+        .join()
+        .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::Other, "thread panicked")))
+}
 
-    let exit_code = pipeline
-        .stdout
-        .ok_or_else(|| Error::GetStdoutError)?
-        .read_to_end(&mut output)
-        .map_err(Error::ReadStdoutError)?;
+/// Waits for the given process to finish. When the timeout is
+/// reached, the process is killed and the I/O threads are left
+/// behind, since the pipes may still be held open by its children.
+fn wait(child: &mut Child, cmd: &str, timeout: Option<Duration>) -> Result<ExitStatus> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => {
+            return child
+                .wait()
+                .map_err(|err| Error::WaitProcessError(err, cmd.to_owned()))
+        }
+    };
 
-    Ok((output, exit_code))
+    let start = Instant::now();
+
+    loop {
+        let status = child
+            .try_wait()
+            .map_err(|err| Error::WaitProcessError(err, cmd.to_owned()))?;
+
+        if let Some(status) = status {
+            return Ok(status);
+        }
+
+        if start.elapsed() >= timeout {
+            warn!("command {cmd:?} timed out after {timeout:?}, killing it");
+            child
+                .kill()
+                .map_err(|err| Error::KillProcessError(err, cmd.to_owned()))?;
+            child
+                .wait()
+                .map_err(|err| Error::WaitProcessError(err, cmd.to_owned()))?;
+            return Err(Error::CmdTimeoutError(cmd.to_owned(), timeout));
+        }
+
+        thread::sleep(WAIT_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod process {
+    use std::time::{Duration, Instant};
+
+    use super::{pipe, run, run_with_timeout, Error};
+
+    #[test]
+    fn pipe_status_and_output() {
+        let output = pipe("cat; echo err >&2; exit 3", b"input", None).unwrap();
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stdout, b"input");
+        assert_eq!(output.stderr_lossy(), "err");
+    }
+
+    #[test]
+    fn pipe_unread_input() {
+        let input = vec![b'a'; 1024 * 1024];
+        let output = pipe("echo done", &input, None).unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"done\n");
+    }
+
+    #[test]
+    fn pipe_timeout() {
+        let start = Instant::now();
+        let res = pipe("sleep 5", &[], Some(Duration::from_millis(100)));
+        assert!(matches!(res, Err(Error::CmdTimeoutError(_, _))));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn run_pipeline() {
+        let output = run("cat | tr a-z A-Z", b"hello").unwrap();
+        assert_eq!(output, b"HELLO");
+
+        let output = run_with_timeout("cat", b"hello", Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(output, b"hello");
+    }
+
+    #[test]
+    fn run_failed() {
+        match run("echo out; echo bad passwd >&2; exit 1", &[]) {
+            Err(Error::CmdFailedError(_, status, stderr)) => {
+                assert_eq!(status.code(), Some(1));
+                assert_eq!(stderr, "bad passwd");
+            }
+            res => panic!("unexpected result: {res:?}"),
+        }
+    }
 }
@@ -0,0 +1,330 @@
+//! Secret module.
+//!
+//! This module contains the representation of the secrets (passwords,
+//! tokens) used by backends and senders. A secret can be given raw or
+//! retrieved from a command, an environment variable, a file or an
+//! encrypted file. Retrieved secrets are cached in memory, so that
+//! for example a password command runs once per process instead of
+//! once per session.
+
+use serde::Deserialize;
+use std::{
+    env, fmt, fs, io,
+    path::PathBuf,
+    result,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use thiserror::Error;
+
+use crate::process;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("cannot get secret: secret is not defined")]
+    GetSecretUndefinedError,
+    #[error("cannot get secret: secret is empty")]
+    GetSecretEmptyError,
+    #[error("cannot get secret from command")]
+    GetSecretFromCmdError(#[source] process::Error),
+    #[error("cannot get secret from environment variable {1}")]
+    GetSecretFromEnvError(#[source] env::VarError, String),
+    #[error("cannot read secret from file {1}")]
+    ReadSecretFileError(#[source] io::Error, PathBuf),
+    #[error("cannot decrypt secret from file {1}")]
+    DecryptSecretFileError(#[source] process::Error, PathBuf),
+    #[error("cannot lock secret cache: {0}")]
+    LockSecretCacheError(String),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents the source of a secret. In a configuration file, the
+/// source is given as a table with one key named after the variant:
+///
+/// ```toml
+/// passwd.raw = "password"
+/// passwd.cmd = "pass show example"
+/// passwd.env = "EXAMPLE_PASSWD"
+/// passwd.file = "~/.example-passwd"
+/// passwd.encrypted-file = { path = "~/.example-passwd.gpg", decrypt-cmd = "gpg -dq" }
+/// ```
+#[derive(Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(
+    rename_all = "kebab-case",
+    rename_all_fields = "kebab-case",
+    deny_unknown_fields
+)]
+pub enum SecretSource {
+    /// Represents an undefined secret.
+    #[default]
+    #[serde(skip)]
+    Undefined,
+    /// Represents a secret given as is.
+    Raw(String),
+    /// Represents a shell command. The first line of its output is
+    /// the secret.
+    Cmd(String),
+    /// Represents the name of an environment variable holding the
+    /// secret.
+    Env(String),
+    /// Represents the path of a file. Its first line is the secret.
+    File(PathBuf),
+    /// Represents the path of an encrypted file, and the command used
+    /// to decrypt it. The file content is piped to the command, and
+    /// the first line of its output is the secret.
+    EncryptedFile { path: PathBuf, decrypt_cmd: String },
+}
+
+impl fmt::Debug for SecretSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Undefined => write!(f, "Undefined"),
+            Self::Raw(_) => write!(f, "Raw(<redacted>)"),
+            Self::Cmd(cmd) => f.debug_tuple("Cmd").field(cmd).finish(),
+            Self::Env(name) => f.debug_tuple("Env").field(name).finish(),
+            Self::File(path) => f.debug_tuple("File").field(path).finish(),
+            Self::EncryptedFile { path, decrypt_cmd } => f
+                .debug_struct("EncryptedFile")
+                .field("path", path)
+                .field("decrypt_cmd", decrypt_cmd)
+                .finish(),
+        }
+    }
+}
+
+/// Represents the cache of a secret. The cache is shared between
+/// clones of the secret, and it is not taken into account when
+/// comparing secrets.
+#[derive(Clone, Default)]
+pub struct SecretCache(Arc<Mutex<Option<String>>>);
+
+impl fmt::Debug for SecretCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretCache")
+    }
+}
+
+impl PartialEq for SecretCache {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for SecretCache {}
+
+/// Represents a secret.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize)]
+#[serde(from = "SecretSource")]
+pub struct Secret {
+    pub source: SecretSource,
+    pub cache: SecretCache,
+    /// Represents the maximum duration of the commands retrieving the
+    /// secret (the command itself, or the decrypt command of an
+    /// encrypted file). Commands running longer are killed. Defaults
+    /// to no timeout.
+    pub timeout: Option<Duration>,
+}
+
+impl Secret {
+    pub fn new_raw<S: ToString>(secret: S) -> Self {
+        SecretSource::Raw(secret.to_string()).into()
+    }
+
+    pub fn new_cmd<S: ToString>(cmd: S) -> Self {
+        SecretSource::Cmd(cmd.to_string()).into()
+    }
+
+    pub fn new_env<S: ToString>(name: S) -> Self {
+        SecretSource::Env(name.to_string()).into()
+    }
+
+    pub fn new_file<P: Into<PathBuf>>(path: P) -> Self {
+        SecretSource::File(path.into()).into()
+    }
+
+    pub fn new_encrypted_file<P: Into<PathBuf>, S: ToString>(path: P, decrypt_cmd: S) -> Self {
+        SecretSource::EncryptedFile {
+            path: path.into(),
+            decrypt_cmd: decrypt_cmd.to_string(),
+        }
+        .into()
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn is_undefined(&self) -> bool {
+        self.source == SecretSource::Undefined
+    }
+
+    /// Gets the secret, from the cache if it has already been
+    /// retrieved.
+    pub fn get(&self) -> Result<String> {
+        if let SecretSource::Raw(secret) = &self.source {
+            return Ok(secret.clone());
+        }
+
+        let mut cache = self
+            .cache
+            .0
+            .lock()
+            .map_err(|err| Error::LockSecretCacheError(err.to_string()))?;
+
+        if let Some(secret) = cache.as_ref() {
+            return Ok(secret.clone());
+        }
+
+        let secret = self.retrieve()?;
+        *cache = Some(secret.clone());
+        Ok(secret)
+    }
+
+    /// Clears the cache, so that the secret is retrieved again the
+    /// next time, for example after it has been rejected.
+    pub fn reset_cache(&self) {
+        if let Ok(mut cache) = self.cache.0.lock() {
+            *cache = None;
+        }
+    }
+
+    fn retrieve(&self) -> Result<String> {
+        let secret = match &self.source {
+            SecretSource::Undefined => return Err(Error::GetSecretUndefinedError),
+            SecretSource::Raw(secret) => return Ok(secret.clone()),
+            SecretSource::Cmd(cmd) => {
+                let output = process::run_with_timeout(cmd, &[], self.timeout)
+                    .map_err(Error::GetSecretFromCmdError)?;
+                first_line(&output)?
+            }
+            SecretSource::Env(name) => {
+                env::var(name).map_err(|err| Error::GetSecretFromEnvError(err, name.clone()))?
+            }
+            SecretSource::File(path) => {
+                let path = expand_path(path);
+                let content =
+                    fs::read(&path).map_err(|err| Error::ReadSecretFileError(err, path.clone()))?;
+                first_line(&content)?
+            }
+            SecretSource::EncryptedFile { path, decrypt_cmd } => {
+                let path = expand_path(path);
+                let content =
+                    fs::read(&path).map_err(|err| Error::ReadSecretFileError(err, path.clone()))?;
+                let output = process::run_with_timeout(decrypt_cmd, &content, self.timeout)
+                    .map_err(|err| Error::DecryptSecretFileError(err, path.clone()))?;
+                first_line(&output)?
+            }
+        };
+
+        if secret.is_empty() {
+            return Err(Error::GetSecretEmptyError);
+        }
+
+        Ok(secret)
+    }
+}
+
+impl From<SecretSource> for Secret {
+    fn from(source: SecretSource) -> Self {
+        Self {
+            source,
+            cache: SecretCache::default(),
+            timeout: None,
+        }
+    }
+}
+
+/// Expands the shell variables of the given path, like `~`.
+fn expand_path(path: &PathBuf) -> PathBuf {
+    path.to_str()
+        .and_then(|path| shellexpand::full(path).ok())
+        .map(|path| PathBuf::from(path.to_string()))
+        .unwrap_or_else(|| path.clone())
+}
+
+fn first_line(output: &[u8]) -> Result<String> {
+    String::from_utf8_lossy(output)
+        .lines()
+        .next()
+        .map(ToOwned::to_owned)
+        .ok_or(Error::GetSecretEmptyError)
+}
+
+#[cfg(test)]
+mod secret {
+    use std::{env, fs, time::Duration};
+
+    use crate::process;
+
+    use super::{Error, Secret};
+
+    #[test]
+    fn raw() {
+        let secret = Secret::new_raw("passwd");
+        assert_eq!(secret.get().unwrap(), "passwd");
+        assert!(!format!("{secret:?}").contains("passwd"));
+    }
+
+    #[test]
+    fn cmd_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let counter = dir.path().join("counter");
+
+        // the command appends a line to the counter file each time it
+        // runs
+        let secret = Secret::new_cmd(format!("echo run >> {0}; echo passwd", counter.display()));
+        let clone = secret.clone();
+        assert_eq!(secret.get().unwrap(), "passwd");
+        assert_eq!(clone.get().unwrap(), "passwd");
+        assert_eq!(fs::read_to_string(&counter).unwrap().lines().count(), 1);
+
+        secret.reset_cache();
+        assert_eq!(clone.get().unwrap(), "passwd");
+        assert_eq!(fs::read_to_string(&counter).unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn cmd_timeout() {
+        let secret =
+            Secret::new_cmd("sleep 5; echo passwd").with_timeout(Duration::from_millis(100));
+        assert!(matches!(
+            secret.get(),
+            Err(Error::GetSecretFromCmdError(
+                process::Error::CmdTimeoutError(_, _)
+            ))
+        ));
+
+        let secret = Secret::new_cmd("echo passwd").with_timeout(Duration::from_secs(5));
+        assert_eq!(secret.get().unwrap(), "passwd");
+    }
+
+    #[test]
+    fn env() {
+        env::set_var("HIMALAYA_LIB_TEST_SECRET", "passwd");
+        let secret = Secret::new_env("HIMALAYA_LIB_TEST_SECRET");
+        assert_eq!(secret.get().unwrap(), "passwd");
+        assert!(Secret::new_env("HIMALAYA_LIB_TEST_SECRET_UNDEFINED")
+            .get()
+            .is_err());
+    }
+
+    #[test]
+    fn files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret");
+        fs::write(&path, "dpasswd\nsecond line\n").unwrap();
+
+        assert_eq!(Secret::new_file(&path).get().unwrap(), "dpasswd");
+        // the decrypt command receives the file content
+        let secret = Secret::new_encrypted_file(&path, "cut -c 2-");
+        assert_eq!(secret.get().unwrap(), "passwd");
+    }
+
+    #[test]
+    fn undefined() {
+        assert!(Secret::default().is_undefined());
+        assert!(Secret::default().get().is_err());
+    }
+}
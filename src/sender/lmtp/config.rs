@@ -0,0 +1,49 @@
+//! LMTP config module.
+//!
+//! This module contains the representation of the LMTP email sender
+//! configuration of the user account.
+
+use serde::Deserialize;
+use std::{path::PathBuf, time::Duration};
+
+/// Represents the default name sent with the `LHLO` command.
+pub const DEFAULT_LMTP_LHLO_NAME: &str = "localhost";
+
+/// Represents the default read and write timeout, in seconds.
+pub const DEFAULT_LMTP_TIMEOUT: u64 = 60;
+
+/// Represents the LMTP sender config.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct LmtpConfig {
+    /// Represents the LMTP server host.
+    #[serde(default)]
+    pub host: String,
+    /// Represents the LMTP server port.
+    #[serde(default)]
+    pub port: u16,
+    /// Represents the path of the Unix socket of the LMTP server,
+    /// used instead of the host and the port when defined.
+    pub socket: Option<PathBuf>,
+    /// Represents the name sent with the `LHLO` command. Defaults to
+    /// `localhost`.
+    pub lhlo_name: Option<String>,
+    /// Represents the read and write timeout of the connection, in
+    /// seconds. A server that does not reply in time makes the
+    /// delivery fail. Defaults to 60, 0 disables the timeout.
+    pub timeout: Option<u64>,
+}
+
+impl LmtpConfig {
+    pub fn lhlo_name(&self) -> &str {
+        self.lhlo_name.as_deref().unwrap_or(DEFAULT_LMTP_LHLO_NAME)
+    }
+
+    /// Gets the read and write timeout of the connection, if any.
+    pub fn timeout(&self) -> Option<Duration> {
+        match self.timeout.unwrap_or(DEFAULT_LMTP_TIMEOUT) {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+}
@@ -0,0 +1,302 @@
+//! LMTP module.
+//!
+//! This module contains the representation of the LMTP email sender,
+//! as defined in the [RFC 2033]. Unlike SMTP, the server answers the
+//! end of the email data with one reply per accepted recipient, so
+//! the delivery result is known for each recipient.
+//!
+//! [RFC 2033]: https://www.rfc-editor.org/rfc/rfc2033
+
+use log::{debug, info, trace, warn};
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    result,
+};
+use thiserror::Error;
+
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+use crate::{
+    process,
+    sender::{self, sender_envelope},
+    AccountConfig, LmtpConfig, RecipientReport, SendReport, Sender, SenderEnvelope, SenderReply,
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("cannot connect to lmtp server {1}")]
+    ConnectError(#[source] io::Error, String),
+    #[error("cannot set timeout of lmtp connection")]
+    SetTimeoutError(#[source] io::Error),
+    #[error("cannot connect to lmtp server {0}: unix sockets are not supported")]
+    ConnectUnixSocketNotSupportedError(String),
+    #[error("cannot read lmtp server reply")]
+    ReadReplyError(#[source] io::Error),
+    #[error("cannot read lmtp server reply: connection closed")]
+    ReadReplyClosedError,
+    #[error("cannot parse lmtp server reply {0}")]
+    ParseReplyError(String),
+    #[error("cannot write lmtp command")]
+    WriteCommandError(#[source] io::Error),
+    #[error("cannot execute lmtp command {0}: {1}")]
+    ExecuteCommandError(String, SenderReply),
+    #[error("cannot deliver email: no recipient found")]
+    GetRecipientsEmptyError,
+    #[error("cannot parse email before sending")]
+    ParseEmailError(#[source] mailparse::MailParseError),
+    #[error("cannot execute pre-send hook")]
+    ExecutePreSendHookError(#[source] process::Error),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+trait Stream: Read + Write {}
+
+impl<T: Read + Write> Stream for T {}
+
+/// Represents an open connection to the LMTP server.
+struct LmtpConnection {
+    reader: BufReader<Box<dyn Stream>>,
+    /// Represents the extensions announced in the `LHLO` reply.
+    extensions: Vec<String>,
+}
+
+impl LmtpConnection {
+    fn new(stream: Box<dyn Stream>) -> Self {
+        Self {
+            reader: BufReader::new(stream),
+            extensions: Vec::new(),
+        }
+    }
+
+    fn read_reply(&mut self) -> Result<SenderReply> {
+        let mut lines = Vec::new();
+
+        loop {
+            let mut line = String::new();
+            let len = self
+                .reader
+                .read_line(&mut line)
+                .map_err(Error::ReadReplyError)?;
+            if len == 0 {
+                return Err(Error::ReadReplyClosedError);
+            }
+
+            let line = line.trim_end_matches(['\r', '\n']);
+            trace!("<< {line}");
+
+            let code = line
+                .get(..3)
+                .and_then(|code| code.parse::<u16>().ok())
+                .ok_or_else(|| Error::ParseReplyError(line.to_owned()))?;
+            let last = !line.get(3..4).map(|sep| sep == "-").unwrap_or_default();
+            lines.push(line.get(4..).unwrap_or_default().to_owned());
+
+            if last {
+                return Ok(SenderReply {
+                    code,
+                    message: lines.join("\n"),
+                });
+            }
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        let stream = self.reader.get_mut();
+        stream.write_all(data).map_err(Error::WriteCommandError)?;
+        stream.flush().map_err(Error::WriteCommandError)
+    }
+
+    fn command(&mut self, cmd: &str) -> Result<SenderReply> {
+        trace!(">> {cmd}");
+        self.write(format!("{cmd}\r\n").as_bytes())?;
+        self.read_reply()
+    }
+
+    /// Sends the given command and expects a positive reply.
+    fn expect(&mut self, cmd: &str) -> Result<SenderReply> {
+        let reply = self.command(cmd)?;
+        if reply.is_positive() {
+            Ok(reply)
+        } else {
+            Err(Error::ExecuteCommandError(cmd.to_owned(), reply))
+        }
+    }
+}
+
+pub struct Lmtp<'a> {
+    account_config: &'a AccountConfig,
+    lmtp_config: &'a LmtpConfig,
+}
+
+impl<'a> Lmtp<'a> {
+    pub fn new(account_config: &'a AccountConfig, lmtp_config: &'a LmtpConfig) -> Self {
+        Self {
+            account_config,
+            lmtp_config,
+        }
+    }
+
+    fn connect(&self) -> Result<LmtpConnection> {
+        let timeout = self.lmtp_config.timeout();
+        let stream: Box<dyn Stream> = match &self.lmtp_config.socket {
+            #[cfg(unix)]
+            Some(path) => {
+                let stream = UnixStream::connect(path)
+                    .map_err(|err| Error::ConnectError(err, path.display().to_string()))?;
+                stream
+                    .set_read_timeout(timeout)
+                    .and_then(|()| stream.set_write_timeout(timeout))
+                    .map_err(Error::SetTimeoutError)?;
+                Box::new(stream)
+            }
+            #[cfg(not(unix))]
+            Some(path) => {
+                return Err(Error::ConnectUnixSocketNotSupportedError(
+                    path.display().to_string(),
+                ))
+            }
+            None => {
+                let addr = (self.lmtp_config.host.as_str(), self.lmtp_config.port);
+                let stream = TcpStream::connect(addr)
+                    .map_err(|err| Error::ConnectError(err, format!("{}:{}", addr.0, addr.1)))?;
+                stream
+                    .set_read_timeout(timeout)
+                    .and_then(|()| stream.set_write_timeout(timeout))
+                    .map_err(Error::SetTimeoutError)?;
+                Box::new(stream)
+            }
+        };
+
+        let mut conn = LmtpConnection::new(stream);
+
+        let greeting = conn.read_reply()?;
+        if !greeting.is_positive() {
+            return Err(Error::ExecuteCommandError("greeting".into(), greeting));
+        }
+        let reply = conn.expect(&format!("LHLO {}", self.lmtp_config.lhlo_name()))?;
+        // the first line holds the name of the server
+        conn.extensions = reply.message.lines().skip(1).map(String::from).collect();
+
+        Ok(conn)
+    }
+
+    /// Connects to the server, then returns the extensions it
+    /// announced.
+    pub fn capabilities(&self) -> Result<Vec<String>> {
+        let mut conn = self.connect()?;
+        let _ = conn.command("QUIT");
+        Ok(conn.extensions)
+    }
+
+    /// Delivers the given email, after the pre-send hook, and returns
+    /// the reply of the server for each recipient. Recipients
+    /// rejected at the `RCPT TO` stage get the reply of this command.
+    fn deliver(&mut self, email: &[u8]) -> Result<SendReport> {
+        let mut email = mailparse::parse_mail(email).map_err(Error::ParseEmailError)?;
+        let buffer;
+
+        if let Some(cmd) = self.account_config.email_hooks.pre_send.as_deref() {
+            buffer = process::run(cmd, email.raw_bytes).map_err(Error::ExecutePreSendHookError)?;
+            email = mailparse::parse_mail(&buffer).map_err(Error::ParseEmailError)?;
+        };
+
+        let envelope = SenderEnvelope::from_email(&email);
+        if envelope.to.is_empty() {
+            return Err(Error::GetRecipientsEmptyError);
+        }
+
+        info!("delivering email to {} recipient(s)", envelope.to.len());
+        let mut conn = self.connect()?;
+
+        let from = envelope.from.as_deref().unwrap_or_default();
+        conn.expect(&format!("MAIL FROM:<{from}>"))?;
+
+        let mut recipients = Vec::new();
+        let mut accepted = Vec::new();
+
+        for rcpt in envelope.to {
+            let reply = conn.command(&format!("RCPT TO:<{rcpt}>"))?;
+            if reply.is_positive() {
+                accepted.push(rcpt);
+            } else {
+                debug!("recipient {rcpt} rejected: {reply}");
+                recipients.push(RecipientReport::from_reply(rcpt, reply));
+            }
+        }
+
+        if !accepted.is_empty() {
+            conn.expect("DATA")?;
+            conn.write(&dot_stuff(&sender_envelope::strip_bcc(email.raw_bytes)))?;
+
+            // The server sends one reply per accepted recipient, in
+            // the order of the RCPT TO commands.
+            for rcpt in accepted {
+                let reply = conn.read_reply()?;
+                if !reply.is_positive() {
+                    debug!("delivery to {rcpt} failed: {reply}");
+                }
+                recipients.push(RecipientReport::from_reply(rcpt, reply));
+            }
+        }
+
+        let _ = conn.command("QUIT");
+
+        Ok(SendReport {
+            email: email.raw_bytes.to_vec(),
+            recipients,
+        })
+    }
+}
+
+impl<'a> Sender for Lmtp<'a> {
+    fn send(&mut self, email: &[u8]) -> sender::Result<SendReport> {
+        let report = self.deliver(email)?;
+
+        if report.accepted().next().is_none() {
+            return Err(sender::sender::Error::RecipientsRejectedError(
+                report.recipients,
+            ));
+        }
+
+        for rcpt in report.rejected() {
+            warn!("cannot deliver email to {rcpt}");
+        }
+
+        Ok(report)
+    }
+}
+
+/// Normalizes line endings to CRLF, escapes lines starting with a dot
+/// and appends the end of data marker.
+fn dot_stuff(email: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(email.len() + 5);
+
+    for line in email.split_inclusive(|b| *b == b'\n') {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.starts_with(b".") {
+            data.push(b'.');
+        }
+        data.extend_from_slice(line);
+        data.extend_from_slice(b"\r\n");
+    }
+
+    data.extend_from_slice(b".\r\n");
+    data
+}
+
+#[cfg(test)]
+mod lmtp {
+    use super::dot_stuff;
+
+    #[test]
+    fn dot_stuffing() {
+        assert_eq!(
+            dot_stuff(b"Subject: a\n\n.hidden\r\nend"),
+            b"Subject: a\r\n\r\n..hidden\r\nend\r\n.\r\n"
+        );
+    }
+}
@@ -0,0 +1,5 @@
+pub mod config;
+pub mod lmtp;
+
+pub use config::LmtpConfig;
+pub use lmtp::{Error, Lmtp};
@@ -1,8 +1,20 @@
 pub mod sender;
 pub use sender::*;
 
+pub mod sender_envelope;
+pub use sender_envelope::SenderEnvelope;
+
+pub mod sender_report;
+pub use sender_report::{RecipientReport, SendReport, SenderReply};
+
+pub mod outbox;
+pub use outbox::{Outbox, OutboxEntry, OutboxReport, OutboxStatus};
+
 pub mod smtp;
 pub use smtp::*;
 
+pub mod lmtp;
+pub use lmtp::*;
+
 pub mod sendmail;
 pub use sendmail::*;
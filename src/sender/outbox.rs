@@ -0,0 +1,703 @@
+//! Outbox module.
+//!
+//! This module contains the persistent queue of outgoing emails. An
+//! email is first stored in the outbox, then handed to a sender. When
+//! the delivery fails, the email stays in the outbox and it is retried
+//! later with an exponential backoff, until it is sent or until the
+//! maximum number of attempts is reached.
+//!
+//! Emails can also be scheduled, in which case they are kept in the
+//! outbox until their send time is reached.
+
+use chrono::{DateTime, Local, TimeZone, Utc};
+use log::{debug, info, warn};
+use mailparse::MailHeaderMap;
+use rusqlite::OptionalExtension;
+use std::{fmt, path::Path, result, str::FromStr, time::Duration};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::{account, error::error_chain, AccountConfig, Sender, SenderEnvelope};
+
+/// Represents the default maximum number of delivery attempts.
+pub const DEFAULT_OUTBOX_RETRY_MAX: usize = 5;
+
+/// Represents the default delay before the first retry. The delay
+/// doubles after each failed attempt.
+pub const DEFAULT_OUTBOX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Represents the time after which an email claimed by a sender that
+/// never reported back (for example after a crash) can be claimed
+/// again.
+const SENDING_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+const CREATE_OUTBOX_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS outbox (
+        id              TEXT    PRIMARY KEY,
+        email           BLOB    NOT NULL,
+        subject         TEXT    NOT NULL,
+        recipients      TEXT    NOT NULL,
+        status          TEXT    NOT NULL,
+        attempts        INTEGER NOT NULL,
+        last_error      TEXT    DEFAULT NULL,
+        created_at      INTEGER NOT NULL,
+        send_at         INTEGER NOT NULL,
+        updated_at      INTEGER NOT NULL,
+        next_attempt_at INTEGER NOT NULL
+    )
+";
+
+const INSERT_ENTRY: &str = "
+    INSERT INTO outbox
+    VALUES (?, ?, ?, ?, 'queued', 0, NULL, ?, ?, ?, ?)
+";
+
+const SELECT_ENTRIES: &str = "
+    SELECT id, subject, recipients, status, attempts, last_error, created_at, send_at, updated_at, next_attempt_at
+    FROM outbox
+    ORDER BY created_at, rowid
+";
+
+const SELECT_ENTRY: &str = "
+    SELECT id, subject, recipients, status, attempts, last_error, created_at, send_at, updated_at, next_attempt_at
+    FROM outbox
+    WHERE id = ?
+";
+
+const SELECT_SCHEDULED_ENTRIES: &str = "
+    SELECT id, subject, recipients, status, attempts, last_error, created_at, send_at, updated_at, next_attempt_at
+    FROM outbox
+    WHERE status = 'queued'
+    AND send_at > ?
+    ORDER BY send_at, rowid
+";
+
+const SELECT_EMAIL: &str = "
+    SELECT email
+    FROM outbox
+    WHERE id = ?
+";
+
+const SELECT_SENDABLE_IDS: &str = "
+    SELECT id
+    FROM outbox
+    WHERE (status = 'queued' AND send_at <= ?1 AND next_attempt_at <= ?2)
+    OR (status = 'sending' AND updated_at <= ?3)
+    ORDER BY created_at, rowid
+";
+
+const CLAIM_ENTRY: &str = "
+    UPDATE outbox
+    SET status = 'sending', updated_at = ?1
+    WHERE id = ?2
+    AND ((status = 'queued' AND send_at <= ?1 AND next_attempt_at <= ?3) OR (status = 'sending' AND updated_at <= ?4))
+";
+
+const UPDATE_ENTRY_STATUS: &str = "
+    UPDATE outbox
+    SET status = ?, attempts = ?, last_error = ?, updated_at = ?, next_attempt_at = ?
+    WHERE id = ?
+";
+
+const REQUEUE_ENTRY: &str = "
+    UPDATE outbox
+    SET status = 'queued', attempts = 0, updated_at = ?1, next_attempt_at = ?1
+    WHERE id = ?2
+    AND status = 'failed'
+";
+
+const RESCHEDULE_ENTRY: &str = "
+    UPDATE outbox
+    SET send_at = ?1, updated_at = ?2, next_attempt_at = ?1
+    WHERE id = ?3
+    AND status = 'queued'
+";
+
+const DELETE_ENTRY: &str = "
+    DELETE FROM outbox
+    WHERE id = ?
+    AND status != 'sending'
+";
+
+const DELETE_SENT_ENTRIES: &str = "
+    DELETE FROM outbox
+    WHERE status = 'sent'
+";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("cannot open outbox database at {1}")]
+    OpenDatabaseError(#[source] rusqlite::Error, String),
+    #[error("cannot parse email before queuing it")]
+    ParseEmailError(#[source] mailparse::MailParseError),
+    #[error("cannot find outbox email {0}")]
+    FindEntryError(String),
+    #[error("cannot cancel outbox email {0}: email is being sent")]
+    CancelSendingEntryError(String),
+    #[error("cannot requeue outbox email {0}: email did not fail")]
+    RequeueEntryError(String),
+    #[error("cannot reschedule outbox email {0}: email is not queued")]
+    RescheduleEntryError(String),
+    #[error("cannot parse outbox status {0}")]
+    ParseStatusError(String),
+
+    #[error(transparent)]
+    SqliteError(#[from] rusqlite::Error),
+    #[error(transparent)]
+    ConfigError(#[from] account::config::Error),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+/// Represents the delivery status of an outbox email.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum OutboxStatus {
+    /// The email is waiting to be sent, either for the first time or
+    /// for a retry.
+    Queued,
+    /// The email is being sent.
+    Sending,
+    /// The email has been sent.
+    Sent,
+    /// The delivery failed too many times. The email needs to be
+    /// requeued to be sent again.
+    Failed,
+}
+
+impl OutboxStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::Sending => "sending",
+            Self::Sent => "sent",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+impl FromStr for OutboxStatus {
+    type Err = Error;
+
+    fn from_str(status: &str) -> Result<Self> {
+        match status {
+            "queued" => Ok(Self::Queued),
+            "sending" => Ok(Self::Sending),
+            "sent" => Ok(Self::Sent),
+            "failed" => Ok(Self::Failed),
+            status => Err(Error::ParseStatusError(status.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for OutboxStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Represents an email stored in the outbox, without its content.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OutboxEntry {
+    pub id: String,
+    pub subject: String,
+    pub recipients: Vec<String>,
+    pub status: OutboxStatus,
+    /// Represents the number of failed delivery attempts.
+    pub attempts: usize,
+    /// Represents the error of the last failed delivery attempt.
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Local>,
+    /// Represents the time from which the email can be sent. It
+    /// matches the creation time, unless the email was scheduled.
+    pub send_at: DateTime<Local>,
+    pub updated_at: DateTime<Local>,
+    /// Represents the time from which the email can be sent again.
+    pub next_attempt_at: DateTime<Local>,
+}
+
+/// Represents the result of a flush of the outbox.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct OutboxReport {
+    /// Represents the ids of the emails that have been sent.
+    pub sent: Vec<String>,
+    /// Represents the ids of the emails that could not be sent and
+    /// that will be retried later.
+    pub deferred: Vec<String>,
+    /// Represents the ids of the emails that could not be sent and
+    /// that reached the maximum number of attempts.
+    pub failed: Vec<String>,
+}
+
+/// Represents the outbox, backed by a SQLite database.
+pub struct Outbox {
+    db: rusqlite::Connection,
+    retry_max: usize,
+    retry_delay: Duration,
+}
+
+impl Outbox {
+    /// Opens the outbox of the given account, stored in its sync
+    /// directory.
+    pub fn new(account_config: &AccountConfig) -> Result<Self> {
+        Self::open(account_config.sync_dir()?.join(".outbox.sqlite"))
+    }
+
+    /// Opens the outbox stored in the given database file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        info!("opening outbox at {:?}", path);
+
+        let db = rusqlite::Connection::open(path)
+            .map_err(|err| Error::OpenDatabaseError(err, path.display().to_string()))?;
+
+        Self::from_connection(db)
+    }
+
+    /// Creates an outbox from an already opened database connection.
+    pub fn from_connection(db: rusqlite::Connection) -> Result<Self> {
+        db.execute(CREATE_OUTBOX_TABLE, ())?;
+
+        Ok(Self {
+            db,
+            retry_max: DEFAULT_OUTBOX_RETRY_MAX,
+            retry_delay: DEFAULT_OUTBOX_RETRY_DELAY,
+        })
+    }
+
+    /// Sets the maximum number of delivery attempts (at least one).
+    pub fn retry_max(mut self, retry_max: usize) -> Self {
+        self.retry_max = retry_max.max(1);
+        self
+    }
+
+    /// Sets the delay before the first retry.
+    pub fn retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    /// Stores the given email in the outbox and returns its id. The
+    /// email is sent at the next flush.
+    pub fn queue(&self, email: &[u8]) -> Result<String> {
+        self.schedule(email, Local::now())
+    }
+
+    /// Stores the given email in the outbox and returns its id. The
+    /// email is not sent before the given time.
+    pub fn schedule(&self, email: &[u8], send_at: DateTime<Local>) -> Result<String> {
+        let parsed = mailparse::parse_mail(email).map_err(Error::ParseEmailError)?;
+        let subject = parsed
+            .headers
+            .get_first_value("Subject")
+            .unwrap_or_default();
+        let recipients = SenderEnvelope::from_email(&parsed).to.join(", ");
+
+        let id = Uuid::new_v4().to_string();
+        let now = Utc::now().timestamp();
+        let send_at = send_at.timestamp();
+        info!("queuing email {id} to outbox, to be sent at {send_at}");
+
+        self.db.execute(
+            INSERT_ENTRY,
+            rusqlite::params![id, email, subject, recipients, now, send_at, now, send_at],
+        )?;
+
+        Ok(id)
+    }
+
+    /// Stores the given email in the outbox, then tries to send it
+    /// straight away. If the delivery fails, the email stays in the
+    /// outbox and it is retried at a later flush.
+    pub fn send(&self, sender: &mut dyn Sender, email: &[u8]) -> Result<OutboxEntry> {
+        let id = self.queue(email)?;
+        self.try_send(sender, &id, Utc::now().timestamp())?;
+        self.get(&id)
+    }
+
+    /// Lists all the emails of the outbox, oldest first.
+    pub fn list(&self) -> Result<Vec<OutboxEntry>> {
+        let mut stmt = self.db.prepare(SELECT_ENTRIES)?;
+        let entries = stmt
+            .query_map([], |row| row_to_entry(row))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(entries)
+    }
+
+    /// Lists the queued emails whose send time is not reached yet,
+    /// the next to be sent first.
+    pub fn list_scheduled(&self) -> Result<Vec<OutboxEntry>> {
+        let mut stmt = self.db.prepare(SELECT_SCHEDULED_ENTRIES)?;
+        let entries = stmt
+            .query_map([Utc::now().timestamp()], |row| row_to_entry(row))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(entries)
+    }
+
+    /// Gets the outbox email matching the given id.
+    pub fn get(&self, id: &str) -> Result<OutboxEntry> {
+        self.db
+            .query_row(SELECT_ENTRY, [id], |row| row_to_entry(row))
+            .optional()?
+            .ok_or_else(|| Error::FindEntryError(id.to_owned()))
+    }
+
+    /// Gets the raw content of the outbox email matching the given
+    /// id.
+    pub fn get_email(&self, id: &str) -> Result<Vec<u8>> {
+        self.db
+            .query_row(SELECT_EMAIL, [id], |row| row.get(0))
+            .optional()?
+            .ok_or_else(|| Error::FindEntryError(id.to_owned()))
+    }
+
+    /// Removes the email matching the given id from the outbox, so
+    /// that it is not sent. An email being sent cannot be cancelled.
+    pub fn cancel(&self, id: &str) -> Result<()> {
+        info!("cancelling outbox email {id}");
+
+        let entry = self.get(id)?;
+        if self.db.execute(DELETE_ENTRY, [id])? == 0 {
+            return Err(match entry.status {
+                OutboxStatus::Sending => Error::CancelSendingEntryError(id.to_owned()),
+                _ => Error::FindEntryError(id.to_owned()),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Puts back in the queue an email that reached the maximum
+    /// number of attempts.
+    pub fn requeue(&self, id: &str) -> Result<()> {
+        info!("requeuing outbox email {id}");
+
+        let now = Utc::now().timestamp();
+        if self.db.execute(REQUEUE_ENTRY, rusqlite::params![now, id])? == 0 {
+            self.get(id)?;
+            return Err(Error::RequeueEntryError(id.to_owned()));
+        }
+
+        Ok(())
+    }
+
+    /// Changes the send time of the queued email matching the given
+    /// id. The pending retry delay, if any, is reset.
+    pub fn reschedule(&self, id: &str, send_at: DateTime<Local>) -> Result<()> {
+        info!("rescheduling outbox email {id} at {send_at}");
+
+        let now = Utc::now().timestamp();
+        let params = rusqlite::params![send_at.timestamp(), now, id];
+        if self.db.execute(RESCHEDULE_ENTRY, params)? == 0 {
+            self.get(id)?;
+            return Err(Error::RescheduleEntryError(id.to_owned()));
+        }
+
+        Ok(())
+    }
+
+    /// Removes the emails that have been sent from the outbox.
+    pub fn purge_sent(&self) -> Result<usize> {
+        info!("purging sent emails from outbox");
+        Ok(self.db.execute(DELETE_SENT_ENTRIES, ())?)
+    }
+
+    /// Sends all the queued emails whose send time is reached and
+    /// whose retry delay has elapsed. This function is meant to be
+    /// called periodically, for example by a daemon.
+    pub fn process_due(&self, sender: &mut dyn Sender) -> Result<OutboxReport> {
+        self.send_queued(sender, Utc::now().timestamp())
+    }
+
+    /// Sends all the queued emails whose send time is reached,
+    /// without waiting for their retry delay, for example once the
+    /// connection is back.
+    pub fn flush(&self, sender: &mut dyn Sender) -> Result<OutboxReport> {
+        self.send_queued(sender, i64::MAX)
+    }
+
+    fn send_queued(&self, sender: &mut dyn Sender, due_at: i64) -> Result<OutboxReport> {
+        info!("sending queued emails from outbox");
+
+        let now = Utc::now().timestamp();
+        let stale_at = now - SENDING_TIMEOUT.as_secs() as i64;
+        let ids = {
+            let mut stmt = self.db.prepare(SELECT_SENDABLE_IDS)?;
+            let ids = stmt
+                .query_map([now, due_at, stale_at], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+            ids
+        };
+        debug!("{} email(s) to send", ids.len());
+
+        let mut report = OutboxReport::default();
+
+        for id in ids {
+            match self.try_send(sender, &id, due_at)? {
+                Some(OutboxStatus::Sent) => report.sent.push(id),
+                Some(OutboxStatus::Queued) => report.deferred.push(id),
+                Some(OutboxStatus::Failed) => report.failed.push(id),
+                // The email has been cancelled or claimed by another
+                // sender in the meantime.
+                _ => (),
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Claims then sends the email matching the given id, and records
+    /// the result of the delivery. Returns the new status of the
+    /// email, or `None` if the email could not be claimed.
+    fn try_send(
+        &self,
+        sender: &mut dyn Sender,
+        id: &str,
+        due_at: i64,
+    ) -> Result<Option<OutboxStatus>> {
+        let now = Utc::now().timestamp();
+        let stale_at = now - SENDING_TIMEOUT.as_secs() as i64;
+
+        let claimed = self
+            .db
+            .execute(CLAIM_ENTRY, rusqlite::params![now, id, due_at, stale_at])?;
+        if claimed == 0 {
+            debug!("outbox email {id} already claimed, skipping it");
+            return Ok(None);
+        }
+
+        let entry = self.get(id)?;
+        let email = self.get_email(id)?;
+
+        let (status, attempts, last_error, next_attempt_at) = match sender.send(&email) {
+            Ok(_) => {
+                info!("outbox email {id} sent");
+                (OutboxStatus::Sent, entry.attempts, None, now)
+            }
+            Err(err) => {
+                let attempts = entry.attempts + 1;
+                let err = error_chain(&err);
+
+                if attempts >= self.retry_max {
+                    warn!("cannot send outbox email {id}, giving up: {err}");
+                    (OutboxStatus::Failed, attempts, Some(err), now)
+                } else {
+                    let delay = self.retry_delay * 2u32.saturating_pow(attempts as u32 - 1);
+                    warn!("cannot send outbox email {id}, retrying in {delay:?}: {err}");
+                    let next_attempt_at = now.saturating_add(delay.as_secs() as i64);
+                    (OutboxStatus::Queued, attempts, Some(err), next_attempt_at)
+                }
+            }
+        };
+
+        self.db.execute(
+            UPDATE_ENTRY_STATUS,
+            rusqlite::params![
+                status.as_str(),
+                attempts,
+                last_error,
+                Utc::now().timestamp(),
+                next_attempt_at,
+                id
+            ],
+        )?;
+
+        Ok(Some(status))
+    }
+}
+
+fn row_to_entry(row: &rusqlite::Row) -> rusqlite::Result<OutboxEntry> {
+    let recipients: String = row.get(2)?;
+    let status: String = row.get(3)?;
+
+    Ok(OutboxEntry {
+        id: row.get(0)?,
+        subject: row.get(1)?,
+        recipients: recipients
+            .split(", ")
+            .filter(|addr| !addr.is_empty())
+            .map(ToOwned::to_owned)
+            .collect(),
+        status: status.parse().map_err(|err| {
+            rusqlite::Error::FromSqlConversionFailure(3, rusqlite::types::Type::Text, Box::new(err))
+        })?,
+        attempts: row.get(4)?,
+        last_error: row.get(5)?,
+        created_at: timestamp_to_date(row.get(6)?),
+        send_at: timestamp_to_date(row.get(7)?),
+        updated_at: timestamp_to_date(row.get(8)?),
+        next_attempt_at: timestamp_to_date(row.get(9)?),
+    })
+}
+
+fn timestamp_to_date(timestamp: i64) -> DateTime<Local> {
+    Local
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .unwrap_or_else(|| Local.timestamp_opt(0, 0).unwrap())
+}
+
+#[cfg(test)]
+mod outbox {
+    use chrono::Local;
+    use concat_with::concat_line;
+    use std::time::Duration;
+
+    use crate::{sender::sender, SendReport, Sender};
+
+    use super::{Outbox, OutboxStatus};
+
+    const EMAIL: &[u8] = concat_line!(
+        "From: alice@localhost",
+        "To: bob@localhost",
+        "Cc: carol@localhost",
+        "Subject: subject",
+        "",
+        "Hello!",
+    )
+    .as_bytes();
+
+    /// Represents a sender that fails the given number of times
+    /// before succeeding.
+    #[derive(Default)]
+    struct FlakySender {
+        failures: usize,
+        sent: Vec<Vec<u8>>,
+    }
+
+    impl Sender for FlakySender {
+        fn send(&mut self, email: &[u8]) -> sender::Result<SendReport> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(sender::Error::BuildEmailSenderMissingError);
+            }
+            self.sent.push(email.to_vec());
+            Ok(SendReport {
+                email: email.to_vec(),
+                recipients: Vec::new(),
+            })
+        }
+    }
+
+    fn outbox() -> Outbox {
+        Outbox::from_connection(rusqlite::Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn queue_and_flush() {
+        let outbox = outbox();
+        let id = outbox.queue(EMAIL).unwrap();
+
+        let entry = outbox.get(&id).unwrap();
+        assert_eq!(entry.status, OutboxStatus::Queued);
+        assert_eq!(entry.subject, "subject");
+        assert_eq!(entry.recipients, vec!["bob@localhost", "carol@localhost"]);
+
+        let mut sender = FlakySender::default();
+        let report = outbox.flush(&mut sender).unwrap();
+        assert_eq!(report.sent, vec![id.clone()]);
+        assert_eq!(sender.sent, vec![EMAIL.to_vec()]);
+        assert_eq!(outbox.get(&id).unwrap().status, OutboxStatus::Sent);
+
+        // sent emails are not sent again
+        let report = outbox.flush(&mut sender).unwrap();
+        assert!(report.sent.is_empty());
+
+        assert_eq!(outbox.purge_sent().unwrap(), 1);
+        assert!(outbox.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn retry_with_backoff() {
+        let outbox = outbox().retry_max(3).retry_delay(Duration::from_secs(3600));
+        let mut sender = FlakySender {
+            failures: 1,
+            ..FlakySender::default()
+        };
+
+        let entry = outbox.send(&mut sender, EMAIL).unwrap();
+        assert_eq!(entry.status, OutboxStatus::Queued);
+        assert_eq!(entry.attempts, 1);
+        assert!(entry.last_error.unwrap().contains("sender is not defined"));
+        assert!(entry.next_attempt_at > entry.updated_at);
+
+        // the retry delay did not elapse yet
+        let report = outbox.process_due(&mut sender).unwrap();
+        assert!(report.sent.is_empty());
+        assert!(sender.sent.is_empty());
+
+        let report = outbox.flush(&mut sender).unwrap();
+        assert_eq!(report.sent, vec![entry.id.clone()]);
+        assert_eq!(outbox.get(&entry.id).unwrap().status, OutboxStatus::Sent);
+    }
+
+    #[test]
+    fn fail_then_requeue() {
+        let outbox = outbox().retry_max(2).retry_delay(Duration::ZERO);
+        let mut sender = FlakySender {
+            failures: 2,
+            ..FlakySender::default()
+        };
+
+        let id = outbox.queue(EMAIL).unwrap();
+        assert_eq!(
+            outbox.process_due(&mut sender).unwrap().deferred,
+            vec![id.clone()]
+        );
+        assert_eq!(
+            outbox.process_due(&mut sender).unwrap().failed,
+            vec![id.clone()]
+        );
+
+        // failed emails are not retried anymore
+        assert!(outbox.flush(&mut sender).unwrap().sent.is_empty());
+        assert_eq!(outbox.get(&id).unwrap().status, OutboxStatus::Failed);
+
+        outbox.requeue(&id).unwrap();
+        assert_eq!(outbox.flush(&mut sender).unwrap().sent, vec![id]);
+    }
+
+    #[test]
+    fn schedule_and_reschedule() {
+        let outbox = outbox();
+        let mut sender = FlakySender::default();
+
+        let id = outbox
+            .schedule(EMAIL, Local::now() + chrono::Duration::days(1))
+            .unwrap();
+        let scheduled = outbox.list_scheduled().unwrap();
+        assert_eq!(scheduled.len(), 1);
+        assert_eq!(scheduled[0].id, id);
+
+        // scheduled emails are not sent before their send time, even
+        // when flushing
+        assert!(outbox.process_due(&mut sender).unwrap().sent.is_empty());
+        assert!(outbox.flush(&mut sender).unwrap().sent.is_empty());
+
+        outbox
+            .reschedule(&id, Local::now() - chrono::Duration::minutes(1))
+            .unwrap();
+        assert!(outbox.list_scheduled().unwrap().is_empty());
+        assert_eq!(
+            outbox.process_due(&mut sender).unwrap().sent,
+            vec![id.clone()]
+        );
+
+        // sent emails cannot be rescheduled
+        assert!(outbox.reschedule(&id, Local::now()).is_err());
+    }
+
+    #[test]
+    fn cancel() {
+        let outbox = outbox();
+        let id = outbox.queue(EMAIL).unwrap();
+
+        outbox.cancel(&id).unwrap();
+        assert!(outbox.list().unwrap().is_empty());
+        assert!(outbox.cancel(&id).is_err());
+
+        let mut sender = FlakySender::default();
+        assert!(outbox.flush(&mut sender).unwrap().sent.is_empty());
+    }
+}
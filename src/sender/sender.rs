@@ -2,10 +2,14 @@
 //!
 //! This module contains the sender interface.
 
+use log::{debug, info};
 use std::result;
 use thiserror::Error;
 
-use crate::{account, email, sendmail, AccountConfig, EmailSender, Sendmail};
+use crate::{
+    account, backend, email, lmtp, sendmail, AccountConfig, Backend, EmailSender, Flag, Flags,
+    Lmtp, RecipientReport, SendReport, Sendmail,
+};
 
 #[cfg(feature = "smtp-sender")]
 use crate::{smtp, Smtp};
@@ -14,6 +18,10 @@ use crate::{smtp, Smtp};
 pub enum Error {
     #[error("cannot build email sender: sender is not defined")]
     BuildEmailSenderMissingError,
+    #[error("cannot save copy of sent email to folder {1}")]
+    SaveCopyError(#[source] Box<backend::Error>, String),
+    #[error("cannot send email: all recipients were rejected: {}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))]
+    RecipientsRejectedError(Vec<RecipientReport>),
 
     #[error(transparent)]
     EmailError(#[from] email::Error),
@@ -24,12 +32,52 @@ pub enum Error {
     SmtpError(#[from] smtp::Error),
     #[error(transparent)]
     SendmailError(#[from] sendmail::Error),
+    #[error(transparent)]
+    LmtpError(#[from] lmtp::Error),
 }
 
 pub type Result<T> = result::Result<T, Error>;
 
 pub trait Sender {
-    fn send(&mut self, mime_msg: &[u8]) -> Result<()>;
+    /// Sends the given email. Returns a report containing the sent
+    /// email and the delivery result of each recipient. When some
+    /// recipients only are rejected, the email is considered as sent:
+    /// the rejected recipients are listed in the report.
+    fn send(&mut self, mime_msg: &[u8]) -> Result<SendReport>;
+}
+
+/// Sends the given email using the given sender, then saves a copy
+/// of the sent email to the sent folder of the given backend with the
+/// `Seen` flag, unless disabled by the account configuration. Returns
+/// the send report.
+///
+/// The copy is [`SendReport::email`]: the email after the pre-send
+/// hook, with its `Bcc` headers kept. It therefore differs from the
+/// transmitted message, whose `Bcc` headers are stripped.
+///
+/// When the email is sent but the copy cannot be saved, a
+/// [`Error::SaveCopyError`] is returned: the email must not be sent
+/// again.
+pub fn send_and_save_copy(
+    account_config: &AccountConfig,
+    sender: &mut dyn Sender,
+    backend: &dyn Backend,
+    email: &[u8],
+) -> Result<SendReport> {
+    let report = sender.send(email)?;
+
+    if account_config.email_sending_save_copy() {
+        let folder = account_config.sent_folder_alias()?;
+        info!("saving copy of sent email to folder {folder}");
+
+        backend
+            .add_email(&folder, &report.email, &Flags::from_iter([Flag::Seen]))
+            .map_err(|err| Error::SaveCopyError(Box::new(err), folder))?;
+    } else {
+        debug!("saving copy of sent email disabled, skipping it");
+    }
+
+    Ok(report)
 }
 
 #[derive(Debug, Default, Clone, Eq, PartialEq)]
@@ -42,6 +90,7 @@ impl<'a> SenderBuilder {
             EmailSender::Sendmail(sendmail_config) => {
                 Ok(Box::new(Sendmail::new(account_config, sendmail_config)))
             }
+            EmailSender::Lmtp(lmtp_config) => Ok(Box::new(Lmtp::new(account_config, lmtp_config))),
             EmailSender::None => return Err(Error::BuildEmailSenderMissingError),
         }
     }
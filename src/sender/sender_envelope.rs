@@ -0,0 +1,192 @@
+//! Sender envelope module.
+//!
+//! This module contains the extraction of the envelope (sender and
+//! recipients) of an email about to be sent, shared by the SMTP and
+//! the sendmail senders.
+
+use mailparse::{addrparse_header, MailAddr, MailHeader, MailHeaderMap, ParsedMail};
+
+/// Represents the envelope of an email to send, as opposed to its
+/// headers: the address the email is sent from and the addresses it
+/// is delivered to.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SenderEnvelope {
+    /// Represents the sender address.
+    pub from: Option<String>,
+    /// Represents the recipient addresses, without duplicates.
+    pub to: Vec<String>,
+}
+
+impl SenderEnvelope {
+    /// Builds the envelope of the given email. Recipients are taken
+    /// from the `To`, `Cc` and `Bcc` headers, or from their `Resent-*`
+    /// counterparts when the email is being resent, as described in
+    /// the [RFC 5322].
+    ///
+    /// [RFC 5322]: https://www.rfc-editor.org/rfc/rfc5322#section-3.6.6
+    pub fn from_email(email: &ParsedMail) -> Self {
+        let headers = email.get_headers();
+        let resent = ["Resent-To", "Resent-Cc", "Resent-Bcc"]
+            .iter()
+            .any(|key| headers.get_first_header(key).is_some());
+
+        let (from_keys, to_keys) = if resent {
+            (
+                ["Resent-Sender", "Resent-From"],
+                ["Resent-To", "Resent-Cc", "Resent-Bcc"],
+            )
+        } else {
+            (["Sender", "From"], ["To", "Cc", "Bcc"])
+        };
+
+        let from = from_keys
+            .iter()
+            .find_map(|key| headers.get_first_header(key))
+            .and_then(|header| addrs(header).into_iter().next());
+
+        let mut to: Vec<String> = Vec::new();
+        for key in to_keys {
+            for header in headers.get_all_headers(key) {
+                for addr in addrs(header) {
+                    if !to.contains(&addr) {
+                        to.push(addr);
+                    }
+                }
+            }
+        }
+
+        Self { from, to }
+    }
+}
+
+/// Extracts the addresses of the given header, including the
+/// members of groups.
+fn addrs(header: &MailHeader) -> Vec<String> {
+    addrparse_header(header)
+        .map(|addrs| {
+            addrs
+                .iter()
+                .flat_map(|addr| match addr {
+                    MailAddr::Group(group) => group
+                        .addrs
+                        .iter()
+                        .map(|addr| addr.addr.clone())
+                        .collect::<Vec<_>>(),
+                    MailAddr::Single(single) => vec![single.addr.clone()],
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Removes the `Bcc` and `Resent-Bcc` headers from the given raw
+/// email, so that blind carbon copy recipients are not disclosed to
+/// the other recipients. The rest of the email is left untouched.
+pub fn strip_bcc(email: &[u8]) -> Vec<u8> {
+    let mut stripped = Vec::with_capacity(email.len());
+    let mut skipping = false;
+    let mut lines = email.split_inclusive(|b| *b == b'\n');
+
+    for line in lines.by_ref() {
+        if line == b"\r\n" || line == b"\n" {
+            stripped.extend_from_slice(line);
+            break;
+        }
+
+        let folded = line.starts_with(b" ") || line.starts_with(b"\t");
+        if !folded {
+            let key = line.split(|b| *b == b':').next().unwrap_or_default();
+            skipping = key.eq_ignore_ascii_case(b"bcc") || key.eq_ignore_ascii_case(b"resent-bcc");
+        }
+
+        if !skipping {
+            stripped.extend_from_slice(line);
+        }
+    }
+
+    for line in lines {
+        stripped.extend_from_slice(line);
+    }
+
+    stripped
+}
+
+#[cfg(test)]
+mod sender_envelope {
+    use concat_with::concat_line;
+
+    use super::{strip_bcc, SenderEnvelope};
+
+    #[test]
+    fn from_email() {
+        let email = concat_line!(
+            "From: Alice <alice@localhost>",
+            "To: bob@localhost, Group: carol@localhost, dave@localhost;",
+            "Cc: Bob <bob@localhost>, eve@localhost",
+            "Bcc: frank@localhost",
+            "Subject: Hello",
+            "",
+            "Hello!",
+        );
+        let email = mailparse::parse_mail(email.as_bytes()).unwrap();
+
+        assert_eq!(
+            SenderEnvelope::from_email(&email),
+            SenderEnvelope {
+                from: Some("alice@localhost".into()),
+                to: vec![
+                    "bob@localhost".into(),
+                    "carol@localhost".into(),
+                    "dave@localhost".into(),
+                    "eve@localhost".into(),
+                    "frank@localhost".into(),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn from_resent_email() {
+        let email = concat_line!(
+            "Resent-From: carol@localhost",
+            "Resent-To: dave@localhost",
+            "Resent-Bcc: eve@localhost",
+            "From: alice@localhost",
+            "To: bob@localhost",
+            "",
+            "Hello!",
+        );
+        let email = mailparse::parse_mail(email.as_bytes()).unwrap();
+
+        assert_eq!(
+            SenderEnvelope::from_email(&email),
+            SenderEnvelope {
+                from: Some("carol@localhost".into()),
+                to: vec!["dave@localhost".into(), "eve@localhost".into()],
+            }
+        );
+    }
+
+    #[test]
+    fn strip_bcc_headers() {
+        let email = concat_line!(
+            "From: alice@localhost",
+            "BCC: bob@localhost,",
+            "  carol@localhost",
+            "To: dave@localhost",
+            "Resent-Bcc: eve@localhost",
+            "",
+            "Bcc: not a header",
+        );
+
+        assert_eq!(
+            String::from_utf8(strip_bcc(email.as_bytes())).unwrap(),
+            concat_line!(
+                "From: alice@localhost",
+                "To: dave@localhost",
+                "",
+                "Bcc: not a header",
+            )
+        );
+    }
+}
@@ -0,0 +1,118 @@
+//! Sender report module.
+//!
+//! This module contains the report returned by senders once an email
+//! has been sent, listing the recipients accepted or rejected by the
+//! server.
+
+use std::fmt;
+
+/// Represents a reply of the server.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SenderReply {
+    /// Represents the three-digit code of the reply.
+    pub code: u16,
+    /// Represents the text of the reply, lines joined by new lines.
+    pub message: String,
+}
+
+impl SenderReply {
+    /// Returns `true` if the reply is a positive one (2xx or 3xx).
+    pub fn is_positive(&self) -> bool {
+        (200..400).contains(&self.code)
+    }
+}
+
+impl fmt::Display for SenderReply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.code, self.message)
+    }
+}
+
+/// Represents the delivery result of one recipient.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RecipientReport {
+    /// Represents the address of the recipient.
+    pub addr: String,
+    /// Tells if the recipient has been accepted by the server.
+    pub accepted: bool,
+    /// Represents the reply of the server for this recipient, if the
+    /// sender has access to it.
+    pub reply: Option<SenderReply>,
+}
+
+impl RecipientReport {
+    /// Builds the report of a recipient from the reply of the server.
+    pub fn from_reply<A: ToString>(addr: A, reply: SenderReply) -> Self {
+        Self {
+            addr: addr.to_string(),
+            accepted: reply.is_positive(),
+            reply: Some(reply),
+        }
+    }
+}
+
+impl fmt::Display for RecipientReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.reply {
+            Some(reply) => write!(f, "{} ({})", self.addr, reply),
+            None => write!(f, "{}", self.addr),
+        }
+    }
+}
+
+/// Represents the report of a sent email.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct SendReport {
+    /// Represents the sent email, as it was after the pre-send hook.
+    /// Its `Bcc` headers are kept, so that the copy saved to the sent
+    /// folder records the blind carbon copy recipients: it is not
+    /// the exact message transmitted by the SMTP and LMTP senders,
+    /// which strip those headers.
+    pub email: Vec<u8>,
+    /// Represents the delivery result of each recipient.
+    pub recipients: Vec<RecipientReport>,
+}
+
+impl SendReport {
+    /// Returns the recipients accepted by the server.
+    pub fn accepted(&self) -> impl Iterator<Item = &RecipientReport> {
+        self.recipients.iter().filter(|rcpt| rcpt.accepted)
+    }
+
+    /// Returns the recipients rejected by the server.
+    pub fn rejected(&self) -> impl Iterator<Item = &RecipientReport> {
+        self.recipients.iter().filter(|rcpt| !rcpt.accepted)
+    }
+
+    /// Returns `true` if the server rejected some recipients only.
+    pub fn is_partial(&self) -> bool {
+        self.accepted().next().is_some() && self.rejected().next().is_some()
+    }
+}
+
+#[cfg(test)]
+mod sender_report {
+    use super::{RecipientReport, SendReport, SenderReply};
+
+    #[test]
+    fn partial_report() {
+        let reply = |code| SenderReply {
+            code,
+            message: "message".into(),
+        };
+        let report = SendReport {
+            email: Vec::new(),
+            recipients: vec![
+                RecipientReport::from_reply("bob@localhost", reply(250)),
+                RecipientReport::from_reply("carol@localhost", reply(550)),
+            ],
+        };
+
+        assert!(report.is_partial());
+        assert_eq!(report.accepted().count(), 1);
+        assert_eq!(
+            report.rejected().next().unwrap().to_string(),
+            "carol@localhost (550 message)"
+        );
+    }
+}
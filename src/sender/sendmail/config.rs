@@ -3,8 +3,14 @@
 //! This module contains the representation of the Sendmail email
 //! sender configuration of the user account.
 
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+use serde::Deserialize;
+
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct SendmailConfig {
-    /// Represents the sendmail command.
+    /// Represents the sendmail command. The `<recipients>`
+    /// placeholder is replaced by the recipients of the email (from
+    /// `To`, `Cc` and `Bcc` headers), in which case the `Bcc` header
+    /// is stripped from the email given to the command.
     pub cmd: String,
 }
@@ -7,7 +7,15 @@ use mailparse::MailParseError;
 use std::result;
 use thiserror::Error;
 
-use crate::{process, sender, AccountConfig, Sender, SendmailConfig};
+use crate::{
+    process,
+    sender::{self, sender_envelope},
+    AccountConfig, RecipientReport, SendReport, Sender, SenderEnvelope, SendmailConfig,
+};
+
+/// Represents the placeholder of the sendmail command replaced by
+/// the recipients of the email.
+const RECIPIENTS_PLACEHOLDER: &str = "<recipients>";
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -36,7 +44,7 @@ impl<'a> Sendmail<'a> {
 }
 
 impl<'a> Sender for Sendmail<'a> {
-    fn send(&mut self, email: &[u8]) -> sender::Result<()> {
+    fn send(&mut self, email: &[u8]) -> sender::Result<SendReport> {
         let mut email = mailparse::parse_mail(email).map_err(Error::ParseEmailError)?;
         let buffer;
 
@@ -45,7 +53,55 @@ impl<'a> Sender for Sendmail<'a> {
             email = mailparse::parse_mail(&buffer).map_err(Error::ParseEmailError)?;
         };
 
-        process::run(&self.sendmail_config.cmd, email.raw_bytes).map_err(Error::RunCmdError)?;
-        Ok(())
+        let cmd = &self.sendmail_config.cmd;
+        let envelope = SenderEnvelope::from_email(&email);
+
+        if cmd.contains(RECIPIENTS_PLACEHOLDER) {
+            // Recipients are given explicitly, so the command will
+            // not strip the Bcc header by itself.
+            let recipients = envelope
+                .to
+                .iter()
+                .map(|addr| shell_quote(addr))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let cmd = cmd.replace(RECIPIENTS_PLACEHOLDER, &recipients);
+            let stripped_email = sender_envelope::strip_bcc(email.raw_bytes);
+            process::run(&cmd, &stripped_email).map_err(Error::RunCmdError)?;
+        } else {
+            process::run(cmd, email.raw_bytes).map_err(Error::RunCmdError)?;
+        }
+
+        // The command does not report the delivery result of each
+        // recipient, they are all considered as accepted.
+        Ok(SendReport {
+            email: email.raw_bytes.to_vec(),
+            recipients: envelope
+                .to
+                .into_iter()
+                .map(|addr| RecipientReport {
+                    addr,
+                    accepted: true,
+                    reply: None,
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Quotes the given argument so that it is passed as is to the
+/// sendmail command by the shell.
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod sendmail {
+    use super::shell_quote;
+
+    #[test]
+    fn shell_quote_recipients() {
+        assert_eq!(shell_quote("bob@localhost"), "'bob@localhost'");
+        assert_eq!(shell_quote("o'hara@localhost"), "'o'\\''hara@localhost'");
     }
 }
@@ -6,22 +6,22 @@
 use std::result;
 
 use lettre::transport::smtp::authentication::Credentials as SmtpCredentials;
+use serde::Deserialize;
 use thiserror::Error;
 
-use crate::process;
+use crate::{secret, OAuth2Config, Secret};
 
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("cannot get smtp password")]
-    GetPasswdError(#[source] process::Error),
-    #[error("cannot get smtp password: password is empty")]
-    GetPasswdEmptyError,
+    GetPasswdError(#[source] secret::Error),
 }
 
 pub type Result<T> = result::Result<T, Error>;
 
 /// Represents the internal sender config.
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct SmtpConfig {
     /// Represents the SMTP server host.
     pub host: String,
@@ -35,23 +35,99 @@ pub struct SmtpConfig {
     pub insecure: Option<bool>,
     /// Represents the SMTP server login.
     pub login: String,
-    /// Represents the SMTP password command.
-    pub passwd_cmd: String,
+    /// Represents the SMTP server password.
+    #[serde(default)]
+    pub passwd: Secret,
+    /// Represents the OAuth 2.0 config, used instead of the password
+    /// when defined.
+    pub oauth2: Option<OAuth2Config>,
+    /// Represents the delivery status notifications config. DSN are
+    /// requested only when the server supports them.
+    pub dsn: Option<SmtpDsnConfig>,
+}
+
+/// Represents a condition under which a delivery status notification
+/// is requested, as defined in the [RFC 3461].
+///
+/// [RFC 3461]: https://www.rfc-editor.org/rfc/rfc3461
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpDsnNotify {
+    Success,
+    Failure,
+    Delay,
+}
+
+impl SmtpDsnNotify {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Success => "SUCCESS",
+            Self::Failure => "FAILURE",
+            Self::Delay => "DELAY",
+        }
+    }
+}
+
+/// Represents the part of the email returned with a failure
+/// notification.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpDsnRet {
+    /// Returns the whole email.
+    Full,
+    /// Returns the headers of the email only.
+    Headers,
+}
+
+impl SmtpDsnRet {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Full => "FULL",
+            Self::Headers => "HDRS",
+        }
+    }
+}
+
+/// Represents the delivery status notifications config.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct SmtpDsnConfig {
+    /// Represents the conditions under which notifications are
+    /// requested (`NOTIFY` parameter). No condition means that
+    /// notifications are never sent.
+    #[serde(default)]
+    pub notify: Vec<SmtpDsnNotify>,
+    /// Represents the part of the email returned with failure
+    /// notifications (`RET` parameter). Defaults to the server
+    /// choice.
+    pub ret: Option<SmtpDsnRet>,
+    /// Represents the identifier of the envelope, given back with
+    /// notifications (`ENVID` parameter). Defaults to the
+    /// `Message-ID` of the email.
+    pub envid: Option<String>,
+}
+
+impl SmtpDsnConfig {
+    /// Builds the value of the `NOTIFY` parameter.
+    pub fn notify_param(&self) -> String {
+        if self.notify.is_empty() {
+            String::from("NEVER")
+        } else {
+            self.notify
+                .iter()
+                .map(SmtpDsnNotify::as_str)
+                .collect::<Vec<_>>()
+                .join(",")
+        }
+    }
 }
 
 impl SmtpConfig {
-    /// Builds the internal SMTP sender credentials.
+    /// Builds the internal SMTP sender credentials. The password is
+    /// cached, see [`Secret::get`].
     pub fn credentials(&self) -> Result<SmtpCredentials> {
-        let passwd = process::run(&self.passwd_cmd, &[]).map_err(Error::GetPasswdError)?;
-        let passwd = String::from_utf8_lossy(&passwd).to_string();
-        let passwd = passwd
-            .lines()
-            .next()
-            .ok_or_else(|| Error::GetPasswdEmptyError)?;
-        Ok(SmtpCredentials::new(
-            self.login.to_owned(),
-            passwd.to_owned(),
-        ))
+        let passwd = self.passwd.get().map_err(Error::GetPasswdError)?;
+        Ok(SmtpCredentials::new(self.login.to_owned(), passwd))
     }
 
     pub fn ssl(&self) -> bool {
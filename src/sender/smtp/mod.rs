@@ -4,6 +4,6 @@ pub mod config;
 pub mod smtp;
 
 #[cfg(feature = "smtp-sender")]
-pub use config::SmtpConfig;
+pub use config::{SmtpConfig, SmtpDsnConfig, SmtpDsnNotify, SmtpDsnRet};
 #[cfg(feature = "smtp-sender")]
 pub use smtp::{Error, Smtp};
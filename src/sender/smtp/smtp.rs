@@ -1,29 +1,35 @@
 //! SMTP module.
 //!
 //! This module contains the representation of the SMTP email sender.
+//! Recipients are submitted one by one, so that the recipients
+//! rejected by the server do not prevent the others from receiving
+//! the email.
 
 use lettre::{
     self,
-    address::Envelope,
-    error::Error as LettreError,
+    address::Address,
     transport::smtp::{
-        client::{Tls, TlsParameters},
-        SmtpTransport,
+        authentication::Mechanism,
+        client::{SmtpConnection, TlsParameters},
+        commands::{Data, Ehlo, Mail, Rcpt, Rset},
+        extension::{ClientId, Extension, MailBodyParameter, MailParameter, RcptParameter},
+        response::Response,
     },
-    Transport,
 };
-use mailparse::{addrparse_header, MailAddr, MailHeaderMap};
-use std::result;
+use log::{debug, info, warn};
+use mailparse::MailHeaderMap;
+use std::{error::Error as StdError, result};
 use thiserror::Error;
 
-use crate::{account, email, process, sender, AccountConfig, Sender, SmtpConfig};
+use crate::{
+    account, email, oauth2, process,
+    sender::{self, sender_envelope},
+    AccountConfig, OAuth2Config, RecipientReport, SendReport, Sender, SenderEnvelope, SenderReply,
+    SmtpConfig, SmtpDsnConfig,
+};
 
 #[derive(Debug, Error)]
 pub enum Error {
-    #[error("cannot build envelope")]
-    BuildEnvelopeError(#[source] LettreError),
-    #[error("cannot build smtp transport relay")]
-    BuildTransportRelayError(#[source] lettre::transport::smtp::Error),
     #[error("cannot build smtp tls parameters")]
     BuildTlsParamsError(#[source] lettre::transport::smtp::Error),
     #[error("cannot parse email before sending")]
@@ -32,6 +38,16 @@ pub enum Error {
     SendError(#[source] lettre::transport::smtp::Error),
     #[error("cannot execute pre-send hook")]
     ExecutePreSendHookError(#[source] process::Error),
+    #[error("cannot check smtp server extensions")]
+    CheckExtensionsError(#[source] lettre::transport::smtp::Error),
+    #[error("cannot connect to smtp server")]
+    ConnectError(#[source] lettre::transport::smtp::Error),
+    #[error("cannot authenticate to smtp server")]
+    AuthenticatePasswdError(#[source] lettre::transport::smtp::Error),
+    #[error("cannot authenticate to smtp server using oauth2")]
+    AuthenticateError(#[source] lettre::transport::smtp::Error),
+    #[error("cannot authenticate to smtp server using oauth2: {0}")]
+    AuthenticateChallengeError(String),
 
     #[error(transparent)]
     SmtpConfigError(#[from] sender::smtp::config::Error),
@@ -39,6 +55,8 @@ pub enum Error {
     ConfigError(#[from] account::config::Error),
     #[error(transparent)]
     MsgError(#[from] email::email::Error),
+    #[error(transparent)]
+    OAuth2Error(#[from] oauth2::Error),
 }
 
 pub type Result<T> = result::Result<T, Error>;
@@ -46,7 +64,6 @@ pub type Result<T> = result::Result<T, Error>;
 pub struct Smtp<'a> {
     account_config: &'a AccountConfig,
     smtp_config: &'a SmtpConfig,
-    transport: Option<SmtpTransport>,
 }
 
 impl<'a> Smtp<'a> {
@@ -54,50 +71,267 @@ impl<'a> Smtp<'a> {
         Self {
             account_config,
             smtp_config,
-            transport: None,
         }
     }
 
-    fn transport(&mut self) -> Result<&SmtpTransport> {
-        if let Some(ref transport) = self.transport {
-            Ok(transport)
+    fn tls_params(&self) -> Result<TlsParameters> {
+        TlsParameters::builder(self.smtp_config.host.to_owned())
+            .dangerous_accept_invalid_hostnames(self.smtp_config.insecure())
+            .dangerous_accept_invalid_certs(self.smtp_config.insecure())
+            .build()
+            .map_err(Error::BuildTlsParamsError)
+    }
+
+    /// Opens a new connection to the SMTP server, without
+    /// authenticating.
+    fn connect(&self) -> Result<SmtpConnection> {
+        let addr = (self.smtp_config.host.as_str(), self.smtp_config.port);
+        let hello = ClientId::default();
+
+        if !self.smtp_config.ssl() {
+            return SmtpConnection::connect(addr, None, &hello, None, None)
+                .map_err(Error::ConnectError);
+        }
+
+        let tls = self.tls_params()?;
+
+        if self.smtp_config.starttls() {
+            let mut conn = SmtpConnection::connect(addr, None, &hello, None, None)
+                .map_err(Error::ConnectError)?;
+            conn.starttls(&tls, &hello).map_err(Error::ConnectError)?;
+            Ok(conn)
+        } else {
+            SmtpConnection::connect(addr, None, &hello, Some(&tls), None)
+                .map_err(Error::ConnectError)
+        }
+    }
+
+    /// Authenticates the given connection using the given OAuth 2.0
+    /// access token.
+    fn authenticate(
+        &self,
+        conn: &mut SmtpConnection,
+        oauth2: &OAuth2Config,
+        token: &str,
+    ) -> Result<()> {
+        let response = oauth2.method.sasl_response(
+            &self.smtp_config.login,
+            &self.smtp_config.host,
+            self.smtp_config.port,
+            token,
+        );
+        let res = conn
+            .command(format!(
+                "AUTH {} {}\r\n",
+                oauth2.method,
+                base64::encode(response)
+            ))
+            .map_err(Error::AuthenticateError)?;
+
+        if res.has_code(334) {
+            // The server sent an error challenge, which needs to be
+            // acknowledged before it sends the final error response.
+            let challenge = res
+                .first_line()
+                .and_then(|line| base64::decode(line).ok())
+                .map(|challenge| String::from_utf8_lossy(&challenge).to_string())
+                .unwrap_or_default();
+            let _ = conn.command(format!(
+                "{}\r\n",
+                base64::encode(oauth2.method.sasl_error_response())
+            ));
+            return Err(Error::AuthenticateChallengeError(challenge));
+        }
+
+        Ok(())
+    }
+
+    /// Authenticates the given connection using OAuth 2.0. If the
+    /// access token is rejected, it is refreshed and the
+    /// authentication is retried once on a new connection.
+    fn authenticate_with_oauth2(
+        &self,
+        mut conn: SmtpConnection,
+        oauth2: &OAuth2Config,
+    ) -> Result<SmtpConnection> {
+        if let Err(err) = self.authenticate(&mut conn, oauth2, &oauth2.access_token()?) {
+            warn!("smtp oauth2 authentication failed, refreshing access token");
+            debug!("smtp oauth2 authentication error: {err}");
+            conn.abort();
+            conn = self.connect()?;
+            self.authenticate(&mut conn, oauth2, &oauth2.refresh_access_token()?)?;
+        }
+
+        Ok(conn)
+    }
+
+    /// Authenticates the given connection, using OAuth 2.0 when
+    /// configured, otherwise using the password.
+    fn login(&self, mut conn: SmtpConnection) -> Result<SmtpConnection> {
+        if let Some(oauth2) = &self.smtp_config.oauth2 {
+            conn = self.authenticate_with_oauth2(conn, oauth2)?;
+        } else {
+            let credentials = self.smtp_config.credentials().map_err(Error::from)?;
+            conn.auth(&[Mechanism::Plain, Mechanism::Login], &credentials)
+                .map_err(|err| {
+                    // the password may have changed since it has been
+                    // cached
+                    self.smtp_config.passwd.reset_cache();
+                    Error::AuthenticatePasswdError(err)
+                })?;
+        }
+
+        Ok(conn)
+    }
+
+    /// Lists the extensions announced by the server, with their
+    /// parameters. The extensions are listed with a new `EHLO`
+    /// command, since they are not all exposed by the connection.
+    fn extensions(&self, conn: &mut SmtpConnection) -> Result<Vec<String>> {
+        let res = conn
+            .command(Ehlo::new(ClientId::default()))
+            .map_err(Error::CheckExtensionsError)?;
+
+        // the first line holds the name of the server
+        Ok(res.message().skip(1).map(ToOwned::to_owned).collect())
+    }
+
+    /// Connects and authenticates to the server, then returns the
+    /// extensions it announced.
+    pub fn capabilities(&self) -> Result<Vec<String>> {
+        let mut conn = self.connect()?;
+        let extensions = self.extensions(&mut conn)?;
+        let mut conn = self.login(conn)?;
+        let _ = conn.quit();
+        Ok(extensions)
+    }
+
+    /// Returns the DSN config if the server supports the extension.
+    fn dsn(&self, conn: &mut SmtpConnection) -> Result<Option<&SmtpDsnConfig>> {
+        let dsn = match &self.smtp_config.dsn {
+            Some(dsn) => dsn,
+            None => return Ok(None),
+        };
+
+        let supported = self.extensions(conn)?.iter().any(|line| {
+            line.split_whitespace()
+                .next()
+                .map(|keyword| keyword.eq_ignore_ascii_case("DSN"))
+                .unwrap_or_default()
+        });
+
+        if supported {
+            Ok(Some(dsn))
         } else {
-            let builder = if self.smtp_config.ssl() {
-                let tls = TlsParameters::builder(self.smtp_config.host.to_owned())
-                    .dangerous_accept_invalid_hostnames(self.smtp_config.insecure())
-                    .dangerous_accept_invalid_certs(self.smtp_config.insecure())
-                    .build()
-                    .map_err(Error::BuildTlsParamsError)?;
-
-                if self.smtp_config.starttls() {
-                    SmtpTransport::starttls_relay(&self.smtp_config.host)
-                        .map_err(Error::BuildTransportRelayError)?
-                        .tls(Tls::Required(tls))
-                } else {
-                    SmtpTransport::relay(&self.smtp_config.host)
-                        .map_err(Error::BuildTransportRelayError)?
-                        .tls(Tls::Wrapper(tls))
+            warn!("smtp server does not support delivery status notifications, skipping them");
+            Ok(None)
+        }
+    }
+
+    /// Submits the given email to the recipients of the given
+    /// envelope, and returns the delivery result of each recipient.
+    /// Recipients rejected at the `RCPT TO` stage get the reply of
+    /// this command, others get the reply to the email data.
+    fn transmit(
+        &self,
+        conn: &mut SmtpConnection,
+        dsn: Option<&SmtpDsnConfig>,
+        envelope: &SenderEnvelope,
+        envid: Option<&str>,
+        email: &[u8],
+    ) -> Result<Vec<RecipientReport>> {
+        let from: Option<Address> = envelope.from.as_deref().and_then(|addr| addr.parse().ok());
+
+        let mut mail_params = Vec::new();
+        if !email.is_ascii() && conn.server_info().supports_feature(Extension::EightBitMime) {
+            mail_params.push(MailParameter::Body(MailBodyParameter::EightBitMime));
+        }
+        let ascii_addrs = envelope
+            .from
+            .iter()
+            .chain(&envelope.to)
+            .all(|a| a.is_ascii());
+        if !ascii_addrs && conn.server_info().supports_feature(Extension::SmtpUtfEight) {
+            mail_params.push(MailParameter::SmtpUtfEight);
+        }
+        if let Some(dsn) = dsn {
+            if let Some(ret) = &dsn.ret {
+                mail_params.push(MailParameter::Other {
+                    keyword: "RET".into(),
+                    value: Some(ret.as_str().into()),
+                });
+            }
+            if let Some(envid) = dsn.envid.as_deref().or(envid) {
+                mail_params.push(MailParameter::Other {
+                    keyword: "ENVID".into(),
+                    value: Some(envid.into()),
+                });
+            }
+        }
+
+        conn.command(Mail::new(from, mail_params))
+            .map_err(Error::SendError)?;
+
+        let mut recipients = Vec::new();
+        let mut accepted = Vec::new();
+
+        for addr in &envelope.to {
+            let rcpt: Address = match addr.parse() {
+                Ok(rcpt) => rcpt,
+                Err(err) => {
+                    warn!("skipping invalid recipient {addr}: {err}");
+                    recipients.push(RecipientReport {
+                        addr: addr.clone(),
+                        accepted: false,
+                        reply: None,
+                    });
+                    continue;
                 }
-            } else {
-                SmtpTransport::relay(&self.smtp_config.host)
-                    .map_err(Error::BuildTransportRelayError)?
-                    .tls(Tls::None)
             };
 
-            self.transport = Some(
-                builder
-                    .port(self.smtp_config.port)
-                    .credentials(self.smtp_config.credentials()?)
-                    .build(),
-            );
+            let mut rcpt_params = Vec::new();
+            if let Some(dsn) = dsn {
+                rcpt_params.push(RcptParameter::Other {
+                    keyword: "NOTIFY".into(),
+                    value: Some(dsn.notify_param()),
+                });
+                rcpt_params.push(RcptParameter::Other {
+                    keyword: "ORCPT".into(),
+                    value: Some(format!("rfc822;{addr}")),
+                });
+            }
+
+            match conn.command(Rcpt::new(rcpt, rcpt_params)) {
+                Ok(_) => accepted.push(addr),
+                Err(err) => {
+                    let reply = error_to_reply(&err).ok_or(Error::SendError(err))?;
+                    debug!("recipient {addr} rejected: {reply}");
+                    recipients.push(RecipientReport::from_reply(addr, reply));
+                }
+            }
+        }
+
+        if accepted.is_empty() {
+            let _ = conn.command(Rset);
+            return Ok(recipients);
+        }
+
+        conn.command(Data).map_err(Error::SendError)?;
+        let reply = match conn.message(email) {
+            Ok(res) => response_to_reply(&res),
+            Err(err) => error_to_reply(&err).ok_or(Error::SendError(err))?,
+        };
 
-            Ok(self.transport.as_ref().unwrap())
+        for addr in accepted {
+            recipients.push(RecipientReport::from_reply(addr, reply.clone()));
         }
+
+        Ok(recipients)
     }
 }
 
 impl<'a> Sender for Smtp<'a> {
-    fn send(&mut self, email: &[u8]) -> sender::Result<()> {
+    fn send(&mut self, email: &[u8]) -> sender::Result<SendReport> {
         let mut email = mailparse::parse_mail(&email).map_err(Error::ParseEmailError)?;
         let buffer;
 
@@ -106,46 +340,57 @@ impl<'a> Sender for Smtp<'a> {
             email = mailparse::parse_mail(&buffer).map_err(Error::ParseEmailError)?;
         };
 
-        let envelope = Envelope::new(
-            email
-                .get_headers()
-                .get_first_header("From")
-                .and_then(|header| addrparse_header(header).ok())
-                .and_then(|addrs| addrs.first().cloned())
-                .and_then(|addr| match addr {
-                    MailAddr::Group(group) => {
-                        group.addrs.first().and_then(|addr| addr.addr.parse().ok())
-                    }
-                    MailAddr::Single(single) => single.addr.parse().ok(),
-                }),
-            email
-                .get_headers()
-                .get_all_headers("To")
-                .into_iter()
-                .flat_map(|header| addrparse_header(header))
-                .flat_map(|addrs| {
-                    addrs
-                        .iter()
-                        .map(|addr| match addr {
-                            MailAddr::Group(group) => group
-                                .addrs
-                                .iter()
-                                .map(|addr| addr.addr.clone())
-                                .collect::<Vec<_>>(),
-                            MailAddr::Single(single) => vec![single.addr.clone()],
-                        })
-                        .collect::<Vec<_>>()
-                })
-                .flatten()
-                .flat_map(|addr| addr.parse())
-                .collect::<Vec<_>>(),
-        )
-        .map_err(Error::BuildEnvelopeError)?;
-
-        self.transport()?
-            .send_raw(&envelope, email.raw_bytes)
-            .map_err(Error::SendError)?;
+        let envelope = SenderEnvelope::from_email(&email);
+        let envid = email.headers.get_first_value("Message-ID").map(|id| {
+            id.trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_owned()
+        });
+        let stripped_email = sender_envelope::strip_bcc(email.raw_bytes);
 
-        Ok(())
+        let mut conn = self.connect()?;
+        let dsn = self.dsn(&mut conn)?;
+        let mut conn = self.login(conn)?;
+
+        info!("sending email to {} recipient(s)", envelope.to.len());
+        let recipients =
+            self.transmit(&mut conn, dsn, &envelope, envid.as_deref(), &stripped_email)?;
+        let _ = conn.quit();
+
+        let report = SendReport {
+            email: email.raw_bytes.to_vec(),
+            recipients,
+        };
+
+        if report.accepted().next().is_none() {
+            return Err(sender::sender::Error::RecipientsRejectedError(
+                report.recipients,
+            ));
+        }
+
+        for rcpt in report.rejected() {
+            warn!("cannot send email to {rcpt}");
+        }
+
+        Ok(report)
+    }
+}
+
+fn response_to_reply(res: &Response) -> SenderReply {
+    SenderReply {
+        code: res.code().to_string().parse().unwrap_or_default(),
+        message: res.message().collect::<Vec<_>>().join("\n"),
     }
 }
+
+/// Extracts the reply of the server from the given error, if the
+/// error comes from a negative reply.
+fn error_to_reply(err: &lettre::transport::smtp::Error) -> Option<SenderReply> {
+    let code = err.status()?;
+
+    Some(SenderReply {
+        code: code.to_string().parse().unwrap_or_default(),
+        message: err.source().map(ToString::to_string).unwrap_or_default(),
+    })
+}
@@ -0,0 +1,208 @@
+use concat_with::concat_line;
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use himalaya_lib::{
+    autoconfig::{self, SrvRecord},
+    AutoconfigBuilder, AutoconfigDns, AutoconfigHttp, AutoconfigSource, Secret,
+};
+
+const XML: &str = concat_line!(
+    r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+    r#"<clientConfig version="1.1">"#,
+    r#"  <emailProvider id="example.com">"#,
+    r#"    <incomingServer type="imap">"#,
+    r#"      <hostname>imap.example.com</hostname>"#,
+    r#"      <port>993</port>"#,
+    r#"      <socketType>SSL</socketType>"#,
+    r#"      <username>%EMAILLOCALPART%</username>"#,
+    r#"    </incomingServer>"#,
+    r#"    <outgoingServer type="smtp">"#,
+    r#"      <hostname>smtp.example.com</hostname>"#,
+    r#"      <port>587</port>"#,
+    r#"      <socketType>STARTTLS</socketType>"#,
+    r#"      <username>%EMAILADDRESS%</username>"#,
+    r#"    </outgoingServer>"#,
+    r#"  </emailProvider>"#,
+    r#"</clientConfig>"#,
+);
+
+/// Serves the registered documents, and records the requested URLs.
+#[derive(Clone, Default)]
+struct LocalHttp {
+    docs: HashMap<String, String>,
+    requests: Rc<RefCell<Vec<String>>>,
+}
+
+impl LocalHttp {
+    fn with(mut self, url: &str, doc: &str) -> Self {
+        self.docs.insert(url.to_owned(), doc.to_owned());
+        self
+    }
+}
+
+impl AutoconfigHttp for LocalHttp {
+    fn get(&self, url: &str) -> autoconfig::Result<Option<String>> {
+        self.requests.borrow_mut().push(url.to_owned());
+        Ok(self.docs.get(url).cloned())
+    }
+}
+
+/// Serves the registered SRV records.
+#[derive(Clone, Default)]
+struct LocalDns {
+    records: HashMap<String, Vec<SrvRecord>>,
+}
+
+impl LocalDns {
+    fn with(mut self, name: &str, priority: u16, weight: u16, port: u16, target: &str) -> Self {
+        self.records
+            .entry(name.to_owned())
+            .or_default()
+            .push(SrvRecord {
+                priority,
+                weight,
+                port,
+                target: target.to_owned(),
+            });
+        self
+    }
+}
+
+impl AutoconfigDns for LocalDns {
+    fn srv(&self, name: &str) -> autoconfig::Result<Vec<SrvRecord>> {
+        Ok(self.records.get(name).cloned().unwrap_or_default())
+    }
+}
+
+const PROVIDER_URL: &str =
+    "https://autoconfig.example.com/mail/config-v1.1.xml?emailaddress=alice%40example.com";
+const WELL_KNOWN_URL: &str = "https://example.com/.well-known/autoconfig/mail/config-v1.1.xml?emailaddress=alice%40example.com";
+const ISPDB_URL: &str = "http://localhost/ispdb/example.com";
+
+fn builder(http: LocalHttp, dns: LocalDns) -> AutoconfigBuilder {
+    AutoconfigBuilder::new()
+        .http(http)
+        .dns(dns)
+        .ispdb_url("http://localhost/ispdb/")
+}
+
+#[test]
+fn test_autoconfig_provider() {
+    let http = LocalHttp::default()
+        .with(PROVIDER_URL, XML)
+        .with(ISPDB_URL, "invalid");
+    let config = builder(http, LocalDns::default())
+        .build("alice@example.com")
+        .unwrap();
+
+    assert_eq!(
+        config.source,
+        AutoconfigSource::Provider(PROVIDER_URL.to_owned())
+    );
+
+    let imap = config.imap.unwrap();
+    assert_eq!(imap.host, "imap.example.com");
+    assert_eq!(imap.port, 993);
+    assert_eq!(imap.ssl, Some(true));
+    assert_eq!(imap.starttls, Some(false));
+    assert_eq!(imap.login, "alice");
+    assert_eq!(imap.passwd, Secret::default());
+
+    let smtp = config.smtp.unwrap();
+    assert_eq!(smtp.host, "smtp.example.com");
+    assert_eq!(smtp.port, 587);
+    assert_eq!(smtp.ssl, Some(true));
+    assert_eq!(smtp.starttls, Some(true));
+    assert_eq!(smtp.login, "alice@example.com");
+}
+
+#[test]
+fn test_autoconfig_fallbacks() {
+    // the invalid well-known document is skipped, the ISPDB is used
+    let http = LocalHttp::default()
+        .with(WELL_KNOWN_URL, "invalid")
+        .with(ISPDB_URL, XML);
+    let requests = http.requests.clone();
+    let config = builder(http, LocalDns::default())
+        .build("alice@example.com")
+        .unwrap();
+
+    assert_eq!(config.source, AutoconfigSource::Ispdb(ISPDB_URL.to_owned()));
+    assert_eq!(
+        *requests.borrow(),
+        vec![PROVIDER_URL, WELL_KNOWN_URL, ISPDB_URL]
+    );
+}
+
+#[test]
+fn test_autoconfig_srv() {
+    let dns = LocalDns::default()
+        .with("_imaps._tcp.example.com", 0, 0, 0, ".")
+        .with("_imap._tcp.example.com", 10, 0, 143, "backup.example.com")
+        .with("_imap._tcp.example.com", 0, 1, 143, "imap2.example.com")
+        .with("_imap._tcp.example.com", 0, 5, 143, "imap.example.com")
+        .with(
+            "_submissions._tcp.example.com",
+            0,
+            0,
+            465,
+            "smtp.example.com",
+        );
+    let config = builder(LocalHttp::default(), dns)
+        .build("alice@example.com")
+        .unwrap();
+
+    assert_eq!(config.source, AutoconfigSource::DnsSrv);
+
+    let imap = config.imap.unwrap();
+    assert_eq!(imap.host, "imap.example.com");
+    assert_eq!(imap.port, 143);
+    assert_eq!(imap.ssl, Some(true));
+    assert_eq!(imap.starttls, Some(true));
+    assert_eq!(imap.login, "alice@example.com");
+
+    let smtp = config.smtp.unwrap();
+    assert_eq!(smtp.host, "smtp.example.com");
+    assert_eq!(smtp.port, 465);
+    assert_eq!(smtp.ssl, Some(true));
+    assert_eq!(smtp.starttls, Some(false));
+}
+
+#[test]
+fn test_autoconfig_not_found() {
+    let builder = builder(LocalHttp::default(), LocalDns::default());
+
+    assert!(matches!(
+        builder.build("alice@example.com"),
+        Err(autoconfig::Error::DiscoverConfigNotFoundError(_))
+    ));
+    assert!(matches!(
+        builder.build("alice"),
+        Err(autoconfig::Error::ParseEmailAddressError(_))
+    ));
+}
+
+#[test]
+fn test_autoconfig_invalid_domain() {
+    let http = LocalHttp::default();
+    let requests = http.requests.clone();
+    let builder = builder(http, LocalDns::default());
+
+    // the domain is not put in the urls
+    for email in [
+        "a@example.com/evil?",
+        "a@example.com:8080",
+        "a@ex ample.com",
+        "a@example..com",
+        "a@exämple.com",
+    ] {
+        assert!(
+            matches!(
+                builder.build(email),
+                Err(autoconfig::Error::ParseEmailAddressError(_))
+            ),
+            "{email:?} should be rejected"
+        );
+    }
+    assert!(requests.borrow().is_empty());
+}
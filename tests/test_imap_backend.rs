@@ -6,7 +6,7 @@ use std::borrow::Cow;
 
 #[cfg(feature = "imap-backend")]
 use himalaya_lib::{
-    AccountConfig, Backend, CompilerBuilder, Flag, ImapBackend, ImapConfig, TplBuilder,
+    AccountConfig, Backend, CompilerBuilder, Flag, ImapBackend, ImapConfig, Secret, TplBuilder,
     DEFAULT_INBOX_FOLDER,
 };
 
@@ -32,7 +32,7 @@ fn test_imap_backend() {
             starttls: Some(false),
             insecure: Some(true),
             login: "bob@localhost".into(),
-            passwd_cmd: "echo 'password'".into(),
+            passwd: Secret::new_cmd("echo 'password'"),
             ..ImapConfig::default()
         }),
     )
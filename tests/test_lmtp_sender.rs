@@ -0,0 +1,214 @@
+use concat_with::concat_line;
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+
+use himalaya_lib::{AccountConfig, Lmtp, LmtpConfig, Sender};
+
+const EMAIL: &str = concat_line!(
+    "From: alice@localhost",
+    "To: bob@localhost, unknown@localhost",
+    "Bcc: full@localhost",
+    "Subject: subject",
+    "",
+    "Hello!",
+    ".",
+);
+
+/// Serves one LMTP session over the given stream, then sends back the
+/// received email data. Recipients starting with `unknown` are
+/// rejected at the `RCPT TO` stage, recipients starting with `full`
+/// are rejected after the data.
+fn serve<S: Read + Write>(stream: S, tx: mpsc::Sender<String>) {
+    let mut reader = BufReader::new(stream);
+    let mut rcpts = Vec::new();
+    let write = |reader: &mut BufReader<S>, reply: &str| {
+        reader.get_mut().write_all(reply.as_bytes()).unwrap();
+    };
+
+    write(&mut reader, "220 localhost LMTP ready\r\n");
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+
+        let cmd = line.trim_end();
+        if cmd.starts_with("LHLO") {
+            write(&mut reader, "250-localhost\r\n250 PIPELINING\r\n");
+        } else if cmd.starts_with("MAIL FROM:") {
+            write(&mut reader, "250 2.1.0 OK\r\n");
+        } else if let Some(rcpt) = cmd.strip_prefix("RCPT TO:") {
+            if rcpt.starts_with("<unknown") {
+                write(&mut reader, "550 5.1.1 User unknown\r\n");
+            } else {
+                rcpts.push(rcpt.to_owned());
+                write(&mut reader, "250 2.1.5 OK\r\n");
+            }
+        } else if cmd == "DATA" {
+            write(&mut reader, "354 OK\r\n");
+
+            let mut data = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == ".\r\n" {
+                    break;
+                }
+                data.push_str(&line);
+            }
+            tx.send(data).unwrap();
+
+            for rcpt in &rcpts {
+                if rcpt.starts_with("<full") {
+                    write(&mut reader, "452 4.2.2 Mailbox full\r\n");
+                } else {
+                    write(&mut reader, "250 2.0.0 Saved\r\n");
+                }
+            }
+        } else if cmd == "QUIT" {
+            write(&mut reader, "221 Bye\r\n");
+            break;
+        } else {
+            write(&mut reader, "500 Unknown command\r\n");
+        }
+    }
+}
+
+#[test]
+fn test_lmtp_sender_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        serve(stream, tx);
+    });
+
+    let account_config = AccountConfig::default();
+    let lmtp_config = LmtpConfig {
+        host: "127.0.0.1".into(),
+        port,
+        ..LmtpConfig::default()
+    };
+    let mut lmtp = Lmtp::new(&account_config, &lmtp_config);
+
+    // the email is sent as long as one recipient accepted it
+    let report = lmtp.send(EMAIL.as_bytes()).unwrap();
+    let replies: Vec<_> = report
+        .recipients
+        .iter()
+        .map(|rcpt| {
+            (
+                rcpt.addr.as_str(),
+                rcpt.accepted,
+                rcpt.reply.as_ref().unwrap().code,
+            )
+        })
+        .collect();
+    assert_eq!(
+        replies,
+        vec![
+            ("unknown@localhost", false, 550),
+            ("bob@localhost", true, 250),
+            ("full@localhost", false, 452),
+        ]
+    );
+    assert!(report.is_partial());
+
+    // the data is dot-stuffed and the Bcc header is stripped
+    let data = rx.recv().unwrap();
+    assert!(!data.contains("Bcc"));
+    assert!(data.ends_with("Hello!\r\n..\r\n"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_lmtp_sender_unix_socket() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("lmtp.sock");
+    let listener = UnixListener::bind(&socket).unwrap();
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        serve(stream, tx);
+    });
+
+    let account_config = AccountConfig::default();
+    let lmtp_config = LmtpConfig {
+        socket: Some(socket),
+        ..LmtpConfig::default()
+    };
+    let mut lmtp = Lmtp::new(&account_config, &lmtp_config);
+
+    let email = concat_line!(
+        "From: alice@localhost",
+        "To: unknown@localhost",
+        "Subject: subject",
+        "",
+        "Hello!",
+    );
+
+    // no recipient accepted the email
+    assert!(lmtp.send(email.as_bytes()).is_err());
+    assert!(rx.try_recv().is_err());
+}
+
+#[test]
+fn test_lmtp_sender_capabilities() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (tx, _rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        serve(stream, tx);
+    });
+
+    let account_config = AccountConfig::default();
+    let lmtp_config = LmtpConfig {
+        host: "127.0.0.1".into(),
+        port,
+        ..LmtpConfig::default()
+    };
+    let lmtp = Lmtp::new(&account_config, &lmtp_config);
+
+    assert_eq!(lmtp.capabilities().unwrap(), vec!["PIPELINING"]);
+}
+
+#[test]
+fn test_lmtp_sender_timeout() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (tx, rx) = mpsc::channel::<()>();
+
+    // the server accepts the connection but never replies
+    thread::spawn(move || {
+        let (_stream, _) = listener.accept().unwrap();
+        let _ = rx.recv();
+    });
+
+    let account_config = AccountConfig::default();
+    let lmtp_config = LmtpConfig {
+        host: "127.0.0.1".into(),
+        port,
+        timeout: Some(1),
+        ..LmtpConfig::default()
+    };
+    let mut lmtp = Lmtp::new(&account_config, &lmtp_config);
+
+    let start = Instant::now();
+    assert!(lmtp.send(EMAIL.as_bytes()).is_err());
+    assert!(start.elapsed() < Duration::from_secs(10));
+    drop(tx);
+}
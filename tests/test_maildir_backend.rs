@@ -1,11 +1,12 @@
 #![allow(clippy::all)]
 use concat_with::concat_line;
 use maildir::Maildir;
-use std::{borrow::Cow, collections::HashMap, fs, iter::FromIterator};
+use std::{borrow::Cow, collections::HashMap, fs, iter::FromIterator, time::Duration};
 use tempfile::tempdir;
 
 use himalaya_lib::{
-    AccountConfig, Backend, CompilerBuilder, Flag, Flags, MaildirBackend, MaildirConfig, TplBuilder,
+    AccountConfig, Backend, BackendEvent, CompilerBuilder, Flag, Flags, MaildirBackend,
+    MaildirConfig, SearchQuery, SortCriteria, TplBuilder,
 };
 
 #[test]
@@ -86,6 +87,27 @@ fn test_maildir_backend() {
     assert_eq!("alice@localhost", envelope.from.addr);
     assert_eq!("Plain message!", envelope.subject);
 
+    // check that the envelope can be searched
+    let query: SearchQuery = "from alice subject plain".parse().unwrap();
+    let envelopes = mdir
+        .search_envelopes("INBOX", &query, &SortCriteria::default(), 10, 0)
+        .unwrap();
+    assert_eq!(1, envelopes.len());
+    assert_eq!(envelope.id, envelopes.first().unwrap().id);
+    let query = SearchQuery::Or(
+        Box::new(SearchQuery::Not(Box::new(SearchQuery::Flag(Flag::Seen)))),
+        Box::new(SearchQuery::Body("unknown".into())),
+    );
+    let envelopes = mdir
+        .search_envelopes("INBOX", &query, &"date:desc".parse().unwrap(), 10, 0)
+        .unwrap();
+    assert_eq!(0, envelopes.len());
+    let query: SearchQuery = "flag seen text \"plain message\"".parse().unwrap();
+    let envelopes = mdir
+        .search_envelopes("INBOX", &query, &"subject:asc".parse().unwrap(), 0, 0)
+        .unwrap();
+    assert_eq!(1, envelopes.len());
+
     // check that a flag can be added to the message
     let flags = Flags::from_iter([Flag::Flagged]);
     mdir.add_flags("INBOX", vec![&envelope.id], &flags).unwrap();
@@ -168,3 +190,69 @@ fn test_maildir_backend() {
     let trash = mdir.list_envelopes("Trash", 0, 0).unwrap();
     assert_eq!(0, trash.len());
 }
+
+#[test]
+fn test_maildir_backend_subscribe() {
+    let mdir: Maildir = tempdir().unwrap().path().to_owned().into();
+    mdir.create_dirs().unwrap();
+
+    let account_config = AccountConfig {
+        name: "account".into(),
+        ..AccountConfig::default()
+    };
+
+    let backend = MaildirBackend::new(
+        Cow::Borrowed(&account_config),
+        Cow::Owned(MaildirConfig {
+            root_dir: mdir.path().to_owned(),
+        }),
+    )
+    .unwrap();
+
+    let subscription = backend.subscribe("INBOX").unwrap();
+    let next_event = || {
+        subscription
+            .next_timeout(Duration::from_secs(5))
+            .expect("missing maildir event")
+            .unwrap()
+    };
+
+    // check that an email delivered by an external program is
+    // notified, but not its move from new/ to cur/
+    let internal_id = mdir
+        .store_new(b"From: alice@localhost\r\nSubject: Hello\r\n\r\nHello!")
+        .unwrap();
+    match next_event() {
+        BackendEvent::EnvelopeAdded(folder, envelope) => {
+            assert_eq!(folder, "INBOX");
+            assert_eq!(envelope.internal_id, internal_id);
+            assert_eq!(envelope.subject, "Hello");
+        }
+        event => panic!("unexpected event {event:?}"),
+    }
+    mdir.move_new_to_cur(&internal_id).unwrap();
+
+    // check that flag changes are notified
+    mdir.set_flags(&internal_id, "S").unwrap();
+    assert_eq!(
+        next_event(),
+        BackendEvent::FlagsChanged(
+            "INBOX".into(),
+            internal_id.clone(),
+            Flags::from_iter([Flag::Seen])
+        )
+    );
+
+    // check that removals are notified
+    mdir.delete(&internal_id).unwrap();
+    assert_eq!(
+        next_event(),
+        BackendEvent::EnvelopeRemoved("INBOX".into(), internal_id)
+    );
+
+    // check that new folders are notified
+    Maildir::from(mdir.path().join(".Archives"))
+        .create_dirs()
+        .unwrap();
+    assert_eq!(next_event(), BackendEvent::FolderAdded("Archives".into()));
+}
@@ -0,0 +1,89 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    sync::mpsc,
+    thread,
+};
+
+use himalaya_lib::{OAuth2Config, Secret};
+
+/// Spawns a token endpoint stand-in answering the given responses,
+/// one per connection, and sending back the received request bodies.
+fn spawn_token_endpoint(responses: Vec<(u16, &'static str)>) -> (String, mpsc::Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/token", listener.local_addr().unwrap());
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        for (status, body) in responses {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some((key, val)) = line.split_once(':') {
+                    if key.eq_ignore_ascii_case("content-length") {
+                        content_length = val.trim().parse().unwrap();
+                    }
+                }
+            }
+
+            let mut req_body = vec![0; content_length];
+            reader.read_exact(&mut req_body).unwrap();
+            tx.send(String::from_utf8(req_body).unwrap()).unwrap();
+
+            let res = format!(
+                "HTTP/1.1 {status} Status\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            reader.get_mut().write_all(res.as_bytes()).unwrap();
+        }
+    });
+
+    (url, rx)
+}
+
+#[test]
+fn test_oauth2_refresh() {
+    let (token_url, requests) = spawn_token_endpoint(vec![
+        (
+            200,
+            r#"{"access_token":"token-1","expires_in":30,"refresh_token":"refresh-2"}"#,
+        ),
+        (200, r#"{"access_token":"token-2","expires_in":3600}"#),
+        (400, r#"{"error":"invalid_grant"}"#),
+    ]);
+
+    let config = OAuth2Config {
+        client_id: "client".into(),
+        client_secret: Secret::new_raw("secret"),
+        token_url,
+        refresh_token: Secret::new_raw("refresh-1"),
+        ..OAuth2Config::default()
+    };
+
+    // check that a missing access token is refreshed
+    assert_eq!(config.access_token().unwrap(), "token-1");
+    assert_eq!(
+        requests.recv().unwrap(),
+        "grant_type=refresh_token&refresh_token=refresh-1&client_id=client&client_secret=secret"
+    );
+
+    // check that an access token about to expire is refreshed, using
+    // the rotated refresh token
+    assert_eq!(config.access_token().unwrap(), "token-2");
+    assert!(requests.recv().unwrap().contains("refresh_token=refresh-2"));
+
+    // check that the access token is cached and shared between clones
+    assert_eq!(config.clone().access_token().unwrap(), "token-2");
+    assert!(requests.try_recv().is_err());
+
+    // check that token endpoint errors are reported
+    let err = config.refresh_access_token().unwrap_err();
+    assert!(err.to_string().contains("invalid_grant"));
+}
@@ -0,0 +1,74 @@
+use concat_with::concat_line;
+use maildir::Maildir;
+use std::borrow::Cow;
+use tempfile::tempdir;
+
+use himalaya_lib::{
+    sender, AccountConfig, Backend, EmailHooks, Flag, MaildirBackend, MaildirConfig, Sendmail,
+    SendmailConfig,
+};
+
+#[test]
+fn test_send_and_save_copy() {
+    let dir = tempdir().unwrap();
+    let mdir: Maildir = dir.path().to_owned().into();
+    mdir.create_dirs().unwrap();
+    Maildir::from(dir.path().join(".Sent"))
+        .create_dirs()
+        .unwrap();
+
+    let account_config = AccountConfig {
+        email_hooks: EmailHooks {
+            pre_send: Some("sed 's/Hello/Bye/'".into()),
+        },
+        ..AccountConfig::default()
+    };
+    let sendmail_config = SendmailConfig {
+        cmd: "cat > /dev/null".into(),
+    };
+    let mut sendmail = Sendmail::new(&account_config, &sendmail_config);
+    let mdir = MaildirBackend::new(
+        Cow::Borrowed(&account_config),
+        Cow::Owned(MaildirConfig {
+            root_dir: dir.path().to_owned(),
+        }),
+    )
+    .unwrap();
+
+    let email = concat_line!(
+        "From: alice@localhost",
+        "To: bob@localhost",
+        "Bcc: carol@localhost",
+        "Subject: subject",
+        "",
+        "Hello!",
+    );
+
+    // the copy is the email after the pre-send hook, with the Bcc
+    // header kept
+    let report =
+        sender::send_and_save_copy(&account_config, &mut sendmail, &mdir, email.as_bytes())
+            .unwrap();
+    assert_eq!(
+        String::from_utf8(report.email.clone()).unwrap(),
+        email.replace("Hello", "Bye")
+    );
+
+    let envelopes = mdir.list_envelopes("Sent", 10, 0).unwrap();
+    assert_eq!(envelopes.len(), 1);
+    assert!(envelopes[0].flags.contains(&Flag::Seen));
+
+    let emails = mdir.get_emails("Sent", vec![&envelopes[0].id]).unwrap();
+    assert_eq!(
+        emails.first().unwrap().raw().unwrap(),
+        report.email.as_slice()
+    );
+
+    // no copy is saved when disabled
+    let account_config = AccountConfig {
+        email_sending_save_copy: Some(false),
+        ..account_config.clone()
+    };
+    sender::send_and_save_copy(&account_config, &mut sendmail, &mdir, email.as_bytes()).unwrap();
+    assert_eq!(mdir.list_envelopes("Sent", 10, 0).unwrap().len(), 1);
+}
@@ -4,7 +4,7 @@ use std::{borrow::Cow, thread, time::Duration};
 use himalaya_lib::{AccountConfig, CompilerBuilder, Sender, Sendmail, SendmailConfig, TplBuilder};
 
 #[cfg(feature = "imap-backend")]
-use himalaya_lib::{Backend, ImapBackend, ImapConfig};
+use himalaya_lib::{Backend, ImapBackend, ImapConfig, Secret};
 
 #[cfg(feature = "imap-backend")]
 #[test]
@@ -32,7 +32,7 @@ fn test_sendmail_sender() {
             port: 3143,
             ssl: Some(false),
             login: "bob@localhost".into(),
-            passwd_cmd: "echo 'password'".into(),
+            passwd: Secret::new_cmd("echo 'password'"),
             ..ImapConfig::default()
         }),
     )
@@ -4,7 +4,7 @@ use std::{borrow::Cow, thread, time::Duration};
 use himalaya_lib::{AccountConfig, CompilerBuilder, Sender, TplBuilder};
 
 #[cfg(feature = "imap-backend")]
-use himalaya_lib::{Backend, ImapBackend, ImapConfig};
+use himalaya_lib::{Backend, ImapBackend, ImapConfig, Secret};
 #[cfg(feature = "smtp-sender")]
 use himalaya_lib::{Smtp, SmtpConfig};
 
@@ -22,7 +22,7 @@ fn test_smtp_sender() {
         starttls: Some(false),
         insecure: Some(true),
         login: "alice@localhost".into(),
-        passwd_cmd: "echo 'password'".into(),
+        passwd: Secret::new_cmd("echo 'password'"),
         ..SmtpConfig::default()
     };
     let mut smtp = Smtp::new(&account_config, &smtp_config);
@@ -36,7 +36,7 @@ fn test_smtp_sender() {
             starttls: Some(false),
             insecure: Some(true),
             login: "bob@localhost".into(),
-            passwd_cmd: "echo password".into(),
+            passwd: Secret::new_cmd("echo password"),
             ..ImapConfig::default()
         }),
     )
@@ -0,0 +1,131 @@
+#![cfg(feature = "smtp-sender")]
+
+use concat_with::concat_line;
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    thread,
+};
+
+use himalaya_lib::{
+    AccountConfig, Secret, Sender, Smtp, SmtpConfig, SmtpDsnConfig, SmtpDsnNotify, SmtpDsnRet,
+};
+
+/// Spawns a SMTP server stand-in serving one session, then sending
+/// back the received commands. Recipients starting with `unknown` are
+/// rejected.
+fn spawn_smtp_server() -> (u16, mpsc::Receiver<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut cmds = Vec::new();
+        let write = |reader: &mut BufReader<TcpStream>, reply: &str| {
+            reader.get_mut().write_all(reply.as_bytes()).unwrap();
+        };
+
+        write(&mut reader, "220 localhost ESMTP\r\n");
+
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 {
+                break;
+            }
+
+            let cmd = line.trim_end().to_owned();
+            if cmd.starts_with("EHLO") {
+                write(
+                    &mut reader,
+                    "250-localhost\r\n250-AUTH PLAIN\r\n250-DSN\r\n250 8BITMIME\r\n",
+                );
+            } else if cmd.starts_with("AUTH") {
+                write(&mut reader, "235 2.7.0 Authenticated\r\n");
+            } else if cmd.starts_with("MAIL FROM:") {
+                write(&mut reader, "250 2.1.0 OK\r\n");
+            } else if cmd.starts_with("RCPT TO:<unknown") {
+                write(&mut reader, "550 5.1.1 User unknown\r\n");
+            } else if cmd.starts_with("RCPT TO:") {
+                write(&mut reader, "250 2.1.5 OK\r\n");
+            } else if cmd == "DATA" {
+                write(&mut reader, "354 OK\r\n");
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == ".\r\n" {
+                        break;
+                    }
+                }
+                write(&mut reader, "250 2.0.0 Queued as 42\r\n");
+            } else if cmd == "QUIT" {
+                write(&mut reader, "221 Bye\r\n");
+                cmds.push(cmd);
+                break;
+            } else {
+                write(&mut reader, "500 Unknown command\r\n");
+            }
+
+            cmds.push(cmd);
+        }
+
+        tx.send(cmds).unwrap();
+    });
+
+    (port, rx)
+}
+
+#[test]
+fn test_smtp_sender_report() {
+    let (port, cmds) = spawn_smtp_server();
+
+    let account_config = AccountConfig::default();
+    let smtp_config = SmtpConfig {
+        host: "127.0.0.1".into(),
+        port,
+        ssl: Some(false),
+        login: "alice@localhost".into(),
+        passwd: Secret::new_cmd("echo password"),
+        dsn: Some(SmtpDsnConfig {
+            notify: vec![SmtpDsnNotify::Success, SmtpDsnNotify::Failure],
+            ret: Some(SmtpDsnRet::Headers),
+            envid: None,
+        }),
+        ..SmtpConfig::default()
+    };
+    let mut smtp = Smtp::new(&account_config, &smtp_config);
+
+    let email = concat_line!(
+        "Message-ID: <id@localhost>",
+        "From: alice@localhost",
+        "To: bob@localhost, unknown@localhost",
+        "Subject: subject",
+        "",
+        "Hello!",
+    );
+
+    let report = smtp.send(email.as_bytes()).unwrap();
+    assert!(report.is_partial());
+
+    let rejected: Vec<_> = report.rejected().collect();
+    assert_eq!(rejected.len(), 1);
+    assert_eq!(rejected[0].addr, "unknown@localhost");
+    assert_eq!(rejected[0].reply.as_ref().unwrap().code, 550);
+
+    let accepted: Vec<_> = report.accepted().collect();
+    assert_eq!(accepted.len(), 1);
+    assert_eq!(accepted[0].addr, "bob@localhost");
+    assert_eq!(
+        accepted[0].reply.as_ref().unwrap().to_string(),
+        "250 2.0.0 Queued as 42"
+    );
+
+    // delivery status notifications are requested
+    let cmds = cmds.recv().unwrap();
+    assert!(cmds.contains(&"MAIL FROM:<alice@localhost> RET=HDRS ENVID=id@localhost".into()));
+    assert!(cmds.contains(
+        &"RCPT TO:<bob@localhost> NOTIFY=SUCCESS,FAILURE ORCPT=rfc822;bob@localhost".into()
+    ));
+}
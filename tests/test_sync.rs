@@ -5,7 +5,7 @@ use tempfile::tempdir;
 
 use himalaya_lib::{
     envelope, folder, AccountConfig, Backend, BackendSyncBuilder, CompilerBuilder, Flag, Flags,
-    ImapBackend, ImapConfig, MaildirBackend, MaildirConfig, TplBuilder,
+    ImapBackend, ImapConfig, MaildirBackend, MaildirConfig, Secret, TplBuilder,
 };
 
 #[test]
@@ -34,7 +34,7 @@ fn test_sync() {
             starttls: Some(false),
             insecure: Some(true),
             login: "bob@localhost".into(),
-            passwd_cmd: "echo 'password'".into(),
+            passwd: Secret::new_cmd("echo 'password'"),
             ..ImapConfig::default()
         }),
     )
@@ -0,0 +1,382 @@
+use maildir::Maildir;
+use std::{borrow::Cow, collections::HashSet, path::Path, sync::Mutex};
+use tempfile::tempdir;
+
+use himalaya_lib::{
+    envelope::{
+        self,
+        sync::{BackendHunk, HunkKind, HunkKindRestricted, HunkRecovery, Journal},
+    },
+    AccountConfig, Backend, BackendSyncBuilder, BackendSyncMode, BackendSyncProgressEvent,
+    CompilerBuilder, Envelope, Flag, Flags, MaildirBackend, MaildirConfig, TplBuilder,
+};
+
+fn maildir<'a>(account: &'a AccountConfig, path: &Path) -> MaildirBackend<'a> {
+    Maildir::from(path.to_owned()).create_dirs().unwrap();
+    MaildirBackend::new(
+        Cow::Borrowed(account),
+        Cow::Owned(MaildirConfig {
+            root_dir: path.to_owned(),
+        }),
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_sync_backends() {
+    let sync_dir = tempdir().unwrap();
+    let account = AccountConfig {
+        name: "account".into(),
+        sync_dir: Some(sync_dir.path().to_owned()),
+        ..AccountConfig::default()
+    };
+
+    let (dir_a, dir_b) = (tempdir().unwrap(), tempdir().unwrap());
+    let a = maildir(&account, &dir_a.path().join("a"));
+    let b = maildir(&account, &dir_b.path().join("b"));
+
+    a.add_folder("Archives").unwrap();
+    a.add_email(
+        "INBOX",
+        &TplBuilder::default()
+            .message_id("<a@localhost>")
+            .from("alice@localhost")
+            .to("bob@localhost")
+            .subject("A")
+            .text_plain_part("A")
+            .compile(CompilerBuilder::default())
+            .unwrap(),
+        &Flags::from_iter([Flag::Seen]),
+    )
+    .unwrap();
+
+    // sync the two maildirs, which does not require the account
+    // synchronization to be enabled
+
+    let sync_builder = BackendSyncBuilder::new(&account);
+    sync_builder.sync_backends(&a, &b).unwrap();
+
+    let folders = b.list_folders().unwrap();
+    assert!(folders.iter().any(|folder| folder.name == "Archives"));
+
+    let envelopes = b.list_envelopes("INBOX", 0, 0).unwrap();
+    assert_eq!(envelopes.len(), 1);
+    assert_eq!(envelopes[0].subject, "A");
+    assert_eq!(envelopes[0].flags, Flags::from_iter([Flag::Seen]));
+
+    // a second sync has nothing to do
+
+    let report = sync_builder.sync_backends(&a, &b).unwrap();
+    assert!(report.folders_patch.is_empty());
+    assert!(report.envelopes_patch.is_empty());
+
+    // changes made on one side are synchronized to the other one
+
+    b.add_flags(
+        "INBOX",
+        vec![&envelopes[0].id],
+        &Flags::from_iter([Flag::Flagged]),
+    )
+    .unwrap();
+    sync_builder.sync_backends(&a, &b).unwrap();
+
+    let envelopes = a.list_envelopes("INBOX", 0, 0).unwrap();
+    assert_eq!(
+        envelopes[0].flags,
+        Flags::from_iter([Flag::Seen, Flag::Flagged])
+    );
+
+    // the cache is keyed by both endpoints
+
+    let mut conn = rusqlite::Connection::open(sync_dir.path().join(".sync.sqlite")).unwrap();
+    let cache_key = format!("{} <> {}", a.endpoint(), b.endpoint());
+    let cached =
+        envelope::sync::Cache::list_local_envelopes(&mut conn, &cache_key, "INBOX").unwrap();
+    assert_eq!(cached.len(), 1);
+    let cached =
+        envelope::sync::Cache::list_local_envelopes(&mut conn, "account", "INBOX").unwrap();
+    assert!(cached.is_empty());
+}
+
+#[test]
+fn test_sync_backends_parallel_folders() {
+    let sync_dir = tempdir().unwrap();
+    let account = AccountConfig {
+        name: "account".into(),
+        sync_dir: Some(sync_dir.path().to_owned()),
+        ..AccountConfig::default()
+    };
+
+    let (dir_a, dir_b) = (tempdir().unwrap(), tempdir().unwrap());
+    let a = maildir(&account, &dir_a.path().join("a"));
+    let b = maildir(&account, &dir_b.path().join("b"));
+
+    let folders = ["INBOX", "Archives", "Drafts", "Sent", "Trash"];
+    for (i, folder) in folders.iter().enumerate() {
+        if *folder != "INBOX" {
+            a.add_folder(folder).unwrap();
+        }
+        a.add_email(
+            folder,
+            &TplBuilder::default()
+                .message_id(format!("<{i}@localhost>"))
+                .from("alice@localhost")
+                .to("bob@localhost")
+                .subject(*folder)
+                .text_plain_part(*folder)
+                .compile(CompilerBuilder::default())
+                .unwrap(),
+            &Flags::default(),
+        )
+        .unwrap();
+    }
+
+    let events = Mutex::new(Vec::new());
+    let report = BackendSyncBuilder::new(&account)
+        .folders_parallelism(3)
+        .on_progress(|evt| {
+            events.lock().unwrap().push(evt);
+            Ok(())
+        })
+        .sync_backends(&a, &b)
+        .unwrap();
+
+    assert_eq!(report.folders.len(), folders.len());
+
+    for folder in folders {
+        let envelopes = b.list_envelopes(folder, 0, 0).unwrap();
+        assert_eq!(envelopes.len(), 1);
+        assert_eq!(envelopes[0].subject, folder);
+    }
+
+    // progress events are attributed to their folder
+
+    let events = events.into_inner().unwrap();
+    let started: HashSet<_> = events
+        .iter()
+        .filter_map(|evt| match evt {
+            BackendSyncProgressEvent::StartEnvelopesSync(folder, _, total) => {
+                assert_eq!(*total, folders.len());
+                Some(folder.as_str())
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(started, HashSet::from(folders));
+
+    let patched: HashSet<_> = events
+        .iter()
+        .filter_map(|evt| match evt {
+            BackendSyncProgressEvent::ProcessEnvelopesPatch(folder, _) => Some(folder.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(patched, HashSet::from(folders));
+
+    // a second sync has nothing to do
+
+    let report = BackendSyncBuilder::new(&account)
+        .folders_parallelism(3)
+        .sync_backends(&a, &b)
+        .unwrap();
+    assert!(report.envelopes_patch.is_empty());
+}
+
+fn email(message_id: &str, subject: &str) -> Vec<u8> {
+    TplBuilder::default()
+        .message_id(message_id)
+        .from("alice@localhost")
+        .to("bob@localhost")
+        .subject(subject)
+        .text_plain_part(subject)
+        .compile(CompilerBuilder::default())
+        .unwrap()
+}
+
+#[test]
+fn test_sync_backends_recover_journal() {
+    let sync_dir = tempdir().unwrap();
+    let account = AccountConfig {
+        name: "account".into(),
+        sync_dir: Some(sync_dir.path().to_owned()),
+        ..AccountConfig::default()
+    };
+
+    let (dir_a, dir_b) = (tempdir().unwrap(), tempdir().unwrap());
+    let a = maildir(&account, &dir_a.path().join("a"));
+    let b = maildir(&account, &dir_b.path().join("b"));
+
+    a.add_email("INBOX", &email("<a@localhost>", "A"), &Flags::default())
+        .unwrap();
+
+    let sync_builder = BackendSyncBuilder::new(&account);
+    sync_builder.sync_backends(&a, &b).unwrap();
+
+    // simulate a synchronization interrupted while copying B and C
+    // from a to b: B has been copied, C has not
+
+    a.add_email("INBOX", &email("<b@localhost>", "B"), &Flags::default())
+        .unwrap();
+    a.add_email("INBOX", &email("<c@localhost>", "C"), &Flags::default())
+        .unwrap();
+    b.add_email("INBOX", &email("<b@localhost>", "B"), &Flags::default())
+        .unwrap();
+
+    let patch = a
+        .list_envelopes("INBOX", 0, 0)
+        .unwrap()
+        .iter()
+        .filter(|envelope| envelope.subject != "A")
+        .map(|envelope| {
+            vec![BackendHunk::CopyEmail(
+                "INBOX".into(),
+                envelope.clone(),
+                HunkKindRestricted::Local,
+                HunkKindRestricted::Remote,
+                true,
+            )]
+        })
+        .collect::<Vec<_>>();
+
+    let mut conn = rusqlite::Connection::open(sync_dir.path().join(".sync.sqlite")).unwrap();
+    let cache_key = format!("{} <> {}", a.endpoint(), b.endpoint());
+    Journal::record(&mut conn, &cache_key, "INBOX", &patch).unwrap();
+
+    // the next synchronization recovers the journal
+
+    let report = sync_builder.sync_backends(&a, &b).unwrap();
+
+    let mut recovered = report
+        .envelopes_recovered_patch
+        .iter()
+        .map(|(hunk, recovery)| match (hunk, recovery) {
+            (BackendHunk::CopyEmail(_, envelope, _, _, _), HunkRecovery::Replayed) => {
+                (envelope.subject.as_str(), "replayed")
+            }
+            (BackendHunk::CopyEmail(_, envelope, _, _, _), HunkRecovery::RolledBack) => {
+                (envelope.subject.as_str(), "rolled back")
+            }
+            (hunk, recovery) => panic!("unexpected recovery {recovery:?} of {hunk:?}"),
+        })
+        .collect::<Vec<_>>();
+    recovered.sort();
+    assert_eq!(recovered, vec![("B", "replayed"), ("C", "rolled back")]);
+
+    // B is not copied twice, C is copied by the new patch
+
+    let mut subjects = b
+        .list_envelopes("INBOX", 0, 0)
+        .unwrap()
+        .iter()
+        .map(|envelope| envelope.subject.clone())
+        .collect::<Vec<_>>();
+    subjects.sort();
+    assert_eq!(subjects, vec!["A", "B", "C"]);
+
+    assert!(Journal::list(&mut conn, &cache_key, "INBOX")
+        .unwrap()
+        .is_empty());
+    let cached =
+        envelope::sync::Cache::list_remote_envelopes(&mut conn, &cache_key, "INBOX").unwrap();
+    assert_eq!(cached.len(), 3);
+
+    // a last synchronization has nothing to do
+
+    let report = sync_builder.sync_backends(&a, &b).unwrap();
+    assert!(report.envelopes_patch.is_empty());
+    assert!(report.envelopes_recovered_patch.is_empty());
+}
+
+#[test]
+fn test_sync_backends_recover_journal_changed_flags() {
+    let sync_dir = tempdir().unwrap();
+    let account = AccountConfig {
+        name: "account".into(),
+        sync_dir: Some(sync_dir.path().to_owned()),
+        ..AccountConfig::default()
+    };
+
+    let (dir_a, dir_b) = (tempdir().unwrap(), tempdir().unwrap());
+    let a = maildir(&account, &dir_a.path().join("a"));
+    let b = maildir(&account, &dir_b.path().join("b"));
+
+    let flagged = Flags::from_iter([Flag::Flagged]);
+    a.add_email("INBOX", &email("<a@localhost>", "A"), &flagged)
+        .unwrap();
+
+    let sync_builder = BackendSyncBuilder::new(&account);
+    sync_builder.sync_backends(&a, &b).unwrap();
+
+    // simulate a synchronization interrupted while unflagging A on
+    // b, after the user unflagged it on a
+
+    let id = a.list_envelopes("INBOX", 0, 0).unwrap()[0].id.clone();
+    a.remove_flags("INBOX", vec![&id], &flagged).unwrap();
+
+    let envelope = b.list_envelopes("INBOX", 0, 0).unwrap()[0].clone();
+    let patch = vec![vec![BackendHunk::SetFlags(
+        "INBOX".into(),
+        Envelope {
+            flags: Flags::default(),
+            ..envelope
+        },
+        HunkKind::Remote,
+    )]];
+
+    let mut conn = rusqlite::Connection::open(sync_dir.path().join(".sync.sqlite")).unwrap();
+    let cache_key = format!("{} <> {}", a.endpoint(), b.endpoint());
+    Journal::record(&mut conn, &cache_key, "INBOX", &patch).unwrap();
+
+    // the user flags A again before the next synchronization, which
+    // must not replay the stale hunk
+
+    a.add_flags("INBOX", vec![&id], &flagged).unwrap();
+
+    let report = sync_builder.sync_backends(&a, &b).unwrap();
+    assert!(matches!(
+        report.envelopes_recovered_patch.as_slice(),
+        [(
+            BackendHunk::SetFlags(_, _, HunkKind::Remote),
+            HunkRecovery::RolledBack
+        )]
+    ));
+
+    assert_eq!(a.list_envelopes("INBOX", 0, 0).unwrap()[0].flags, flagged);
+    assert_eq!(b.list_envelopes("INBOX", 0, 0).unwrap()[0].flags, flagged);
+    assert!(Journal::list(&mut conn, &cache_key, "INBOX")
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn test_sync_backends_pull_only_expunge() {
+    let sync_dir = tempdir().unwrap();
+    let account = AccountConfig {
+        name: "account".into(),
+        sync_dir: Some(sync_dir.path().to_owned()),
+        ..AccountConfig::default()
+    };
+
+    let (dir_local, dir_remote) = (tempdir().unwrap(), tempdir().unwrap());
+    let local = maildir(&account, &dir_local.path().join("local"));
+    let remote = maildir(&account, &dir_remote.path().join("remote"));
+
+    remote
+        .add_email(
+            "INBOX",
+            &email("<a@localhost>", "A"),
+            &Flags::from_iter([Flag::Deleted]),
+        )
+        .unwrap();
+
+    // the authoritative remote side is never expunged
+
+    BackendSyncBuilder::new(&account)
+        .mode(BackendSyncMode::PullOnly)
+        .sync_backends(&local, &remote)
+        .unwrap();
+
+    let envelopes = remote.list_envelopes("INBOX", 0, 0).unwrap();
+    assert_eq!(envelopes.len(), 1);
+    assert_eq!(envelopes[0].flags, Flags::from_iter([Flag::Deleted]));
+}